@@ -1,16 +1,14 @@
 use anyhow::{Context, Result};
 use std::{
     collections::{BTreeMap, HashMap},
-    fs::File,
-    io::BufReader,
     mem::Discriminant,
-    path::PathBuf,
+    path::Path,
     time::Instant,
 };
 use tracing::info;
 
 use blockzilla_format::{
-    CompactBlockRecord, CompactTxWithMeta, PostcardFramedReader,
+    CompactReader, CompactTxWithMeta,
     compact::{CompactMessage, CompactTransaction},
 };
 
@@ -54,6 +52,9 @@ pub struct EpochReport {
     pub atl_container_bytes: u64, // serialized size of address_table_lookups Vec
     pub atl_payload_bytes: u64,   // serialized size of ATL fields
 
+    pub vote_records: u64,
+    pub vote_bytes: u64, // serialized size of vote records (meta included)
+
     // meta breakdown (extra details)
     pub bytes_meta_logs: u64,            // serialized size of meta.logs
     pub bytes_meta_logs_strings: u64,    // serialized size of logs.strings
@@ -105,6 +106,7 @@ fn instr_data_raw_len(tx: &CompactTransaction) -> u64 {
     let ixs = match &tx.message {
         CompactMessage::Legacy(m) => &m.instructions,
         CompactMessage::V0(m) => &m.instructions,
+        CompactMessage::Vote(_) => return 0,
     };
     ixs.iter().map(|ix| ix.data.len() as u64).sum()
 }
@@ -135,14 +137,17 @@ struct DiscAgg {
 }
 
 pub fn analyze_epoch_file(
-    path: &PathBuf,
+    path: &Path,
     progress_every: u64,       // blocks, 0 disables
     limit_blocks: Option<u64>, // optional early stop + ETA
 ) -> Result<EpochReport> {
-    info!("analyze-epoch input={} (framed)", path.display());
-
-    let f = File::open(path).with_context(|| format!("open {}", path.display()))?;
-    let mut reader = PostcardFramedReader::new(BufReader::with_capacity(64 << 20, f));
+    let mut reader = CompactReader::open(path)?;
+    info!(
+        "analyze-epoch input={} (v{} {:?})",
+        path.display(),
+        reader.header().version,
+        reader.header().layout
+    );
 
     let start = Instant::now();
     let mut rep = EpochReport::default();
@@ -151,10 +156,7 @@ pub fn analyze_epoch_file(
     // Fast aggregation keyed by discriminant (avoid allocating String per event)
     let mut disc_map: HashMap<Discriminant<LogEvent>, DiscAgg> = HashMap::new();
 
-    while let Some(block) = reader
-        .read::<CompactBlockRecord>()
-        .context("decode CompactBlockRecord")?
-    {
+    while let Some(block) = reader.read().context("decode CompactBlockRecord")? {
         if let Some(max) = limit_blocks
             && rep.blocks >= max
         {
@@ -167,6 +169,15 @@ pub fn analyze_epoch_file(
         rep.bytes_header += sz(&block.header)?;
         rep.txs += block.txs.len() as u64;
 
+        // Block-level log tables (v2); per-tx tables below are empty then.
+        let tables = &block.log_tables;
+        rep.bytes_meta += sz(tables)?;
+        rep.bytes_meta_logs += sz(tables)?;
+        rep.bytes_meta_logs_strings += sz(&tables.strings)?;
+        rep.meta_log_lines += tables.strings.strings.len() as u64;
+        rep.bytes_meta_logs_data += sz(&tables.data)?;
+        rep.meta_log_data_arrays += tables.data.arrays.len() as u64;
+
         for CompactTxWithMeta { tx, metadata } in &block.txs {
             // tx sizing
             let tx_sz = sz(&tx)?;
@@ -207,6 +218,10 @@ pub fn analyze_epoch_file(
                         rep.atl_payload_bytes += sz(&l.readonly_indexes)?;
                     }
                 }
+                CompactMessage::Vote(v) => {
+                    rep.vote_records += 1;
+                    rep.vote_bytes += sz(v)?;
+                }
             }
 
             // meta sizing (details)
@@ -401,6 +416,15 @@ pub fn print_epoch_report(rep: &EpochReport) {
         pct_tx(rep.ix_overhead_bytes_approx())
     );
 
+    if rep.vote_records > 0 {
+        println!(
+            "{:>14} {:>8.2}%  vote_records({})",
+            rep.vote_bytes,
+            pct_tx(rep.vote_bytes),
+            rep.vote_records
+        );
+    }
+
     if rep.atl_container_bytes > 0 || rep.atl_payload_bytes > 0 {
         println!(
             "{:>14} {:>8.2}%  atl.container",
@@ -1,13 +1,16 @@
 use anyhow::{Context, Result};
 use std::{
     fs::File,
-    io::{self, BufReader, Write},
-    path::{Path, PathBuf},
+    io::{self, Write},
+    path::Path,
     time::Instant,
 };
 use tracing::info;
 
-use blockzilla_format::{PostcardFramedReader, compact::CompactBlockRecord, log::DataTable};
+use blockzilla_format::{
+    CompactLayout, CompactReader, SectionedBlock, decode_compact_frame,
+    log::{DataTable, StringTable},
+};
 
 fn fmt_dur(secs: u64) -> String {
     let h = secs / 3600;
@@ -20,18 +23,48 @@ fn fmt_dur(secs: u64) -> String {
     }
 }
 
+fn write_tables(
+    out: &mut dyn Write,
+    strings: &StringTable,
+    data: Option<&DataTable>,
+    max_lines: u64,
+    lines_out: &mut u64,
+) -> Result<()> {
+    for s in strings.strings.iter() {
+        if max_lines != 0 && *lines_out >= max_lines {
+            return Ok(());
+        }
+        writeln!(out, "{s}")?;
+        *lines_out += 1;
+    }
+
+    for data in data.iter().flat_map(|d| d.arrays.iter()) {
+        if max_lines != 0 && *lines_out >= max_lines {
+            break;
+        }
+        let rendered = DataTable::render_array(data);
+        writeln!(out, "{rendered}")?;
+        *lines_out += 1;
+    }
+    Ok(())
+}
+
 pub fn dump_log_strings(
-    path: &PathBuf,
+    path: &Path,
     out_path: Option<&Path>,
     limit_blocks: Option<u64>,
     progress_every: u64,
     max_lines: u64,
     include_data: bool,
 ) -> Result<()> {
-    info!("dump-log-strings input={} (framed)", path.display());
-
-    let f = File::open(path).with_context(|| format!("open {}", path.display()))?;
-    let mut reader = PostcardFramedReader::new(BufReader::with_capacity(64 << 20, f));
+    let mut reader = CompactReader::open(path)?;
+    let header = reader.header();
+    info!(
+        "dump-log-strings input={} (v{} {:?})",
+        path.display(),
+        header.version,
+        header.layout
+    );
 
     let mut out: Box<dyn Write> = match out_path {
         Some(p) => Box::new(io::BufWriter::with_capacity(
@@ -45,10 +78,7 @@ pub fn dump_log_strings(
     let mut blocks = 0u64;
     let mut lines_out = 0u64;
 
-    while let Some(rec) = reader
-        .read::<CompactBlockRecord>()
-        .context("postcard decode CompactBlockRecord")?
-    {
+    while let Some(frame) = reader.read_bytes().context("read frame")? {
         if let Some(max) = limit_blocks
             && blocks >= max
         {
@@ -58,35 +88,54 @@ pub fn dump_log_strings(
             break;
         }
 
-        for tx in rec.txs.iter() {
-            if max_lines != 0 && lines_out >= max_lines {
-                break;
-            }
-
-            let Some(meta) = tx.metadata.as_ref() else {
-                continue;
-            };
-            let Some(logs) = meta.logs.as_ref() else {
-                continue;
+        if header.layout == CompactLayout::Sectioned {
+            // Only the strings (and data) sections are decoded, events are skipped.
+            let block = SectionedBlock::parse(frame, header).context("parse SectionedBlock")?;
+            let strings = block.log_strings()?;
+            let data = if include_data {
+                Some(block.log_data()?)
+            } else {
+                None
             };
+            write_tables(&mut out, &strings, data.as_ref(), max_lines, &mut lines_out)?;
 
-            for s in logs.strings.strings.iter() {
+            for tx in block.txs() {
                 if max_lines != 0 && lines_out >= max_lines {
                     break;
                 }
-                writeln!(out, "{s}")?;
-                lines_out += 1;
+                let tx = tx?;
+                let Some(strings) = tx.log_strings()? else {
+                    continue;
+                };
+                let data = if include_data { tx.log_data()? } else { None };
+                write_tables(&mut out, &strings, data.as_ref(), max_lines, &mut lines_out)?;
             }
-
-            if include_data {
-                for data in logs.data.arrays.iter() {
-                    if max_lines != 0 && lines_out >= max_lines {
-                        break;
-                    }
-                    let rendered = DataTable::render_array(data);
-                    writeln!(out, "{rendered}")?;
-                    lines_out += 1;
+        } else {
+            let rec = decode_compact_frame(header, frame).context("decode CompactBlockRecord")?;
+            let data = include_data.then_some(&rec.log_tables.data);
+            write_tables(
+                &mut out,
+                &rec.log_tables.strings,
+                data,
+                max_lines,
+                &mut lines_out,
+            )?;
+
+            // Per-transaction tables: v1 files, or self-contained streams.
+            for tx in rec.txs.iter() {
+                if max_lines != 0 && lines_out >= max_lines {
+                    break;
                 }
+
+                let Some(meta) = tx.metadata.as_ref() else {
+                    continue;
+                };
+                let Some(logs) = meta.logs.as_ref() else {
+                    continue;
+                };
+
+                let data = include_data.then_some(&logs.data);
+                write_tables(&mut out, &logs.strings, data, max_lines, &mut lines_out)?;
             }
         }
 
@@ -38,6 +38,7 @@ enum Commands {
     ///
     /// Important: With V1 layout (events serialized before strings) we must deserialize the whole
     /// CompactLogStream to reach strings (postcard cannot skip via deserialize_any).
+    /// A sectioned compact.bin is detected from its header and only decodes the string/data sections.
     DumpLogStrings {
         /// Input file containing a stream of (varint_u32_len + postcard(CompactBlockRecord))
         #[arg(short, long)]
@@ -0,0 +1,228 @@
+use anyhow::{Context, Result};
+use gxhash::HashMap as GxHashMap;
+
+use crate::{CompactFileHeader, CompactMessage, CompactMetaV1, CompactPubkey, CompactTransaction};
+
+#[inline]
+pub(crate) fn zigzag(v: i64) -> u64 {
+    ((v << 1) ^ (v >> 63)) as u64
+}
+
+#[inline]
+pub(crate) fn unzigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+/// `pre` resized to `len` with the fee taken from the payer: what post
+/// balances are stored against.
+fn fee_paid(pre: &[u64], fee: u64, len: usize) -> Vec<u64> {
+    let mut base = pre.to_vec();
+    base.resize(len, 0);
+    if let Some(payer) = base.first_mut() {
+        *payer = payer.wrapping_sub(fee);
+    }
+    base
+}
+
+/// Post balances as (index gap, zigzag delta) pairs for the accounts that
+/// moved other than by the fee, then the post length if it is not the pre
+/// one (odd length). Empty when only the fee was paid.
+pub fn encode_post_balances(pre: &[u64], post: &[u64], fee: u64) -> Vec<u64> {
+    let base = fee_paid(pre, fee, post.len());
+    let mut out = Vec::new();
+    let mut next = 0;
+    for (i, (&b, &p)) in base.iter().zip(post).enumerate() {
+        if b != p {
+            out.push((i - next) as u64);
+            out.push(zigzag(p.wrapping_sub(b) as i64));
+            next = i + 1;
+        }
+    }
+    if post.len() != pre.len() {
+        out.push(post.len() as u64);
+    }
+    out
+}
+
+/// Inverse of [`encode_post_balances`].
+pub fn decode_post_balances(pre: &[u64], enc: &[u64], fee: u64) -> Result<Vec<u64>> {
+    let (pairs, len) = match enc.split_last() {
+        Some((&len, pairs)) if enc.len() % 2 == 1 => (pairs, usize::try_from(len)?),
+        _ => (enc, pre.len()),
+    };
+    let mut post = fee_paid(pre, fee, len);
+    let mut next = 0usize;
+    for pair in pairs.chunks_exact(2) {
+        let i = usize::try_from(pair[0])
+            .ok()
+            .and_then(|gap| next.checked_add(gap))
+            .context("post balance index overflows")?;
+        let b = post
+            .get_mut(i)
+            .with_context(|| format!("post balance {} of {}", i, len))?;
+        *b = b.wrapping_add(unzigzag(pair[1]) as u64);
+        next = i + 1;
+    }
+    Ok(post)
+}
+
+/// Last balance of every account seen earlier in the block, what pre
+/// balances are stored against from format v9 (not in sectioned files,
+/// whose transactions decode on their own). Vote-only transactions stay out
+/// of it, so blocks read without votes.bin decode the same.
+#[derive(Default)]
+pub struct BalanceChain {
+    last: GxHashMap<CompactPubkey, u64>,
+}
+
+impl BalanceChain {
+    /// Call before the transactions of each block.
+    pub fn begin_block(&mut self) {
+        self.last.clear();
+    }
+
+    /// Zigzag delta against the last balance for accounts seen before, the
+    /// balance itself for the others.
+    pub fn encode_pre(&self, keys: &[CompactPubkey], pre: &[u64]) -> Vec<u64> {
+        pre.iter()
+            .enumerate()
+            .map(|(i, &b)| match keys.get(i).and_then(|k| self.last.get(k)) {
+                Some(&last) => zigzag(b.wrapping_sub(last) as i64),
+                None => b,
+            })
+            .collect()
+    }
+
+    /// Inverse of [`BalanceChain::encode_pre`].
+    pub fn decode_pre(&self, keys: &[CompactPubkey], enc: &[u64]) -> Vec<u64> {
+        enc.iter()
+            .enumerate()
+            .map(|(i, &v)| match keys.get(i).and_then(|k| self.last.get(k)) {
+                Some(&last) => last.wrapping_add(unzigzag(v) as u64),
+                None => v,
+            })
+            .collect()
+    }
+
+    /// Remember the post balances of a transaction.
+    pub fn record(&mut self, keys: &[CompactPubkey], post: &[u64]) {
+        for (k, &b) in keys.iter().zip(post) {
+            self.last.insert(*k, b);
+        }
+    }
+}
+
+/// Keys in balance order: message keys, then loaded writable and readonly
+/// addresses.
+pub fn balance_keys(tx: &CompactTransaction, meta: &CompactMetaV1) -> Vec<CompactPubkey> {
+    let keys = match &tx.message {
+        CompactMessage::Legacy(m) => &m.account_keys,
+        CompactMessage::V0(m) => &m.account_keys,
+        CompactMessage::Vote(_) => return Vec::new(),
+    };
+    keys.iter()
+        .chain(&meta.loaded_writable_indices)
+        .chain(&meta.loaded_readonly_indices)
+        .copied()
+        .collect()
+}
+
+impl CompactMetaV1 {
+    /// Store the balances the format v9 way; `compact_meta_from_proto`
+    /// leaves them as is. `keys` come from [`balance_keys`], `chain` is
+    /// `None` for sectioned files and vote-only transactions.
+    pub fn encode_balances(&mut self, keys: &[CompactPubkey], chain: Option<&mut BalanceChain>) {
+        let post = encode_post_balances(&self.pre_balances, &self.post_balances, self.fee);
+        if let Some(chain) = chain {
+            let pre = chain.encode_pre(keys, &self.pre_balances);
+            chain.record(keys, &self.post_balances);
+            self.pre_balances = pre;
+        }
+        self.post_balances = post;
+    }
+
+    /// (pre, post) balances of a meta read from a file with `file` header.
+    /// `chain` is the block's, fed every transaction in order; it is left
+    /// alone for `vote_only` transactions ([`crate::is_vote_only`]) and
+    /// where the file does not use one.
+    pub fn balances(
+        &self,
+        file: CompactFileHeader,
+        keys: &[CompactPubkey],
+        chain: &mut BalanceChain,
+        vote_only: bool,
+    ) -> Result<(Vec<u64>, Vec<u64>)> {
+        if !file.uses_balance_deltas() {
+            return Ok((self.pre_balances.clone(), self.post_balances.clone()));
+        }
+        let chain = (file.uses_balance_chain() && !vote_only).then_some(chain);
+        let pre = match &chain {
+            Some(c) => c.decode_pre(keys, &self.pre_balances),
+            None => self.pre_balances.clone(),
+        };
+        let post = decode_post_balances(&pre, &self.post_balances, self.fee)?;
+        if let Some(c) = chain {
+            c.record(keys, &post);
+        }
+        Ok((pre, post))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balances_round_trip() {
+        let fee = 5000;
+        for (pre, post) in [
+            // Only the fee: nothing stored.
+            (vec![10_000, 7, 1], vec![5_000, 7, 1]),
+            (vec![10_000, 7, 1], vec![4_000, 1_007, 1]),
+            // Fee payer short of the fee, balances up and down.
+            (vec![10, 7, 1], vec![3, 0, u64::MAX]),
+            (vec![], vec![]),
+            (vec![10_000, 7], vec![5_000, 7, 0, 9]),
+            (vec![10_000, 7, 1], vec![5_000]),
+        ] {
+            let enc = encode_post_balances(&pre, &post, fee);
+            assert_eq!(decode_post_balances(&pre, &enc, fee).unwrap(), post);
+        }
+        assert!(encode_post_balances(&[10_000, 7], &[5_000, 7], fee).is_empty());
+        assert_eq!(
+            encode_post_balances(&[10_000, 7], &[4_000, 8], fee),
+            [0, 1999, 0, 2]
+        );
+
+        // (keys, pre, post) of two transactions sharing keys 1 and 2.
+        let txs = [
+            (
+                vec![1, 2],
+                vec![1_000_000, 2_000_000],
+                vec![995_000, 2_000_000],
+            ),
+            (
+                vec![3, 2, 1],
+                vec![50_000, 2_000_000, 995_000],
+                vec![40_000, 2_005_000, 995_000],
+            ),
+        ]
+        .map(|(ids, pre, post)| {
+            let keys: Vec<_> = ids.into_iter().map(CompactPubkey::Id).collect();
+            (keys, pre, post)
+        });
+        let (mut writer, mut reader) = (BalanceChain::default(), BalanceChain::default());
+        let mut stored = Vec::new();
+        for (keys, pre, post) in &txs {
+            let enc = writer.encode_pre(keys, pre);
+            writer.record(keys, post);
+            stored.push(enc);
+        }
+        // Known accounts are stored as the (zero) change since their last post balance.
+        assert_eq!(stored[1], [50_000, 0, 0]);
+        for ((keys, pre, post), enc) in txs.iter().zip(&stored) {
+            assert_eq!(reader.decode_pre(keys, enc), *pre);
+            reader.record(keys, post);
+        }
+    }
+}
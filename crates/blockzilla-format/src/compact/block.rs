@@ -1,14 +1,92 @@
 use serde::{Deserialize, Serialize};
 
-use crate::CompactMetaV1;
+use crate::{
+    BlockLogTables, CompactEntry, CompactMetaV1, CompactReward, CompactTokenBalanceChange,
+    LegacyTokenBalances,
+};
 
+/// One compact.bin frame (format v12).
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CompactBlockRecord<'a> {
     pub header: CompactBlockHeader,
+    /// Strings and data referenced by every transaction's log events.
+    pub log_tables: BlockLogTables,
+    /// Block-level rewards (fees, rent, epoch rewards).
+    pub rewards: Vec<CompactReward>,
+    /// Entry structure of the block, `None` when not stored (before format v12).
+    pub entries: Option<Vec<CompactEntry>>,
     #[serde(borrow)]
     pub txs: Vec<CompactTxWithMeta<'a>>,
 }
 
+/// Format v11 frame, without entries.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactBlockRecordV3<'a> {
+    pub header: CompactBlockHeader,
+    pub log_tables: BlockLogTables,
+    pub rewards: Vec<CompactReward>,
+    #[serde(borrow)]
+    pub txs: Vec<CompactTxWithMeta<'a>>,
+}
+
+/// Format v2 to v10 frame, without block rewards. `T` as in [`CompactMetaV1`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactBlockRecordV2<'a, T = Vec<CompactTokenBalanceChange>> {
+    pub header: CompactBlockHeader,
+    pub log_tables: BlockLogTables,
+    #[serde(borrow)]
+    pub txs: Vec<CompactTxWithMeta<'a, T>>,
+}
+
+/// Format v1 frame (headerless compact.bin): log tables live in each
+/// transaction's `CompactLogStream`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactBlockRecordV1<'a> {
+    pub header: CompactBlockHeader,
+    #[serde(borrow)]
+    pub txs: Vec<CompactTxWithMeta<'a, LegacyTokenBalances>>,
+}
+
+impl<'a> From<CompactBlockRecordV1<'a>> for CompactBlockRecord<'a> {
+    /// Per-transaction tables are kept, `CompactLogStream::tables` picks them.
+    fn from(b: CompactBlockRecordV1<'a>) -> Self {
+        Self {
+            header: b.header,
+            log_tables: BlockLogTables::default(),
+            rewards: Vec::new(),
+            entries: None,
+            txs: b.txs.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<'a, T> From<CompactBlockRecordV2<'a, T>> for CompactBlockRecord<'a>
+where
+    CompactTxWithMeta<'a, T>: Into<CompactTxWithMeta<'a>>,
+{
+    fn from(b: CompactBlockRecordV2<'a, T>) -> Self {
+        Self {
+            header: b.header,
+            log_tables: b.log_tables,
+            rewards: Vec::new(),
+            entries: None,
+            txs: b.txs.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<'a> From<CompactBlockRecordV3<'a>> for CompactBlockRecord<'a> {
+    fn from(b: CompactBlockRecordV3<'a>) -> Self {
+        Self {
+            header: b.header,
+            log_tables: b.log_tables,
+            rewards: b.rewards,
+            entries: None,
+            txs: b.txs,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompactBlockHeader {
     pub slot: u64,
@@ -20,8 +98,17 @@ pub struct CompactBlockHeader {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct CompactTxWithMeta<'a> {
+pub struct CompactTxWithMeta<'a, T = Vec<CompactTokenBalanceChange>> {
     #[serde(borrow)]
     pub tx: crate::compact::CompactTransaction<'a>,
-    pub metadata: Option<CompactMetaV1>,
+    pub metadata: Option<CompactMetaV1<T>>,
+}
+
+impl<'a> From<CompactTxWithMeta<'a, LegacyTokenBalances>> for CompactTxWithMeta<'a> {
+    fn from(t: CompactTxWithMeta<'a, LegacyTokenBalances>) -> Self {
+        Self {
+            tx: t.tx,
+            metadata: t.metadata.map(Into::into),
+        }
+    }
 }
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use car_reader::node::EntryNode;
+use serde::{Deserialize, Serialize};
+
+/// One PoH entry of a block (format v12).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactEntry {
+    pub num_hashes: u64,
+    /// The entry holds the next `tx_count` transactions of the block.
+    pub tx_count: u32,
+    /// `None` for the last entry when its hash is the block's registry
+    /// blockhash. The others would take a PoH replay to derive, so they are
+    /// kept, and so is a last hash the registry disagrees with.
+    pub hash: Option<[u8; 32]>,
+}
+
+/// Entries of a block as found in the CAR, in order. `blockhash` is the
+/// registry hash the block header points at.
+pub fn compact_entries(
+    entries: &[EntryNode<'_>],
+    blockhash: Option<&[u8; 32]>,
+) -> Result<Vec<CompactEntry>> {
+    let last = entries.len().saturating_sub(1);
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, e)| {
+            let hash: [u8; 32] = e
+                .hash
+                .try_into()
+                .with_context(|| format!("entry {} hash of {} bytes", i, e.hash.len()))?;
+            let tx_count = e.transactions.len();
+            Ok(CompactEntry {
+                num_hashes: e.num_hashes,
+                tx_count: u32::try_from(tx_count)
+                    .with_context(|| format!("entry {i} holds {tx_count} transactions"))?,
+                hash: (i != last || blockhash != Some(&hash)).then_some(hash),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BlockLogTables, BlockhashRegistry, CompactBlockHeader, CompactBlockRecord,
+        CompactFileHeader, CompactLayout, KeyStore, LogDicts, VoteChain, rehydrate_block,
+    };
+    use car_reader::node::{Node, decode_node};
+
+    /// CBOR entry node: `[1, num_hashes, hash, [tx_count CIDs]]`, with `0`s
+    /// standing in for the CIDs since only the array length is read.
+    fn entry_node(num_hashes: u8, hash: [u8; 32], tx_count: u8) -> Vec<u8> {
+        let mut out = vec![0x84, 0x01, num_hashes, 0x58, 32];
+        out.extend_from_slice(&hash);
+        out.push(0x80 | tx_count);
+        out.resize(out.len() + tx_count as usize, 0);
+        out
+    }
+
+    fn compact(nodes: &[Vec<u8>], blockhash: Option<&[u8; 32]>) -> Vec<CompactEntry> {
+        let entries = nodes
+            .iter()
+            .map(|n| match decode_node(n).unwrap() {
+                Node::Entry(e) => e,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        compact_entries(&entries, blockhash).unwrap()
+    }
+
+    #[test]
+    fn last_hash_is_kept_unless_it_is_the_blockhash() {
+        let nodes = [entry_node(12, [1; 32], 0), entry_node(3, [2; 32], 2)];
+
+        let entries = compact(&nodes, Some(&[2; 32]));
+        assert_eq!(
+            entries,
+            [
+                CompactEntry {
+                    num_hashes: 12,
+                    tx_count: 0,
+                    hash: Some([1; 32]),
+                },
+                CompactEntry {
+                    num_hashes: 3,
+                    tx_count: 2,
+                    hash: None,
+                },
+            ]
+        );
+        let bytes = postcard::to_allocvec(&entries).unwrap();
+        assert_eq!(
+            postcard::from_bytes::<Vec<CompactEntry>>(&bytes).unwrap(),
+            entries
+        );
+
+        // A registry that disagrees (or lacks the block) must not lose the hash.
+        for blockhash in [Some(&[9; 32]), None] {
+            let entries = compact(&nodes, blockhash);
+            assert_eq!(entries[1].hash, Some([2; 32]));
+        }
+    }
+
+    #[test]
+    fn entries_round_trip_through_rehydrate() {
+        let nodes = [entry_node(12, [1; 32], 1), entry_node(3, [2; 32], 0)];
+        // Block 0 sits in the registry, block 1 was indexed with another hash.
+        let bh = BlockhashRegistry::new(vec![[2; 32], [8; 32]], vec![]);
+        let file = CompactFileHeader::new(CompactLayout::Plain);
+        let store = KeyStore { keys: Vec::new() };
+
+        for block_i in [0, 1] {
+            let record = CompactBlockRecord {
+                header: CompactBlockHeader {
+                    slot: 10 + block_i as u64,
+                    parent_slot: 9 + block_i as u64,
+                    blockhash: block_i,
+                    previous_blockhash: block_i.saturating_sub(1),
+                    block_time: None,
+                    block_height: None,
+                },
+                log_tables: BlockLogTables::default(),
+                rewards: Vec::new(),
+                entries: Some(compact(&nodes, bh.hashes.get(block_i as usize))),
+                txs: Vec::new(),
+            };
+            let bytes = postcard::to_allocvec(&record).unwrap();
+            let record: CompactBlockRecord = postcard::from_bytes(&bytes).unwrap();
+            let block = rehydrate_block(
+                &record,
+                file,
+                &store,
+                &bh,
+                &LogDicts::default(),
+                &mut VoteChain::default(),
+            )
+            .unwrap();
+
+            let entries = block.entries.unwrap();
+            let got = entries
+                .iter()
+                .map(|e| (e.num_hashes, *e.hash, e.tx_count))
+                .collect::<Vec<_>>();
+            assert_eq!(got, [(12, [1; 32], 1), (3, [2; 32], 0)]);
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{CompactFileHeader, CompactMetaV1, instructions::Cursor};
+
+/// `TransactionError`, variants in bincode tag order (u32 tag, see
+/// car_reader `StoredTransactionError`). Stored with postcard, so the tag
+/// and the instruction index take a byte each.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompactTxError {
+    AccountInUse,
+    AccountLoadedTwice,
+    AccountNotFound,
+    ProgramAccountNotFound,
+    InsufficientFundsForFee,
+    InvalidAccountForFee,
+    AlreadyProcessed,
+    BlockhashNotFound,
+    InstructionError(u8, CompactInstructionError),
+    CallChainTooDeep,
+    MissingSignatureForFee,
+    InvalidAccountIndex,
+    SignatureFailure,
+    InvalidProgramForExecution,
+    SanitizeFailure,
+    ClusterMaintenance,
+    AccountBorrowOutstanding,
+    WouldExceedMaxBlockCostLimit,
+    UnsupportedVersion,
+    InvalidWritableAccount,
+    WouldExceedMaxAccountCostLimit,
+    WouldExceedAccountDataBlockLimit,
+    TooManyAccountLocks,
+    AddressLookupTableNotFound,
+    InvalidAddressLookupTableOwner,
+    InvalidAddressLookupTableData,
+    InvalidAddressLookupTableIndex,
+    InvalidRentPayingAccount,
+    WouldExceedMaxVoteCostLimit,
+    WouldExceedAccountDataTotalLimit,
+    DuplicateInstruction(u8),
+    InsufficientFundsForRent {
+        account_index: u8,
+    },
+    MaxLoadedAccountsDataSizeExceeded,
+    InvalidLoadedAccountsDataSizeLimit,
+    ResanitizationNeeded,
+    ProgramExecutionTemporarilyRestricted {
+        account_index: u8,
+    },
+    UnbalancedTransaction,
+    ProgramCacheHitMaxLimit,
+    CommitCancelled,
+    /// Bytes this version can't write back as is (newer variant, bad data).
+    Unknown(Vec<u8>),
+}
+
+/// `InstructionError`, variants in bincode tag order (u32 tag).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompactInstructionError {
+    GenericError,
+    InvalidArgument,
+    InvalidInstructionData,
+    InvalidAccountData,
+    AccountDataTooSmall,
+    InsufficientFunds,
+    IncorrectProgramId,
+    MissingRequiredSignature,
+    AccountAlreadyInitialized,
+    UninitializedAccount,
+    UnbalancedInstruction,
+    ModifiedProgramId,
+    ExternalAccountLamportSpend,
+    ExternalAccountDataModified,
+    ReadonlyLamportChange,
+    ReadonlyDataModified,
+    DuplicateAccountIndex,
+    ExecutableModified,
+    RentEpochModified,
+    NotEnoughAccountKeys,
+    AccountDataSizeChanged,
+    AccountNotExecutable,
+    AccountBorrowFailed,
+    AccountBorrowOutstanding,
+    DuplicateAccountOutOfSync,
+    Custom(u32),
+    InvalidError,
+    ExecutableDataModified,
+    ExecutableLamportChange,
+    ExecutableAccountNotRentExempt,
+    UnsupportedProgramId,
+    CallDepth,
+    MissingAccount,
+    ReentrancyNotAllowed,
+    MaxSeedLengthExceeded,
+    InvalidSeeds,
+    InvalidRealloc,
+    ComputationalBudgetExceeded,
+    PrivilegeEscalation,
+    ProgramEnvironmentSetupFailure,
+    ProgramFailedToComplete,
+    ProgramFailedToCompile,
+    Immutable,
+    IncorrectAuthority,
+    BorshIoError(String),
+    AccountNotRentExempt,
+    InvalidAccountOwner,
+    ArithmeticOverflow,
+    UnsupportedSysvar,
+    IllegalOwner,
+    MaxAccountsDataAllocationsExceeded,
+    MaxAccountsExceeded,
+    MaxInstructionTraceLengthExceeded,
+    BuiltinProgramsMustConsumeComputeUnits,
+}
+
+impl CompactTxError {
+    /// `TransactionError.err` bytes as a typed error, `Unknown` unless the
+    /// typed error writes back the exact same bytes.
+    pub fn from_bytes(b: &[u8]) -> Self {
+        match Self::parse(&mut Cursor::new(b)) {
+            Some(e) if e.to_bytes() == b => e,
+            _ => Self::Unknown(b.to_vec()),
+        }
+    }
+
+    /// The bincode bytes of the error.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let tag: u32 = match self {
+            Self::AccountInUse => 0,
+            Self::AccountLoadedTwice => 1,
+            Self::AccountNotFound => 2,
+            Self::ProgramAccountNotFound => 3,
+            Self::InsufficientFundsForFee => 4,
+            Self::InvalidAccountForFee => 5,
+            Self::AlreadyProcessed => 6,
+            Self::BlockhashNotFound => 7,
+            Self::InstructionError(..) => 8,
+            Self::CallChainTooDeep => 9,
+            Self::MissingSignatureForFee => 10,
+            Self::InvalidAccountIndex => 11,
+            Self::SignatureFailure => 12,
+            Self::InvalidProgramForExecution => 13,
+            Self::SanitizeFailure => 14,
+            Self::ClusterMaintenance => 15,
+            Self::AccountBorrowOutstanding => 16,
+            Self::WouldExceedMaxBlockCostLimit => 17,
+            Self::UnsupportedVersion => 18,
+            Self::InvalidWritableAccount => 19,
+            Self::WouldExceedMaxAccountCostLimit => 20,
+            Self::WouldExceedAccountDataBlockLimit => 21,
+            Self::TooManyAccountLocks => 22,
+            Self::AddressLookupTableNotFound => 23,
+            Self::InvalidAddressLookupTableOwner => 24,
+            Self::InvalidAddressLookupTableData => 25,
+            Self::InvalidAddressLookupTableIndex => 26,
+            Self::InvalidRentPayingAccount => 27,
+            Self::WouldExceedMaxVoteCostLimit => 28,
+            Self::WouldExceedAccountDataTotalLimit => 29,
+            Self::DuplicateInstruction(_) => 30,
+            Self::InsufficientFundsForRent { .. } => 31,
+            Self::MaxLoadedAccountsDataSizeExceeded => 32,
+            Self::InvalidLoadedAccountsDataSizeLimit => 33,
+            Self::ResanitizationNeeded => 34,
+            Self::ProgramExecutionTemporarilyRestricted { .. } => 35,
+            Self::UnbalancedTransaction => 36,
+            Self::ProgramCacheHitMaxLimit => 37,
+            Self::CommitCancelled => 38,
+            Self::Unknown(b) => return b.clone(),
+        };
+        let mut out = tag.to_le_bytes().to_vec();
+        match self {
+            Self::InstructionError(index, e) => {
+                out.push(*index);
+                e.write(&mut out);
+            }
+            Self::DuplicateInstruction(index) => out.push(*index),
+            Self::InsufficientFundsForRent { account_index }
+            | Self::ProgramExecutionTemporarilyRestricted { account_index } => {
+                out.push(*account_index)
+            }
+            _ => {}
+        }
+        out
+    }
+
+    fn parse(c: &mut Cursor) -> Option<Self> {
+        Some(match c.u32()? {
+            0 => Self::AccountInUse,
+            1 => Self::AccountLoadedTwice,
+            2 => Self::AccountNotFound,
+            3 => Self::ProgramAccountNotFound,
+            4 => Self::InsufficientFundsForFee,
+            5 => Self::InvalidAccountForFee,
+            6 => Self::AlreadyProcessed,
+            7 => Self::BlockhashNotFound,
+            8 => Self::InstructionError(c.u8()?, CompactInstructionError::parse(c)?),
+            9 => Self::CallChainTooDeep,
+            10 => Self::MissingSignatureForFee,
+            11 => Self::InvalidAccountIndex,
+            12 => Self::SignatureFailure,
+            13 => Self::InvalidProgramForExecution,
+            14 => Self::SanitizeFailure,
+            15 => Self::ClusterMaintenance,
+            16 => Self::AccountBorrowOutstanding,
+            17 => Self::WouldExceedMaxBlockCostLimit,
+            18 => Self::UnsupportedVersion,
+            19 => Self::InvalidWritableAccount,
+            20 => Self::WouldExceedMaxAccountCostLimit,
+            21 => Self::WouldExceedAccountDataBlockLimit,
+            22 => Self::TooManyAccountLocks,
+            23 => Self::AddressLookupTableNotFound,
+            24 => Self::InvalidAddressLookupTableOwner,
+            25 => Self::InvalidAddressLookupTableData,
+            26 => Self::InvalidAddressLookupTableIndex,
+            27 => Self::InvalidRentPayingAccount,
+            28 => Self::WouldExceedMaxVoteCostLimit,
+            29 => Self::WouldExceedAccountDataTotalLimit,
+            30 => Self::DuplicateInstruction(c.u8()?),
+            31 => Self::InsufficientFundsForRent {
+                account_index: c.u8()?,
+            },
+            32 => Self::MaxLoadedAccountsDataSizeExceeded,
+            33 => Self::InvalidLoadedAccountsDataSizeLimit,
+            34 => Self::ResanitizationNeeded,
+            35 => Self::ProgramExecutionTemporarilyRestricted {
+                account_index: c.u8()?,
+            },
+            36 => Self::UnbalancedTransaction,
+            37 => Self::ProgramCacheHitMaxLimit,
+            38 => Self::CommitCancelled,
+            _ => return None,
+        })
+    }
+}
+
+impl CompactInstructionError {
+    fn parse(c: &mut Cursor) -> Option<Self> {
+        Some(match c.u32()? {
+            0 => Self::GenericError,
+            1 => Self::InvalidArgument,
+            2 => Self::InvalidInstructionData,
+            3 => Self::InvalidAccountData,
+            4 => Self::AccountDataTooSmall,
+            5 => Self::InsufficientFunds,
+            6 => Self::IncorrectProgramId,
+            7 => Self::MissingRequiredSignature,
+            8 => Self::AccountAlreadyInitialized,
+            9 => Self::UninitializedAccount,
+            10 => Self::UnbalancedInstruction,
+            11 => Self::ModifiedProgramId,
+            12 => Self::ExternalAccountLamportSpend,
+            13 => Self::ExternalAccountDataModified,
+            14 => Self::ReadonlyLamportChange,
+            15 => Self::ReadonlyDataModified,
+            16 => Self::DuplicateAccountIndex,
+            17 => Self::ExecutableModified,
+            18 => Self::RentEpochModified,
+            19 => Self::NotEnoughAccountKeys,
+            20 => Self::AccountDataSizeChanged,
+            21 => Self::AccountNotExecutable,
+            22 => Self::AccountBorrowFailed,
+            23 => Self::AccountBorrowOutstanding,
+            24 => Self::DuplicateAccountOutOfSync,
+            25 => Self::Custom(c.u32()?),
+            26 => Self::InvalidError,
+            27 => Self::ExecutableDataModified,
+            28 => Self::ExecutableLamportChange,
+            29 => Self::ExecutableAccountNotRentExempt,
+            30 => Self::UnsupportedProgramId,
+            31 => Self::CallDepth,
+            32 => Self::MissingAccount,
+            33 => Self::ReentrancyNotAllowed,
+            34 => Self::MaxSeedLengthExceeded,
+            35 => Self::InvalidSeeds,
+            36 => Self::InvalidRealloc,
+            37 => Self::ComputationalBudgetExceeded,
+            38 => Self::PrivilegeEscalation,
+            39 => Self::ProgramEnvironmentSetupFailure,
+            40 => Self::ProgramFailedToComplete,
+            41 => Self::ProgramFailedToCompile,
+            42 => Self::Immutable,
+            43 => Self::IncorrectAuthority,
+            44 => Self::BorshIoError(c.string()?),
+            45 => Self::AccountNotRentExempt,
+            46 => Self::InvalidAccountOwner,
+            47 => Self::ArithmeticOverflow,
+            48 => Self::UnsupportedSysvar,
+            49 => Self::IllegalOwner,
+            50 => Self::MaxAccountsDataAllocationsExceeded,
+            51 => Self::MaxAccountsExceeded,
+            52 => Self::MaxInstructionTraceLengthExceeded,
+            53 => Self::BuiltinProgramsMustConsumeComputeUnits,
+            _ => return None,
+        })
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let tag: u32 = match self {
+            Self::GenericError => 0,
+            Self::InvalidArgument => 1,
+            Self::InvalidInstructionData => 2,
+            Self::InvalidAccountData => 3,
+            Self::AccountDataTooSmall => 4,
+            Self::InsufficientFunds => 5,
+            Self::IncorrectProgramId => 6,
+            Self::MissingRequiredSignature => 7,
+            Self::AccountAlreadyInitialized => 8,
+            Self::UninitializedAccount => 9,
+            Self::UnbalancedInstruction => 10,
+            Self::ModifiedProgramId => 11,
+            Self::ExternalAccountLamportSpend => 12,
+            Self::ExternalAccountDataModified => 13,
+            Self::ReadonlyLamportChange => 14,
+            Self::ReadonlyDataModified => 15,
+            Self::DuplicateAccountIndex => 16,
+            Self::ExecutableModified => 17,
+            Self::RentEpochModified => 18,
+            Self::NotEnoughAccountKeys => 19,
+            Self::AccountDataSizeChanged => 20,
+            Self::AccountNotExecutable => 21,
+            Self::AccountBorrowFailed => 22,
+            Self::AccountBorrowOutstanding => 23,
+            Self::DuplicateAccountOutOfSync => 24,
+            Self::Custom(_) => 25,
+            Self::InvalidError => 26,
+            Self::ExecutableDataModified => 27,
+            Self::ExecutableLamportChange => 28,
+            Self::ExecutableAccountNotRentExempt => 29,
+            Self::UnsupportedProgramId => 30,
+            Self::CallDepth => 31,
+            Self::MissingAccount => 32,
+            Self::ReentrancyNotAllowed => 33,
+            Self::MaxSeedLengthExceeded => 34,
+            Self::InvalidSeeds => 35,
+            Self::InvalidRealloc => 36,
+            Self::ComputationalBudgetExceeded => 37,
+            Self::PrivilegeEscalation => 38,
+            Self::ProgramEnvironmentSetupFailure => 39,
+            Self::ProgramFailedToComplete => 40,
+            Self::ProgramFailedToCompile => 41,
+            Self::Immutable => 42,
+            Self::IncorrectAuthority => 43,
+            Self::BorshIoError(_) => 44,
+            Self::AccountNotRentExempt => 45,
+            Self::InvalidAccountOwner => 46,
+            Self::ArithmeticOverflow => 47,
+            Self::UnsupportedSysvar => 48,
+            Self::IllegalOwner => 49,
+            Self::MaxAccountsDataAllocationsExceeded => 50,
+            Self::MaxAccountsExceeded => 51,
+            Self::MaxInstructionTraceLengthExceeded => 52,
+            Self::BuiltinProgramsMustConsumeComputeUnits => 53,
+        };
+        out.extend_from_slice(&tag.to_le_bytes());
+        match self {
+            Self::Custom(code) => out.extend_from_slice(&code.to_le_bytes()),
+            Self::BorshIoError(s) => {
+                out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+                out.extend_from_slice(s.as_bytes());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl CompactInstructionError {
+    /// What the runtime logs after `Program <id> failed: `, for the errors
+    /// vote instructions fail with; `None` for the others.
+    pub fn log_reason(&self) -> Option<String> {
+        let s = match self {
+            Self::Custom(code) => return Some(format!("custom program error: {:#x}", code)),
+            Self::GenericError => "generic instruction error",
+            Self::InvalidArgument => "invalid program argument",
+            Self::InvalidInstructionData => "invalid instruction data",
+            Self::InvalidAccountData => "invalid account data for instruction",
+            Self::InsufficientFunds => "insufficient funds for instruction",
+            Self::IncorrectProgramId => "incorrect program id for instruction",
+            Self::MissingRequiredSignature => "missing required signature for instruction",
+            Self::UninitializedAccount => "instruction requires an initialized account",
+            Self::NotEnoughAccountKeys => "insufficient account keys for instruction",
+            _ => return None,
+        };
+        Some(s.to_string())
+    }
+}
+
+/// `CompactMetaV1::err` from format v8: postcard `CompactTxError`.
+pub fn encode_tx_error(err: &[u8]) -> Result<Vec<u8>> {
+    postcard::to_allocvec(&CompactTxError::from_bytes(err)).context("encode tx error")
+}
+
+/// Inverse of [`encode_tx_error`].
+pub fn decode_tx_error(enc: &[u8]) -> Result<Vec<u8>> {
+    let e: CompactTxError = postcard::from_bytes(enc).context("decode tx error")?;
+    Ok(e.to_bytes())
+}
+
+impl CompactMetaV1 {
+    /// Typed error of a meta read from a file with `file` header.
+    pub fn tx_error(&self, file: CompactFileHeader) -> Result<Option<CompactTxError>> {
+        let Some(err) = &self.err else {
+            return Ok(None);
+        };
+        if file.uses_typed_errors() {
+            postcard::from_bytes(err)
+                .context("decode tx error")
+                .map(Some)
+        } else {
+            Ok(Some(CompactTxError::from_bytes(err)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tx_errors_round_trip() {
+        let custom = [8, 0, 0, 0, 1, 25, 0, 0, 0, 0x71, 0x17, 0, 0];
+        assert_eq!(
+            CompactTxError::from_bytes(&custom),
+            CompactTxError::InstructionError(1, CompactInstructionError::Custom(6001))
+        );
+        // tag, index, tag, varint 6001
+        assert_eq!(encode_tx_error(&custom).unwrap().len(), 5);
+
+        let borsh = [
+            &[8, 0, 0, 0, 0, 44, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0][..],
+            b"io",
+        ]
+        .concat();
+        for bytes in [
+            &custom[..],
+            &borsh,
+            // InsufficientFundsForFee
+            &[4, 0, 0, 0],
+            // InsufficientFundsForRent { account_index: 3 }
+            &[31, 0, 0, 0, 3],
+            // Unknown tag, truncated error and trailing bytes
+            &[99, 0, 0, 0],
+            &[8, 0, 0, 0, 1, 0, 0, 0],
+            &[4, 0, 0, 0, 0],
+        ] {
+            let enc = encode_tx_error(bytes).unwrap();
+            assert_eq!(decode_tx_error(&enc).unwrap(), bytes);
+        }
+        assert!(matches!(
+            CompactTxError::from_bytes(&[4, 0, 0, 0, 0]),
+            CompactTxError::Unknown(_)
+        ));
+    }
+}
@@ -3,26 +3,31 @@ use serde::{Deserialize, Serialize};
 use solana_pubkey::Pubkey;
 use std::str::FromStr;
 
-use crate::{CompactLogStream, KeyIndex};
+use crate::{
+    CompactLogStream, CompactPubkey, CompactTokenBalanceChange, KeyIndex, LogInterner,
+    compact_token_balances, encode_ix_data, encode_tx_error,
+};
 
+/// `T` is [`crate::LegacyTokenBalances`] in metas before format v10.
 #[derive(Debug, Serialize, Deserialize)]
-pub struct CompactMetaV1 {
+pub struct CompactMetaV1<T = Vec<CompactTokenBalanceChange>> {
+    /// Postcard `CompactTxError` from format v8, bincode `TransactionError` before.
     pub err: Option<Vec<u8>>,
 
     pub fee: u64,
+    /// Delta encoded from format v9, see [`CompactMetaV1::balances`].
     pub pre_balances: Vec<u64>,
     pub post_balances: Vec<u64>,
 
     pub inner_instructions: Option<Vec<CompactInnerInstructions>>,
     pub logs: Option<CompactLogStream>,
 
-    pub pre_token_balances: Vec<CompactTokenBalance>,
-    pub post_token_balances: Vec<CompactTokenBalance>,
+    pub token_balances: T,
 
     pub rewards: Vec<CompactReward>,
 
-    pub loaded_writable_indices: Vec<u32>,
-    pub loaded_readonly_indices: Vec<u32>,
+    pub loaded_writable_indices: Vec<CompactPubkey>,
+    pub loaded_readonly_indices: Vec<CompactPubkey>,
 
     pub return_data: Option<CompactReturnData>,
 
@@ -40,24 +45,26 @@ pub struct CompactInnerInstructions {
 pub struct CompactInnerInstruction {
     pub program_id_index: u32, // message index
     pub accounts: Vec<u8>,
+    /// `encode_ix_data` from format v5
     pub data: Vec<u8>,
     pub stack_height: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompactReturnData {
-    pub program_id_index: u32, // registry index
+    pub program_id_index: CompactPubkey,
     pub data: Vec<u8>,
 }
 
+/// Token balance of metas before format v10.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompactTokenBalance {
     pub account_index: u32,
 
-    // registry indices or 0 if missing or not found
-    pub mint_index: u32,
-    pub owner_index: u32,
-    pub program_id_index: u32,
+    // CompactPubkey::NONE if missing
+    pub mint_index: CompactPubkey,
+    pub owner_index: CompactPubkey,
+    pub program_id_index: CompactPubkey,
 
     pub amount: u64,
     pub decimals: u8,
@@ -65,29 +72,35 @@ pub struct CompactTokenBalance {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompactReward {
-    pub pubkey_index: u32,
+    pub pubkey_index: CompactPubkey,
     pub lamports: i64,
     pub post_balance: u64,
     pub reward_type: i32,
     pub commission: Option<u8>,
 }
 
+/// Logs are interned into the block tables held by `logs`.
 pub fn compact_meta_from_proto(
     meta: &car_reader::confirmed_block::TransactionStatusMeta,
     index: &KeyIndex,
+    logs: &mut LogInterner,
 ) -> Result<CompactMetaV1> {
-    let err = meta.err.as_ref().map(|e| e.err.clone());
+    let err = meta
+        .err
+        .as_ref()
+        .map(|e| encode_tx_error(&e.err))
+        .transpose()?;
 
     let loaded_writable_indices = meta
         .loaded_writable_addresses
         .iter()
-        .map(|a| index.lookup_unchecked(a.as_slice().try_into().unwrap()))
-        .collect();
+        .map(|a| compact_address(index, a))
+        .collect::<Result<_>>()?;
     let loaded_readonly_indices = meta
         .loaded_readonly_addresses
         .iter()
-        .map(|a| index.lookup_unchecked(a.as_slice().try_into().unwrap()))
-        .collect();
+        .map(|a| compact_address(index, a))
+        .collect::<Result<_>>()?;
 
     let inner_instructions = if meta.inner_instructions_none {
         None
@@ -103,7 +116,7 @@ pub fn compact_meta_from_proto(
                         .map(|ix| CompactInnerInstruction {
                             program_id_index: ix.program_id_index,
                             accounts: ix.accounts.to_vec(),
-                            data: ix.data.to_vec(),
+                            data: encode_ix_data(&ix.data, index).into_owned(),
                             stack_height: ix.stack_height,
                         })
                         .collect(),
@@ -115,27 +128,17 @@ pub fn compact_meta_from_proto(
     let logs = if meta.log_messages_none {
         None
     } else {
-        Some(crate::log::parse_logs(&meta.log_messages, index))
+        Some(crate::log::parse_logs_into(&meta.log_messages, index, logs))
     };
 
-    //TODO: fix error amangement it is easier to just unwrap to avoid typing issue for now
-    let pre_token_balances = meta
-        .pre_token_balances
-        .iter()
-        .map(|tb| compact_token_balance(tb, index).unwrap())
-        .collect();
-
-    let post_token_balances = meta
-        .post_token_balances
-        .iter()
-        .map(|tb| compact_token_balance(tb, index).unwrap())
-        .collect();
+    let token_balances =
+        compact_token_balances(&meta.pre_token_balances, &meta.post_token_balances, index)?;
 
     let rewards = meta
         .rewards
         .iter()
-        .map(|rw| compact_reward(rw, index).unwrap())
-        .collect();
+        .map(|rw| compact_reward(rw, index))
+        .collect::<Result<_>>()?;
 
     let return_data = if meta.return_data_none {
         None
@@ -143,9 +146,9 @@ pub fn compact_meta_from_proto(
         meta.return_data
             .as_ref()
             .map(|rd| -> Result<CompactReturnData> {
-                let ix = index.lookup_unchecked(rd.program_id.as_slice().try_into().unwrap());
                 Ok(CompactReturnData {
-                    program_id_index: ix,
+                    program_id_index: compact_address(index, &rd.program_id)
+                        .context("return data program id")?,
                     data: rd.data.clone(),
                 })
             })
@@ -162,8 +165,7 @@ pub fn compact_meta_from_proto(
         inner_instructions,
         logs,
 
-        pre_token_balances,
-        post_token_balances,
+        token_balances,
 
         rewards,
 
@@ -178,50 +180,32 @@ pub fn compact_meta_from_proto(
 }
 
 #[inline]
-fn lookup_pubkey_index_optional(index: &KeyIndex, s: &str) -> u32 {
-    if s.is_empty() {
-        return 0;
-    }
-    index.lookup_str(s).expect("invalid pubkey")
+fn compact_address(index: &KeyIndex, a: &[u8]) -> Result<CompactPubkey> {
+    let k: &[u8; 32] = a
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("invalid pubkey length {}", a.len()))?;
+    Ok(index.compact(k))
 }
 
-fn compact_token_balance(
-    tb: &car_reader::confirmed_block::TokenBalance,
-    index: &KeyIndex,
-) -> Result<CompactTokenBalance> {
-    let mint_index = lookup_pubkey_index_optional(index, &tb.mint);
-    let owner_index = lookup_pubkey_index_optional(index, &tb.owner);
-    let program_id_index = lookup_pubkey_index_optional(index, &tb.program_id);
-
-    let (amount, decimals) = match &tb.ui_token_amount {
-        None => (0u64, 0u8),
-        Some(uta) => {
-            let amount = uta
-                .amount
-                .parse::<u64>()
-                .context("parse token amount u64")?;
-            (amount, uta.decimals as u8)
-        }
-    };
-
-    Ok(CompactTokenBalance {
-        account_index: tb.account_index,
-        mint_index,
-        owner_index,
-        program_id_index,
-        amount,
-        decimals,
-    })
+#[inline]
+pub(crate) fn lookup_pubkey_index_optional(index: &KeyIndex, s: &str) -> Result<CompactPubkey> {
+    if s.is_empty() {
+        return Ok(CompactPubkey::NONE);
+    }
+    index
+        .compact_str(s)
+        .with_context(|| format!("invalid pubkey {s:?}"))
 }
 
-fn compact_reward(
+/// Block or transaction reward with its pubkey as a registry id.
+pub fn compact_reward(
     rw: &car_reader::confirmed_block::Reward,
     index: &KeyIndex,
 ) -> Result<CompactReward> {
     let pk = Pubkey::from_str(&rw.pubkey)
         .context("reward pubkey parse")?
         .to_bytes();
-    let pubkey_index = index.lookup_unchecked(&pk);
+    let pubkey_index = index.compact(&pk);
 
     let commission = rw.commission.parse::<u8>().ok();
 
@@ -1,13 +1,25 @@
+pub mod balances;
 pub mod block;
+pub mod entry;
+pub mod error;
 pub mod log;
 pub mod meta;
 pub mod nonce;
+pub mod pubkey;
 pub mod signature;
+pub mod token;
 pub mod tx;
+pub mod vote;
 
+pub use balances::*;
 pub use block::*;
+pub use entry::*;
+pub use error::*;
 pub use log::*;
 pub use meta::*;
 pub use nonce::*;
+pub use pubkey::*;
 pub use signature::*;
+pub use token::*;
 pub use tx::*;
+pub use vote::*;
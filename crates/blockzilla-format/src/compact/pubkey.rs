@@ -0,0 +1,128 @@
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{Error as DeError, SeqAccess, Visitor},
+    ser::SerializeTuple,
+};
+use std::fmt;
+
+use crate::KeyStore;
+
+/// Varint id that announces an inline key.
+pub(crate) const RAW_TAG: u32 = u32::MAX;
+
+/// A pubkey as stored in compact.bin.
+///
+/// Registry members are written as their 1-based id (plain varint, same bytes
+/// as a bare `u32`). Keys missing from the registry are written as `u32::MAX`
+/// followed by the 32 key bytes. `Id(0)` keeps meaning "absent" where a field
+/// is optional.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompactPubkey {
+    Id(u32),
+    Raw([u8; 32]),
+}
+
+impl CompactPubkey {
+    pub const NONE: Self = Self::Id(0);
+
+    /// Resolve against the registry the file was written with.
+    #[inline]
+    pub fn resolve<'a>(&'a self, store: &'a KeyStore) -> Option<&'a [u8; 32]> {
+        match self {
+            Self::Id(id) => store.get(*id),
+            Self::Raw(k) => Some(k),
+        }
+    }
+}
+
+impl Serialize for CompactPubkey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Id(RAW_TAG) => Err(serde::ser::Error::custom(
+                "registry id u32::MAX is the inline key tag",
+            )),
+            Self::Id(id) => serializer.serialize_u32(*id),
+            Self::Raw(k) => {
+                let mut t = serializer.serialize_tuple(2)?;
+                t.serialize_element(&RAW_TAG)?;
+                t.serialize_element(k)?;
+                t.end()
+            }
+        }
+    }
+}
+
+struct CompactPubkeyVisitor;
+
+impl<'de> Visitor<'de> for CompactPubkeyVisitor {
+    type Value = CompactPubkey;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a registry id or an inline pubkey")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let id: u32 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        if id != RAW_TAG {
+            return Ok(CompactPubkey::Id(id));
+        }
+
+        let k: [u8; 32] = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        Ok(CompactPubkey::Raw(k))
+    }
+}
+
+impl<'de> Deserialize<'de> for CompactPubkey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // The key is only read when the id says so. This only works with
+        // postcard, whose tuples carry no length, so stopping after the first
+        // element is fine; formats that check the tuple length would reject
+        // an `Id`.
+        deserializer.deserialize_tuple(2, CompactPubkeyVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_keep_the_bare_u32_encoding() {
+        for id in [0u32, 1, 127, 128, 70_000, u32::MAX - 1] {
+            let bytes = postcard::to_allocvec(&CompactPubkey::Id(id)).unwrap();
+            assert_eq!(bytes, postcard::to_allocvec(&id).unwrap());
+            assert_eq!(
+                postcard::from_bytes::<CompactPubkey>(&bytes).unwrap(),
+                CompactPubkey::Id(id)
+            );
+        }
+        assert!(postcard::to_allocvec(&CompactPubkey::Id(u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn raw_keys_round_trip() {
+        let keys = vec![
+            CompactPubkey::Id(5),
+            CompactPubkey::Raw([0xAB; 32]),
+            CompactPubkey::Id(0),
+        ];
+        let bytes = postcard::to_allocvec(&keys).unwrap();
+        assert_eq!(
+            postcard::from_bytes::<Vec<CompactPubkey>>(&bytes).unwrap(),
+            keys
+        );
+    }
+}
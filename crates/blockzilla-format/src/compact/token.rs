@@ -0,0 +1,390 @@
+use anyhow::{Context, Result};
+use car_reader::{
+    confirmed_block::{TokenBalance, UiTokenAmount},
+    real_number_string_trimmed,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CompactMetaV1, CompactPubkey, CompactTokenBalance, KeyIndex,
+    compact::{
+        balances::{unzigzag, zigzag},
+        meta::lookup_pubkey_index_optional,
+    },
+};
+
+/// (pre, post) token balances of metas before format v10.
+pub type LegacyTokenBalances = (Vec<CompactTokenBalance>, Vec<CompactTokenBalance>);
+
+/// Pre and post token balance of one account (format v10), the fields they
+/// share stored once. Pre balances in entry order give `pre_token_balances`,
+/// post balances `post_token_balances`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompactTokenBalanceChange {
+    pub account_index: u32,
+
+    // CompactPubkey::NONE if missing
+    pub mint_index: CompactPubkey,
+    pub owner_index: CompactPubkey,
+    pub program_id_index: CompactPubkey,
+
+    /// Unused by `Missing` amounts.
+    pub decimals: u32,
+    pub pre: Option<CompactTokenAmount>,
+    /// An `Amount` after an `Amount` pre is the zigzag change, see
+    /// [`CompactTokenBalanceChange::post_amount`].
+    pub post: Option<CompactTokenAmount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CompactTokenAmount {
+    /// Raw amount; the UI fields are the ones the RPC derives from it.
+    Amount(u64),
+    /// No `ui_token_amount`.
+    Missing,
+    /// Anything else (amounts above u64, other UI fields), as found.
+    Raw {
+        amount: String,
+        ui_amount: f64,
+        ui_amount_string: String,
+    },
+}
+
+impl CompactTokenAmount {
+    /// Stored form of `uta` and its decimals (`None` when missing).
+    fn new(uta: Option<&UiTokenAmount>) -> (Self, Option<u32>) {
+        let Some(uta) = uta else {
+            return (Self::Missing, None);
+        };
+        let derived = uta.amount.parse::<u64>().ok().filter(|&a| {
+            a.to_string() == uta.amount
+                && u8::try_from(uta.decimals).is_ok_and(|d| {
+                    uta.ui_amount.to_bits() == ui_amount(a, d).to_bits()
+                        && uta.ui_amount_string == real_number_string_trimmed(a, d)
+                })
+        });
+        let amount = match derived {
+            Some(a) => Self::Amount(a),
+            None => Self::Raw {
+                amount: uta.amount.clone(),
+                ui_amount: uta.ui_amount,
+                ui_amount_string: uta.ui_amount_string.clone(),
+            },
+        };
+        (amount, Some(uta.decimals))
+    }
+
+    /// Inverse of [`CompactTokenAmount::new`]. Fails on an `Amount` with
+    /// decimals above u8, which `new` never stores.
+    pub fn ui_token_amount(&self, decimals: u32) -> Result<Option<UiTokenAmount>> {
+        Ok(match self {
+            Self::Amount(a) => {
+                let d = u8::try_from(decimals)
+                    .with_context(|| format!("token amount {} with {} decimals", a, decimals))?;
+                Some(UiTokenAmount {
+                    ui_amount: ui_amount(*a, d),
+                    decimals,
+                    amount: a.to_string(),
+                    ui_amount_string: real_number_string_trimmed(*a, d),
+                })
+            }
+            Self::Missing => None,
+            Self::Raw {
+                amount,
+                ui_amount,
+                ui_amount_string,
+            } => Some(UiTokenAmount {
+                ui_amount: *ui_amount,
+                decimals,
+                amount: amount.clone(),
+                ui_amount_string: ui_amount_string.clone(),
+            }),
+        })
+    }
+}
+
+/// Same conversion as the RPC: f64 from the raw amount.
+#[inline]
+fn ui_amount(amount: u64, decimals: u8) -> f64 {
+    amount as f64 / 10f64.powi(decimals as i32)
+}
+
+impl CompactTokenBalanceChange {
+    /// Post amount with the change against `pre` applied.
+    pub fn post_amount(&self) -> Option<CompactTokenAmount> {
+        match (&self.pre, &self.post) {
+            (Some(CompactTokenAmount::Amount(pre)), Some(CompactTokenAmount::Amount(d))) => Some(
+                CompactTokenAmount::Amount(pre.wrapping_add(unzigzag(*d) as u64)),
+            ),
+            (_, post) => post.clone(),
+        }
+    }
+
+    /// Whether a post balance of `other`'s account (with `decimals`, `None`
+    /// when missing) can share this pre-only entry.
+    fn takes_post(&self, other: &Self, decimals: Option<u32>) -> bool {
+        let pre_decimals = match self.pre {
+            Some(CompactTokenAmount::Missing) => None,
+            Some(_) => Some(self.decimals),
+            None => return false,
+        };
+        self.post.is_none()
+            && self.account_index == other.account_index
+            && self.mint_index == other.mint_index
+            && self.owner_index == other.owner_index
+            && self.program_id_index == other.program_id_index
+            && (pre_decimals.is_none() || decimals.is_none() || pre_decimals == decimals)
+    }
+}
+
+fn token_balance_change(
+    tb: &TokenBalance,
+    index: &KeyIndex,
+) -> Result<(CompactTokenBalanceChange, CompactTokenAmount, Option<u32>)> {
+    let (amount, decimals) = CompactTokenAmount::new(tb.ui_token_amount.as_ref());
+    let change = CompactTokenBalanceChange {
+        account_index: tb.account_index,
+        mint_index: lookup_pubkey_index_optional(index, &tb.mint)?,
+        owner_index: lookup_pubkey_index_optional(index, &tb.owner)?,
+        program_id_index: lookup_pubkey_index_optional(index, &tb.program_id)?,
+        decimals: decimals.unwrap_or(0),
+        pre: None,
+        post: None,
+    };
+    Ok((change, amount, decimals))
+}
+
+/// Merge `pre` and `post` token balances into entries whose pre and post
+/// balances keep their original order. A post balance shares the first
+/// fitting entry after the previous post one, or gets its own entry there.
+pub fn compact_token_balances(
+    pre: &[TokenBalance],
+    post: &[TokenBalance],
+    index: &KeyIndex,
+) -> Result<Vec<CompactTokenBalanceChange>> {
+    let mut out = pre
+        .iter()
+        .map(|tb| {
+            let (change, amount, _) = token_balance_change(tb, index)?;
+            Ok(CompactTokenBalanceChange {
+                pre: Some(amount),
+                ..change
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut next = 0;
+    for tb in post {
+        let (change, amount, decimals) = token_balance_change(tb, index)?;
+        match out[next..]
+            .iter()
+            .position(|c| c.takes_post(&change, decimals))
+        {
+            Some(i) => {
+                let c = &mut out[next + i];
+                let amount = match (&c.pre, amount) {
+                    (Some(CompactTokenAmount::Amount(pre)), CompactTokenAmount::Amount(post)) => {
+                        CompactTokenAmount::Amount(zigzag(post.wrapping_sub(*pre) as i64))
+                    }
+                    (_, amount) => amount,
+                };
+                if let Some(d) = decimals {
+                    c.decimals = d;
+                }
+                c.post = Some(amount);
+                next += i + 1;
+            }
+            None => {
+                let change = CompactTokenBalanceChange {
+                    post: Some(amount),
+                    ..change
+                };
+                out.insert(next, change);
+                next += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+impl From<CompactTokenBalance> for CompactTokenBalanceChange {
+    /// Pre-only entry; legacy amounts were always derived ones.
+    fn from(tb: CompactTokenBalance) -> Self {
+        Self {
+            account_index: tb.account_index,
+            mint_index: tb.mint_index,
+            owner_index: tb.owner_index,
+            program_id_index: tb.program_id_index,
+            decimals: tb.decimals as u32,
+            pre: Some(CompactTokenAmount::Amount(tb.amount)),
+            post: None,
+        }
+    }
+}
+
+/// Pre-only then post-only entries.
+pub fn upgrade_token_balances((pre, post): LegacyTokenBalances) -> Vec<CompactTokenBalanceChange> {
+    let post = post.into_iter().map(|tb| CompactTokenBalanceChange {
+        pre: None,
+        post: Some(CompactTokenAmount::Amount(tb.amount)),
+        ..tb.into()
+    });
+    pre.into_iter().map(Into::into).chain(post).collect()
+}
+
+impl From<CompactMetaV1<LegacyTokenBalances>> for CompactMetaV1 {
+    fn from(m: CompactMetaV1<LegacyTokenBalances>) -> Self {
+        Self {
+            err: m.err,
+            fee: m.fee,
+            pre_balances: m.pre_balances,
+            post_balances: m.post_balances,
+            inner_instructions: m.inner_instructions,
+            logs: m.logs,
+            token_balances: upgrade_token_balances(m.token_balances),
+            rewards: m.rewards,
+            loaded_writable_indices: m.loaded_writable_indices,
+            loaded_readonly_indices: m.loaded_readonly_indices,
+            return_data: m.return_data,
+            compute_units_consumed: m.compute_units_consumed,
+            cost_units: m.cost_units,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyStore;
+    use solana_pubkey::Pubkey;
+    use std::sync::Arc;
+
+    #[test]
+    fn token_balances_round_trip() {
+        let keys: Vec<[u8; 32]> = (1..=3).map(|i| [i; 32]).collect();
+        let index = KeyIndex::build(Arc::new(KeyStore { keys: keys.clone() }));
+        let b58 = |i: usize| Pubkey::new_from_array(keys[i]).to_string();
+
+        let uta = |amount: u64, decimals: u8| UiTokenAmount {
+            ui_amount: ui_amount(amount, decimals),
+            decimals: decimals as u32,
+            amount: amount.to_string(),
+            ui_amount_string: real_number_string_trimmed(amount, decimals),
+        };
+        let tb = |account_index, owner: usize, ui_token_amount| TokenBalance {
+            account_index,
+            mint: b58(0),
+            ui_token_amount,
+            owner: b58(owner),
+            program_id: String::new(),
+        };
+        let big = UiTokenAmount {
+            ui_amount: 3.4e20,
+            decimals: 0,
+            amount: "340282366920938463463".to_string(),
+            ui_amount_string: "340282366920938463463".to_string(),
+        };
+        let odd = UiTokenAmount {
+            ui_amount: 0.0,
+            decimals: 300,
+            amount: "007".to_string(),
+            ui_amount_string: "7e-300".to_string(),
+        };
+
+        let pre = vec![
+            tb(1, 1, Some(uta(1_500_000, 6))),
+            tb(2, 1, Some(uta(10, 0))),
+            tb(3, 2, Some(big.clone())),
+            tb(5, 2, None),
+        ];
+        let post = vec![
+            // Account 2 changed owner, so it is not merged.
+            tb(2, 2, Some(uta(10, 0))),
+            tb(1, 1, Some(uta(500_000, 6))),
+            tb(3, 2, Some(odd)),
+            tb(4, 1, Some(uta(u64::MAX, 9))),
+            tb(5, 2, Some(uta(0, 2))),
+        ];
+
+        let changes = compact_token_balances(&pre, &post, &index).unwrap();
+        let bytes = postcard::to_allocvec(&changes).unwrap();
+        let changes: Vec<CompactTokenBalanceChange> = postcard::from_bytes(&bytes).unwrap();
+        // Accounts 2 (new owner) and 3 (new decimals) get a post-only entry
+        // each, account 4 has no pre balance.
+        assert_eq!(changes.len(), 7);
+        assert_eq!(changes[1].post, Some(CompactTokenAmount::Amount(1_999_999)));
+
+        let expected = |tbs: &[TokenBalance]| -> Vec<_> {
+            tbs.iter()
+                .map(|t| {
+                    let owner = index.compact_str(&t.owner).unwrap();
+                    (t.account_index, owner, t.ui_token_amount.clone())
+                })
+                .collect()
+        };
+        let stored = |post: bool| -> Vec<_> {
+            changes
+                .iter()
+                .filter_map(|c| {
+                    let a = if post { c.post_amount() } else { c.pre.clone() }?;
+                    Some((
+                        c.account_index,
+                        c.owner_index,
+                        a.ui_token_amount(c.decimals).unwrap(),
+                    ))
+                })
+                .collect()
+        };
+        assert_eq!(stored(false), expected(&pre));
+        assert_eq!(stored(true), expected(&post));
+    }
+
+    #[test]
+    fn oversized_amounts_stay_raw() {
+        let index = KeyIndex::build(Arc::new(KeyStore {
+            keys: vec![[1; 32]],
+        }));
+        // As car_reader converts a stored amount above u64::MAX.
+        let amount = "123456789012345678901234";
+        let uta = UiTokenAmount {
+            ui_amount: 123_456_789_012_345.68,
+            decimals: 9,
+            amount: amount.to_string(),
+            ui_amount_string: car_reader::ui_amount_string(amount, 9),
+        };
+        assert_eq!(uta.ui_amount_string, "123456789012345.678901234");
+        let tb = |ui_token_amount| TokenBalance {
+            account_index: 1,
+            mint: Pubkey::new_from_array([1; 32]).to_string(),
+            ui_token_amount,
+            owner: String::new(),
+            program_id: String::new(),
+        };
+        let small = UiTokenAmount {
+            ui_amount: 5e-9,
+            decimals: 9,
+            amount: "5".to_string(),
+            ui_amount_string: car_reader::ui_amount_string("5", 9),
+        };
+
+        let changes =
+            compact_token_balances(&[tb(Some(small))], &[tb(Some(uta.clone()))], &index).unwrap();
+        let bytes = postcard::to_allocvec(&changes).unwrap();
+        let changes: Vec<CompactTokenBalanceChange> = postcard::from_bytes(&bytes).unwrap();
+        let [c] = &changes[..] else {
+            panic!("{:?}", changes);
+        };
+        assert_eq!(c.pre, Some(CompactTokenAmount::Amount(5)));
+        let post = c.post_amount().unwrap();
+        assert_eq!(
+            post,
+            CompactTokenAmount::Raw {
+                amount: amount.to_string(),
+                ui_amount: uta.ui_amount,
+                ui_amount_string: uta.ui_amount_string.clone(),
+            }
+        );
+        assert_eq!(post.ui_token_amount(c.decimals).unwrap(), Some(uta));
+        assert!(CompactTokenAmount::Amount(5).ui_token_amount(256).is_err());
+    }
+}
@@ -1,6 +1,16 @@
+use anyhow::Result;
+use car_reader::{
+    confirmed_block::TransactionStatusMeta,
+    versioned_transaction::{CompiledInstruction, VersionedMessage, VersionedTransaction},
+};
+use gxhash::HashMap as GxHashMap;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 
-use crate::{Nonce, Signature};
+use crate::{
+    CompactPubkey, CompactVoteTx, KeyIndex, Nonce, Signature, VoteChain, encode_ix_data,
+    instructions,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompactTransaction<'a> {
@@ -14,6 +24,8 @@ pub struct CompactTransaction<'a> {
 pub enum CompactMessage<'a> {
     Legacy(#[serde(borrow)] CompactLegacyMessage<'a>),
     V0(#[serde(borrow)] CompactV0Message<'a>),
+    /// Format v7: a plain vote, with its meta.
+    Vote(#[serde(borrow)] Box<CompactVoteTx<'a>>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,14 +40,16 @@ pub struct CompactInstruction<'a> {
     pub program_id_index: u8,
     #[serde(borrow)]
     pub accounts: &'a [u8],
+    /// Encoded by `encode_ix_data` from format v5 on and by
+    /// `instructions::encode_data` from v6 on, same bytes on the wire
     #[serde(borrow)]
-    pub data: &'a [u8],
+    pub data: Cow<'a, [u8]>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompactLegacyMessage<'a> {
     pub header: CompactMessageHeader,
-    pub account_keys: Vec<u32>,
+    pub account_keys: Vec<CompactPubkey>,
     pub recent_blockhash: CompactRecentBlockhash<'a>,
     #[serde(borrow)]
     pub instructions: Vec<CompactInstruction<'a>>,
@@ -43,7 +57,7 @@ pub struct CompactLegacyMessage<'a> {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompactAddressTableLookup<'a> {
-    pub account_key: u32, // registry index of the table address
+    pub account_key: CompactPubkey, // table address
     #[serde(borrow)]
     pub writable_indexes: &'a [u8],
     #[serde(borrow)]
@@ -62,10 +76,135 @@ pub enum CompactRecentBlockhash<'a> {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompactV0Message<'a> {
     pub header: CompactMessageHeader,
-    pub account_keys: Vec<u32>,
+    pub account_keys: Vec<CompactPubkey>,
     pub recent_blockhash: CompactRecentBlockhash<'a>,
     #[serde(borrow)]
     pub instructions: Vec<CompactInstruction<'a>>,
     #[serde(borrow)]
     pub address_table_lookups: Vec<CompactAddressTableLookup<'a>>,
 }
+
+/// Stored instruction data: typed for native programs, key refs otherwise.
+fn ix_data<'a>(keys: &[&[u8; 32]], ix: &'a CompiledInstruction, index: &KeyIndex) -> Cow<'a, [u8]> {
+    match keys.get(ix.program_id_index as usize) {
+        Some(program) => instructions::encode_data(program, &ix.data, index),
+        None => encode_ix_data(&ix.data, index),
+    }
+}
+
+fn compact_recent_blockhash<'a>(
+    hash: &'a [u8; 32],
+    bh_index: &GxHashMap<[u8; 32], i32>,
+) -> CompactRecentBlockhash<'a> {
+    bh_index
+        .get(hash)
+        .copied()
+        .map(CompactRecentBlockhash::Id)
+        .unwrap_or_else(|| CompactRecentBlockhash::Nonce(Nonce(hash)))
+}
+
+/// Plain vote transactions become a `CompactMessage::Vote` record, with the
+/// meta folded in. `None` when the tx does not fit the record.
+pub fn to_compact_vote_transaction<'a>(
+    vtx: &'a VersionedTransaction,
+    meta: &TransactionStatusMeta,
+    index: &KeyIndex,
+    bh_index: &GxHashMap<[u8; 32], i32>,
+    votes: &mut VoteChain,
+) -> Option<CompactTransaction<'a>> {
+    let VersionedMessage::Legacy(m) = &vtx.message else {
+        return None;
+    };
+    let recent_blockhash = compact_recent_blockhash(m.recent_blockhash, bh_index);
+    let vote = CompactVoteTx::from_tx(vtx, meta, index, recent_blockhash, votes)?;
+    Some(CompactTransaction {
+        signatures: vtx.signatures.iter().map(|s| Signature(s)).collect(),
+        message: CompactMessage::Vote(Box::new(vote)),
+    })
+}
+
+pub fn to_compact_transaction<'a>(
+    vtx: &'a VersionedTransaction,
+    index: &KeyIndex,
+    bh_index: &GxHashMap<[u8; 32], i32>,
+) -> Result<CompactTransaction<'a>> {
+    let signatures = vtx.signatures.iter().map(|s| Signature(s)).collect();
+
+    let message = match &vtx.message {
+        VersionedMessage::Legacy(m) => {
+            let header = CompactMessageHeader {
+                num_required_signatures: m.header.num_required_signatures,
+                num_readonly_signed_accounts: m.header.num_readonly_signed_accounts,
+                num_readonly_unsigned_accounts: m.header.num_readonly_unsigned_accounts,
+            };
+
+            let account_keys: Vec<CompactPubkey> =
+                m.account_keys.iter().map(|a| index.compact(a)).collect();
+
+            let recent_blockhash = compact_recent_blockhash(m.recent_blockhash, bh_index);
+
+            let instructions = m
+                .instructions
+                .iter()
+                .map(|ix| CompactInstruction {
+                    program_id_index: ix.program_id_index,
+                    accounts: ix.accounts.as_ref(),
+                    data: ix_data(&m.account_keys, ix, index),
+                })
+                .collect();
+
+            CompactMessage::Legacy(CompactLegacyMessage {
+                header,
+                account_keys,
+                recent_blockhash,
+                instructions,
+            })
+        }
+
+        VersionedMessage::V0(m) => {
+            let header = CompactMessageHeader {
+                num_required_signatures: m.header.num_required_signatures,
+                num_readonly_signed_accounts: m.header.num_readonly_signed_accounts,
+                num_readonly_unsigned_accounts: m.header.num_readonly_unsigned_accounts,
+            };
+
+            let account_keys: Vec<CompactPubkey> =
+                m.account_keys.iter().map(|a| index.compact(a)).collect();
+
+            let recent_blockhash = compact_recent_blockhash(m.recent_blockhash, bh_index);
+
+            let instructions = m
+                .instructions
+                .iter()
+                .map(|ix| CompactInstruction {
+                    program_id_index: ix.program_id_index,
+                    accounts: ix.accounts.as_ref(),
+                    data: ix_data(&m.account_keys, ix, index),
+                })
+                .collect();
+
+            let address_table_lookups = m
+                .address_table_lookups
+                .iter()
+                .map(|lookup| CompactAddressTableLookup {
+                    account_key: index.compact(lookup.account_key),
+                    writable_indexes: lookup.writable_indexes.as_ref(),
+                    readonly_indexes: lookup.readonly_indexes.as_ref(),
+                })
+                .collect();
+
+            CompactMessage::V0(CompactV0Message {
+                header,
+                account_keys,
+                recent_blockhash,
+                instructions,
+                address_table_lookups,
+            })
+        }
+    };
+
+    Ok(CompactTransaction {
+        signatures,
+        message,
+    })
+}
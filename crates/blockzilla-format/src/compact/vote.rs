@@ -0,0 +1,625 @@
+use anyhow::{Context, Result};
+use car_reader::{
+    confirmed_block::{TransactionError, TransactionStatusMeta},
+    versioned_transaction::{MessageHeader, VersionedMessage, VersionedTransaction},
+};
+use gxhash::HashMap as GxHashMap;
+use serde::{Deserialize, Serialize};
+use solana_pubkey::Pubkey;
+
+use crate::instructions::{
+    NativeIx,
+    vote::{self, CompactTower, Vote, VoteIx},
+};
+use crate::{
+    CompactBlockHeader, CompactMessage, CompactPubkey, CompactRecentBlockhash, CompactTransaction,
+    CompactTxError, KeyIndex,
+};
+
+/// A vote delta chain restarts at the first block of every window of this
+/// many slots: a reader seeking to a slot replays votes from there
+/// (`SlotReader::seed_votes`).
+pub const VOTE_CHAIN_SLOTS: u64 = 432;
+
+/// Vote program id at a `'static` address, for rehydrated account keys.
+pub static VOTE_PROGRAM: Pubkey = vote::ID;
+
+const VOTE_INVOKE: &str = "Program Vote111111111111111111111111111111111111111 invoke [1]";
+const VOTE_SUCCESS: &str = "Program Vote111111111111111111111111111111111111111 success";
+const VOTE_FAILED: &str = "Program Vote111111111111111111111111111111111111111 failed: ";
+
+/// A transaction that only casts a vote, with the meta that follows from it
+/// (format v7). Stored as `CompactMessage::Vote`, the transaction's
+/// `metadata` is then `None`.
+///
+/// Account keys are `[authority, vote_account, sysvars.., Vote program]`,
+/// the authority alone signs and pays, and the only balance that moves is
+/// the fee. Logs are the invoke line plus `success` or `failed: <reason>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactVoteTx<'a> {
+    pub authority: CompactPubkey,
+    pub vote_account: CompactPubkey,
+    /// Sysvars passed to older vote instructions.
+    pub sysvars: Vec<CompactPubkey>,
+    #[serde(borrow)]
+    pub recent_blockhash: CompactRecentBlockhash<'a>,
+    #[serde(borrow)]
+    pub accounts: &'a [u8],
+    pub vote: VoteDelta,
+
+    pub fee: u64,
+    pub status: VoteStatus,
+    /// Pre balances, one per account key.
+    pub balances: Vec<u64>,
+    pub compute_units_consumed: Option<u64>,
+    pub cost_units: Option<u64>,
+}
+
+/// How a vote transaction ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VoteStatus {
+    Success,
+    /// The log line follows from the error.
+    Failed(CompactTxError),
+}
+
+impl VoteStatus {
+    /// `err` bytes and log reason, `None` on success.
+    fn failure(&self) -> Result<Option<(Vec<u8>, String)>> {
+        match self {
+            Self::Success => Ok(None),
+            Self::Failed(e) => {
+                let reason = match e {
+                    CompactTxError::InstructionError(0, ie) => ie.log_reason(),
+                    _ => None,
+                }
+                .with_context(|| format!("no vote log line for {:?}", e))?;
+                Ok(Some((e.to_bytes(), reason)))
+            }
+        }
+    }
+}
+
+/// Which vote instruction a [`VoteDelta`] writes back to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VoteKind {
+    Vote,
+    CompactUpdateVoteState,
+    TowerSync { block_id: [u8; 32] },
+}
+
+/// A vote against the previous vote of the same vote account in the chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteDelta {
+    pub kind: VoteKind,
+    /// Root minus the previous root, wrapping (0 for `Vote`).
+    pub root: i64,
+    /// Previous slots skipped from the front, then previous slots kept as is.
+    pub dropped: u32,
+    pub kept: u32,
+    /// Slots after the kept ones, each minus the slot before it.
+    pub new: Vec<i64>,
+    /// Lockout confirmation counts, `None` when they are `n, n - 1, .., 1`.
+    pub confirmations: Option<Vec<u8>>,
+    pub hash: [u8; 32],
+    /// Minus the previous timestamp when there is one.
+    pub timestamp: Option<i64>,
+}
+
+/// Absolute form of the vote instructions a `VoteDelta` can hold.
+struct Tower {
+    root: u64,
+    slots: Vec<u64>,
+    confirmations: Vec<u8>,
+    hash: [u8; 32],
+    timestamp: Option<i64>,
+}
+
+impl Tower {
+    fn from_ix(ix: &VoteIx, prev_root: u64) -> Option<(VoteKind, Self)> {
+        let (kind, t) = match ix {
+            VoteIx::Vote(v) => {
+                return Some((
+                    VoteKind::Vote,
+                    Self {
+                        root: prev_root,
+                        slots: v.slots.clone(),
+                        confirmations: Vec::new(),
+                        hash: v.hash,
+                        timestamp: v.timestamp,
+                    },
+                ));
+            }
+            VoteIx::CompactUpdateVoteState(t) => (VoteKind::CompactUpdateVoteState, t),
+            VoteIx::TowerSync { tower, block_id } => (
+                VoteKind::TowerSync {
+                    block_id: *block_id,
+                },
+                tower,
+            ),
+            _ => return None,
+        };
+        let mut slot = t.root;
+        let slots = t
+            .lockout_offsets
+            .iter()
+            .map(|&(off, _)| {
+                slot = slot.wrapping_add(off);
+                slot
+            })
+            .collect();
+        Some((
+            kind,
+            Self {
+                root: t.root,
+                slots,
+                confirmations: t.lockout_offsets.iter().map(|&(_, c)| c).collect(),
+                hash: t.hash,
+                timestamp: t.timestamp,
+            },
+        ))
+    }
+
+    fn into_ix(self, kind: VoteKind) -> VoteIx {
+        if kind == VoteKind::Vote {
+            return VoteIx::Vote(Vote {
+                slots: self.slots,
+                hash: self.hash,
+                timestamp: self.timestamp,
+            });
+        }
+        let mut prev = self.root;
+        let tower = CompactTower {
+            root: self.root,
+            lockout_offsets: self
+                .slots
+                .iter()
+                .zip(self.confirmations)
+                .map(|(&s, c)| (s.wrapping_sub(std::mem::replace(&mut prev, s)), c))
+                .collect(),
+            hash: self.hash,
+            timestamp: self.timestamp,
+        };
+        match kind {
+            VoteKind::TowerSync { block_id } => VoteIx::TowerSync { tower, block_id },
+            _ => VoteIx::CompactUpdateVoteState(tower),
+        }
+    }
+}
+
+#[derive(Default)]
+struct LastVote {
+    root: u64,
+    slots: Vec<u64>,
+    timestamp: Option<i64>,
+}
+
+impl LastVote {
+    fn update(&mut self, t: &Tower) {
+        self.root = t.root;
+        self.slots.clone_from(&t.slots);
+        if t.timestamp.is_some() {
+            self.timestamp = t.timestamp;
+        }
+    }
+}
+
+/// Last vote of every vote account, what vote deltas are taken against.
+/// Writers and readers feed it the same blocks in the same order, each the
+/// child of the one before, from the first block of a `VOTE_CHAIN_SLOTS`
+/// window. Until it sees one, the chain is out of sync: writers store votes
+/// as plain transactions and [`VoteChain::decode`] fails.
+#[derive(Default)]
+pub struct VoteChain {
+    last: GxHashMap<CompactPubkey, LastVote>,
+    /// Slot of the block being fed, while in sync.
+    tip: Option<u64>,
+}
+
+impl VoteChain {
+    /// Call before the votes of each block.
+    pub fn begin_block(&mut self, h: &CompactBlockHeader) {
+        let window = h.slot / VOTE_CHAIN_SLOTS;
+        let starts =
+            h.slot.is_multiple_of(VOTE_CHAIN_SLOTS) || h.parent_slot / VOTE_CHAIN_SLOTS != window;
+        let linked = !starts && self.tip == Some(h.parent_slot);
+        if !linked {
+            self.last.clear();
+        }
+        self.tip = (starts || linked).then_some(h.slot);
+    }
+
+    /// Forget the blocks fed so far, for when the next one does not follow
+    /// them (blocks were lost in between). Out of sync until a window starts.
+    pub fn desync(&mut self) {
+        self.last.clear();
+        self.tip = None;
+    }
+
+    /// Feed the chain the votes of a block without rehydrating it.
+    pub fn replay<'t>(
+        &mut self,
+        h: &CompactBlockHeader,
+        txs: impl IntoIterator<Item = &'t CompactTransaction<'t>>,
+    ) -> Result<()> {
+        self.begin_block(h);
+        for (i, tx) in txs.into_iter().enumerate() {
+            if let CompactMessage::Vote(v) = &tx.message {
+                self.decode(v.vote_account, &v.vote)
+                    .with_context(|| format!("slot {} vote tx {}", h.slot, i))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `ix` as a delta, `None` for vote instructions without one or while
+    /// the chain is out of sync.
+    pub fn encode(&mut self, account: CompactPubkey, ix: &VoteIx) -> Option<VoteDelta> {
+        self.tip?;
+        let last = self.last.entry(account).or_default();
+        let (kind, t) = Tower::from_ix(ix, last.root)?;
+
+        let prev = &last.slots;
+        let dropped = t
+            .slots
+            .first()
+            .and_then(|s| prev.iter().position(|p| p == s))
+            .unwrap_or(prev.len());
+        let kept = prev[dropped..]
+            .iter()
+            .zip(&t.slots)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut base = match kept {
+            0 => prev.last().copied().unwrap_or(0),
+            k => t.slots[k - 1],
+        };
+        let new = t.slots[kept..]
+            .iter()
+            .map(|&s| s.wrapping_sub(std::mem::replace(&mut base, s)) as i64)
+            .collect();
+
+        let n = t.confirmations.len();
+        let standard = t
+            .confirmations
+            .iter()
+            .enumerate()
+            .all(|(i, &c)| c as usize == n - i);
+
+        let delta = VoteDelta {
+            kind,
+            root: t.root.wrapping_sub(last.root) as i64,
+            dropped: dropped as u32,
+            kept: kept as u32,
+            new,
+            confirmations: (!standard).then(|| t.confirmations.clone()),
+            hash: t.hash,
+            timestamp: match (t.timestamp, last.timestamp) {
+                (Some(ts), Some(prev)) => Some(ts.wrapping_sub(prev)),
+                (ts, _) => ts,
+            },
+        };
+        last.update(&t);
+        Some(delta)
+    }
+
+    /// Inverse of [`VoteChain::encode`].
+    pub fn decode(&mut self, account: CompactPubkey, d: &VoteDelta) -> Result<VoteIx> {
+        anyhow::ensure!(
+            self.tip.is_some(),
+            "vote chain was not fed the earlier blocks of this {} slot window",
+            VOTE_CHAIN_SLOTS
+        );
+        let last = self.last.entry(account).or_default();
+        let (dropped, kept) = (d.dropped as usize, d.kept as usize);
+        anyhow::ensure!(
+            dropped + kept <= last.slots.len(),
+            "vote keeps slots {}..{} of a previous vote with {}",
+            dropped,
+            dropped + kept,
+            last.slots.len()
+        );
+
+        let mut slots = last.slots[dropped..dropped + kept].to_vec();
+        let mut base = slots.last().or(last.slots.last()).copied().unwrap_or(0);
+        for &off in &d.new {
+            base = base.wrapping_add(off as u64);
+            slots.push(base);
+        }
+        let confirmations = match &d.confirmations {
+            Some(c) => c.clone(),
+            None if d.kind == VoteKind::Vote => Vec::new(),
+            None => (1..=slots.len() as u8).rev().collect(),
+        };
+        anyhow::ensure!(
+            d.kind == VoteKind::Vote || confirmations.len() == slots.len(),
+            "{} confirmation counts for {} lockouts",
+            confirmations.len(),
+            slots.len()
+        );
+
+        let t = Tower {
+            root: last.root.wrapping_add(d.root as u64),
+            slots,
+            confirmations,
+            hash: d.hash,
+            timestamp: match (d.timestamp, last.timestamp) {
+                (Some(dt), Some(prev)) => Some(prev.wrapping_add(dt)),
+                (ts, _) => ts,
+            },
+        };
+        last.update(&t);
+        Ok(t.into_ix(d.kind.clone()))
+    }
+}
+
+impl<'a> CompactVoteTx<'a> {
+    /// `vtx` as a vote record if it has the expected shape and `meta` is
+    /// exactly what [`CompactVoteTx::meta`] rebuilds. Only then is the vote
+    /// added to `chain`.
+    pub fn from_tx(
+        vtx: &'a VersionedTransaction<'_>,
+        meta: &TransactionStatusMeta,
+        index: &KeyIndex,
+        recent_blockhash: CompactRecentBlockhash<'a>,
+        chain: &mut VoteChain,
+    ) -> Option<Self> {
+        let VersionedMessage::Legacy(m) = &vtx.message else {
+            return None;
+        };
+        let n = m.account_keys.len();
+        if vtx.signatures.len() != 1
+            || n < 3
+            || m.header != vote_header(n)
+            || m.account_keys[n - 1] != VOTE_PROGRAM.as_array()
+        {
+            return None;
+        }
+        let [ix] = &m.instructions[..] else {
+            return None;
+        };
+        if ix.program_id_index as usize != n - 1 {
+            return None;
+        }
+        let native = NativeIx::parse(VOTE_PROGRAM.as_array(), &ix.data, index)?;
+        if native.to_bytes(index.store()).ok()? != ix.data {
+            return None;
+        }
+        let NativeIx::Vote(vote_ix) = native else {
+            return None;
+        };
+
+        let status = match &meta.err {
+            None => VoteStatus::Success,
+            Some(e) => VoteStatus::Failed(CompactTxError::from_bytes(&e.err)),
+        };
+        let balances = meta.pre_balances.clone();
+        if vote_meta(
+            meta.fee,
+            &status,
+            &balances,
+            meta.compute_units_consumed,
+            meta.cost_units,
+        )
+        .ok()?
+            != *meta
+        {
+            return None;
+        }
+
+        let vote_account = index.compact(m.account_keys[1]);
+        Some(Self {
+            authority: index.compact(m.account_keys[0]),
+            vote_account,
+            sysvars: m.account_keys[2..n - 1]
+                .iter()
+                .map(|k| index.compact(k))
+                .collect(),
+            recent_blockhash,
+            accounts: &ix.accounts,
+            vote: chain.encode(vote_account, &vote_ix)?,
+            fee: meta.fee,
+            status,
+            balances,
+            compute_units_consumed: meta.compute_units_consumed,
+            cost_units: meta.cost_units,
+        })
+    }
+
+    /// The status meta this vote was stored from.
+    pub fn meta(&self) -> Result<TransactionStatusMeta> {
+        vote_meta(
+            self.fee,
+            &self.status,
+            &self.balances,
+            self.compute_units_consumed,
+            self.cost_units,
+        )
+    }
+
+    #[inline]
+    pub fn header(&self) -> MessageHeader {
+        vote_header(self.sysvars.len() + 3)
+    }
+}
+
+/// One signer paying for everything, every key past the vote account readonly.
+#[inline]
+fn vote_header(n: usize) -> MessageHeader {
+    MessageHeader {
+        num_required_signatures: 1,
+        num_readonly_signed_accounts: 0,
+        num_readonly_unsigned_accounts: (n - 2) as u8,
+    }
+}
+
+/// Fails when the fee is more than the payer had.
+fn vote_meta(
+    fee: u64,
+    status: &VoteStatus,
+    balances: &[u64],
+    compute_units_consumed: Option<u64>,
+    cost_units: Option<u64>,
+) -> Result<TransactionStatusMeta> {
+    let mut post_balances = balances.to_vec();
+    let payer = post_balances
+        .first_mut()
+        .and_then(|b| b.checked_sub(fee))
+        .context("vote fee exceeds the payer balance")?;
+    post_balances[0] = payer;
+
+    let failure = status.failure()?;
+    let last = match &failure {
+        None => VOTE_SUCCESS.to_string(),
+        Some((_, reason)) => format!("{}{}", VOTE_FAILED, reason),
+    };
+    Ok(TransactionStatusMeta {
+        err: failure.map(|(err, _)| TransactionError { err }),
+        fee,
+        pre_balances: balances.to_vec(),
+        post_balances,
+        inner_instructions: Vec::new(),
+        inner_instructions_none: false,
+        log_messages: vec![VOTE_INVOKE.to_string(), last],
+        log_messages_none: false,
+        pre_token_balances: Vec::new(),
+        post_token_balances: Vec::new(),
+        rewards: Vec::new(),
+        loaded_writable_addresses: Vec::new(),
+        loaded_readonly_addresses: Vec::new(),
+        return_data: None,
+        return_data_none: true,
+        compute_units_consumed,
+        cost_units,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompactInstructionError, KeyStore};
+    use car_reader::versioned_transaction::{CompiledInstruction, LegacyMessage};
+    use std::sync::Arc;
+
+    fn tower_sync(root: u64, slots: &[u64], ts: i64) -> VoteIx {
+        let mut prev = root;
+        VoteIx::TowerSync {
+            tower: CompactTower {
+                root,
+                lockout_offsets: slots
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &s)| {
+                        let off = s - std::mem::replace(&mut prev, s);
+                        (off, (slots.len() - i) as u8)
+                    })
+                    .collect(),
+                hash: [root as u8; 32],
+                timestamp: Some(ts),
+            },
+            block_id: [7; 32],
+        }
+    }
+
+    #[test]
+    fn votes_round_trip_through_the_chain() {
+        let (authority, vote_account) = ([1u8; 32], [2u8; 32]);
+        let store = Arc::new(KeyStore {
+            keys: vec![authority, vote_account],
+        });
+        let index = KeyIndex::build(store.clone());
+        assert_eq!(VOTE_INVOKE, format!("Program {} invoke [1]", VOTE_PROGRAM));
+
+        let sig = [9u8; 64];
+        let blockhash = [3u8; 32];
+        let votes = [
+            tower_sync(100, &[101, 102, 104], 1_700_000_000),
+            tower_sync(101, &[102, 104, 105], 1_700_000_001),
+            // An expired lockout popped from the top.
+            tower_sync(101, &[102, 107], 1_700_000_003),
+        ];
+
+        // One vote per block, from the start of a window.
+        let block = |i: usize| CompactBlockHeader {
+            slot: 10 * VOTE_CHAIN_SLOTS + i as u64,
+            parent_slot: (10 * VOTE_CHAIN_SLOTS + i as u64).saturating_sub(1),
+            blockhash: 0,
+            previous_blockhash: 0,
+            block_time: None,
+            block_height: None,
+        };
+        let mut writer = VoteChain::default();
+        let mut stored = Vec::new();
+        for (i, ix) in votes.iter().enumerate() {
+            writer.begin_block(&block(i));
+            let data = NativeIx::Vote(ix.clone()).to_bytes(&store).unwrap();
+            let vtx = VersionedTransaction {
+                signatures: vec![&sig],
+                message: VersionedMessage::Legacy(LegacyMessage {
+                    header: vote_header(3),
+                    account_keys: vec![&authority, &vote_account, VOTE_PROGRAM.as_array()],
+                    recent_blockhash: &blockhash,
+                    instructions: vec![CompiledInstruction {
+                        program_id_index: 2,
+                        accounts: vec![1, 0],
+                        data: data.clone(),
+                    }],
+                }),
+            };
+            let status = match i {
+                1 => VoteStatus::Failed(CompactTxError::InstructionError(
+                    0,
+                    CompactInstructionError::Custom(0),
+                )),
+                _ => VoteStatus::Success,
+            };
+            let meta = vote_meta(5000, &status, &[10_000, 20, 1], Some(2100), Some(2200)).unwrap();
+            if i == 1 {
+                assert_eq!(
+                    meta.log_messages[1],
+                    format!("{VOTE_FAILED}custom program error: 0x0")
+                );
+            }
+
+            // Anything else in the meta keeps the generic encoding.
+            let mut odd = meta.clone();
+            odd.log_messages.push("Program log: extra".into());
+            let rb = || CompactRecentBlockhash::Id(4);
+            assert!(CompactVoteTx::from_tx(&vtx, &odd, &index, rb(), &mut writer).is_none());
+
+            let v = CompactVoteTx::from_tx(&vtx, &meta, &index, rb(), &mut writer).unwrap();
+            assert_eq!(v.meta().unwrap(), meta);
+            stored.push((postcard::to_allocvec(&v).unwrap(), data));
+        }
+
+        let mut reader = VoteChain::default();
+        for (i, (bytes, data)) in stored.iter().enumerate() {
+            reader.begin_block(&block(i));
+            let v: CompactVoteTx = postcard::from_bytes(bytes).unwrap();
+            let ix = reader.decode(v.vote_account, &v.vote).unwrap();
+            assert_eq!(NativeIx::Vote(ix).to_bytes(&store).unwrap(), *data);
+        }
+        // Later votes only store the new slots and small offsets.
+        assert!(stored[2].0.len() < stored[0].0.len());
+
+        // A chain that starts mid-window can't decode, nor encode, until it
+        // is fed the window from its first block.
+        let last: CompactVoteTx = postcard::from_bytes(&stored[2].0).unwrap();
+        let mut cold = VoteChain::default();
+        cold.begin_block(&block(2));
+        assert!(cold.decode(last.vote_account, &last.vote).is_err());
+        assert!(cold.encode(last.vote_account, &votes[2]).is_none());
+        let mut seeded = VoteChain::default();
+        for (i, (bytes, _)) in stored[..2].iter().enumerate() {
+            let v: CompactVoteTx = postcard::from_bytes(bytes).unwrap();
+            let tx = CompactTransaction {
+                signatures: heapless::Vec::new(),
+                message: CompactMessage::Vote(Box::new(v)),
+            };
+            seeded.replay(&block(i), [&tx]).unwrap();
+        }
+        seeded.begin_block(&block(2));
+        let ix = seeded.decode(last.vote_account, &last.vote).unwrap();
+        assert_eq!(NativeIx::Vote(ix).to_bytes(&store).unwrap(), stored[2].1);
+    }
+}
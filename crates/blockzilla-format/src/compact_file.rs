@@ -0,0 +1,720 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter},
+    path::Path,
+};
+
+use crate::{
+    CompactBlockRecord, CompactBlockRecordV1, CompactBlockRecordV2, CompactBlockRecordV3,
+    LegacyTokenBalances, PostcardFramedReader, PostcardFramedWriter, SectionedBlock,
+    decode_vote_frame, votes_path,
+};
+
+/// Magic at the start of compact.bin, block.bin, runtime.bin and votes.bin,
+/// and from format v13 of every other archive file.
+pub const COMPACT_MAGIC: [u8; 8] = *b"BZCOMPCT";
+/// Current format version. Files without a header are version 1.
+///
+/// - 2: file header, block-level log tables
+/// - 3: log `StrId`s start after the epoch string dictionary (strings.bin),
+///   whose id (`LogDicts::id`) the header holds
+/// - 4: `ProgramLog::Template` over the epoch template dictionary (templates.bin)
+/// - 5: registry keys in block log strings and instruction data, inner
+///   included, are ids (`key_refs`)
+/// - 6: instruction data of core native programs may be typed (`instructions`)
+/// - 7: plain vote transactions may be `CompactMessage::Vote`, delta encoded
+///   through blocks linked by parent slot since their window started
+/// - 8: transaction errors are postcard `CompactTxError`s
+/// - 9: post balances are deltas, pre balances too outside sectioned files
+/// - 10: pre and post token balances merged into `CompactTokenBalanceChange`s
+/// - 11: block rewards (`CompactBlockRecord::rewards`)
+/// - 12: block entries (`CompactBlockRecord::entries`)
+/// - 13: epoch, producer and flags in the header, CRC32C after every frame,
+///   whole-file hash trailer; registries, indexes and dictionaries get the
+///   same header and trailer
+/// - 14: frames start with `FRAME_MAGIC`, so readers can resync after bad bytes
+pub const COMPACT_VERSION: u32 = 14;
+/// Format v2: magic + version u32 + layout u32
+pub const COMPACT_HEADER_LEN: usize = 8 + 4 + 4;
+/// Format v3 to v12: `COMPACT_HEADER_LEN` + log dictionaries id u64
+pub const DICTS_HEADER_LEN: usize = COMPACT_HEADER_LEN + 8;
+/// From format v13: `DICTS_HEADER_LEN` + epoch u64 + producer u32 + flags u32
+pub const FILE_HEADER_LEN: usize = DICTS_HEADER_LEN + 8 + 4 + 4;
+
+/// Header flag: vote-only transactions were moved to votes.bin.
+pub const FLAG_SPLIT_VOTES: u32 = 1 << 0;
+/// Header flag: written with `--skip-bad-txs`, transactions that failed to
+/// convert may have been left out (the compact pass logs how many).
+pub const FLAG_SKIPPED_BAD_TXS: u32 = 1 << 1;
+
+/// First word of every frame from format v14, before its length.
+pub const FRAME_MAGIC: u32 = u32::from_le_bytes(*b"BZfr");
+/// Frame length (frame magic from v14) marking the trailer, no frame is that
+/// long.
+pub const TRAILER_MARK: u32 = u32::MAX;
+/// `TRAILER_MARK` + blake3 hash of every byte before the trailer.
+pub const TRAILER_LEN: usize = 4 + 32;
+
+/// What the frames of an archive file hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactLayout {
+    /// compact.bin, postcard `CompactBlockRecord`
+    Plain,
+    /// compact.bin, `SectionedBlock`
+    Sectioned,
+    /// block.bin, `CompactReplayBlock`
+    SplitBlock,
+    /// runtime.bin, `CompactRuntimeBlock`
+    SplitRuntime,
+    /// votes.bin, `CompactVoteBlock`
+    Votes,
+    /// registry.bin, raw 32 byte keys (no frames)
+    Registry,
+    /// blockhash_registry.bin, raw 32 byte hashes (no frames)
+    BlockhashRegistry,
+    /// global-registry.bin, raw 32 byte keys (no frames)
+    GlobalRegistry,
+    /// global-remap.bin, u32 global ids (no frames)
+    GlobalRemap,
+    /// registry.idx, `KeyIndex` (no frames)
+    KeyIndex,
+    /// slot-index.bin and votes-slot-index.bin, `SlotIndexEntry`s (no frames)
+    SlotIndex,
+    /// sig-index.bin, `SigIndex` (no frames)
+    SigIndex,
+    /// strings.bin, `StringDict` (no frames)
+    StringDict,
+    /// templates.bin, `TemplateDict` (no frames)
+    TemplateDict,
+}
+
+impl CompactLayout {
+    #[inline]
+    fn to_u32(self) -> u32 {
+        match self {
+            CompactLayout::Plain => 0,
+            CompactLayout::Sectioned => 1,
+            CompactLayout::SplitBlock => 2,
+            CompactLayout::SplitRuntime => 3,
+            CompactLayout::Votes => 4,
+            CompactLayout::Registry => 5,
+            CompactLayout::BlockhashRegistry => 6,
+            CompactLayout::GlobalRegistry => 7,
+            CompactLayout::GlobalRemap => 8,
+            CompactLayout::KeyIndex => 9,
+            CompactLayout::SlotIndex => 10,
+            CompactLayout::SigIndex => 11,
+            CompactLayout::StringDict => 12,
+            CompactLayout::TemplateDict => 13,
+        }
+    }
+
+    #[inline]
+    fn from_u32(v: u32) -> Result<Self> {
+        Ok(match v {
+            0 => CompactLayout::Plain,
+            1 => CompactLayout::Sectioned,
+            2 => CompactLayout::SplitBlock,
+            3 => CompactLayout::SplitRuntime,
+            4 => CompactLayout::Votes,
+            5 => CompactLayout::Registry,
+            6 => CompactLayout::BlockhashRegistry,
+            7 => CompactLayout::GlobalRegistry,
+            8 => CompactLayout::GlobalRemap,
+            9 => CompactLayout::KeyIndex,
+            10 => CompactLayout::SlotIndex,
+            11 => CompactLayout::SigIndex,
+            12 => CompactLayout::StringDict,
+            13 => CompactLayout::TemplateDict,
+            _ => anyhow::bail!("unknown compact layout {}", v),
+        })
+    }
+}
+
+/// Leading header of an archive file.
+///
+/// Layout (little endian): magic, version u32, layout u32, then from format
+/// v3 dicts u64, then from v13 epoch u64, producer u32 and flags u32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactFileHeader {
+    pub version: u32,
+    pub layout: CompactLayout,
+    /// 0 before format v13.
+    pub epoch: u64,
+    /// Version of the crates that wrote the file, see [`producer_version`];
+    /// 0 before format v13.
+    pub producer: u32,
+    /// `FLAG_*` bits.
+    pub flags: u32,
+    /// [`LogDicts::id`](crate::LogDicts::id) of the dictionaries log ids
+    /// resolve through; 0 before format v3.
+    pub dicts: u64,
+}
+
+/// `major.minor.patch` of this crate as `major << 20 | minor << 10 | patch`.
+pub fn producer_version() -> u32 {
+    let mut parts = env!("CARGO_PKG_VERSION")
+        .split(['.', '-', '+'])
+        .map(|p| p.parse::<u32>().unwrap_or(0) & 0x3ff);
+    let mut next = || parts.next().unwrap_or(0);
+    (next() << 20) | (next() << 10) | next()
+}
+
+impl CompactFileHeader {
+    /// Header for a file written by this version.
+    pub fn new(layout: CompactLayout) -> Self {
+        Self {
+            version: COMPACT_VERSION,
+            layout,
+            epoch: 0,
+            producer: producer_version(),
+            flags: 0,
+            dicts: 0,
+        }
+    }
+
+    pub fn with_epoch(self, epoch: u64) -> Self {
+        Self { epoch, ..self }
+    }
+
+    pub fn with_flags(self, flags: u32) -> Self {
+        Self { flags, ..self }
+    }
+
+    pub fn with_dicts(self, dicts: u64) -> Self {
+        Self { dicts, ..self }
+    }
+
+    /// What a headerless (pre-v2) compact.bin holds.
+    pub const LEGACY: Self = Self {
+        version: 1,
+        layout: CompactLayout::Plain,
+        epoch: 0,
+        producer: 0,
+        flags: 0,
+        dicts: 0,
+    };
+
+    /// Bytes the header takes at the start of the file.
+    #[inline]
+    pub fn encoded_len(&self) -> usize {
+        match self.version {
+            1 => 0,
+            2 => COMPACT_HEADER_LEN,
+            v if v < 13 => DICTS_HEADER_LEN,
+            _ => FILE_HEADER_LEN,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(FILE_HEADER_LEN);
+        b.extend_from_slice(&COMPACT_MAGIC);
+        b.extend_from_slice(&self.version.to_le_bytes());
+        b.extend_from_slice(&self.layout.to_u32().to_le_bytes());
+        b.extend_from_slice(&self.dicts.to_le_bytes());
+        b.extend_from_slice(&self.epoch.to_le_bytes());
+        b.extend_from_slice(&self.producer.to_le_bytes());
+        b.extend_from_slice(&self.flags.to_le_bytes());
+        b.truncate(self.encoded_len());
+        b
+    }
+
+    /// Header from the first bytes of a file; `None` if there is none (v1).
+    pub fn from_prefix(b: &[u8]) -> Result<Option<Self>> {
+        if b.len() < COMPACT_MAGIC.len() || b[..8] != COMPACT_MAGIC {
+            return Ok(None);
+        }
+        anyhow::ensure!(b.len() >= COMPACT_HEADER_LEN, "truncated compact header");
+
+        let u32_at = |i: usize| u32::from_le_bytes(b[i..i + 4].try_into().unwrap());
+        let version = u32_at(8);
+        anyhow::ensure!(
+            (2..=COMPACT_VERSION).contains(&version),
+            "unsupported compact format version {}",
+            version
+        );
+        let mut h = Self {
+            version,
+            layout: CompactLayout::from_u32(u32_at(12))?,
+            ..Self::LEGACY
+        };
+        anyhow::ensure!(b.len() >= h.encoded_len(), "truncated compact header");
+        if h.encoded_len() >= DICTS_HEADER_LEN {
+            h.dicts = u64::from_le_bytes(b[16..24].try_into().unwrap());
+        }
+        if h.encoded_len() >= FILE_HEADER_LEN {
+            h.epoch = u64::from_le_bytes(b[24..32].try_into().unwrap());
+            h.producer = u32_at(32);
+            h.flags = u32_at(36);
+        }
+        Ok(Some(h))
+    }
+
+    /// Consume the header at the start of `r`. Headerless input is
+    /// `LEGACY` and is left untouched.
+    pub fn read<R: BufRead>(r: &mut R) -> Result<Self> {
+        // Only bytes that match the magic are consumed, however short the
+        // reads are.
+        let mut b = Vec::with_capacity(FILE_HEADER_LEN);
+        while b.len() < COMPACT_MAGIC.len() {
+            let buf = r.fill_buf().context("read compact header")?;
+            let seen = b.len();
+            let n = buf.len().min(COMPACT_MAGIC.len() - seen);
+            if n == 0 || buf[..n] != COMPACT_MAGIC[seen..seen + n] {
+                anyhow::ensure!(seen == 0, "truncated compact header");
+                return Ok(Self::LEGACY);
+            }
+            b.extend_from_slice(&buf[..n]);
+            r.consume(n);
+        }
+
+        b.resize(COMPACT_HEADER_LEN, 0);
+        r.read_exact(&mut b[COMPACT_MAGIC.len()..])
+            .context("truncated compact header")?;
+        let version = u32::from_le_bytes(b[8..12].try_into().unwrap());
+        let len = Self {
+            version,
+            ..Self::LEGACY
+        }
+        .encoded_len();
+        if version <= COMPACT_VERSION && len > b.len() {
+            b.resize(len, 0);
+            r.read_exact(&mut b[COMPACT_HEADER_LEN..])
+                .context("truncated compact header")?;
+        }
+        Ok(Self::from_prefix(&b)?.expect("magic checked above"))
+    }
+
+    /// Whether blocks carry `BlockLogTables` shared by their log streams.
+    #[inline]
+    pub fn uses_block_log_tables(&self) -> bool {
+        self.version >= 2
+    }
+
+    /// Whether log strings resolve through the epoch dictionary.
+    #[inline]
+    pub fn uses_string_dict(&self) -> bool {
+        self.version >= 3
+    }
+
+    /// Whether program logs may reference the epoch template dictionary.
+    #[inline]
+    pub fn uses_templates(&self) -> bool {
+        self.version >= 4
+    }
+
+    /// Whether block log strings and instruction data carry key refs.
+    #[inline]
+    pub fn uses_key_refs(&self) -> bool {
+        self.version >= 5
+    }
+
+    /// Whether instruction data may be a typed `instructions::NativeIx`.
+    #[inline]
+    pub fn uses_native_ix(&self) -> bool {
+        self.version >= 6
+    }
+
+    /// Whether `CompactMetaV1::err` holds a `CompactTxError`.
+    #[inline]
+    pub fn uses_typed_errors(&self) -> bool {
+        self.version >= 8
+    }
+
+    /// Whether post balances are stored against the pre balances.
+    #[inline]
+    pub fn uses_balance_deltas(&self) -> bool {
+        self.version >= 9
+    }
+
+    /// Whether pre balances are stored against a `BalanceChain`.
+    #[inline]
+    pub fn uses_balance_chain(&self) -> bool {
+        self.uses_balance_deltas() && self.layout != CompactLayout::Sectioned
+    }
+
+    /// Whether metas hold `CompactTokenBalanceChange`s rather than
+    /// [`LegacyTokenBalances`].
+    #[inline]
+    pub fn uses_token_balance_changes(&self) -> bool {
+        self.version >= 10
+    }
+
+    /// Whether frames carry the block rewards.
+    #[inline]
+    pub fn uses_block_rewards(&self) -> bool {
+        self.version >= 11
+    }
+
+    /// Whether frames carry the block entries.
+    #[inline]
+    pub fn uses_entries(&self) -> bool {
+        self.version >= 12
+    }
+
+    /// Whether frames end with a CRC32C and the file with a trailer.
+    #[inline]
+    pub fn uses_checksums(&self) -> bool {
+        self.version >= 13
+    }
+
+    /// Whether frames start with `FRAME_MAGIC`.
+    #[inline]
+    pub fn uses_frame_magic(&self) -> bool {
+        self.version >= 14
+    }
+
+    /// Fail unless the file holds `layout` frames.
+    pub fn expect(&self, layout: CompactLayout) -> Result<()> {
+        anyhow::ensure!(
+            self.layout == layout,
+            "expected a {:?} file, found {:?}",
+            layout,
+            self.layout
+        );
+        Ok(())
+    }
+}
+
+/// Check a file trailer against `hash`, the hash of every byte before it.
+pub fn check_trailer(trailer: &[u8], hash: blake3::Hash) -> Result<()> {
+    anyhow::ensure!(
+        trailer.len() == TRAILER_LEN && trailer[..4] == TRAILER_MARK.to_le_bytes(),
+        "file has no trailer (truncated?)"
+    );
+    let stored: [u8; 32] = trailer[4..].try_into().unwrap();
+    anyhow::ensure!(
+        stored == *hash.as_bytes(),
+        "file hash {} does not match its content ({})",
+        blake3::Hash::from_bytes(stored),
+        hash
+    );
+    Ok(())
+}
+
+/// Content of an unframed `layout` file (registry.bin, blockhash_registry.bin,
+/// indexes, dictionaries) between its header and trailer, hash checked.
+pub fn raw_file_body(bytes: &[u8], layout: CompactLayout) -> Result<&[u8]> {
+    let h = CompactFileHeader::from_prefix(bytes)?.context("file has no header")?;
+    h.expect(layout)?;
+    let end = bytes
+        .len()
+        .checked_sub(TRAILER_LEN)
+        .filter(|&end| end >= h.encoded_len())
+        .context("file has no trailer (truncated?)")?;
+    check_trailer(&bytes[end..], blake3::hash(&bytes[..end]))?;
+    Ok(&bytes[h.encoded_len()..end])
+}
+
+/// Write an unframed `header.layout` file: header, what `body` writes,
+/// trailer.
+pub fn write_raw_file<F>(path: &Path, header: CompactFileHeader, body: F) -> Result<()>
+where
+    F: FnOnce(&mut PostcardFramedWriter<BufWriter<File>>) -> Result<()>,
+{
+    let f = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut w = PostcardFramedWriter::with_header(BufWriter::with_capacity(8 << 20, f), header)?;
+    body(&mut w)
+        .and_then(|()| w.finish())
+        .with_context(|| format!("write {}", path.display()))
+}
+
+/// Reads compact.bin of any version and layout as current `CompactBlockRecord`s.
+pub struct CompactReader<R> {
+    r: PostcardFramedReader<R>,
+    header: CompactFileHeader,
+    /// votes.bin, merged back into every block when attached.
+    votes: Option<PostcardFramedReader<R>>,
+}
+
+impl CompactReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Self::new(BufReader::with_capacity(8 << 20, f))
+            .with_context(|| format!("Failed to read {}", path.display()))
+    }
+
+    /// Open compact.bin with the votes.bin next to it merged back in.
+    pub fn open_with_votes(path: &Path) -> Result<Self> {
+        let votes = votes_path(path);
+        let f =
+            File::open(&votes).with_context(|| format!("Failed to open {}", votes.display()))?;
+        Self::open(path)?
+            .with_votes(BufReader::with_capacity(8 << 20, f))
+            .with_context(|| format!("Failed to read {}", votes.display()))
+    }
+}
+
+impl<R: BufRead> CompactReader<R> {
+    pub fn new(mut r: R) -> Result<Self> {
+        let header = CompactFileHeader::read(&mut r)?;
+        anyhow::ensure!(
+            matches!(
+                header.layout,
+                CompactLayout::Plain | CompactLayout::Sectioned
+            ),
+            "{:?} file is not a compact.bin",
+            header.layout
+        );
+        Ok(Self {
+            r: PostcardFramedReader::with_header(r, header),
+            header,
+            votes: None,
+        })
+    }
+
+    /// Merge the frames of `votes`, a votes.bin, into the blocks read.
+    pub fn with_votes(mut self, mut votes: R) -> Result<Self> {
+        let header = CompactFileHeader::read(&mut votes)?;
+        header.expect(CompactLayout::Votes)?;
+        self.votes = Some(PostcardFramedReader::with_header(votes, header));
+        Ok(self)
+    }
+
+    /// Fail on frames longer than `max` bytes, votes.bin included when
+    /// already attached.
+    pub fn with_max_frame_len(self, max: u32) -> Self {
+        Self {
+            r: self.r.with_max_frame_len(max),
+            votes: self.votes.map(|v| v.with_max_frame_len(max)),
+            ..self
+        }
+    }
+
+    /// Skip bad frames, see [`PostcardFramedReader::recovering`]. The blocks
+    /// in them are lost, and so are the votes.bin frames of those blocks.
+    pub fn recovering(self) -> Self {
+        Self {
+            r: self.r.recovering(),
+            ..self
+        }
+    }
+
+    /// Bytes of compact.bin dropped to resync.
+    #[inline]
+    pub fn skipped(&self) -> u64 {
+        self.r.skipped()
+    }
+
+    /// Whether blocks may be missing before the one last read. A
+    /// [`crate::VoteChain`] fed these blocks must then be
+    /// [`desync`](crate::VoteChain::desync)ed.
+    #[inline]
+    pub fn resynced(&self) -> bool {
+        self.r.resynced()
+    }
+
+    #[inline]
+    pub fn header(&self) -> CompactFileHeader {
+        self.header
+    }
+
+    /// Next raw frame, to be decoded according to `header()`. Skips the
+    /// matching votes.bin frame if one is attached, which a resync leaves
+    /// unknown: use [`Self::read`] then.
+    #[inline]
+    pub fn read_bytes(&mut self) -> Result<Option<&[u8]>> {
+        let more = self.r.advance()?;
+        if let Some(votes) = self.votes.as_mut() {
+            anyhow::ensure!(
+                !self.r.resynced(),
+                "raw frames can not keep votes.bin in step after a resync"
+            );
+            votes.skip()?;
+        }
+        Ok(more.then_some(self.r.frame()))
+    }
+
+    /// Next block, fully decoded, with its votes when votes.bin is attached.
+    pub fn read(&mut self) -> Result<Option<CompactBlockRecord<'_>>> {
+        let h = self.header;
+        let more = self.r.advance()?;
+        let resynced = self.r.resynced();
+        let mut block = if more {
+            Some(decode_compact_frame(h, self.r.frame())?)
+        } else {
+            None
+        };
+        let Some(votes) = self.votes.as_mut() else {
+            return Ok(block);
+        };
+        // After a resync, drop the vote frames of the lost blocks.
+        let slot = block.as_ref().map(|b| b.header.slot);
+        let more = loop {
+            if !votes.advance()? {
+                break false;
+            }
+            if resynced {
+                let vote_slot = frame_slot(votes.frame())?;
+                if slot.is_none_or(|s| vote_slot < s) {
+                    continue;
+                }
+            }
+            break true;
+        };
+        let vote_block = if more {
+            Some(decode_vote_frame(h, votes.frame())?)
+        } else {
+            None
+        };
+        match (block.as_mut(), vote_block) {
+            (Some(b), Some(v)) => b.merge_votes(v)?,
+            (None, None) => {}
+            (Some(b), None) => anyhow::bail!("votes.bin ended before slot {}", b.header.slot),
+            (None, Some(v)) => anyhow::bail!("compact.bin ended before slot {}", v.slot),
+        }
+        Ok(block)
+    }
+}
+
+/// Slot of a compact.bin, block.bin, runtime.bin or votes.bin frame, which
+/// all start with it.
+pub(crate) fn frame_slot(frame: &[u8]) -> Result<u64> {
+    let (slot, _) = postcard::take_from_bytes(frame).context("frame slot")?;
+    Ok(slot)
+}
+
+/// Decode one compact.bin frame of a file with `header`.
+pub fn decode_compact_frame<'a>(
+    header: CompactFileHeader,
+    frame: &'a [u8],
+) -> Result<CompactBlockRecord<'a>> {
+    if header.version == 1 {
+        let b: CompactBlockRecordV1 = postcard::from_bytes(frame).context("postcard decode")?;
+        return Ok(b.into());
+    }
+    match header.layout {
+        CompactLayout::Plain if !header.uses_token_balance_changes() => {
+            let b: CompactBlockRecordV2<LegacyTokenBalances> =
+                postcard::from_bytes(frame).context("postcard decode")?;
+            Ok(b.into())
+        }
+        CompactLayout::Plain if !header.uses_block_rewards() => {
+            let b: CompactBlockRecordV2 = postcard::from_bytes(frame).context("postcard decode")?;
+            Ok(b.into())
+        }
+        CompactLayout::Plain if !header.uses_entries() => {
+            let b: CompactBlockRecordV3 = postcard::from_bytes(frame).context("postcard decode")?;
+            Ok(b.into())
+        }
+        CompactLayout::Plain => postcard::from_bytes(frame).context("postcard decode"),
+        CompactLayout::Sectioned => SectionedBlock::parse(frame, header)?.to_record(header),
+        l => anyhow::bail!("{:?} frames are not compact.bin blocks", l),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlockLogTables, CompactBlockHeader, FrameError, PostcardFramedWriter};
+
+    fn header(slot: u64) -> CompactBlockHeader {
+        CompactBlockHeader {
+            slot,
+            parent_slot: slot - 1,
+            blockhash: 0,
+            previous_blockhash: 0,
+            block_time: None,
+            block_height: None,
+        }
+    }
+
+    #[test]
+    fn headerless_files_read_as_v1() {
+        let mut w = PostcardFramedWriter::new(Vec::new());
+        w.write(&CompactBlockRecordV1 {
+            header: header(3),
+            txs: Vec::new(),
+        })
+        .unwrap();
+        let v1 = w.into_inner();
+
+        let mut r = CompactReader::new(&v1[..]).unwrap();
+        assert_eq!(r.header(), CompactFileHeader::LEGACY);
+        assert_eq!(r.read().unwrap().unwrap().header.slot, 3);
+        assert!(r.read().unwrap().is_none());
+
+        let h = CompactFileHeader::new(CompactLayout::Plain).with_epoch(800);
+        let mut w = PostcardFramedWriter::with_header(Vec::new(), h).unwrap();
+        w.write(&CompactBlockRecord {
+            header: header(4),
+            log_tables: BlockLogTables::default(),
+            rewards: Vec::new(),
+            entries: None,
+            txs: Vec::new(),
+        })
+        .unwrap();
+        w.finish().unwrap();
+        let v2 = w.into_inner();
+
+        let mut r = CompactReader::new(&v2[..]).unwrap();
+        assert_eq!(r.header(), h);
+        assert_eq!(r.read().unwrap().unwrap().header.slot, 4);
+        assert!(r.read().unwrap().is_none());
+
+        // Truncation and bit rot are errors, not a short file.
+        let frame_error = |file: &[u8]| {
+            let mut r = CompactReader::new(file).unwrap();
+            let err = loop {
+                if let Err(e) = r.read() {
+                    break e;
+                }
+            };
+            err.downcast::<FrameError>().unwrap()
+        };
+        let trailer = v2.len() - TRAILER_LEN;
+        assert_eq!(
+            frame_error(&v2[..trailer]),
+            FrameError::MissingTrailer {
+                offset: trailer as u64
+            }
+        );
+        let h = FILE_HEADER_LEN as u64;
+        assert!(matches!(
+            frame_error(&v2[..trailer - 1]),
+            FrameError::TruncatedFrame { offset, got, .. } if offset == h && got == trailer as u64 - h - 1
+        ));
+        let mut rotten = v2.clone();
+        rotten[FILE_HEADER_LEN + 8] ^= 0x10;
+        assert!(matches!(
+            frame_error(&rotten),
+            FrameError::BadChecksum { offset, .. } if offset == h
+        ));
+
+        // Newer versions are refused rather than misread.
+        let mut future = v2.clone();
+        future[8..12].copy_from_slice(&(COMPACT_VERSION + 1).to_le_bytes());
+        assert!(CompactReader::new(&future[..]).is_err());
+
+        // Short reads still see the whole header, or leave v1 input alone.
+        let mut r = std::io::BufReader::with_capacity(3, &v2[..]);
+        assert_eq!(CompactFileHeader::read(&mut r).unwrap().epoch, 800);
+        assert_eq!(r.fill_buf().unwrap(), &v2[FILE_HEADER_LEN..][..3]);
+        let mut r = std::io::BufReader::with_capacity(3, &v1[..]);
+        assert_eq!(
+            CompactFileHeader::read(&mut r).unwrap(),
+            CompactFileHeader::LEGACY
+        );
+        assert_eq!(r.fill_buf().unwrap(), &v1[..3]);
+        let mut r = std::io::BufReader::with_capacity(3, &v2[..FILE_HEADER_LEN - 1]);
+        assert!(CompactFileHeader::read(&mut r).is_err());
+    }
+
+    #[test]
+    fn raw_files_check_their_hash() {
+        let keys = [[1u8; 32], [2u8; 32]];
+        let h = CompactFileHeader::new(CompactLayout::Registry).with_epoch(3);
+        let mut w = PostcardFramedWriter::with_header(Vec::new(), h).unwrap();
+        w.write_raw(keys.as_flattened()).unwrap();
+        w.finish().unwrap();
+        let file = w.into_inner();
+
+        let body = raw_file_body(&file, CompactLayout::Registry).unwrap();
+        assert_eq!(body, keys.as_flattened());
+        assert!(raw_file_body(&file, CompactLayout::BlockhashRegistry).is_err());
+        assert!(raw_file_body(&file[..file.len() - 1], CompactLayout::Registry).is_err());
+
+        assert!(raw_file_body(keys.as_flattened(), CompactLayout::Registry).is_err());
+    }
+}
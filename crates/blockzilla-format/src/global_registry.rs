@@ -0,0 +1,297 @@
+use anyhow::{Context, Result};
+use gxhash::{HashMap as GxHashMap, HashMapExt};
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    CompactFileHeader, CompactLayout, KeyStore, TRAILER_LEN, TRAILER_MARK, raw_file_body,
+    write_raw_file,
+};
+
+/// Append-only registry shared by all epochs.
+///
+/// File format is the same as registry.bin (header, raw 32-byte pubkeys,
+/// trailer), but ids are assigned in first-seen order and never change: new
+/// keys are only ever appended. Ids are 1-based (0 reserved).
+#[derive(Debug, Clone, Default)]
+pub struct GlobalRegistry {
+    /// Keys in id order.
+    pub keys: Vec<[u8; 32]>,
+    /// Key → 1-based id.
+    index: GxHashMap<[u8; 32], u32>,
+    /// Number of keys already on disk.
+    persisted: usize,
+    /// Header of the file on disk, `None` while there is no file.
+    header: Option<CompactFileHeader>,
+}
+
+impl GlobalRegistry {
+    /// Load `path`, or start empty if it doesn't exist yet.
+    pub fn open(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let (header, store) = KeyStore::load_as(path, CompactLayout::GlobalRegistry)?;
+        let mut index = GxHashMap::with_capacity(store.len());
+        for (i, k) in store.keys.iter().enumerate() {
+            anyhow::ensure!(
+                index.insert(*k, i as u32 + 1).is_none(),
+                "duplicate key #{} in {}",
+                i + 1,
+                path.display()
+            );
+        }
+
+        Ok(Self {
+            persisted: store.len(),
+            keys: store.keys,
+            index,
+            header: Some(header),
+        })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// 1-based global id -> key
+    #[inline]
+    pub fn get(&self, id: u32) -> Option<&[u8; 32]> {
+        self.keys.get(id.checked_sub(1)? as usize)
+    }
+
+    #[inline]
+    pub fn lookup(&self, k: &[u8; 32]) -> Option<u32> {
+        self.index.get(k).copied()
+    }
+
+    /// Global id of `k`, assigning the next id if it is new.
+    pub fn intern(&mut self, k: &[u8; 32]) -> u32 {
+        if let Some(id) = self.lookup(k) {
+            return id;
+        }
+
+        self.keys.push(*k);
+        let id = self.keys.len() as u32;
+        self.index.insert(*k, id);
+        id
+    }
+
+    /// Map every key of an epoch registry to its global id, interning new ones.
+    pub fn remap(&mut self, store: &KeyStore) -> GlobalRemap {
+        GlobalRemap {
+            ids: store.keys.iter().map(|k| self.intern(k)).collect(),
+        }
+    }
+
+    /// Append keys interned since `open` (or the last flush) to `path`.
+    ///
+    /// Existing keys are never rewritten, so ids already handed out stay valid:
+    /// the trailer is cut, the new keys and a new trailer appended. An
+    /// interrupted flush leaves a file without trailer, rejected by
+    /// [`GlobalRegistry::open`]. The first flush writes the whole file, to a
+    /// temporary file renamed into place.
+    pub fn flush(&mut self, path: &Path) -> Result<usize> {
+        let on_disk = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        let expected = match self.header {
+            Some(h) => (h.encoded_len() + self.persisted * 32 + TRAILER_LEN) as u64,
+            None => self.persisted as u64 * 32,
+        };
+        anyhow::ensure!(
+            on_disk == expected,
+            "{} changed since it was opened ({} bytes, expected {})",
+            path.display(),
+            on_disk,
+            expected
+        );
+
+        let new = &self.keys[self.persisted..];
+        if new.is_empty() {
+            return Ok(0);
+        }
+        let n = new.len();
+
+        match self.header {
+            Some(header) => {
+                let mut f = OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open {}", path.display()))?;
+                f.set_len(on_disk - TRAILER_LEN as u64)
+                    .context("cut global registry trailer")?;
+                f.seek(SeekFrom::End(0))
+                    .context("seek global registry end")?;
+
+                let mut hash = blake3::Hasher::new();
+                hash.update(&header.to_bytes());
+                hash.update(self.keys.as_flattened());
+
+                let mut w = BufWriter::with_capacity(64 << 20, f);
+                w.write_all(new.as_flattened()).context("write pubkeys")?;
+                w.write_all(&TRAILER_MARK.to_le_bytes())
+                    .context("write global registry trailer")?;
+                w.write_all(hash.finalize().as_bytes())
+                    .context("write global registry trailer")?;
+                let f = w.into_inner().context("flush global registry")?;
+                f.sync_all().context("sync global registry")?;
+            }
+            None => {
+                let header = CompactFileHeader::new(CompactLayout::GlobalRegistry);
+                let tmp_path = path.with_extension("bin.tmp");
+                write_raw_file(&tmp_path, header, |w| {
+                    w.write_raw(self.keys.as_flattened())
+                        .context("write pubkeys")
+                })?;
+                OpenOptions::new()
+                    .write(true)
+                    .open(&tmp_path)
+                    .and_then(|f| f.sync_all())
+                    .context("sync global registry")?;
+                std::fs::rename(&tmp_path, path).with_context(|| {
+                    format!("rename {} -> {}", tmp_path.display(), path.display())
+                })?;
+                self.header = Some(header);
+            }
+        }
+
+        self.persisted = self.keys.len();
+        Ok(n)
+    }
+}
+
+/// Per-epoch translation from local registry ids to global ids.
+///
+/// File format: one u32 LE global id per registry.bin entry, in file order,
+/// between the file header and trailer.
+#[derive(Debug, Clone)]
+pub struct GlobalRemap {
+    /// local id - 1 -> global id
+    pub ids: Vec<u32>,
+}
+
+impl GlobalRemap {
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// 1-based local id -> 1-based global id
+    #[inline]
+    pub fn get(&self, local_id: u32) -> Option<u32> {
+        self.ids.get(local_id.checked_sub(1)? as usize).copied()
+    }
+
+    pub fn write(&self, path: &Path, epoch: u64) -> Result<()> {
+        let header = CompactFileHeader::new(CompactLayout::GlobalRemap).with_epoch(epoch);
+        write_raw_file(path, header, |w| {
+            for id in &self.ids {
+                w.write_all(&id.to_le_bytes()).context("write global id")?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let bytes = raw_file_body(&bytes, CompactLayout::GlobalRemap)
+            .with_context(|| format!("global remap {}", path.display()))?;
+
+        anyhow::ensure!(
+            bytes.len().is_multiple_of(4),
+            "invalid global remap size {} (not multiple of 4)",
+            bytes.len()
+        );
+
+        Ok(Self {
+            ids: bytes
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+                .collect(),
+        })
+    }
+
+    /// Check the remap covers `store` and every id resolves to the same key.
+    pub fn validate(&self, store: &KeyStore, global: &GlobalRegistry) -> Result<()> {
+        anyhow::ensure!(
+            self.len() == store.len(),
+            "global remap has {} ids, registry has {}",
+            self.len(),
+            store.len()
+        );
+
+        for (i, (k, &gid)) in store.keys.iter().zip(&self.ids).enumerate() {
+            anyhow::ensure!(
+                global.get(gid) == Some(k),
+                "local key #{} does not match global id {}",
+                i + 1,
+                gid
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: u8) -> [u8; 32] {
+        [i; 32]
+    }
+
+    #[test]
+    fn ids_are_stable_across_epochs() {
+        let dir = std::env::temp_dir().join(format!("bz-global-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("global-registry.bin");
+
+        let e0 = KeyStore {
+            keys: vec![key(1), key(2), key(3)],
+        };
+        let e1 = KeyStore {
+            keys: vec![key(3), key(4), key(1)],
+        };
+
+        let mut g = GlobalRegistry::open(&path).unwrap();
+        let r0 = g.remap(&e0);
+        assert_eq!(g.flush(&path).unwrap(), 3);
+        assert_eq!(r0.ids, [1, 2, 3]);
+
+        // Reopen: previously assigned ids survive, only key(4) is appended.
+        let mut g = GlobalRegistry::open(&path).unwrap();
+        let r1 = g.remap(&e1);
+        assert_eq!(g.flush(&path).unwrap(), 1);
+        assert_eq!(r1.ids, [3, 4, 1]);
+
+        let remap_path = dir.join("global-remap.bin");
+        r1.write(&remap_path, 1).unwrap();
+        let r1 = GlobalRemap::load(&remap_path).unwrap();
+        let g = GlobalRegistry::open(&path).unwrap();
+        r1.validate(&e1, &g).unwrap();
+        assert_eq!(r1.get(2), Some(4));
+        assert_eq!(r1.get(0), None);
+        assert!(r1.validate(&e0, &g).is_err());
+
+        // Bare keys are not a global registry.
+        std::fs::write(&path, [key(1), key(2)].as_flattened()).unwrap();
+        assert!(GlobalRegistry::open(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use solana_pubkey::{Pubkey, pubkey};
+
+use super::{Cursor, Out};
+
+pub const ID: Pubkey = pubkey!("ComputeBudget111111111111111111111111111111");
+
+/// `ComputeBudgetInstruction`, borsh with a u8 tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ComputeBudgetIx {
+    /// Deprecated, still found in old blocks.
+    RequestUnits {
+        units: u32,
+        additional_fee: u32,
+    },
+    RequestHeapFrame {
+        bytes: u32,
+    },
+    SetComputeUnitLimit {
+        units: u32,
+    },
+    SetComputeUnitPrice {
+        micro_lamports: u64,
+    },
+    SetLoadedAccountsDataSizeLimit {
+        bytes: u32,
+    },
+}
+
+impl ComputeBudgetIx {
+    pub(crate) fn parse(c: &mut Cursor) -> Option<Self> {
+        Some(match c.u8()? {
+            0 => Self::RequestUnits {
+                units: c.u32()?,
+                additional_fee: c.u32()?,
+            },
+            1 => Self::RequestHeapFrame { bytes: c.u32()? },
+            2 => Self::SetComputeUnitLimit { units: c.u32()? },
+            3 => Self::SetComputeUnitPrice {
+                micro_lamports: c.u64()?,
+            },
+            4 => Self::SetLoadedAccountsDataSizeLimit { bytes: c.u32()? },
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn write(&self, o: &mut Out) {
+        match *self {
+            Self::RequestUnits {
+                units,
+                additional_fee,
+            } => {
+                o.u8(0);
+                o.u32(units);
+                o.u32(additional_fee);
+            }
+            Self::RequestHeapFrame { bytes } => {
+                o.u8(1);
+                o.u32(bytes);
+            }
+            Self::SetComputeUnitLimit { units } => {
+                o.u8(2);
+                o.u32(units);
+            }
+            Self::SetComputeUnitPrice { micro_lamports } => {
+                o.u8(3);
+                o.u64(micro_lamports);
+            }
+            Self::SetLoadedAccountsDataSizeLimit { bytes } => {
+                o.u8(4);
+                o.u32(bytes);
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Typed instruction data for core native programs (format v6).
+//!
+//! An instruction is only stored typed if it encodes back to the exact
+//! original bytes; anything else keeps the `key_refs` byte encoding.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::{CompactPubkey, KeyIndex, KeyStore, decode_ix_data, encode_ix_data};
+
+pub mod compute_budget;
+pub mod stake;
+pub mod system;
+pub mod token;
+pub mod vote;
+
+/// Instruction data tag of a postcard `NativeIx` (after `key_refs`' 0xfd and 0xfe).
+const IX_NATIVE: u8 = 0xff;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NativeIx {
+    System(system::SystemIx),
+    ComputeBudget(compute_budget::ComputeBudgetIx),
+    Stake(stake::StakeIx),
+    Vote(vote::VoteIx),
+    Token(token::TokenIx),
+    Token2022(token::TokenIx),
+}
+
+impl NativeIx {
+    /// Decode `data` of an instruction of `program`.
+    pub fn parse(program: &[u8; 32], data: &[u8], index: &KeyIndex) -> Option<Self> {
+        let mut c = Cursor::new(data);
+        if program == system::ID.as_array() {
+            return system::SystemIx::parse(&mut c, index).map(Self::System);
+        }
+        if program == compute_budget::ID.as_array() {
+            return compute_budget::ComputeBudgetIx::parse(&mut c).map(Self::ComputeBudget);
+        }
+        if program == stake::ID.as_array() {
+            return stake::StakeIx::parse(&mut c, index).map(Self::Stake);
+        }
+        if program == vote::ID.as_array() {
+            return vote::VoteIx::parse(&mut c, index).map(Self::Vote);
+        }
+        if program == token::ID.as_array() {
+            return token::TokenIx::parse(&mut c, index).map(Self::Token);
+        }
+        if program == token::ID_2022.as_array() {
+            return token::TokenIx::parse(&mut c, index).map(Self::Token2022);
+        }
+        None
+    }
+
+    /// The instruction data this was parsed from.
+    pub fn to_bytes(&self, store: &KeyStore) -> Result<Vec<u8>> {
+        let mut out = Out::new(store);
+        match self {
+            Self::System(ix) => ix.write(&mut out)?,
+            Self::ComputeBudget(ix) => ix.write(&mut out),
+            Self::Stake(ix) => ix.write(&mut out)?,
+            Self::Vote(ix) => ix.write(&mut out)?,
+            Self::Token(ix) | Self::Token2022(ix) => ix.write(&mut out)?,
+        }
+        Ok(out.buf)
+    }
+}
+
+/// Stored form of the data of an instruction of `program`: typed when it
+/// round trips, `encode_ix_data` otherwise.
+pub fn encode_data<'d>(program: &[u8; 32], data: &'d [u8], index: &KeyIndex) -> Cow<'d, [u8]> {
+    if let Some(ix) = NativeIx::parse(program, data, index)
+        && ix.to_bytes(index.store()).is_ok_and(|b| b == data)
+        && let Ok(out) = postcard::to_extend(&ix, vec![IX_NATIVE])
+    {
+        return Cow::Owned(out);
+    }
+    encode_ix_data(data, index)
+}
+
+/// Original instruction data from its stored form.
+pub fn decode_data<'e>(enc: &'e [u8], store: &KeyStore) -> Result<Cow<'e, [u8]>> {
+    match native(enc)? {
+        Some(ix) => ix.to_bytes(store).map(Cow::Owned),
+        None => decode_ix_data(enc, store),
+    }
+}
+
+/// Typed view of stored instruction data, `None` when it was kept as bytes.
+pub fn native(enc: &[u8]) -> Result<Option<NativeIx>> {
+    match enc.split_first() {
+        Some((&IX_NATIVE, rest)) => postcard::from_bytes(rest)
+            .context("native instruction")
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Little endian reader over instruction data. Getters return `None` past the end.
+pub(crate) struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    #[inline]
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    #[inline]
+    pub(crate) fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    #[inline]
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    #[inline]
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    #[inline]
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    #[inline]
+    pub(crate) fn i64(&mut self) -> Option<i64> {
+        Some(i64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    #[inline]
+    pub(crate) fn hash(&mut self) -> Option<[u8; 32]> {
+        self.take(32)?.try_into().ok()
+    }
+
+    #[inline]
+    pub(crate) fn pubkey(&mut self, index: &KeyIndex) -> Option<CompactPubkey> {
+        Some(index.compact(&self.hash()?))
+    }
+
+    /// LEB128, as `serde_varint` and `short_vec` write it
+    pub(crate) fn varint(&mut self) -> Option<u64> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            v |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Some(v);
+            }
+        }
+        None
+    }
+
+    /// bincode `String`: u64 length + utf8
+    pub(crate) fn string(&mut self) -> Option<String> {
+        let n = usize::try_from(self.u64()?).ok()?;
+        String::from_utf8(self.take(n)?.to_vec()).ok()
+    }
+
+    /// bincode `Option<T>`: u8 0/1 + value
+    pub(crate) fn option<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Option<T>,
+    ) -> Option<Option<T>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => f(self).map(Some),
+            _ => None,
+        }
+    }
+}
+
+/// Writes what `Cursor` reads, resolving pubkeys against the registry.
+pub(crate) struct Out<'s> {
+    pub(crate) buf: Vec<u8>,
+    store: &'s KeyStore,
+}
+
+impl<'s> Out<'s> {
+    #[inline]
+    fn new(store: &'s KeyStore) -> Self {
+        Self {
+            buf: Vec::new(),
+            store,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    #[inline]
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    #[inline]
+    pub(crate) fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    #[inline]
+    pub(crate) fn i64(&mut self, v: i64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    #[inline]
+    pub(crate) fn bytes(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    #[inline]
+    pub(crate) fn pubkey(&mut self, k: &CompactPubkey) -> Result<()> {
+        let k = k
+            .resolve(self.store)
+            .with_context(|| format!("pubkey {:?} not in registry", k))?;
+        self.buf.extend_from_slice(k);
+        Ok(())
+    }
+
+    pub(crate) fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.u8(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.u8(v as u8);
+    }
+
+    #[inline]
+    pub(crate) fn string(&mut self, s: &str) {
+        self.u64(s.len() as u64);
+        self.bytes(s.as_bytes());
+    }
+
+    /// bincode `Option` tag of `v`; the value, if any, is the caller's to write.
+    #[inline]
+    pub(crate) fn option<'v, T>(&mut self, v: &'v Option<T>) -> Option<&'v T> {
+        self.u8(v.is_some() as u8);
+        v.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn index() -> KeyIndex {
+        let keys = (1..=4u8).map(|i| [i; 32]).collect();
+        KeyIndex::build(Arc::new(KeyStore { keys }))
+    }
+
+    fn round_trip(program: &[u8; 32], data: &[u8], index: &KeyIndex) -> Option<NativeIx> {
+        let enc = encode_data(program, data, index);
+        assert_eq!(&*decode_data(&enc, index.store()).unwrap(), data);
+        native(&enc).unwrap()
+    }
+
+    #[test]
+    fn native_instructions_round_trip() {
+        let index = index();
+        let known = [2u8; 32];
+        let unknown = [9u8; 32];
+
+        // System transfer of 5 SOL
+        let mut data = 2u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&5_000_000_000u64.to_le_bytes());
+        assert_eq!(
+            round_trip(system::ID.as_array(), &data, &index),
+            Some(NativeIx::System(system::SystemIx::Transfer {
+                lamports: 5_000_000_000
+            }))
+        );
+
+        // CreateAccountWithSeed, base in the registry, owner outside
+        let mut data = 3u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&known);
+        data.extend_from_slice(&4u64.to_le_bytes());
+        data.extend_from_slice(b"seed");
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&165u64.to_le_bytes());
+        data.extend_from_slice(&unknown);
+        assert_eq!(
+            round_trip(system::ID.as_array(), &data, &index),
+            Some(NativeIx::System(system::SystemIx::CreateAccountWithSeed {
+                base: CompactPubkey::Id(2),
+                seed: "seed".to_string(),
+                lamports: 1,
+                space: 165,
+                owner: CompactPubkey::Raw(unknown),
+            }))
+        );
+
+        // Trailing bytes are accepted on chain but don't re-encode: kept raw.
+        let mut data = 2u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 0xff]);
+        assert_eq!(round_trip(system::ID.as_array(), &data, &index), None);
+
+        // Compute budget price
+        let mut data = vec![3u8];
+        data.extend_from_slice(&12_345u64.to_le_bytes());
+        assert_eq!(
+            round_trip(compute_budget::ID.as_array(), &data, &index),
+            Some(NativeIx::ComputeBudget(
+                compute_budget::ComputeBudgetIx::SetComputeUnitPrice {
+                    micro_lamports: 12_345
+                }
+            ))
+        );
+
+        // Token TransferChecked, Token-2022 SetAuthority to a registry key
+        let mut data = vec![12u8];
+        data.extend_from_slice(&1_000u64.to_le_bytes());
+        data.push(6);
+        assert_eq!(
+            round_trip(token::ID.as_array(), &data, &index),
+            Some(NativeIx::Token(token::TokenIx::TransferChecked {
+                amount: 1_000,
+                decimals: 6,
+            }))
+        );
+        let mut data = vec![6u8, 2, 1];
+        data.extend_from_slice(&known);
+        assert_eq!(
+            round_trip(token::ID_2022.as_array(), &data, &index),
+            Some(NativeIx::Token2022(token::TokenIx::SetAuthority {
+                authority_type: 2,
+                new_authority: Some(CompactPubkey::Id(2)),
+            }))
+        );
+
+        // Stake split, vote tower sync
+        let mut data = 3u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&42u64.to_le_bytes());
+        assert_eq!(
+            round_trip(stake::ID.as_array(), &data, &index),
+            Some(NativeIx::Stake(stake::StakeIx::Split { lamports: 42 }))
+        );
+
+        let mut data = 14u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&100u64.to_le_bytes()); // root
+        data.extend_from_slice(&[2, 1, 31, 0x81, 0x01, 30]); // 2 lockouts
+        data.extend_from_slice(&[7; 32]); // hash
+        data.extend_from_slice(&[1, 5, 0, 0, 0, 0, 0, 0, 0]); // timestamp
+        data.extend_from_slice(&[8; 32]); // block id
+        assert_eq!(
+            round_trip(vote::ID.as_array(), &data, &index),
+            Some(NativeIx::Vote(vote::VoteIx::TowerSync {
+                tower: vote::CompactTower {
+                    root: 100,
+                    lockout_offsets: vec![(1, 31), (129, 30)],
+                    hash: [7; 32],
+                    timestamp: Some(5),
+                },
+                block_id: [8; 32],
+            }))
+        );
+
+        // Other programs keep their bytes.
+        assert_eq!(round_trip(&unknown, &[3, 0, 0, 0], &index), None);
+    }
+}
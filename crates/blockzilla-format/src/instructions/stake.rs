@@ -0,0 +1,225 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_pubkey::{Pubkey, pubkey};
+
+use super::{Cursor, Out};
+use crate::{CompactPubkey, KeyIndex};
+
+pub const ID: Pubkey = pubkey!("Stake11111111111111111111111111111111111111");
+
+/// `StakeInstruction`, bincode with a u32 tag. `stake_authorize` is the
+/// `StakeAuthorize` tag (0 staker, 1 withdrawer).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StakeIx {
+    Initialize {
+        staker: CompactPubkey,
+        withdrawer: CompactPubkey,
+        unix_timestamp: i64,
+        epoch: u64,
+        custodian: CompactPubkey,
+    },
+    Authorize {
+        new_authority: CompactPubkey,
+        stake_authorize: u32,
+    },
+    DelegateStake,
+    Split {
+        lamports: u64,
+    },
+    Withdraw {
+        lamports: u64,
+    },
+    Deactivate,
+    SetLockup {
+        unix_timestamp: Option<i64>,
+        epoch: Option<u64>,
+        custodian: Option<CompactPubkey>,
+    },
+    Merge,
+    AuthorizeWithSeed {
+        new_authority: CompactPubkey,
+        stake_authorize: u32,
+        authority_seed: String,
+        authority_owner: CompactPubkey,
+    },
+    InitializeChecked,
+    AuthorizeChecked {
+        stake_authorize: u32,
+    },
+    AuthorizeCheckedWithSeed {
+        stake_authorize: u32,
+        authority_seed: String,
+        authority_owner: CompactPubkey,
+    },
+    SetLockupChecked {
+        unix_timestamp: Option<i64>,
+        epoch: Option<u64>,
+    },
+    GetMinimumDelegation,
+    DeactivateDelinquent,
+    Redelegate,
+    MoveStake {
+        lamports: u64,
+    },
+    MoveLamports {
+        lamports: u64,
+    },
+}
+
+impl StakeIx {
+    pub(crate) fn parse(c: &mut Cursor, index: &KeyIndex) -> Option<Self> {
+        Some(match c.u32()? {
+            0 => Self::Initialize {
+                staker: c.pubkey(index)?,
+                withdrawer: c.pubkey(index)?,
+                unix_timestamp: c.i64()?,
+                epoch: c.u64()?,
+                custodian: c.pubkey(index)?,
+            },
+            1 => Self::Authorize {
+                new_authority: c.pubkey(index)?,
+                stake_authorize: c.u32()?,
+            },
+            2 => Self::DelegateStake,
+            3 => Self::Split { lamports: c.u64()? },
+            4 => Self::Withdraw { lamports: c.u64()? },
+            5 => Self::Deactivate,
+            6 => Self::SetLockup {
+                unix_timestamp: c.option(Cursor::i64)?,
+                epoch: c.option(Cursor::u64)?,
+                custodian: c.option(|c| c.pubkey(index))?,
+            },
+            7 => Self::Merge,
+            8 => Self::AuthorizeWithSeed {
+                new_authority: c.pubkey(index)?,
+                stake_authorize: c.u32()?,
+                authority_seed: c.string()?,
+                authority_owner: c.pubkey(index)?,
+            },
+            9 => Self::InitializeChecked,
+            10 => Self::AuthorizeChecked {
+                stake_authorize: c.u32()?,
+            },
+            11 => Self::AuthorizeCheckedWithSeed {
+                stake_authorize: c.u32()?,
+                authority_seed: c.string()?,
+                authority_owner: c.pubkey(index)?,
+            },
+            12 => Self::SetLockupChecked {
+                unix_timestamp: c.option(Cursor::i64)?,
+                epoch: c.option(Cursor::u64)?,
+            },
+            13 => Self::GetMinimumDelegation,
+            14 => Self::DeactivateDelinquent,
+            15 => Self::Redelegate,
+            16 => Self::MoveStake { lamports: c.u64()? },
+            17 => Self::MoveLamports { lamports: c.u64()? },
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn write(&self, o: &mut Out) -> Result<()> {
+        match self {
+            Self::Initialize {
+                staker,
+                withdrawer,
+                unix_timestamp,
+                epoch,
+                custodian,
+            } => {
+                o.u32(0);
+                o.pubkey(staker)?;
+                o.pubkey(withdrawer)?;
+                o.i64(*unix_timestamp);
+                o.u64(*epoch);
+                o.pubkey(custodian)?;
+            }
+            Self::Authorize {
+                new_authority,
+                stake_authorize,
+            } => {
+                o.u32(1);
+                o.pubkey(new_authority)?;
+                o.u32(*stake_authorize);
+            }
+            Self::DelegateStake => o.u32(2),
+            Self::Split { lamports } => {
+                o.u32(3);
+                o.u64(*lamports);
+            }
+            Self::Withdraw { lamports } => {
+                o.u32(4);
+                o.u64(*lamports);
+            }
+            Self::Deactivate => o.u32(5),
+            Self::SetLockup {
+                unix_timestamp,
+                epoch,
+                custodian,
+            } => {
+                o.u32(6);
+                if let Some(v) = o.option(unix_timestamp) {
+                    o.i64(*v);
+                }
+                if let Some(v) = o.option(epoch) {
+                    o.u64(*v);
+                }
+                if let Some(k) = o.option(custodian) {
+                    o.pubkey(k)?;
+                }
+            }
+            Self::Merge => o.u32(7),
+            Self::AuthorizeWithSeed {
+                new_authority,
+                stake_authorize,
+                authority_seed,
+                authority_owner,
+            } => {
+                o.u32(8);
+                o.pubkey(new_authority)?;
+                o.u32(*stake_authorize);
+                o.string(authority_seed);
+                o.pubkey(authority_owner)?;
+            }
+            Self::InitializeChecked => o.u32(9),
+            Self::AuthorizeChecked { stake_authorize } => {
+                o.u32(10);
+                o.u32(*stake_authorize);
+            }
+            Self::AuthorizeCheckedWithSeed {
+                stake_authorize,
+                authority_seed,
+                authority_owner,
+            } => {
+                o.u32(11);
+                o.u32(*stake_authorize);
+                o.string(authority_seed);
+                o.pubkey(authority_owner)?;
+            }
+            Self::SetLockupChecked {
+                unix_timestamp,
+                epoch,
+            } => {
+                o.u32(12);
+                if let Some(v) = o.option(unix_timestamp) {
+                    o.i64(*v);
+                }
+                if let Some(v) = o.option(epoch) {
+                    o.u64(*v);
+                }
+            }
+            Self::GetMinimumDelegation => o.u32(13),
+            Self::DeactivateDelinquent => o.u32(14),
+            Self::Redelegate => o.u32(15),
+            Self::MoveStake { lamports } => {
+                o.u32(16);
+                o.u64(*lamports);
+            }
+            Self::MoveLamports { lamports } => {
+                o.u32(17);
+                o.u64(*lamports);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_pubkey::{Pubkey, pubkey};
+
+use super::{Cursor, Out};
+use crate::{CompactPubkey, KeyIndex};
+
+pub const ID: Pubkey = pubkey!("11111111111111111111111111111111");
+
+/// `SystemInstruction`, bincode with a u32 tag.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SystemIx {
+    CreateAccount {
+        lamports: u64,
+        space: u64,
+        owner: CompactPubkey,
+    },
+    Assign {
+        owner: CompactPubkey,
+    },
+    Transfer {
+        lamports: u64,
+    },
+    CreateAccountWithSeed {
+        base: CompactPubkey,
+        seed: String,
+        lamports: u64,
+        space: u64,
+        owner: CompactPubkey,
+    },
+    AdvanceNonceAccount,
+    WithdrawNonceAccount {
+        lamports: u64,
+    },
+    InitializeNonceAccount {
+        authority: CompactPubkey,
+    },
+    AuthorizeNonceAccount {
+        authority: CompactPubkey,
+    },
+    Allocate {
+        space: u64,
+    },
+    AllocateWithSeed {
+        base: CompactPubkey,
+        seed: String,
+        space: u64,
+        owner: CompactPubkey,
+    },
+    AssignWithSeed {
+        base: CompactPubkey,
+        seed: String,
+        owner: CompactPubkey,
+    },
+    TransferWithSeed {
+        lamports: u64,
+        from_seed: String,
+        from_owner: CompactPubkey,
+    },
+    UpgradeNonceAccount,
+}
+
+impl SystemIx {
+    pub(crate) fn parse(c: &mut Cursor, index: &KeyIndex) -> Option<Self> {
+        Some(match c.u32()? {
+            0 => Self::CreateAccount {
+                lamports: c.u64()?,
+                space: c.u64()?,
+                owner: c.pubkey(index)?,
+            },
+            1 => Self::Assign {
+                owner: c.pubkey(index)?,
+            },
+            2 => Self::Transfer { lamports: c.u64()? },
+            3 => Self::CreateAccountWithSeed {
+                base: c.pubkey(index)?,
+                seed: c.string()?,
+                lamports: c.u64()?,
+                space: c.u64()?,
+                owner: c.pubkey(index)?,
+            },
+            4 => Self::AdvanceNonceAccount,
+            5 => Self::WithdrawNonceAccount { lamports: c.u64()? },
+            6 => Self::InitializeNonceAccount {
+                authority: c.pubkey(index)?,
+            },
+            7 => Self::AuthorizeNonceAccount {
+                authority: c.pubkey(index)?,
+            },
+            8 => Self::Allocate { space: c.u64()? },
+            9 => Self::AllocateWithSeed {
+                base: c.pubkey(index)?,
+                seed: c.string()?,
+                space: c.u64()?,
+                owner: c.pubkey(index)?,
+            },
+            10 => Self::AssignWithSeed {
+                base: c.pubkey(index)?,
+                seed: c.string()?,
+                owner: c.pubkey(index)?,
+            },
+            11 => Self::TransferWithSeed {
+                lamports: c.u64()?,
+                from_seed: c.string()?,
+                from_owner: c.pubkey(index)?,
+            },
+            12 => Self::UpgradeNonceAccount,
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn write(&self, o: &mut Out) -> Result<()> {
+        match self {
+            Self::CreateAccount {
+                lamports,
+                space,
+                owner,
+            } => {
+                o.u32(0);
+                o.u64(*lamports);
+                o.u64(*space);
+                o.pubkey(owner)?;
+            }
+            Self::Assign { owner } => {
+                o.u32(1);
+                o.pubkey(owner)?;
+            }
+            Self::Transfer { lamports } => {
+                o.u32(2);
+                o.u64(*lamports);
+            }
+            Self::CreateAccountWithSeed {
+                base,
+                seed,
+                lamports,
+                space,
+                owner,
+            } => {
+                o.u32(3);
+                o.pubkey(base)?;
+                o.string(seed);
+                o.u64(*lamports);
+                o.u64(*space);
+                o.pubkey(owner)?;
+            }
+            Self::AdvanceNonceAccount => o.u32(4),
+            Self::WithdrawNonceAccount { lamports } => {
+                o.u32(5);
+                o.u64(*lamports);
+            }
+            Self::InitializeNonceAccount { authority } => {
+                o.u32(6);
+                o.pubkey(authority)?;
+            }
+            Self::AuthorizeNonceAccount { authority } => {
+                o.u32(7);
+                o.pubkey(authority)?;
+            }
+            Self::Allocate { space } => {
+                o.u32(8);
+                o.u64(*space);
+            }
+            Self::AllocateWithSeed {
+                base,
+                seed,
+                space,
+                owner,
+            } => {
+                o.u32(9);
+                o.pubkey(base)?;
+                o.string(seed);
+                o.u64(*space);
+                o.pubkey(owner)?;
+            }
+            Self::AssignWithSeed { base, seed, owner } => {
+                o.u32(10);
+                o.pubkey(base)?;
+                o.string(seed);
+                o.pubkey(owner)?;
+            }
+            Self::TransferWithSeed {
+                lamports,
+                from_seed,
+                from_owner,
+            } => {
+                o.u32(11);
+                o.u64(*lamports);
+                o.string(from_seed);
+                o.pubkey(from_owner)?;
+            }
+            Self::UpgradeNonceAccount => o.u32(12),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_pubkey::{Pubkey, pubkey};
+
+use super::{Cursor, Out};
+use crate::{CompactPubkey, KeyIndex};
+
+pub const ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const ID_2022: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PhnBqCXEpPxuEb");
+
+/// `TokenInstruction` of SPL Token, shared by Token-2022 for tags 0..=23.
+/// Packed with a u8 tag; `COption<Pubkey>` is a u8 0/1 + the key.
+/// Token-2022 extension instructions stay raw bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenIx {
+    InitializeMint {
+        decimals: u8,
+        mint_authority: CompactPubkey,
+        freeze_authority: Option<CompactPubkey>,
+    },
+    InitializeAccount,
+    InitializeMultisig {
+        m: u8,
+    },
+    Transfer {
+        amount: u64,
+    },
+    Approve {
+        amount: u64,
+    },
+    Revoke,
+    SetAuthority {
+        authority_type: u8,
+        new_authority: Option<CompactPubkey>,
+    },
+    MintTo {
+        amount: u64,
+    },
+    Burn {
+        amount: u64,
+    },
+    CloseAccount,
+    FreezeAccount,
+    ThawAccount,
+    TransferChecked {
+        amount: u64,
+        decimals: u8,
+    },
+    ApproveChecked {
+        amount: u64,
+        decimals: u8,
+    },
+    MintToChecked {
+        amount: u64,
+        decimals: u8,
+    },
+    BurnChecked {
+        amount: u64,
+        decimals: u8,
+    },
+    InitializeAccount2 {
+        owner: CompactPubkey,
+    },
+    SyncNative,
+    InitializeAccount3 {
+        owner: CompactPubkey,
+    },
+    InitializeMultisig2 {
+        m: u8,
+    },
+    InitializeMint2 {
+        decimals: u8,
+        mint_authority: CompactPubkey,
+        freeze_authority: Option<CompactPubkey>,
+    },
+    GetAccountDataSize,
+    InitializeImmutableOwner,
+    AmountToUiAmount {
+        amount: u64,
+    },
+}
+
+impl TokenIx {
+    pub(crate) fn parse(c: &mut Cursor, index: &KeyIndex) -> Option<Self> {
+        Some(match c.u8()? {
+            0 => Self::InitializeMint {
+                decimals: c.u8()?,
+                mint_authority: c.pubkey(index)?,
+                freeze_authority: c.option(|c| c.pubkey(index))?,
+            },
+            1 => Self::InitializeAccount,
+            2 => Self::InitializeMultisig { m: c.u8()? },
+            3 => Self::Transfer { amount: c.u64()? },
+            4 => Self::Approve { amount: c.u64()? },
+            5 => Self::Revoke,
+            6 => Self::SetAuthority {
+                authority_type: c.u8()?,
+                new_authority: c.option(|c| c.pubkey(index))?,
+            },
+            7 => Self::MintTo { amount: c.u64()? },
+            8 => Self::Burn { amount: c.u64()? },
+            9 => Self::CloseAccount,
+            10 => Self::FreezeAccount,
+            11 => Self::ThawAccount,
+            12 => Self::TransferChecked {
+                amount: c.u64()?,
+                decimals: c.u8()?,
+            },
+            13 => Self::ApproveChecked {
+                amount: c.u64()?,
+                decimals: c.u8()?,
+            },
+            14 => Self::MintToChecked {
+                amount: c.u64()?,
+                decimals: c.u8()?,
+            },
+            15 => Self::BurnChecked {
+                amount: c.u64()?,
+                decimals: c.u8()?,
+            },
+            16 => Self::InitializeAccount2 {
+                owner: c.pubkey(index)?,
+            },
+            17 => Self::SyncNative,
+            18 => Self::InitializeAccount3 {
+                owner: c.pubkey(index)?,
+            },
+            19 => Self::InitializeMultisig2 { m: c.u8()? },
+            20 => Self::InitializeMint2 {
+                decimals: c.u8()?,
+                mint_authority: c.pubkey(index)?,
+                freeze_authority: c.option(|c| c.pubkey(index))?,
+            },
+            21 => Self::GetAccountDataSize,
+            22 => Self::InitializeImmutableOwner,
+            23 => Self::AmountToUiAmount { amount: c.u64()? },
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn write(&self, o: &mut Out) -> Result<()> {
+        match self {
+            Self::InitializeMint {
+                decimals,
+                mint_authority,
+                freeze_authority,
+            } => {
+                o.u8(0);
+                o.u8(*decimals);
+                o.pubkey(mint_authority)?;
+                if let Some(k) = o.option(freeze_authority) {
+                    o.pubkey(k)?;
+                }
+            }
+            Self::InitializeAccount => o.u8(1),
+            Self::InitializeMultisig { m } => {
+                o.u8(2);
+                o.u8(*m);
+            }
+            Self::Transfer { amount } => {
+                o.u8(3);
+                o.u64(*amount);
+            }
+            Self::Approve { amount } => {
+                o.u8(4);
+                o.u64(*amount);
+            }
+            Self::Revoke => o.u8(5),
+            Self::SetAuthority {
+                authority_type,
+                new_authority,
+            } => {
+                o.u8(6);
+                o.u8(*authority_type);
+                if let Some(k) = o.option(new_authority) {
+                    o.pubkey(k)?;
+                }
+            }
+            Self::MintTo { amount } => {
+                o.u8(7);
+                o.u64(*amount);
+            }
+            Self::Burn { amount } => {
+                o.u8(8);
+                o.u64(*amount);
+            }
+            Self::CloseAccount => o.u8(9),
+            Self::FreezeAccount => o.u8(10),
+            Self::ThawAccount => o.u8(11),
+            Self::TransferChecked { amount, decimals } => {
+                o.u8(12);
+                o.u64(*amount);
+                o.u8(*decimals);
+            }
+            Self::ApproveChecked { amount, decimals } => {
+                o.u8(13);
+                o.u64(*amount);
+                o.u8(*decimals);
+            }
+            Self::MintToChecked { amount, decimals } => {
+                o.u8(14);
+                o.u64(*amount);
+                o.u8(*decimals);
+            }
+            Self::BurnChecked { amount, decimals } => {
+                o.u8(15);
+                o.u64(*amount);
+                o.u8(*decimals);
+            }
+            Self::InitializeAccount2 { owner } => {
+                o.u8(16);
+                o.pubkey(owner)?;
+            }
+            Self::SyncNative => o.u8(17),
+            Self::InitializeAccount3 { owner } => {
+                o.u8(18);
+                o.pubkey(owner)?;
+            }
+            Self::InitializeMultisig2 { m } => {
+                o.u8(19);
+                o.u8(*m);
+            }
+            Self::InitializeMint2 {
+                decimals,
+                mint_authority,
+                freeze_authority,
+            } => {
+                o.u8(20);
+                o.u8(*decimals);
+                o.pubkey(mint_authority)?;
+                if let Some(k) = o.option(freeze_authority) {
+                    o.pubkey(k)?;
+                }
+            }
+            Self::GetAccountDataSize => o.u8(21),
+            Self::InitializeImmutableOwner => o.u8(22),
+            Self::AmountToUiAmount { amount } => {
+                o.u8(23);
+                o.u64(*amount);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_pubkey::{Pubkey, pubkey};
+
+use super::{Cursor, Out};
+use crate::{CompactPubkey, KeyIndex};
+
+pub const ID: Pubkey = pubkey!("Vote111111111111111111111111111111111111111");
+
+/// `Vote`: bincode, u64 length slots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vote {
+    pub slots: Vec<u64>,
+    pub hash: [u8; 32],
+    pub timestamp: Option<i64>,
+}
+
+/// `VoteStateUpdate` in its bincode form: (slot, confirmation count) lockouts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteStateUpdate {
+    pub lockouts: Vec<(u64, u32)>,
+    pub root: Option<u64>,
+    pub hash: [u8; 32],
+    pub timestamp: Option<i64>,
+}
+
+/// Compact `VoteStateUpdate` / `TowerSync` body: `root` is `u64::MAX` for
+/// none, lockouts are (slot offset from the previous one, confirmation count).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactTower {
+    pub root: u64,
+    pub lockout_offsets: Vec<(u64, u8)>,
+    pub hash: [u8; 32],
+    pub timestamp: Option<i64>,
+}
+
+/// `VoteInstruction`, bincode with a u32 tag. `authorize` is the
+/// `VoteAuthorize` tag (0 voter, 1 withdrawer).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VoteIx {
+    InitializeAccount {
+        node: CompactPubkey,
+        authorized_voter: CompactPubkey,
+        authorized_withdrawer: CompactPubkey,
+        commission: u8,
+    },
+    Authorize {
+        new_authority: CompactPubkey,
+        authorize: u32,
+    },
+    Vote(Vote),
+    Withdraw {
+        lamports: u64,
+    },
+    UpdateValidatorIdentity,
+    UpdateCommission {
+        commission: u8,
+    },
+    VoteSwitch(Vote, [u8; 32]),
+    AuthorizeChecked {
+        authorize: u32,
+    },
+    UpdateVoteState(VoteStateUpdate),
+    UpdateVoteStateSwitch(VoteStateUpdate, [u8; 32]),
+    AuthorizeWithSeed {
+        authorize: u32,
+        owner: CompactPubkey,
+        seed: String,
+        new_authority: CompactPubkey,
+    },
+    AuthorizeCheckedWithSeed {
+        authorize: u32,
+        owner: CompactPubkey,
+        seed: String,
+    },
+    CompactUpdateVoteState(CompactTower),
+    CompactUpdateVoteStateSwitch(CompactTower, [u8; 32]),
+    TowerSync {
+        tower: CompactTower,
+        block_id: [u8; 32],
+    },
+    TowerSyncSwitch {
+        tower: CompactTower,
+        block_id: [u8; 32],
+        switch_hash: [u8; 32],
+    },
+}
+
+impl Vote {
+    fn parse(c: &mut Cursor) -> Option<Self> {
+        let n = c.u64()?;
+        let slots = (0..n).map(|_| c.u64()).collect::<Option<_>>()?;
+        Some(Self {
+            slots,
+            hash: c.hash()?,
+            timestamp: c.option(Cursor::i64)?,
+        })
+    }
+
+    fn write(&self, o: &mut Out) {
+        o.u64(self.slots.len() as u64);
+        for s in &self.slots {
+            o.u64(*s);
+        }
+        o.bytes(&self.hash);
+        if let Some(t) = o.option(&self.timestamp) {
+            o.i64(*t);
+        }
+    }
+}
+
+impl VoteStateUpdate {
+    fn parse(c: &mut Cursor) -> Option<Self> {
+        let n = c.u64()?;
+        let lockouts = (0..n)
+            .map(|_| Some((c.u64()?, c.u32()?)))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            lockouts,
+            root: c.option(Cursor::u64)?,
+            hash: c.hash()?,
+            timestamp: c.option(Cursor::i64)?,
+        })
+    }
+
+    fn write(&self, o: &mut Out) {
+        o.u64(self.lockouts.len() as u64);
+        for &(slot, count) in &self.lockouts {
+            o.u64(slot);
+            o.u32(count);
+        }
+        if let Some(r) = o.option(&self.root) {
+            o.u64(*r);
+        }
+        o.bytes(&self.hash);
+        if let Some(t) = o.option(&self.timestamp) {
+            o.i64(*t);
+        }
+    }
+}
+
+impl CompactTower {
+    fn parse(c: &mut Cursor) -> Option<Self> {
+        let root = c.u64()?;
+        let n = u16::try_from(c.varint()?).ok()?;
+        let lockout_offsets = (0..n)
+            .map(|_| Some((c.varint()?, c.u8()?)))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            root,
+            lockout_offsets,
+            hash: c.hash()?,
+            timestamp: c.option(Cursor::i64)?,
+        })
+    }
+
+    fn write(&self, o: &mut Out) {
+        o.u64(self.root);
+        o.varint(self.lockout_offsets.len() as u64);
+        for &(offset, count) in &self.lockout_offsets {
+            o.varint(offset);
+            o.u8(count);
+        }
+        o.bytes(&self.hash);
+        if let Some(t) = o.option(&self.timestamp) {
+            o.i64(*t);
+        }
+    }
+}
+
+impl VoteIx {
+    pub(crate) fn parse(c: &mut Cursor, index: &KeyIndex) -> Option<Self> {
+        Some(match c.u32()? {
+            0 => Self::InitializeAccount {
+                node: c.pubkey(index)?,
+                authorized_voter: c.pubkey(index)?,
+                authorized_withdrawer: c.pubkey(index)?,
+                commission: c.u8()?,
+            },
+            1 => Self::Authorize {
+                new_authority: c.pubkey(index)?,
+                authorize: c.u32()?,
+            },
+            2 => Self::Vote(Vote::parse(c)?),
+            3 => Self::Withdraw { lamports: c.u64()? },
+            4 => Self::UpdateValidatorIdentity,
+            5 => Self::UpdateCommission {
+                commission: c.u8()?,
+            },
+            6 => Self::VoteSwitch(Vote::parse(c)?, c.hash()?),
+            7 => Self::AuthorizeChecked {
+                authorize: c.u32()?,
+            },
+            8 => Self::UpdateVoteState(VoteStateUpdate::parse(c)?),
+            9 => Self::UpdateVoteStateSwitch(VoteStateUpdate::parse(c)?, c.hash()?),
+            10 => Self::AuthorizeWithSeed {
+                authorize: c.u32()?,
+                owner: c.pubkey(index)?,
+                seed: c.string()?,
+                new_authority: c.pubkey(index)?,
+            },
+            11 => Self::AuthorizeCheckedWithSeed {
+                authorize: c.u32()?,
+                owner: c.pubkey(index)?,
+                seed: c.string()?,
+            },
+            12 => Self::CompactUpdateVoteState(CompactTower::parse(c)?),
+            13 => Self::CompactUpdateVoteStateSwitch(CompactTower::parse(c)?, c.hash()?),
+            14 => Self::TowerSync {
+                tower: CompactTower::parse(c)?,
+                block_id: c.hash()?,
+            },
+            15 => Self::TowerSyncSwitch {
+                tower: CompactTower::parse(c)?,
+                block_id: c.hash()?,
+                switch_hash: c.hash()?,
+            },
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn write(&self, o: &mut Out) -> Result<()> {
+        match self {
+            Self::InitializeAccount {
+                node,
+                authorized_voter,
+                authorized_withdrawer,
+                commission,
+            } => {
+                o.u32(0);
+                o.pubkey(node)?;
+                o.pubkey(authorized_voter)?;
+                o.pubkey(authorized_withdrawer)?;
+                o.u8(*commission);
+            }
+            Self::Authorize {
+                new_authority,
+                authorize,
+            } => {
+                o.u32(1);
+                o.pubkey(new_authority)?;
+                o.u32(*authorize);
+            }
+            Self::Vote(v) => {
+                o.u32(2);
+                v.write(o);
+            }
+            Self::Withdraw { lamports } => {
+                o.u32(3);
+                o.u64(*lamports);
+            }
+            Self::UpdateValidatorIdentity => o.u32(4),
+            Self::UpdateCommission { commission } => {
+                o.u32(5);
+                o.u8(*commission);
+            }
+            Self::VoteSwitch(v, switch_hash) => {
+                o.u32(6);
+                v.write(o);
+                o.bytes(switch_hash);
+            }
+            Self::AuthorizeChecked { authorize } => {
+                o.u32(7);
+                o.u32(*authorize);
+            }
+            Self::UpdateVoteState(u) => {
+                o.u32(8);
+                u.write(o);
+            }
+            Self::UpdateVoteStateSwitch(u, switch_hash) => {
+                o.u32(9);
+                u.write(o);
+                o.bytes(switch_hash);
+            }
+            Self::AuthorizeWithSeed {
+                authorize,
+                owner,
+                seed,
+                new_authority,
+            } => {
+                o.u32(10);
+                o.u32(*authorize);
+                o.pubkey(owner)?;
+                o.string(seed);
+                o.pubkey(new_authority)?;
+            }
+            Self::AuthorizeCheckedWithSeed {
+                authorize,
+                owner,
+                seed,
+            } => {
+                o.u32(11);
+                o.u32(*authorize);
+                o.pubkey(owner)?;
+                o.string(seed);
+            }
+            Self::CompactUpdateVoteState(t) => {
+                o.u32(12);
+                t.write(o);
+            }
+            Self::CompactUpdateVoteStateSwitch(t, switch_hash) => {
+                o.u32(13);
+                t.write(o);
+                o.bytes(switch_hash);
+            }
+            Self::TowerSync { tower, block_id } => {
+                o.u32(14);
+                tower.write(o);
+                o.bytes(block_id);
+            }
+            Self::TowerSyncSwitch {
+                tower,
+                block_id,
+                switch_hash,
+            } => {
+                o.u32(15);
+                tower.write(o);
+                o.bytes(block_id);
+                o.bytes(switch_hash);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+//! Registry keys embedded in log text and instruction data, stored as ids
+//! (format v5).
+
+use anyhow::{Context, Result};
+use solana_pubkey::Pubkey;
+use std::borrow::Cow;
+
+use crate::{BlockLogTables, KeyIndex, KeyStore, StringTable, read_u32_varint, write_u32_varint};
+
+/// Brackets a decimal registry id in log text. Two in a row are a literal one.
+pub const KEY_REF: char = '\u{FFFF}';
+
+/// Instruction data tags (`instructions` uses 0xff for
+/// typed data). Data starting with any other byte is stored as is.
+const IX_ESCAPED: u8 = 0xfd;
+const IX_KEYED: u8 = 0xfe;
+/// Lowest reserved first byte: plain data starting at or above it is escaped.
+const IX_TAG_MIN: u8 = IX_ESCAPED;
+
+/// Registry id of a base58 word, if it prints back the same.
+#[inline]
+fn text_key_id(word: &str, index: &KeyIndex) -> Option<u32> {
+    if !(32..=44).contains(&word.len()) {
+        return None;
+    }
+    let id = index.lookup_str(word)?;
+    let k = index.store().get(id)?;
+    (Pubkey::new_from_array(*k).to_string() == word).then_some(id)
+}
+
+#[inline]
+fn push_escaped(out: &mut String, s: &str) {
+    for (i, part) in s.split(KEY_REF).enumerate() {
+        if i > 0 {
+            out.push(KEY_REF);
+            out.push(KEY_REF);
+        }
+        out.push_str(part);
+    }
+}
+
+/// `s` with every base58 registry key (a whole ascii alphanumeric word)
+/// turned into a key ref; `None` when it would come out unchanged.
+pub fn encode_text(s: &str, index: &KeyIndex) -> Option<String> {
+    let b = s.as_bytes();
+    let mut out = String::new();
+    let (mut copied, mut i) = (0, 0);
+    while i < b.len() {
+        if !b[i].is_ascii_alphanumeric() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < b.len() && b[i].is_ascii_alphanumeric() {
+            i += 1;
+        }
+        if let Some(id) = text_key_id(&s[start..i], index) {
+            push_escaped(&mut out, &s[copied..start]);
+            out.push(KEY_REF);
+            out.push_str(&id.to_string());
+            out.push(KEY_REF);
+            copied = i;
+        }
+    }
+    if copied == 0 && !s.contains(KEY_REF) {
+        return None;
+    }
+    push_escaped(&mut out, &s[copied..]);
+    Some(out)
+}
+
+/// Inverse of [`encode_text`].
+pub fn decode_text<'s>(s: &'s str, store: &KeyStore) -> Result<Cow<'s, str>> {
+    if !s.contains(KEY_REF) {
+        return Ok(Cow::Borrowed(s));
+    }
+    let mut out = String::with_capacity(s.len() + 64);
+    let mut rest = s;
+    while let Some(p) = rest.find(KEY_REF) {
+        out.push_str(&rest[..p]);
+        let after = &rest[p + KEY_REF.len_utf8()..];
+        let end = after.find(KEY_REF).context("unterminated key ref")?;
+        if end == 0 {
+            out.push(KEY_REF);
+        } else {
+            let id: u32 = after[..end].parse().context("bad key ref")?;
+            let k = store
+                .get(id)
+                .with_context(|| format!("key ref {} not in registry", id))?;
+            out.push_str(&Pubkey::new_from_array(*k).to_string());
+        }
+        rest = &after[end + KEY_REF.len_utf8()..];
+    }
+    out.push_str(rest);
+    Ok(Cow::Owned(out))
+}
+
+/// Key refs for the block string table, once the block is parsed (the
+/// lossless render check runs on plain strings).
+pub fn encode_log_keys(tables: &mut BlockLogTables, index: &KeyIndex) {
+    for s in &mut tables.strings.strings {
+        if let Some(e) = encode_text(s, index) {
+            *s = e;
+        }
+    }
+}
+
+/// Tables with the key refs of their string table expanded.
+pub fn decode_log_keys<'t>(
+    tables: &'t BlockLogTables,
+    store: &KeyStore,
+) -> Result<Cow<'t, BlockLogTables>> {
+    if !tables.strings.strings.iter().any(|s| s.contains(KEY_REF)) {
+        return Ok(Cow::Borrowed(tables));
+    }
+    let strings = tables
+        .strings
+        .strings
+        .iter()
+        .map(|s| Ok(decode_text(s, store)?.into_owned()))
+        .collect::<Result<_>>()?;
+    Ok(Cow::Owned(BlockLogTables {
+        strings: StringTable { strings },
+        data: tables.data.clone(),
+    }))
+}
+
+/// Instruction data of outer and inner instructions:
+/// - data without registry keys that does not start with a reserved byte
+///   (0xfd and up), as is
+/// - `IX_ESCAPED`, then data without registry keys starting with a reserved byte
+/// - `IX_KEYED`, varint ref count, a (varint gap, varint id) pair per key
+///   found, then the data with those keys cut out
+///
+/// Every 32 byte window is looked up in the registry; plain data is
+/// borrowed, not copied.
+pub fn encode_ix_data<'d>(data: &'d [u8], index: &KeyIndex) -> Cow<'d, [u8]> {
+    let mut refs = Vec::new();
+    let mut i = 0;
+    while i + 32 <= data.len() {
+        let w: &[u8; 32] = data[i..i + 32].try_into().unwrap();
+        match index.lookup(w) {
+            Some(id) => {
+                refs.push((i, id));
+                i += 32;
+            }
+            None => i += 1,
+        }
+    }
+
+    if refs.is_empty() {
+        return match data.first() {
+            Some(&b) if b >= IX_TAG_MIN => {
+                let mut out = Vec::with_capacity(data.len() + 1);
+                out.push(IX_ESCAPED);
+                out.extend_from_slice(data);
+                Cow::Owned(out)
+            }
+            _ => Cow::Borrowed(data),
+        };
+    }
+    let mut out = Vec::with_capacity(data.len() + 1);
+    out.push(IX_KEYED);
+    // Writes to a Vec can't fail.
+    write_u32_varint(&mut out, refs.len() as u32).unwrap();
+    let mut end = 0;
+    for &(at, id) in &refs {
+        write_u32_varint(&mut out, (at - end) as u32).unwrap();
+        write_u32_varint(&mut out, id).unwrap();
+        end = at + 32;
+    }
+    let mut end = 0;
+    for &(at, _) in &refs {
+        out.extend_from_slice(&data[end..at]);
+        end = at + 32;
+    }
+    out.extend_from_slice(&data[end..]);
+    Cow::Owned(out)
+}
+
+/// Inverse of [`encode_ix_data`].
+pub fn decode_ix_data<'e>(enc: &'e [u8], store: &KeyStore) -> Result<Cow<'e, [u8]>> {
+    match enc.split_first() {
+        Some((&IX_ESCAPED, r)) => Ok(Cow::Borrowed(r)),
+        Some((&IX_KEYED, r)) => expand_refs(r, store).map(Cow::Owned),
+        Some((&t, _)) if t >= IX_TAG_MIN => anyhow::bail!("unknown instruction data tag {}", t),
+        _ => Ok(Cow::Borrowed(enc)),
+    }
+}
+
+/// Data with the keys listed in front of it put back.
+fn expand_refs(mut r: &[u8], store: &KeyStore) -> Result<Vec<u8>> {
+    let n = read_u32_varint(&mut r)?.context("key ref count")? as usize;
+    let mut refs = Vec::with_capacity(n);
+    for _ in 0..n {
+        let gap = read_u32_varint(&mut r)?.context("key ref gap")? as usize;
+        let id = read_u32_varint(&mut r)?.context("key ref id")?;
+        refs.push((gap, id));
+    }
+    let mut out = Vec::with_capacity(r.len() + 32 * n);
+    for (gap, id) in refs {
+        anyhow::ensure!(gap <= r.len(), "key ref past the end of the data");
+        let (head, tail) = r.split_at(gap);
+        out.extend_from_slice(head);
+        let k = store
+            .get(id)
+            .with_context(|| format!("key ref {} not in registry", id))?;
+        out.extend_from_slice(k);
+        r = tail;
+    }
+    out.extend_from_slice(r);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn keys_in_text_and_data_round_trip() {
+        let keys: Vec<[u8; 32]> = (1..=3u8).map(|i| [i; 32]).collect();
+        let store = Arc::new(KeyStore { keys: keys.clone() });
+        let index = KeyIndex::build(store.clone());
+        let pk = |i: usize| Pubkey::new_from_array(keys[i]).to_string();
+        let outsider = Pubkey::new_from_array([9; 32]).to_string();
+
+        for s in [
+            format!("Signed by {}", pk(0)),
+            format!("{}:{} -> {} {}", pk(1), pk(2), outsider, pk(1)),
+            format!("literal {} ref {}", KEY_REF, pk(0)),
+            format!("{}{}", KEY_REF, KEY_REF),
+            format!("glued{}", pk(0)),
+            "nothing here".to_string(),
+        ] {
+            let enc = encode_text(&s, &index);
+            let stored = enc.as_deref().unwrap_or(&s);
+            assert_eq!(decode_text(stored, &store).unwrap(), s);
+        }
+        let enc = encode_text(&format!("Signed by {}", pk(0)), &index).unwrap();
+        assert_eq!(enc, format!("Signed by {KEY_REF}1{KEY_REF}"));
+        assert!(encode_text(&format!("glued{}", pk(0)), &index).is_none());
+
+        let mut data = vec![7u8, 7];
+        data.extend_from_slice(&keys[2]);
+        data.extend_from_slice(&keys[0]);
+        data.extend_from_slice(&[9; 32]);
+        data.push(5);
+        let mut escaped = data.clone();
+        escaped[0] = 0xfe;
+        for d in [
+            data.clone(),
+            data[..40].to_vec(),
+            vec![0xfe, 1, 2],
+            escaped,
+            vec![],
+            vec![1, 2, 3],
+            vec![0xff],
+        ] {
+            let enc = encode_ix_data(&d, &index);
+            assert_eq!(decode_ix_data(&enc, &store).unwrap(), d);
+        }
+        // Both registry keys become ids, the outsider stays.
+        assert_eq!(encode_ix_data(&data, &index).len(), data.len() + 6 - 64);
+        assert!(matches!(
+            encode_ix_data(&[1, 2, 3], &index),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(&*encode_ix_data(&[0xfd], &index), &[0xfd, 0xfd]);
+    }
+}
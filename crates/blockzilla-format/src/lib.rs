@@ -5,11 +5,32 @@ pub mod writer;
 
 pub mod blockhash_registry;
 pub mod compact;
+pub mod compact_file;
+pub mod global_registry;
+pub mod instructions;
+pub mod key_refs;
 pub mod program_logs;
+pub mod rehydrate;
+pub mod sectioned;
+pub mod sig_index;
+pub mod slot_index;
+pub mod split;
+pub mod string_dict;
+pub mod votes;
 
 pub use blockhash_registry::BlockhashRegistry;
 pub use compact::*;
+pub use compact_file::*;
 pub use framed::*;
+pub use global_registry::*;
+pub use key_refs::*;
 pub use reader::*;
 pub use registry::*;
+pub use rehydrate::*;
+pub use sectioned::*;
+pub use sig_index::*;
+pub use slot_index::*;
+pub use split::*;
+pub use string_dict::*;
+pub use votes::*;
 pub use writer::*;
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::{StrId, StringTable};
+use crate::{LogInterner, LogStrings, StrId};
 
 /// TODO: confirm account-compression program id
 pub const STR_ID: &str = "AccountCompression11111111111111111111111111111";
@@ -30,7 +30,7 @@ pub enum AccountCompressionLog {
 
 impl AccountCompressionLog {
     #[inline]
-    pub fn parse(payload: &str, st: &mut StringTable) -> Option<Self> {
+    pub fn parse(payload: &str, st: &mut LogInterner) -> Option<Self> {
         if let Some((a, b)) =
             parse_two_braced(payload, "Canopy byte length ", " is not a multiple of ")
         {
@@ -56,7 +56,7 @@ impl AccountCompressionLog {
     }
 
     #[inline]
-    pub fn as_str(&self, st: &StringTable) -> String {
+    pub fn as_str(&self, st: &LogStrings<'_>) -> String {
         match self {
             Self::CanopyLengthMismatch {
                 canopy_bytes_len,
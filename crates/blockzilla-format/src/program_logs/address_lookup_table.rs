@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::{StrId, StringTable};
+use crate::{LogInterner, LogStrings, StrId};
 
 /// TODO: confirm program id
 pub const STR_ID: &str = "AddressLookupTab1e1111111111111111111111111";
@@ -89,7 +89,7 @@ impl AddressLookupTableErrorLog {
 
 impl AddressLookupTableLog {
     #[inline]
-    pub fn parse(payload: &str, st: &mut StringTable) -> Option<Self> {
+    pub fn parse(payload: &str, st: &mut LogInterner) -> Option<Self> {
         // errors first
         if let Some(e) = AddressLookupTableErrorLog::parse(payload) {
             return Some(Self::Error(e));
@@ -136,7 +136,7 @@ impl AddressLookupTableLog {
     }
 
     #[inline]
-    pub fn as_str(&self, st: &StringTable) -> String {
+    pub fn as_str(&self, st: &LogStrings<'_>) -> String {
         match self {
             Self::Error(e) => e.as_str().to_string(),
             Self::NotARecentSlot {
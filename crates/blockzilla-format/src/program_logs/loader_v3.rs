@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::{StrId, StringTable};
+use crate::{LogInterner, LogStrings, StrId};
 
 /// BPF Upgradeable Loader (commonly referred to as loader-v3)
 /// TODO: confirm id in your environment (Agave/Solana)
@@ -76,7 +76,7 @@ pub enum LoaderV3Log {
 
 impl LoaderV3Log {
     #[inline]
-    pub fn parse(payload: &str, st: &mut StringTable) -> Option<Self> {
+    pub fn parse(payload: &str, st: &mut LogInterner) -> Option<Self> {
         // "Write overflow: {} < {}"
         if let Some((a, b)) = parse_two_braced(payload, "Write overflow: ", " < ") {
             return Some(Self::WriteOverflow {
@@ -144,7 +144,7 @@ impl LoaderV3Log {
     }
 
     #[inline]
-    pub fn as_str(&self, st: &StringTable) -> String {
+    pub fn as_str(&self, st: &LogStrings<'_>) -> String {
         match self {
             Self::WriteOverflow {
                 buffer_data_len,
@@ -161,7 +161,7 @@ impl LoaderV3Log {
                 format!("Upgraded program: {}", st.resolve(*program_key))
             }
             Self::NewAuthorityDebug { new_authority } => {
-                format!("New authority: {:?}", st.resolve(*new_authority))
+                format!("New authority: {}", st.resolve(*new_authority))
             }
             Self::ClosedUninitialized { key } => {
                 format!("Closed Uninitialized {}", st.resolve(*key))
@@ -181,7 +181,7 @@ impl LoaderV3Log {
                 st.resolve(*additional_bytes)
             ),
             Self::NewAuthorityDebug2 { new_authority } => {
-                format!("New authority: {:?}", st.resolve(*new_authority))
+                format!("New authority: {}", st.resolve(*new_authority))
             }
         }
     }
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::{StrId, StringTable};
+use crate::{LogInterner, LogStrings, StrId};
 
 /// TODO: confirm loader-v4 id
 pub const STR_ID: &str = "LoaderV411111111111111111111111111111111111";
@@ -17,7 +17,7 @@ pub enum LoaderV4Log {
 
 impl LoaderV4Log {
     #[inline]
-    pub fn parse(payload: &str, st: &mut StringTable) -> Option<Self> {
+    pub fn parse(payload: &str, st: &mut LogInterner) -> Option<Self> {
         if let Some(x) = parse_one_braced(payload, "Insufficient lamports, ", " are required.") {
             return Some(Self::InsufficientLamportsRequired {
                 required_lamports: st.push(x),
@@ -27,7 +27,7 @@ impl LoaderV4Log {
     }
 
     #[inline]
-    pub fn as_str(&self, st: &StringTable) -> String {
+    pub fn as_str(&self, st: &LogStrings<'_>) -> String {
         match self {
             Self::InsufficientLamportsRequired { required_lamports } => format!(
                 "Insufficient lamports, {} are required.",
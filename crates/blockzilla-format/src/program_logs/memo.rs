@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::{StrId, StringTable};
+use crate::{LogInterner, LogStrings, StrId};
 
 pub const STR_ID: &str = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr";
 
@@ -30,7 +30,7 @@ pub enum MemoLog {
 
 impl MemoLog {
     #[inline]
-    pub fn parse(payload: &str, st: &mut StringTable) -> Option<Self> {
+    pub fn parse(payload: &str, st: &mut LogInterner) -> Option<Self> {
         if let Some(x) = payload.strip_prefix("Signed by ") {
             return Some(Self::SignedByDebug {
                 address: st.push(x.trim()),
@@ -54,15 +54,16 @@ impl MemoLog {
         None
     }
 
+    /// `{:?}` fields are stored as printed, quotes and escapes included.
     #[inline]
-    pub fn as_str(&self, st: &StringTable) -> String {
+    pub fn as_str(&self, st: &LogStrings<'_>) -> String {
         match self {
-            Self::SignedByDebug { address } => format!("Signed by {:?}", st.resolve(*address)),
+            Self::SignedByDebug { address } => format!("Signed by {}", st.resolve(*address)),
             Self::InvalidUtf8FromByte { valid_up_to } => {
                 format!("Invalid UTF-8, from byte {}", st.resolve(*valid_up_to))
             }
             Self::MemoLenAndDebug { len, memo } => {
-                format!("Memo (len {}): {:?}", st.resolve(*len), st.resolve(*memo))
+                format!("Memo (len {}): {}", st.resolve(*len), st.resolve(*memo))
             }
         }
     }
@@ -1,7 +1,8 @@
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::{KeyIndex, KeyStore, StrId, StringTable};
+use crate::{KeyIndex, KeyStore, LogInterner, LogStrings, ProgramId, StrId};
 
 pub mod account_compression;
 pub mod address_lookup_table;
@@ -11,6 +12,7 @@ pub mod loader_v4;
 pub mod memo;
 pub mod record;
 pub mod system_program;
+pub mod template;
 pub mod token;
 pub mod token_2022;
 pub mod transfer_hook;
@@ -43,13 +45,19 @@ pub enum ProgramLog {
         msg: StrId,
     },
     Unknown(StrId),
+    /// Payload matching a mined template (templates.bin)
+    Template {
+        id: u32,
+        args: Vec<template::TemplateArg>,
+    },
 }
 
 #[inline]
 pub fn parse_program_log_no_id(
+    invoked: Option<ProgramId>,
     payload: &str,
     index: &KeyIndex,
-    st: &mut StringTable,
+    st: &mut LogInterner,
 ) -> ProgramLog {
     // Fast path: zero-alloc parsers
     if let Some(t) = token::TokenLog::parse(payload) {
@@ -62,7 +70,7 @@ pub fn parse_program_log_no_id(
         return ev;
     }
 
-    // Slow path: parsers using StringTable
+    // Slow path: parsers using the string table
     if let Some(t) = token_2022::Token2022Log::parse(payload, index, st) {
         return ProgramLog::Token2022(t);
     }
@@ -91,15 +99,16 @@ pub fn parse_program_log_no_id(
         return ev;
     }
 
-    ProgramLog::Unknown(st.push(payload))
+    parse_unknown(invoked, payload, index, st)
 }
 
 #[inline]
 pub fn parse_program_log_for_program(
+    pid: ProgramId,
     program: &str,
     payload: &str,
     index: &KeyIndex,
-    st: &mut StringTable,
+    st: &mut LogInterner,
 ) -> ProgramLog {
     if let Some(log) = try_parse_program_log_with_table(program, payload, index, st) {
         return log;
@@ -110,6 +119,22 @@ pub fn parse_program_log_for_program(
     if let Some(ev) = parse_anchor_error(payload, st) {
         return ev;
     }
+    parse_unknown(Some(pid), payload, index, st)
+}
+
+/// Last resort: a mined template of `program`, else the raw payload.
+#[inline]
+fn parse_unknown(
+    program: Option<ProgramId>,
+    payload: &str,
+    index: &KeyIndex,
+    st: &mut LogInterner,
+) -> ProgramLog {
+    if let Some(pid) = program
+        && let Some(log) = st.template(pid, payload, index)
+    {
+        return log;
+    }
     ProgramLog::Unknown(st.push(payload))
 }
 
@@ -128,7 +153,7 @@ pub fn try_parse_program_log_with_table(
     program: &str,
     payload: &str,
     index: &KeyIndex,
-    st: &mut StringTable,
+    st: &mut LogInterner,
 ) -> Option<ProgramLog> {
     try_parse!(
         program,
@@ -196,8 +221,12 @@ pub fn try_parse_program_log_with_table(
 }
 
 #[inline]
-pub fn render_program_log(log: &ProgramLog, store: &KeyStore, st: &StringTable) -> String {
-    match log {
+pub fn render_program_log(
+    log: &ProgramLog,
+    store: &KeyStore,
+    st: &LogStrings<'_>,
+) -> Result<String> {
+    Ok(match log {
         ProgramLog::Token(t) => t.as_str().to_string(),
         ProgramLog::Token2022(t) => t.as_str(st, store),
         ProgramLog::Ata(t) => t.as_str().to_string(),
@@ -232,11 +261,12 @@ pub fn render_program_log(log: &ProgramLog, store: &KeyStore, st: &StringTable)
             st.resolve(*msg)
         ),
         ProgramLog::Unknown(id) => st.resolve(*id).to_string(),
-    }
+        ProgramLog::Template { id, args } => st.template(*id)?.render(args, st, store)?,
+    })
 }
 
 #[inline]
-fn parse_anchor_instruction(text: &str, st: &mut StringTable) -> Option<ProgramLog> {
+fn parse_anchor_instruction(text: &str, st: &mut LogInterner) -> Option<ProgramLog> {
     let name = text.strip_prefix("Instruction: ")?.trim();
     if name.is_empty() {
         return None;
@@ -246,7 +276,7 @@ fn parse_anchor_instruction(text: &str, st: &mut StringTable) -> Option<ProgramL
     })
 }
 
-fn parse_anchor_error(text: &str, st: &mut StringTable) -> Option<ProgramLog> {
+fn parse_anchor_error(text: &str, st: &mut LogInterner) -> Option<ProgramLog> {
     // Try "thrown" variant with file location
     if let Some(rest) = text.strip_prefix("AnchorError thrown in ") {
         let (loc, tail) = rest.split_once(". Error Code: ")?;
@@ -274,7 +304,7 @@ fn parse_anchor_error(text: &str, st: &mut StringTable) -> Option<ProgramLog> {
     None
 }
 
-fn parse_error_fields(text: &str, st: &mut StringTable) -> Option<(StrId, u32, StrId)> {
+fn parse_error_fields(text: &str, st: &mut LogInterner) -> Option<(StrId, u32, StrId)> {
     let (code_str, tail) = text.split_once(". Error Number: ")?;
     let (num_str, msg_str) = tail.split_once(". Error Message: ")?;
 
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::{StrId, StringTable};
+use crate::{LogInterner, LogStrings, StrId};
 
 /// TODO: confirm record program id
 pub const STR_ID: &str = "Record111111111111111111111111111111111111";
@@ -17,7 +17,7 @@ pub enum RecordLog {
 
 impl RecordLog {
     #[inline]
-    pub fn parse(payload: &str, st: &mut StringTable) -> Option<Self> {
+    pub fn parse(payload: &str, st: &mut LogInterner) -> Option<Self> {
         if let Some(x) = parse_one_braced(payload, "reallocating +", " bytes") {
             return Some(Self::ReallocatingPlusBytesDebug { bytes: st.push(x) });
         }
@@ -25,10 +25,10 @@ impl RecordLog {
     }
 
     #[inline]
-    pub fn as_str(&self, st: &StringTable) -> String {
+    pub fn as_str(&self, st: &LogStrings<'_>) -> String {
         match self {
             Self::ReallocatingPlusBytesDebug { bytes } => {
-                format!("reallocating +{:?} bytes", st.resolve(*bytes))
+                format!("reallocating +{} bytes", st.resolve(*bytes))
             }
         }
     }
@@ -1,9 +1,10 @@
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use solana_pubkey::Pubkey;
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::log::{StrId, StringTable};
-use crate::{KeyIndex, KeyStore};
+use crate::log::{LogInterner, StrId};
+use crate::{KeyIndex, KeyStore, LogStrings};
 
 /// System Program id
 pub const STR_ID: &str = "11111111111111111111111111111111";
@@ -111,24 +112,28 @@ fn parse_u64_commas(s: &str) -> Option<u64> {
 }
 
 #[inline]
-fn pubkey_id_to_pubkey(store: &KeyStore, id: PubkeyId) -> Pubkey {
-    assert!(id != 0, "SystemProgramLog: PubkeyId=0 is reserved/invalid");
-    let bytes = store.get(id).unwrap_or_else(|| {
-        panic!(
+fn pubkey_id_to_pubkey(store: &KeyStore, id: PubkeyId) -> Result<Pubkey> {
+    anyhow::ensure!(id != 0, "SystemProgramLog: PubkeyId=0 is reserved/invalid");
+    let bytes = store.get(id).with_context(|| {
+        format!(
             "SystemProgramLog: PubkeyId out of bounds: id={} len={}",
             id,
             store.len()
         )
-    });
-    Pubkey::new_from_array(*bytes)
+    })?;
+    Ok(Pubkey::new_from_array(*bytes))
 }
 
 #[inline]
-fn pubkey_or_string_to_string(v: PubkeyOrString, st: &StringTable, store: &KeyStore) -> String {
-    match v {
-        PubkeyOrString::Pubkey(id) => pubkey_id_to_pubkey(store, id).to_string(),
+fn pubkey_or_string_to_string(
+    v: PubkeyOrString,
+    st: &LogStrings<'_>,
+    store: &KeyStore,
+) -> Result<String> {
+    Ok(match v {
+        PubkeyOrString::Pubkey(id) => pubkey_id_to_pubkey(store, id)?.to_string(),
         PubkeyOrString::Text(sid) => st.resolve(sid).to_string(),
-    }
+    })
 }
 
 #[inline]
@@ -164,9 +169,9 @@ fn parse_debug_address_to_pubkey_id(index: &KeyIndex, addr_txt: &str) -> Option<
     index.lookup_str(s)
 }
 
-/// Try to resolve a pubkey text into the registry. If missing, store the raw text in StringTable.
+/// Try to resolve a pubkey text into the registry. If missing, store the raw text in the string table.
 #[inline]
-fn parse_pubkey_or_string(index: &KeyIndex, st: &mut StringTable, s: &str) -> PubkeyOrString {
+fn parse_pubkey_or_string(index: &KeyIndex, st: &mut LogInterner, s: &str) -> PubkeyOrString {
     let s = s.trim();
     if let Some(id) = index.lookup_str(s) {
         PubkeyOrString::Pubkey(id)
@@ -178,7 +183,7 @@ fn parse_pubkey_or_string(index: &KeyIndex, st: &mut StringTable, s: &str) -> Pu
 impl SystemProgramLog {
     /// `text` is the payload after "Program log: " or after "Program <id> log: "
     #[inline]
-    pub fn parse(text: &str, index: &KeyIndex, st: &mut StringTable) -> Option<Self> {
+    pub fn parse(text: &str, index: &KeyIndex, st: &mut LogInterner) -> Option<Self> {
         let text = text.trim();
 
         // Instruction: <name>
@@ -291,8 +296,8 @@ impl SystemProgramLog {
     }
 
     #[inline]
-    pub fn render(&self, st: &StringTable, store: &KeyStore) -> String {
-        match self {
+    pub fn render(&self, st: &LogStrings<'_>, store: &KeyStore) -> Result<String> {
+        Ok(match self {
             Self::Instruction(ix) => ix.as_str().to_string(),
 
             Self::CreateAddressMismatch {
@@ -300,8 +305,8 @@ impl SystemProgramLog {
                 derived_addr,
             } => format!(
                 "Create: address {} does not match derived address {}",
-                pubkey_id_to_pubkey(store, *provided_addr),
-                pubkey_or_string_to_string(*derived_addr, st, store),
+                pubkey_id_to_pubkey(store, *provided_addr)?,
+                pubkey_or_string_to_string(*derived_addr, st, store)?,
             ),
 
             Self::TransferFromAddressMismatch {
@@ -309,31 +314,31 @@ impl SystemProgramLog {
                 derived_addr,
             } => format!(
                 "Transfer: 'from' address {} does not match derived address {}",
-                pubkey_id_to_pubkey(store, *provided_addr),
-                pubkey_or_string_to_string(*derived_addr, st, store),
+                pubkey_id_to_pubkey(store, *provided_addr)?,
+                pubkey_or_string_to_string(*derived_addr, st, store)?,
             ),
 
             Self::CreateAccountAlreadyInUse { addr }
             | Self::CreateAccountAccountAlreadyInUse { addr } => format!(
                 "Create Account: account {:?} already in use",
-                pubkey_id_to_pubkey(store, *addr),
+                pubkey_id_to_pubkey(store, *addr)?,
             ),
 
             Self::AllocateAlreadyInUse { addr } | Self::AllocateAccountAlreadyInUse { addr } => {
                 format!(
                     "Allocate: account {:?} already in use",
-                    pubkey_id_to_pubkey(store, *addr),
+                    pubkey_id_to_pubkey(store, *addr)?,
                 )
             }
 
             Self::AllocateToMustSign { addr } => format!(
                 "Allocate: 'to' account {:?} must sign",
-                pubkey_id_to_pubkey(store, *addr),
+                pubkey_id_to_pubkey(store, *addr)?,
             ),
 
             Self::AssignAccountMustSign { addr } => format!(
                 "Assign: account {:?} must sign",
-                pubkey_id_to_pubkey(store, *addr),
+                pubkey_id_to_pubkey(store, *addr)?,
             ),
 
             Self::AllocateRequestedTooLarge {
@@ -350,7 +355,7 @@ impl SystemProgramLog {
 
             Self::TransferFromMustSign { from } => format!(
                 "Transfer: `from` account {} must sign",
-                pubkey_id_to_pubkey(store, *from),
+                pubkey_id_to_pubkey(store, *from)?,
             ),
 
             Self::TransferInsufficient { have, need } => {
@@ -368,6 +373,6 @@ impl SystemProgramLog {
             Self::AuthorizeNonceAccount { msg } => {
                 format!("Authorize nonce account: {}", st.resolve(*msg))
             }
-        }
+        })
     }
 }
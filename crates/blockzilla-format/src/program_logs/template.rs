@@ -0,0 +1,527 @@
+//! Log templates mined from an epoch, for programs without a parser module.
+//!
+//! A payload is cut into alternating runs of ascii alphanumerics (words) and
+//! everything else (separators). A template fixes the separators and some of
+//! the words; the other words are typed slots, e.g.
+//! `random message <pubkey> : <int> finalized`.
+
+use anyhow::{Context, Result};
+use gxhash::HashMap as GxHashMap;
+use serde::{Deserialize, Serialize};
+use solana_pubkey::Pubkey;
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+use wincode::{SchemaRead, SchemaWrite};
+
+use crate::{
+    CompactFileHeader, CompactLayout, KeyIndex, KeyStore, LogStrings, StrId, raw_file_body,
+    write_raw_file,
+};
+
+/// Payloads with more tokens are not worth templating.
+const MAX_TOKENS: usize = 64;
+/// Clusters kept per (program, token count) while mining.
+const MAX_CLUSTERS: usize = 32;
+/// Share of words a payload must have in common with a cluster to join it.
+const MIN_SIMILARITY: f32 = 0.6;
+
+/// Value of a template slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, SchemaRead, SchemaWrite)]
+pub enum TemplateArg {
+    /// Registry id
+    Pubkey(u32),
+    Int(u64),
+    Str(StrId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SlotKind {
+    Pubkey,
+    Int,
+    Str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Piece {
+    Lit(String),
+    Slot(SlotKind),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    /// Program the template was mined from
+    pub program: [u8; 32],
+    /// One piece per token
+    pub pieces: Vec<Piece>,
+}
+
+impl Template {
+    pub fn render(
+        &self,
+        args: &[TemplateArg],
+        st: &LogStrings<'_>,
+        store: &KeyStore,
+    ) -> Result<String> {
+        let mut out = String::new();
+        let mut args = args.iter();
+        for p in &self.pieces {
+            match p {
+                Piece::Lit(s) => out.push_str(s),
+                Piece::Slot(_) => match args.next().context("template: missing arg")? {
+                    TemplateArg::Pubkey(id) => {
+                        let k = store.get(*id).with_context(|| {
+                            format!("template: pubkey id out of bounds: {}", id)
+                        })?;
+                        out.push_str(&Pubkey::new_from_array(*k).to_string());
+                    }
+                    TemplateArg::Int(n) => out.push_str(&n.to_string()),
+                    TemplateArg::Str(id) => out.push_str(st.resolve(*id)),
+                },
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Slot value found while matching, strings not interned yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotValue<'a> {
+    Pubkey(u32),
+    Int(u64),
+    Str(&'a str),
+}
+
+/// Epoch template dictionary (templates.bin), most used first.
+#[derive(Debug, Default)]
+pub struct TemplateDict {
+    templates: Vec<Template>,
+    /// program -> template ids
+    by_program: GxHashMap<[u8; 32], Vec<u32>>,
+}
+
+impl TemplateDict {
+    pub fn new(templates: Vec<Template>) -> Self {
+        let mut by_program: GxHashMap<[u8; 32], Vec<u32>> = GxHashMap::default();
+        for (i, t) in templates.iter().enumerate() {
+            by_program.entry(t.program).or_default().push(i as u32);
+        }
+        Self {
+            templates,
+            by_program,
+        }
+    }
+
+    /// registry.bin -> templates.bin
+    pub fn path_for(registry_path: &Path) -> PathBuf {
+        registry_path.with_file_name("templates.bin")
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    #[inline]
+    pub fn get(&self, id: u32) -> Option<&Template> {
+        self.templates.get(id as usize)
+    }
+
+    /// First template of `program` matching `payload`, with its slot values.
+    ///
+    /// Pubkey and int slots fall back to a string when the word isn't one,
+    /// the render is the same.
+    pub fn find<'p>(
+        &self,
+        program: &[u8; 32],
+        payload: &'p str,
+        index: &KeyIndex,
+    ) -> Option<(u32, Vec<SlotValue<'p>>)> {
+        let ids = self.by_program.get(program)?;
+        let tokens = tokenize(payload);
+
+        'templates: for &id in ids {
+            let t = &self.templates[id as usize];
+            if t.pieces.len() != tokens.len() {
+                continue;
+            }
+            let mut values = Vec::new();
+            for (p, tok) in t.pieces.iter().zip(&tokens) {
+                match p {
+                    Piece::Lit(s) if s.as_str() == *tok => {}
+                    Piece::Lit(_) => continue 'templates,
+                    Piece::Slot(_) if !is_word(tok) => continue 'templates,
+                    Piece::Slot(kind) => values.push(match (kind, classify(tok, index)) {
+                        (SlotKind::Pubkey, Word::Pubkey(id)) => SlotValue::Pubkey(id),
+                        (SlotKind::Int, Word::Int(n)) => SlotValue::Int(n),
+                        _ => SlotValue::Str(tok),
+                    }),
+                }
+            }
+            return Some((id, values));
+        }
+        None
+    }
+
+    /// [`Self::encode`] between the file header and trailer.
+    pub fn write(&self, path: &Path, epoch: u64) -> Result<()> {
+        let header = CompactFileHeader::new(CompactLayout::TemplateDict).with_epoch(epoch);
+        write_raw_file(path, header, |w| self.encode(w))
+    }
+
+    /// Layout (little endian): template count u32, then
+    /// every template as program [u8; 32], piece count u32 and the pieces,
+    /// each a tag u8 (0 literal, 1 pubkey, 2 int, 3 str) and for literals
+    /// byte len u32 + utf8 bytes.
+    pub fn encode<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&(self.templates.len() as u32).to_le_bytes())
+            .context("write templates header")?;
+        for t in &self.templates {
+            w.write_all(&t.program).context("write template")?;
+            w.write_all(&(t.pieces.len() as u32).to_le_bytes())
+                .context("write template")?;
+            for p in &t.pieces {
+                let tag: u8 = match p {
+                    Piece::Lit(_) => 0,
+                    Piece::Slot(SlotKind::Pubkey) => 1,
+                    Piece::Slot(SlotKind::Int) => 2,
+                    Piece::Slot(SlotKind::Str) => 3,
+                };
+                w.write_all(&[tag]).context("write template")?;
+                if let Piece::Lit(s) = p {
+                    w.write_all(&(s.len() as u32).to_le_bytes())
+                        .context("write template")?;
+                    w.write_all(s.as_bytes()).context("write template")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut r = raw_file_body(&bytes, CompactLayout::TemplateDict)
+            .with_context(|| format!("template dictionary {}", path.display()))?;
+
+        let mut u32_buf = [0u8; 4];
+        r.read_exact(&mut u32_buf)
+            .context("read templates header")?;
+        let n = u32::from_le_bytes(u32_buf) as usize;
+        let mut templates = Vec::with_capacity(n);
+        for _ in 0..n {
+            let mut program = [0u8; 32];
+            r.read_exact(&mut program).context("read template")?;
+            r.read_exact(&mut u32_buf).context("read template")?;
+            let n_pieces = u32::from_le_bytes(u32_buf) as usize;
+            let mut pieces = Vec::with_capacity(n_pieces);
+            for _ in 0..n_pieces {
+                let mut tag = [0u8; 1];
+                r.read_exact(&mut tag).context("read template")?;
+                pieces.push(match tag[0] {
+                    0 => {
+                        r.read_exact(&mut u32_buf).context("read template")?;
+                        let mut b = vec![0u8; u32::from_le_bytes(u32_buf) as usize];
+                        r.read_exact(&mut b).context("read template")?;
+                        Piece::Lit(String::from_utf8(b).context("template literal is not utf8")?)
+                    }
+                    1 => Piece::Slot(SlotKind::Pubkey),
+                    2 => Piece::Slot(SlotKind::Int),
+                    3 => Piece::Slot(SlotKind::Str),
+                    t => anyhow::bail!("unknown template piece tag {} in {}", t, path.display()),
+                });
+            }
+            templates.push(Template { program, pieces });
+        }
+        anyhow::ensure!(
+            r.read(&mut u32_buf)? == 0,
+            "trailing bytes in {}",
+            path.display()
+        );
+
+        Ok(Self::new(templates))
+    }
+}
+
+/// Alternating runs of ascii alphanumerics and of everything else.
+pub fn tokenize(s: &str) -> Vec<&str> {
+    let b = s.as_bytes();
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    for i in 1..=b.len() {
+        if i == b.len() || b[i].is_ascii_alphanumeric() != b[start].is_ascii_alphanumeric() {
+            tokens.push(&s[start..i]);
+            start = i;
+        }
+    }
+    tokens
+}
+
+#[inline]
+fn is_word(tok: &str) -> bool {
+    tok.as_bytes()[0].is_ascii_alphanumeric()
+}
+
+enum Word {
+    Pubkey(u32),
+    Int(u64),
+    Other,
+}
+
+/// Ints must print back the same (no leading zeros), pubkeys must be in the registry.
+#[inline]
+fn classify(word: &str, index: &KeyIndex) -> Word {
+    if let Ok(n) = word.parse::<u64>()
+        && (word == "0" || !word.starts_with('0'))
+    {
+        return Word::Int(n);
+    }
+    if (32..=44).contains(&word.len())
+        && let Some(id) = index.lookup_str(word)
+    {
+        return Word::Pubkey(id);
+    }
+    Word::Other
+}
+
+const KIND_PUBKEY: u8 = 1;
+const KIND_INT: u8 = 2;
+const KIND_STR: u8 = 4;
+
+enum MinedToken {
+    Lit(Box<str>),
+    /// `KIND_*` bits of the words seen
+    Slot(u8),
+}
+
+struct Cluster {
+    tokens: Vec<MinedToken>,
+    count: u64,
+}
+
+/// Learns templates from unknown program logs, Drain style: payloads are
+/// grouped by program and token count, and join the most similar cluster of
+/// their group, the words that differ turning into slots.
+///
+/// Memory is bounded by `max_groups` groups of `MAX_CLUSTERS` clusters.
+pub struct TemplateMiner {
+    groups: GxHashMap<([u8; 32], u32), Vec<Cluster>>,
+    max_groups: usize,
+}
+
+impl TemplateMiner {
+    pub fn new(max_groups: usize) -> Self {
+        Self {
+            groups: GxHashMap::default(),
+            max_groups,
+        }
+    }
+
+    pub fn add(&mut self, program: &[u8; 32], payload: &str, index: &KeyIndex) {
+        let tokens = tokenize(payload);
+        if tokens.is_empty() || tokens.len() > MAX_TOKENS {
+            return;
+        }
+        let key = (*program, tokens.len() as u32);
+        if !self.groups.contains_key(&key) && self.groups.len() >= self.max_groups {
+            return;
+        }
+
+        // Pubkeys and ints are slots from the start.
+        let kinds: Vec<u8> = tokens
+            .iter()
+            .map(|t| match is_word(t).then(|| classify(t, index)) {
+                None => 0,
+                Some(Word::Pubkey(_)) => KIND_PUBKEY,
+                Some(Word::Int(_)) => KIND_INT,
+                Some(Word::Other) => KIND_STR,
+            })
+            .collect();
+        let n_words = kinds.iter().filter(|k| **k != 0).count().max(1);
+
+        let clusters = self.groups.entry(key).or_default();
+        let best = clusters
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| Some((i, c.similarity(&tokens, &kinds)?)))
+            .max_by_key(|(_, same)| *same);
+
+        match best {
+            Some((i, same)) if same as f32 / n_words as f32 >= MIN_SIMILARITY => {
+                clusters[i].merge(&tokens, &kinds)
+            }
+            _ if clusters.len() < MAX_CLUSTERS => clusters.push(Cluster {
+                tokens: tokens
+                    .iter()
+                    .zip(&kinds)
+                    .map(|(t, &k)| match k {
+                        KIND_PUBKEY | KIND_INT => MinedToken::Slot(k),
+                        _ => MinedToken::Lit((*t).into()),
+                    })
+                    .collect(),
+                count: 1,
+            }),
+            _ => {}
+        }
+    }
+
+    /// The `n` most used templates seen at least `min_count` times, most used first.
+    pub fn top(self, n: usize, min_count: u64) -> Vec<Template> {
+        let mut items: Vec<(u64, Template)> = self
+            .groups
+            .into_iter()
+            .flat_map(|((program, _), clusters)| {
+                clusters.into_iter().filter_map(move |c| {
+                    let has_slot = c.tokens.iter().any(|t| matches!(t, MinedToken::Slot(_)));
+                    (has_slot && c.count >= min_count).then(|| (c.count, c.into_template(program)))
+                })
+            })
+            .collect();
+        items.sort_unstable_by(|(ca, ta), (cb, tb)| {
+            cb.cmp(ca)
+                .then_with(|| ta.program.cmp(&tb.program))
+                .then_with(|| ta.pieces.cmp(&tb.pieces))
+        });
+        items.truncate(n);
+        items.into_iter().map(|(_, t)| t).collect()
+    }
+}
+
+impl Cluster {
+    /// Words in common, `None` if the separators differ.
+    fn similarity(&self, tokens: &[&str], kinds: &[u8]) -> Option<usize> {
+        let mut same = 0;
+        for ((m, t), &k) in self.tokens.iter().zip(tokens).zip(kinds) {
+            match m {
+                MinedToken::Lit(s) if **s == **t => same += (k != 0) as usize,
+                MinedToken::Lit(s) if k == 0 || !is_word(s) => return None,
+                MinedToken::Lit(_) => {}
+                MinedToken::Slot(_) if k == 0 => return None,
+                MinedToken::Slot(_) => same += 1,
+            }
+        }
+        Some(same)
+    }
+
+    fn merge(&mut self, tokens: &[&str], kinds: &[u8]) {
+        self.count += 1;
+        for ((m, t), &k) in self.tokens.iter_mut().zip(tokens).zip(kinds) {
+            match m {
+                MinedToken::Lit(s) if **s != **t => *m = MinedToken::Slot(KIND_STR | k),
+                MinedToken::Slot(bits) => *bits |= k,
+                MinedToken::Lit(_) => {}
+            }
+        }
+    }
+
+    fn into_template(self, program: [u8; 32]) -> Template {
+        let pieces = self
+            .tokens
+            .into_iter()
+            .map(|t| match t {
+                MinedToken::Lit(s) => Piece::Lit(s.into()),
+                MinedToken::Slot(KIND_PUBKEY) => Piece::Slot(SlotKind::Pubkey),
+                MinedToken::Slot(KIND_INT) => Piece::Slot(SlotKind::Int),
+                MinedToken::Slot(_) => Piece::Slot(SlotKind::Str),
+            })
+            .collect();
+        Template { program, pieces }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program_logs::ProgramLog;
+    use crate::{
+        CompactFileHeader, CompactLayout, LogDicts, LogEvent, LogInterner, StringDict,
+        parse_logs_into, render_block_logs,
+    };
+    use std::{str::FromStr, sync::Arc};
+
+    #[test]
+    fn mined_templates_render_byte_for_byte() {
+        let program = Pubkey::from_str("9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM").unwrap();
+        let keys: Vec<[u8; 32]> = std::iter::once(program.to_bytes())
+            .chain((1..=8u8).map(|i| [i; 32]))
+            .collect();
+        let index = KeyIndex::build(Arc::new(KeyStore { keys: keys.clone() }));
+
+        let payload = |i: usize| {
+            let pk = Pubkey::new_from_array(keys[1 + i % 8]);
+            format!("random message {} : {} finalized", pk, i * 37)
+        };
+
+        let mut miner = TemplateMiner::new(16);
+        for i in 0..100 {
+            miner.add(&program.to_bytes(), &payload(i), &index);
+        }
+        let templates = miner.top(16, 2);
+        assert_eq!(templates.len(), 1);
+        assert_eq!(
+            templates[0].pieces[4..7],
+            [
+                Piece::Slot(SlotKind::Pubkey),
+                Piece::Lit(" : ".into()),
+                Piece::Slot(SlotKind::Int),
+            ]
+        );
+
+        let dicts = Arc::new(LogDicts {
+            strings: StringDict::default(),
+            templates: TemplateDict::new(templates),
+        });
+        let lines: Vec<String> = [
+            format!("Program {} invoke [1]", program),
+            format!("Program log: {}", payload(1000)),
+            format!("Program {} log: {}", program, payload(7)),
+            // Leading zero: the int slot takes it as a string.
+            format!("Program log: random message {} : 007 finalized", program),
+            "Program log: random message x : 1 aborted".to_string(),
+            format!("Program {} success", program),
+        ]
+        .into();
+
+        let mut st = LogInterner::with_dicts(dicts.clone());
+        let cls = parse_logs_into(&lines, &index, &mut st);
+        let tables = st.take();
+        assert_eq!(
+            render_block_logs(
+                &cls,
+                CompactFileHeader::new(CompactLayout::Plain),
+                &tables,
+                &dicts,
+                index.store()
+            )
+            .unwrap(),
+            lines
+        );
+
+        let templated = |ev: &LogEvent| {
+            matches!(
+                ev,
+                LogEvent::ProgramLog(ProgramLog::Template { .. })
+                    | LogEvent::ProgramIdLog {
+                        log: ProgramLog::Template { .. },
+                        ..
+                    }
+            )
+        };
+        let hits: Vec<bool> = cls.events.iter().map(templated).collect();
+        assert_eq!(hits, [false, true, true, true, false, false]);
+
+        // Args that don't fit the template are an error, not a panic.
+        let st = LogStrings::new(&dicts, &tables.strings);
+        let t = st.template(0).unwrap();
+        assert!(t.render(&[], &st, index.store()).is_err());
+        let bad = [TemplateArg::Pubkey(99), TemplateArg::Int(1)];
+        assert!(t.render(&bad, &st, index.store()).is_err());
+        assert!(st.template(1).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use solana_pubkey::Pubkey;
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::{KeyIndex, KeyStore, StrId, StringTable};
+use crate::{KeyIndex, KeyStore, LogInterner, LogStrings, StrId};
 
 /// SPL Token-2022 program id
 pub const STR_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
@@ -399,7 +399,7 @@ impl Token2022ErrorLog {
 
 impl Token2022Log {
     #[inline]
-    pub fn parse(payload: &str, index: &KeyIndex, st: &mut StringTable) -> Option<Self> {
+    pub fn parse(payload: &str, index: &KeyIndex, st: &mut LogInterner) -> Option<Self> {
         if let Some(e) = Token2022ErrorLog::parse(payload) {
             return Some(Self::Error(e));
         }
@@ -435,7 +435,7 @@ impl Token2022Log {
     }
 
     #[inline]
-    pub fn as_str(&self, st: &StringTable, store: &KeyStore) -> String {
+    pub fn as_str(&self, st: &LogStrings<'_>, store: &KeyStore) -> String {
         match self {
             Self::Error(e) => e.as_str().to_string(),
 
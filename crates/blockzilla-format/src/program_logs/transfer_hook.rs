@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use wincode::{SchemaRead, SchemaWrite};
 
-use crate::StringTable;
+use crate::{LogInterner, LogStrings};
 
 pub const STR_ID: &str = "TransferHook1111111111111111111111111111111";
 
@@ -47,12 +47,12 @@ impl TransferHookErrorLog {
 
 impl TransferHookLog {
     #[inline]
-    pub fn parse(payload: &str, _st: &mut StringTable) -> Option<Self> {
+    pub fn parse(payload: &str, _st: &mut LogInterner) -> Option<Self> {
         TransferHookErrorLog::parse(payload).map(Self::Error)
     }
 
     #[inline]
-    pub fn as_str(&self, _st: &StringTable) -> String {
+    pub fn as_str(&self, _st: &LogStrings<'_>) -> String {
         match self {
             Self::Error(e) => e.as_str().to_string(),
         }
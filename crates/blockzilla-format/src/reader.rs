@@ -1,17 +1,160 @@
 use anyhow::{Context, Result};
-use std::io::Read;
+use std::{collections::VecDeque, fmt, io::Read};
+
+use crate::{CompactFileHeader, FRAME_MAGIC, TRAILER_LEN, TRAILER_MARK, check_trailer};
+
+/// Longest frame payload read unless `with_max_frame_len` says otherwise.
+pub const DEFAULT_MAX_FRAME_LEN: u32 = 256 << 20;
+
+/// Why a frame could not be read. Carried by the errors of
+/// [`PostcardFramedReader`], `downcast_ref` tells them apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The file ends inside the frame at `offset`: `got` of the `need` bytes
+    /// its prefix announces (of the prefix itself when that is cut).
+    TruncatedFrame { offset: u64, need: u64, got: u64 },
+    /// The length prefix is above the reader's maximum.
+    FrameTooLarge { offset: u64, len: u32, max: u32 },
+    /// No `FRAME_MAGIC` at `offset` (format v14).
+    BadMagic { offset: u64, found: u32 },
+    /// The CRC32C after the payload does not match it (format v13).
+    BadChecksum {
+        offset: u64,
+        len: u32,
+        stored: u32,
+        computed: u32,
+    },
+    /// The frames end at `offset` without the file trailer (format v13).
+    MissingTrailer { offset: u64 },
+    /// The file goes on at `offset`, after its trailer (format v13).
+    TrailingBytes { offset: u64 },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            FrameError::TruncatedFrame { offset, need, got } => {
+                write!(
+                    f,
+                    "frame at offset {offset} truncated: {got} of {need} bytes"
+                )
+            }
+            FrameError::FrameTooLarge { offset, len, max } => {
+                write!(f, "frame at offset {offset}: length {len} above {max}")
+            }
+            FrameError::BadMagic { offset, found } => {
+                write!(f, "frame at offset {offset}: bad magic {found:08x}")
+            }
+            FrameError::BadChecksum {
+                offset,
+                len,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "frame at offset {offset}: crc32c {stored:08x} does not match its {len} bytes ({computed:08x})"
+            ),
+            FrameError::MissingTrailer { offset } => {
+                write!(
+                    f,
+                    "file ends at offset {offset} without its trailer (truncated?)"
+                )
+            }
+            FrameError::TrailingBytes { offset } => {
+                write!(f, "bytes after the trailer at offset {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
 
 pub struct PostcardFramedReader<R> {
     r: R,
     buf: Vec<u8>,
+    /// File offset of the next frame, for errors
+    pos: u64,
+    /// Frames end with a CRC32C and the file with a trailer
+    checksums: bool,
+    /// Frames start with `FRAME_MAGIC`
+    magic: bool,
+    /// Hash of the bytes read so far, dropped once a resync skips some
+    hash: Option<blake3::Hasher>,
+    max_len: u32,
+    /// Resync after a bad frame rather than failing
+    recover: bool,
+    /// Bytes of a bad frame to scan again before reading on from `r`
+    back: VecDeque<u8>,
+    /// Bytes of the frame being read, kept when recovering
+    raw: Vec<u8>,
+    /// Bytes dropped by resyncs
+    skipped: u64,
+    /// The last read resynced
+    resynced: bool,
+    /// The trailer was read
+    done: bool,
 }
 
 impl<R: Read> PostcardFramedReader<R> {
+    /// Frames without checksums, `r` positioned at the first one.
     pub fn new(r: R) -> Self {
         Self {
             r,
             buf: Vec::with_capacity(2 * 1024 * 1024),
+            pos: 0,
+            checksums: false,
+            magic: false,
+            hash: None,
+            max_len: DEFAULT_MAX_FRAME_LEN,
+            recover: false,
+            back: VecDeque::new(),
+            raw: Vec::new(),
+            skipped: 0,
+            resynced: false,
+            done: false,
+        }
+    }
+
+    /// Frames of a file whose `header` was just consumed from `r`.
+    pub fn with_header(r: R, header: CompactFileHeader) -> Self {
+        let mut s = Self::new(r);
+        s.pos = header.encoded_len() as u64;
+        s.checksums = header.uses_checksums();
+        s.magic = header.uses_frame_magic();
+        if s.checksums {
+            let mut h = blake3::Hasher::new();
+            h.update(&header.to_bytes());
+            s.hash = Some(h);
         }
+        s
+    }
+
+    /// Fail on frames longer than `max` bytes instead of allocating for them.
+    pub fn with_max_frame_len(mut self, max: u32) -> Self {
+        self.max_len = max;
+        self
+    }
+
+    /// Skip bad frames: after one, resync on the next `FRAME_MAGIC` whose
+    /// frame checksum matches. The whole-file hash is not checked once bytes
+    /// were skipped. Files before format v14 have nothing to resync on and
+    /// fail as usual.
+    pub fn recovering(mut self) -> Self {
+        self.recover = true;
+        self
+    }
+
+    /// Bytes dropped by resyncs so far.
+    #[inline]
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Whether the last read dropped bytes to resync: frames may be missing
+    /// before the one it returned.
+    #[inline]
+    pub fn resynced(&self) -> bool {
+        self.resynced
     }
 
     pub fn reserve(&mut self, n: usize) {
@@ -23,27 +166,338 @@ impl<R: Read> PostcardFramedReader<R> {
     where
         T: serde::Deserialize<'a>,
     {
-        let mut lenb = [0u8; 4];
+        let Some(buf) = self.read_bytes()? else {
+            return Ok(None);
+        };
+        let v = postcard::from_bytes::<T>(buf).context("postcard decode")?;
+        Ok(Some(v))
+    }
 
-        match self.r.read_exact(&mut lenb) {
-            Ok(()) => {}
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
-            Err(e) => return Err(e).context("read frame len"),
+    /// Fill `out` from the bytes to scan again, then from `r`. Short only at
+    /// EOF.
+    fn fill(&mut self, out: &mut [u8]) -> Result<usize> {
+        let mut n = 0;
+        while n < out.len() {
+            let res = if self.back.is_empty() {
+                self.r.read(&mut out[n..])
+            } else {
+                self.back.read(&mut out[n..])
+            };
+            match res {
+                Ok(0) => break,
+                Ok(k) => n += k,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e).with_context(|| format!("read frame at {}", self.pos)),
+            }
+        }
+        if self.recover {
+            self.raw.extend_from_slice(&out[..n]);
+        }
+        Ok(n)
+    }
+
+    /// Read the next frame into `buf`; false at the end of the frames.
+    fn next_frame(&mut self) -> Result<bool> {
+        if self.done {
+            return Ok(false);
         }
+        self.raw.clear();
+        let offset = self.pos;
+        let truncated = |need: usize, got: usize| FrameError::TruncatedFrame {
+            offset,
+            need: need as u64,
+            got: got as u64,
+        };
 
-        let len = u32::from_le_bytes(lenb) as usize;
+        let mut prefix = [0u8; 8];
+        let prefix_len = if self.magic { 8 } else { 4 };
+        match self.fill(&mut prefix[..4])? {
+            0 if self.checksums => return Err(FrameError::MissingTrailer { offset }.into()),
+            0 => return Ok(false),
+            4 => {}
+            n => return Err(truncated(4, n).into()),
+        }
+        let word = u32::from_le_bytes(prefix[..4].try_into().unwrap());
+        if self.checksums && word == TRAILER_MARK {
+            return self.read_trailer(offset);
+        }
+        if self.magic {
+            if word != FRAME_MAGIC {
+                return Err(FrameError::BadMagic {
+                    offset,
+                    found: word,
+                }
+                .into());
+            }
+            let n = self.fill(&mut prefix[4..])?;
+            if n < 4 {
+                return Err(truncated(8, 4 + n).into());
+            }
+        }
+        let prefix = &prefix[..prefix_len];
+        let len = u32::from_le_bytes(prefix[prefix_len - 4..].try_into().unwrap());
+        if len > self.max_len {
+            return Err(FrameError::FrameTooLarge {
+                offset,
+                len,
+                max: self.max_len,
+            }
+            .into());
+        }
 
-        self.buf.resize(len, 0);
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.resize(len as usize, 0);
+        let res = self.fill(&mut buf);
+        self.buf = buf;
+        let mut got = prefix_len + res?;
+        let mut crcb = [0u8; 4];
+        let crc_len = if self.checksums { 4 } else { 0 };
+        if got == prefix_len + len as usize && self.checksums {
+            got += self.fill(&mut crcb)?;
+        }
+        let need = prefix_len + len as usize + crc_len;
+        if got < need {
+            return Err(truncated(need, got).into());
+        }
 
-        match self.r.read_exact(&mut self.buf) {
-            Ok(()) => {}
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                return Ok(None);
+        if self.checksums {
+            let (stored, computed) = (u32::from_le_bytes(crcb), crc32c::crc32c(&self.buf));
+            if stored != computed {
+                return Err(FrameError::BadChecksum {
+                    offset,
+                    len,
+                    stored,
+                    computed,
+                }
+                .into());
+            }
+            if let Some(h) = self.hash.as_mut() {
+                h.update(prefix);
+                h.update(&self.buf);
+                h.update(&crcb);
             }
-            Err(e) => return Err(e).context("read frame payload"),
         }
+        self.pos += need as u64;
+        Ok(true)
+    }
 
-        let v = postcard::from_bytes::<T>(&self.buf).context("postcard decode")?;
-        Ok(Some(v))
+    /// Rest of the trailer at `offset`, whose mark was just read.
+    fn read_trailer(&mut self, offset: u64) -> Result<bool> {
+        let mut trailer = [0u8; TRAILER_LEN];
+        trailer[..4].copy_from_slice(&TRAILER_MARK.to_le_bytes());
+        let n = self.fill(&mut trailer[4..])?;
+        if n < TRAILER_LEN - 4 {
+            return Err(FrameError::TruncatedFrame {
+                offset,
+                need: TRAILER_LEN as u64,
+                got: 4 + n as u64,
+            }
+            .into());
+        }
+        // Nothing may follow the trailer. After a resync, where the hash is
+        // not checked, that is all that tells a real mark from one inside
+        // skipped bytes.
+        if self.fill(&mut [0u8])? != 0 {
+            return Err(FrameError::TrailingBytes {
+                offset: offset + TRAILER_LEN as u64,
+            }
+            .into());
+        }
+        if let Some(h) = self.hash.take() {
+            check_trailer(&trailer, h.finalize())?;
+        }
+        self.done = true;
+        Ok(false)
+    }
+
+    /// After a bad frame, drop bytes up to the next frame magic or trailer
+    /// mark. False if the file ends first.
+    fn resync(&mut self) -> Result<bool> {
+        self.hash = None;
+        // Scan the bad frame again from its second byte.
+        let raw = std::mem::take(&mut self.raw);
+        for &b in raw.iter().skip(1).rev() {
+            self.back.push_front(b);
+        }
+        let mut start = self.pos + raw.len().min(1) as u64;
+        let mut window = [0u8; 4];
+        let mut have = 0;
+        loop {
+            let next = match self.back.pop_front() {
+                Some(b) => b,
+                None => {
+                    let mut b = [0u8];
+                    match self.r.read(&mut b) {
+                        Ok(0) => return Ok(false),
+                        Ok(_) => b[0],
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e).context("resync"),
+                    }
+                }
+            };
+            window.copy_within(1.., 0);
+            window[3] = next;
+            have += 1;
+            if have < 4 {
+                continue;
+            }
+            let word = u32::from_le_bytes(window);
+            if word == FRAME_MAGIC || word == TRAILER_MARK {
+                for &b in window.iter().rev() {
+                    self.back.push_front(b);
+                }
+                self.skipped += start - self.pos;
+                self.pos = start;
+                return Ok(true);
+            }
+            start += 1;
+        }
+    }
+
+    /// Next raw frame payload, for layouts decoded by hand.
+    #[inline]
+    pub fn read_bytes(&mut self) -> Result<Option<&[u8]>> {
+        Ok(self.advance()?.then_some(&self.buf[..]))
+    }
+
+    /// Move to the next frame, whose payload is then [`Self::frame`]. False
+    /// at the end of the frames.
+    pub fn advance(&mut self) -> Result<bool> {
+        self.resynced = false;
+        loop {
+            match self.next_frame() {
+                Ok(more) => return Ok(more),
+                Err(e) if self.recover && self.magic && e.is::<FrameError>() => {
+                    if !self.resync()? {
+                        return Err(e);
+                    }
+                    self.resynced = true;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Payload of the frame [`Self::advance`] moved to.
+    #[inline]
+    pub fn frame(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Skip the next frame without decoding it. Returns false at EOF.
+    pub fn skip(&mut self) -> Result<bool> {
+        if self.checksums {
+            // The payload is needed for its checksum anyway.
+            return Ok(self.read_bytes()?.is_some());
+        }
+        let offset = self.pos;
+        let mut lenb = [0u8; 4];
+        match self.fill(&mut lenb)? {
+            0 => return Ok(false),
+            4 => {}
+            n => {
+                return Err(FrameError::TruncatedFrame {
+                    offset,
+                    need: 4,
+                    got: n as u64,
+                }
+                .into());
+            }
+        }
+        let len = u32::from_le_bytes(lenb) as u64;
+        let n = std::io::copy(&mut (&mut self.r).take(len), &mut std::io::sink())
+            .context("skip frame payload")?;
+        if n < len {
+            return Err(FrameError::TruncatedFrame {
+                offset,
+                need: 4 + len,
+                got: 4 + n,
+            }
+            .into());
+        }
+        self.pos += 4 + n;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompactLayout, PostcardFramedWriter};
+
+    fn open(mut file: &[u8]) -> PostcardFramedReader<&[u8]> {
+        let header = CompactFileHeader::read(&mut file).unwrap();
+        PostcardFramedReader::with_header(file, header)
+    }
+
+    fn error(r: &mut PostcardFramedReader<&[u8]>) -> FrameError {
+        let err = loop {
+            match r.read_bytes() {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("no error"),
+                Err(e) => break e,
+            }
+        };
+        err.downcast().unwrap()
+    }
+
+    #[test]
+    fn resyncs_past_bad_frames() {
+        let header = CompactFileHeader::new(CompactLayout::Votes);
+        let mut w = PostcardFramedWriter::with_header(Vec::new(), header).unwrap();
+        let mut starts = Vec::new();
+        for i in 0..4u8 {
+            starts.push(w.position() as usize);
+            w.write_bytes(&[i; 40]).unwrap();
+        }
+        w.finish().unwrap();
+        let file = w.into_inner();
+
+        // Bit rot in frame 1, a wild length in frame 2.
+        let mut rotten = file.clone();
+        rotten[starts[1] + 8 + 3] ^= 1;
+        rotten[starts[2] + 4..starts[2] + 8].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        let offset = starts[1] as u64;
+        assert!(matches!(
+            error(&mut open(&rotten)),
+            FrameError::BadChecksum { offset: o, len: 40, .. } if o == offset
+        ));
+
+        let mut r = open(&rotten).recovering();
+        let mut seen = Vec::new();
+        while r.advance().unwrap() {
+            seen.push((r.frame()[0], r.resynced()));
+        }
+        assert_eq!(seen, [(0, false), (3, true)]);
+        assert_eq!(r.skipped(), (starts[3] - starts[1]) as u64);
+
+        // Lengths above the maximum are refused before allocating.
+        assert_eq!(
+            error(&mut open(&file).with_max_frame_len(39)),
+            FrameError::FrameTooLarge {
+                offset: starts[0] as u64,
+                len: 40,
+                max: 39
+            }
+        );
+
+        // A cut frame has nothing to resync on.
+        let mut r = open(&file[..starts[2] + 10]).recovering();
+        assert_eq!(
+            error(&mut r),
+            FrameError::TruncatedFrame {
+                offset: starts[2] as u64,
+                need: 52,
+                got: 10
+            }
+        );
+
+        // The trailer ends the file, recovering or not.
+        let mut longer = file.clone();
+        longer.push(0);
+        let offset = file.len() as u64;
+        for mut r in [open(&longer), open(&longer).recovering()] {
+            assert_eq!(error(&mut r), FrameError::TrailingBytes { offset });
+        }
     }
 }
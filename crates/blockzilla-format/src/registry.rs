@@ -1,17 +1,35 @@
 use anyhow::{Context, Result};
 use gxhash::GxHasher;
+use memmap2::Mmap;
 use ph::fmph;
 use solana_pubkey::Pubkey;
 use std::hash::{Hash, Hasher};
+
+use crate::{
+    CompactFileHeader, CompactLayout, CompactPubkey, RAW_TAG, TRAILER_LEN, check_trailer,
+    raw_file_body, write_raw_file,
+};
 use std::str::FromStr;
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
 use std::{
     fs::File,
-    io::{BufReader, BufWriter, Read, Write},
-    path::Path,
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
 };
 
+/// registry.idx magic + version
+const INDEX_MAGIC: [u8; 8] = *b"BZKEYIDX";
+const INDEX_VERSION: u32 = 1;
+/// magic + version + key count + mphf byte len
+const INDEX_HEADER_LEN: usize = 8 + 4 + 8 + 8;
+/// Registry keys re-checked against a loaded index
+const INDEX_SPOT_CHECKS: usize = 4096;
+
 #[inline]
-fn gxhash64<T: Hash + ?Sized>(v: &T) -> u64 {
+pub(crate) fn gxhash64<T: Hash + ?Sized>(v: &T) -> u64 {
     let mut h = GxHasher::default();
     v.hash(&mut h);
     h.finish()
@@ -26,64 +44,217 @@ pub struct KeyIndex {
 
     /// Small hot cache for base58 string lookups
     cache: HotCache,
+
+    /// Keys in file order, used to verify MPHF hits
+    store: Arc<KeyStore>,
+
+    /// Lookups that fell outside the registry
+    misses: AtomicU64,
 }
 
 impl KeyIndex {
-    /// Build index over keys in file order.
-    ///
-    /// All lookups are assumed to be members of the registry.
-    pub fn build(keys_in_file_order: Vec<[u8; 32]>) -> Self {
-        let n = keys_in_file_order.len();
-        let hot_cap = n.min(10_000);
+    /// Build index over the registry keys (file order).
+    pub fn build(store: Arc<KeyStore>) -> Self {
+        let cache = HotCache::for_keys(&store.keys);
+        Self::build_with_cache(store, cache)
+    }
 
-        // MPHF build
-        let mphf: fmph::GOFunction = keys_in_file_order.as_slice().into();
+    /// [`KeyIndex::build`] without the base58 hot cache, for short-lived
+    /// indexes over a handful of keys.
+    pub fn build_cold(store: Arc<KeyStore>) -> Self {
+        Self::build_with_cache(store, HotCache::new(0))
+    }
 
-        let mut values = vec![0u32; n];
+    fn build_with_cache(store: Arc<KeyStore>, cache: HotCache) -> Self {
+        let n = store.len();
 
-        // size cache at ~50% load
-        let mut cache = HotCache::new(hot_cap * 2);
+        // MPHF build
+        let mphf: fmph::GOFunction = store.keys.as_slice().into();
 
-        for (i, k) in keys_in_file_order.iter().enumerate() {
-            let id = i as u32 + 1;
+        let mut values = vec![0u32; n];
 
+        for (i, k) in store.keys.iter().enumerate() {
             let idx = mphf.get_or_panic(k) as usize;
             debug_assert!(idx < n);
-            values[idx] = id;
-
-            // populate hot string cache
-            if i < hot_cap {
-                let s = Pubkey::new_from_array(*k).to_string();
-                cache.insert(gxhash64(s.as_bytes()), id);
-            }
+            values[idx] = i as u32 + 1;
         }
 
         Self {
             mphf,
             values,
             cache,
+            store,
+            misses: AtomicU64::new(0),
         }
     }
 
-    /// Fast path: key MUST exist.
+    /// registry.bin -> registry.idx
+    pub fn path_for(registry_path: &Path) -> PathBuf {
+        registry_path.with_extension("idx")
+    }
+
+    /// Persist the MPHF and `values` so the index doesn't have to be rebuilt.
+    ///
+    /// Layout (little endian), between the file header and trailer: magic,
+    /// version u32, key count u64, mphf len u64, mphf (ph serialization),
+    /// values (u32 each).
+    pub fn write(&self, path: &Path, epoch: u64) -> Result<()> {
+        anyhow::ensure!(
+            self.values.len() < RAW_TAG as usize,
+            "{} keys: id {} is the inline key tag",
+            self.values.len(),
+            RAW_TAG
+        );
+        let header = CompactFileHeader::new(CompactLayout::KeyIndex).with_epoch(epoch);
+        write_raw_file(path, header, |w| {
+            w.write_all(&INDEX_MAGIC).context("write index header")?;
+            w.write_all(&INDEX_VERSION.to_le_bytes())
+                .context("write index header")?;
+            w.write_all(&(self.values.len() as u64).to_le_bytes())
+                .context("write index header")?;
+            w.write_all(&(self.mphf.write_bytes() as u64).to_le_bytes())
+                .context("write index header")?;
+
+            self.mphf.write(w).context("write mphf")?;
+            for v in &self.values {
+                w.write_all(&v.to_le_bytes())
+                    .context("write index values")?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Open an index written by [`KeyIndex::write`] and check it matches `store`.
+    ///
+    /// The file is mmapped; only the MPHF and values are copied out. Validation is
+    /// the file hash, header + size checks, every value in
+    /// range, and a spread of registry keys resolving to their own id.
+    pub fn load(path: &Path, store: Arc<KeyStore>) -> Result<Self> {
+        let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        // SAFETY: read-only mapping, nothing writes the index while we parse it.
+        let map = unsafe { Mmap::map(&f) }.with_context(|| format!("mmap {}", path.display()))?;
+        let map = raw_file_body(&map, CompactLayout::KeyIndex)
+            .with_context(|| format!("key index {}", path.display()))?;
+
+        anyhow::ensure!(
+            map.len() >= INDEX_HEADER_LEN && map[..8] == INDEX_MAGIC,
+            "not a key index: {}",
+            path.display()
+        );
+
+        let version = u32::from_le_bytes(map[8..12].try_into().unwrap());
+        anyhow::ensure!(
+            version == INDEX_VERSION,
+            "unsupported key index version {} in {}",
+            version,
+            path.display()
+        );
+
+        let n = u64::from_le_bytes(map[12..20].try_into().unwrap()) as usize;
+        anyhow::ensure!(
+            n == store.len(),
+            "key index has {} keys, registry has {}",
+            n,
+            store.len()
+        );
+
+        let mphf_len = u64::from_le_bytes(map[20..28].try_into().unwrap()) as usize;
+        let values_off = INDEX_HEADER_LEN.saturating_add(mphf_len);
+        anyhow::ensure!(
+            map.len() as u64 == values_off as u64 + n as u64 * 4,
+            "invalid key index size {} in {}",
+            map.len(),
+            path.display()
+        );
+
+        let mphf =
+            fmph::GOFunction::read(&mut &map[INDEX_HEADER_LEN..values_off]).context("read mphf")?;
+
+        let values: Vec<u32> = map[values_off..]
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        anyhow::ensure!(
+            values.iter().all(|&id| id != 0 && id as usize <= n),
+            "key index holds out of range ids"
+        );
+
+        let index = Self {
+            mphf,
+            values,
+            cache: HotCache::for_keys(&store.keys),
+            store,
+            misses: AtomicU64::new(0),
+        };
+
+        let step = (n / INDEX_SPOT_CHECKS).max(1);
+        for i in (0..n).step_by(step).chain(n.checked_sub(1)) {
+            let id = i as u32 + 1;
+            anyhow::ensure!(
+                index.lookup(&index.store.keys[i]) == Some(id),
+                "key index does not match registry (key #{})",
+                id
+            );
+        }
+
+        Ok(index)
+    }
+
+    /// Registry keys backing this index.
+    #[inline]
+    pub fn store(&self) -> &KeyStore {
+        &self.store
+    }
+
+    /// 1-based id of `k`, or `None` if `k` is not in the registry.
+    ///
+    /// The MPHF maps any input to some slot, so the hit is checked against the
+    /// stored key before being trusted.
     #[inline(always)]
-    pub fn lookup_unchecked(&self, k: &[u8; 32]) -> u32 {
-        let idx = self.mphf.get_or_panic(k) as usize;
-        let id = self.values[idx];
-        debug_assert!(id != 0);
-        id
+    pub fn lookup(&self, k: &[u8; 32]) -> Option<u32> {
+        let idx = self.mphf.get(k)? as usize;
+        let id = *self.values.get(idx)?;
+        (self.store.get(id)? == k).then_some(id)
     }
 
     /// Lookup from base58 string.
     ///
-    /// Safe as long as all inputs belong to the registry.
+    /// Hot cache hits (64-bit hash of the full string) save the MPHF probe,
+    /// and are checked against the stored key like [`KeyIndex::lookup`] hits.
     pub fn lookup_str(&self, k: &str) -> Option<u32> {
-        if let Some(id) = self.cache.get(gxhash64(k.as_bytes())) {
-            return Some(id);
+        let pk = Pubkey::from_str(k).ok()?;
+        let hit = self
+            .cache
+            .get(gxhash64(k.as_bytes()))
+            .filter(|&id| self.store.get(id) == Some(pk.as_array()));
+        hit.or_else(|| self.lookup(pk.as_array()))
+    }
+
+    /// Registry id when known, raw key otherwise. Raw fallbacks are counted.
+    #[inline]
+    pub fn compact(&self, k: &[u8; 32]) -> CompactPubkey {
+        match self.lookup(k) {
+            Some(id) => CompactPubkey::Id(id),
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                CompactPubkey::Raw(*k)
+            }
+        }
+    }
+
+    /// Base58 variant of [`KeyIndex::compact`]; `None` if `k` is not a pubkey.
+    pub fn compact_str(&self, k: &str) -> Option<CompactPubkey> {
+        if let Some(id) = self.lookup_str(k) {
+            return Some(CompactPubkey::Id(id));
         }
 
         let pk = Pubkey::from_str(k).ok()?;
-        Some(self.lookup_unchecked(pk.as_array()))
+        Some(self.compact(pk.as_array()))
+    }
+
+    /// Number of keys stored raw because they were missing from the registry.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
     }
 }
 
@@ -110,42 +281,74 @@ impl KeyStore {
         self.keys.get(id.checked_sub(1)? as usize)
     }
 
-    /// Sequential load, no extra buffers.
+    /// Sequential load, no extra buffers. Headerless (pre-v13) registries
+    /// are raw keys.
     pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::load_as(path, CompactLayout::Registry)?.1)
+    }
+
+    /// [`KeyStore::load`] of any file of raw keys, with its header (`LEGACY`
+    /// when there is none).
+    pub(crate) fn load_as(path: &Path, layout: CompactLayout) -> Result<(CompactFileHeader, Self)> {
         let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
         let len_bytes = f.metadata().context("stat registry")?.len() as usize;
+        let mut r = BufReader::with_capacity(64 << 20, f);
 
+        let header = CompactFileHeader::read(&mut r)?;
+        // Only registry.bin predates the file header.
         anyhow::ensure!(
-            len_bytes.is_multiple_of(32),
+            header.version > 1 || layout == CompactLayout::Registry,
+            "{} has no file header",
+            path.display()
+        );
+        if header.version > 1 {
+            header.expect(layout)?;
+        }
+        let mut hash = header.uses_checksums().then(|| {
+            let mut h = blake3::Hasher::new();
+            h.update(&header.to_bytes());
+            h
+        });
+        let trailer_len = if hash.is_some() { TRAILER_LEN } else { 0 };
+        let body = len_bytes
+            .checked_sub(header.encoded_len() + trailer_len)
+            .context("registry has no trailer (truncated?)")?;
+
+        anyhow::ensure!(
+            body.is_multiple_of(32),
             "invalid registry size {} (not multiple of 32)",
             len_bytes
         );
 
-        let n = len_bytes / 32;
-        let mut r = BufReader::with_capacity(64 << 20, f);
-
+        let n = body / 32;
         let mut keys = Vec::with_capacity(n);
         for _ in 0..n {
             let mut a = [0u8; 32];
             r.read_exact(&mut a).context("read pubkey")?;
+            if let Some(h) = hash.as_mut() {
+                h.update(&a);
+            }
             keys.push(a);
         }
 
-        Ok(Self { keys })
-    }
-}
-
-/// Write registry.bin (raw 32-byte pubkeys, no header)
-pub fn write_registry(path: &Path, keys: &[[u8; 32]]) -> Result<()> {
-    let f = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
-    let mut w = BufWriter::with_capacity(64 << 20, f);
+        if let Some(h) = hash {
+            let mut trailer = [0u8; TRAILER_LEN];
+            r.read_exact(&mut trailer)
+                .context("read registry trailer")?;
+            check_trailer(&trailer, h.finalize())
+                .with_context(|| format!("registry {}", path.display()))?;
+        }
 
-    for k in keys {
-        w.write_all(k).context("write pubkey")?;
+        Ok((header, Self { keys }))
     }
+}
 
-    w.flush().context("flush registry")?;
-    Ok(())
+/// Write registry.bin: file header, raw 32-byte pubkeys, trailer.
+pub fn write_registry(path: &Path, epoch: u64, keys: &[[u8; 32]]) -> Result<()> {
+    let header = CompactFileHeader::new(CompactLayout::Registry).with_epoch(epoch);
+    write_raw_file(path, header, |w| {
+        w.write_raw(keys.as_flattened()).context("write pubkeys")
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -156,6 +359,19 @@ struct HotCache {
 }
 
 impl HotCache {
+    /// Cache the base58 form of the first (most used) keys.
+    fn for_keys(keys_in_file_order: &[[u8; 32]]) -> Self {
+        let hot_cap = keys_in_file_order.len().min(10_000);
+
+        // size cache at ~50% load
+        let mut cache = HotCache::new(hot_cap * 2);
+        for (i, k) in keys_in_file_order[..hot_cap].iter().enumerate() {
+            let s = Pubkey::new_from_array(*k).to_string();
+            cache.insert(gxhash64(s.as_bytes()), i as u32 + 1);
+        }
+        cache
+    }
+
     fn new(capacity: usize) -> Self {
         let cap = capacity.next_power_of_two().max(8);
         Self {
@@ -192,4 +408,82 @@ impl HotCache {
             i = (i + 1) & self.mask;
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(n: usize, salt: u8) -> Vec<[u8; 32]> {
+        (0..n as u32)
+            .map(|i| {
+                let mut k = [salt; 32];
+                k[..4].copy_from_slice(&i.to_le_bytes());
+                k[4..8].copy_from_slice(&i.wrapping_mul(0x9E37_79B9).to_le_bytes());
+                k
+            })
+            .collect()
+    }
+
+    #[test]
+    fn index_round_trips_through_disk() {
+        let store = KeyStore {
+            keys: keys(5_000, 7),
+        };
+        let path = std::env::temp_dir().join(format!("bz-keyidx-{}.idx", std::process::id()));
+
+        let store = Arc::new(store);
+        KeyIndex::build(store.clone()).write(&path, 3).unwrap();
+        let index = KeyIndex::load(&path, store.clone()).unwrap();
+
+        for (i, k) in store.keys.iter().enumerate() {
+            assert_eq!(index.lookup(k), Some(i as u32 + 1));
+        }
+        let b58 = Pubkey::new_from_array(store.keys[42]).to_string();
+        assert_eq!(index.lookup_str(&b58), Some(43));
+
+        // Same size, different keys: must be rejected.
+        let other = KeyStore {
+            keys: keys(5_000, 9),
+        };
+        assert!(KeyIndex::load(&path, Arc::new(other)).is_err());
+
+        // Bit rot is caught by the file hash.
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last_value = bytes.len() - TRAILER_LEN - 1;
+        bytes[last_value] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        let err = KeyIndex::load(&path, store).err().unwrap();
+        assert!(format!("{err:#}").contains("does not match its content"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unknown_keys_are_not_resolved() {
+        let index = KeyIndex::build(Arc::new(KeyStore {
+            keys: keys(1_000, 7),
+        }));
+
+        for k in keys(1_000, 9) {
+            assert_eq!(index.lookup(&k), None);
+            assert_eq!(index.compact(&k), CompactPubkey::Raw(k));
+        }
+        assert_eq!(index.misses(), 1_000);
+
+        let b58 = Pubkey::new_from_array([9; 32]).to_string();
+        assert_eq!(index.lookup_str(&b58), None);
+        assert_eq!(index.compact_str(&b58), Some(CompactPubkey::Raw([9; 32])));
+        assert_eq!(index.compact_str("not a pubkey"), None);
+    }
+
+    #[test]
+    fn hot_cache_hits_are_checked() {
+        let store = KeyStore { keys: keys(100, 7) };
+        let mut index = KeyIndex::build(Arc::new(store));
+        // A string whose hash collides with a cached one.
+        let b58 = Pubkey::new_from_array([9; 32]).to_string();
+        index.cache.insert(gxhash64(b58.as_bytes()), 1);
+        assert_eq!(index.lookup_str(&b58), None);
+    }
+}
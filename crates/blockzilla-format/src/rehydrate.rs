@@ -0,0 +1,708 @@
+use anyhow::{Context, Result};
+use car_reader::{
+    confirmed_block::{
+        InnerInstruction, InnerInstructions, ReturnData, Reward, TokenBalance, TransactionError,
+        TransactionStatusMeta,
+    },
+    versioned_transaction::{
+        CompiledInstruction, LegacyMessage, MessageAddressTableLookup, MessageHeader, V0Message,
+        VersionedMessage, VersionedTransaction,
+    },
+};
+use solana_pubkey::Pubkey;
+use std::{borrow::Cow, fmt};
+
+use crate::{
+    BalanceChain, BlockLogTables, BlockhashRegistry, CompactBlockRecord, CompactFileHeader,
+    CompactInstruction, CompactMessage, CompactMessageHeader, CompactMetaV1, CompactPubkey,
+    CompactRecentBlockhash, CompactReward, CompactTokenAmount, CompactTokenBalanceChange,
+    CompactTransaction, CompactVoteTx, KeyStore, LogDicts, VOTE_PROGRAM, VoteChain, balance_keys,
+    decode_ix_data, decode_log_keys, decode_tx_error, instructions, is_vote_only,
+    render_block_logs,
+};
+
+/// A compact block turned back into CAR-level types.
+///
+/// Keys and hashes borrow from the registries (or from the record for keys
+/// stored inline), everything else is owned.
+#[derive(Debug, Clone)]
+pub struct RehydratedBlock<'a> {
+    pub slot: u64,
+    pub parent_slot: u64,
+    pub blockhash: Option<&'a [u8; 32]>,
+    pub previous_blockhash: Option<&'a [u8; 32]>,
+    pub block_time: Option<i64>,
+    pub block_height: Option<u64>,
+    /// Empty before format v11.
+    pub rewards: Vec<Reward>,
+    /// `None` when the file has no entries (before format v12).
+    pub entries: Option<Vec<RehydratedEntry<'a>>>,
+    pub transactions: Vec<(VersionedTransaction<'a>, Option<TransactionStatusMeta>)>,
+}
+
+/// One PoH entry, holding the `tx_count` transactions after those of the
+/// entries before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RehydratedEntry<'a> {
+    pub num_hashes: u64,
+    pub hash: &'a [u8; 32],
+    pub tx_count: u32,
+}
+
+/// The transaction a [`rehydrate_block`] error comes from, as error context:
+/// `err.downcast_ref::<TxAt>()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxAt {
+    pub slot: u64,
+    /// Position in the block
+    pub tx: usize,
+}
+
+impl fmt::Display for TxAt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "slot {} tx {}", self.slot, self.tx)
+    }
+}
+
+/// `file` is the header of the file `block` was read from. `votes` carries
+/// vote deltas from block to block: blocks go through it in file order.
+pub fn rehydrate_block<'a>(
+    block: &'a CompactBlockRecord<'a>,
+    file: CompactFileHeader,
+    store: &'a KeyStore,
+    bh: &'a BlockhashRegistry,
+    dicts: &LogDicts,
+    votes: &mut VoteChain,
+) -> Result<RehydratedBlock<'a>> {
+    let h = &block.header;
+    votes.begin_block(h);
+    let mut balances = BalanceChain::default();
+
+    // Header ids are block ordinals; the first block's parent hash is the
+    // newest entry of the previous epoch tail.
+    let previous_blockhash = if h.blockhash == 0 {
+        bh.get(-1)
+    } else {
+        bh.get(h.previous_blockhash as i32)
+    };
+
+    let log_tables = if file.uses_key_refs() {
+        decode_log_keys(&block.log_tables, store)
+            .with_context(|| format!("slot {} log tables", h.slot))?
+    } else {
+        Cow::Borrowed(&block.log_tables)
+    };
+
+    let rewards = rehydrate_rewards(&block.rewards, store)
+        .with_context(|| format!("slot {} rewards", h.slot))?;
+
+    let blockhash = bh.get(h.blockhash as i32);
+    let entries = block
+        .entries
+        .as_ref()
+        .map(|entries| {
+            entries
+                .iter()
+                .map(|e| {
+                    Ok(RehydratedEntry {
+                        num_hashes: e.num_hashes,
+                        hash: e.hash.as_ref().or(blockhash).with_context(|| {
+                            format!("slot {} last entry: blockhash not in registry", h.slot)
+                        })?,
+                        tx_count: e.tx_count,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .transpose()?;
+
+    let transactions = block
+        .txs
+        .iter()
+        .enumerate()
+        .map(|(i, t)| {
+            let at = TxAt {
+                slot: h.slot,
+                tx: i,
+            };
+            if let CompactMessage::Vote(v) = &t.tx.message {
+                return rehydrate_vote(&t.tx, v, store, bh, votes).context(at);
+            }
+            let tx = rehydrate_transaction(&t.tx, file, store, bh).context(at)?;
+            let meta = t
+                .metadata
+                .as_ref()
+                .map(|m| {
+                    let keys = balance_keys(&t.tx, m);
+                    let b = m.balances(file, &keys, &mut balances, is_vote_only(&tx))?;
+                    rehydrate_meta(m, b, file, &log_tables, dicts, store)
+                })
+                .transpose()
+                .context("meta")
+                .context(at)?;
+            Ok((tx, meta))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(RehydratedBlock {
+        slot: h.slot,
+        parent_slot: h.parent_slot,
+        blockhash,
+        previous_blockhash,
+        block_time: h.block_time,
+        block_height: h.block_height,
+        rewards,
+        entries,
+        transactions,
+    })
+}
+
+pub fn rehydrate_transaction<'a>(
+    tx: &'a CompactTransaction<'a>,
+    file: CompactFileHeader,
+    store: &'a KeyStore,
+    bh: &'a BlockhashRegistry,
+) -> Result<VersionedTransaction<'a>> {
+    let signatures = tx.signatures.iter().map(|s| s.0).collect();
+
+    let message = match &tx.message {
+        CompactMessage::Legacy(m) => VersionedMessage::Legacy(LegacyMessage {
+            header: header(&m.header),
+            account_keys: resolve_keys(&m.account_keys, store)?,
+            recent_blockhash: recent_blockhash(&m.recent_blockhash, bh)?,
+            instructions: instructions(&m.instructions, file, store)?,
+        }),
+        CompactMessage::V0(m) => VersionedMessage::V0(V0Message {
+            header: header(&m.header),
+            account_keys: resolve_keys(&m.account_keys, store)?,
+            recent_blockhash: recent_blockhash(&m.recent_blockhash, bh)?,
+            instructions: instructions(&m.instructions, file, store)?,
+            address_table_lookups: m
+                .address_table_lookups
+                .iter()
+                .map(|l| {
+                    Ok(MessageAddressTableLookup {
+                        account_key: resolve(&l.account_key, store)?,
+                        writable_indexes: l.writable_indexes.to_vec(),
+                        readonly_indexes: l.readonly_indexes.to_vec(),
+                    })
+                })
+                .collect::<Result<_>>()?,
+        }),
+        CompactMessage::Vote(_) => {
+            anyhow::bail!("vote transactions are rehydrated with their block (rehydrate_block)")
+        }
+    };
+
+    Ok(VersionedTransaction {
+        signatures,
+        message,
+    })
+}
+
+/// A vote record and the meta it carries, its vote taken off `votes`.
+fn rehydrate_vote<'a>(
+    tx: &'a CompactTransaction<'a>,
+    v: &'a CompactVoteTx<'a>,
+    store: &'a KeyStore,
+    bh: &'a BlockhashRegistry,
+    votes: &mut VoteChain,
+) -> Result<(VersionedTransaction<'a>, Option<TransactionStatusMeta>)> {
+    let ix = votes.decode(v.vote_account, &v.vote)?;
+    let data = instructions::NativeIx::Vote(ix).to_bytes(store)?;
+
+    let mut account_keys = Vec::with_capacity(v.sysvars.len() + 3);
+    account_keys.push(resolve(&v.authority, store)?);
+    account_keys.push(resolve(&v.vote_account, store)?);
+    for k in &v.sysvars {
+        account_keys.push(resolve(k, store)?);
+    }
+    account_keys.push(VOTE_PROGRAM.as_array());
+
+    let message = VersionedMessage::Legacy(LegacyMessage {
+        header: v.header(),
+        recent_blockhash: recent_blockhash(&v.recent_blockhash, bh)?,
+        instructions: vec![CompiledInstruction {
+            program_id_index: (account_keys.len() - 1) as u8,
+            accounts: v.accounts.to_vec(),
+            data,
+        }],
+        account_keys,
+    });
+    let tx = VersionedTransaction {
+        signatures: tx.signatures.iter().map(|s| s.0).collect(),
+        message,
+    };
+    Ok((tx, Some(v.meta()?)))
+}
+
+/// `balances` are the decoded (pre, post) balances ([`CompactMetaV1::balances`]),
+/// `tables` the log tables of the block the meta came from, key refs
+/// expanded, `dicts` the log dictionaries of its file (empty before format v3).
+pub fn rehydrate_meta(
+    meta: &CompactMetaV1,
+    (pre_balances, post_balances): (Vec<u64>, Vec<u64>),
+    file: CompactFileHeader,
+    tables: &BlockLogTables,
+    dicts: &LogDicts,
+    store: &KeyStore,
+) -> Result<TransactionStatusMeta> {
+    let (inner_instructions, inner_instructions_none) = match &meta.inner_instructions {
+        None => (Vec::new(), true),
+        Some(v) => (
+            v.iter()
+                .map(|ii| {
+                    Ok(InnerInstructions {
+                        index: ii.index,
+                        instructions: ii
+                            .instructions
+                            .iter()
+                            .map(|ix| {
+                                let data = if file.uses_key_refs() {
+                                    decode_ix_data(&ix.data, store)?.into_owned()
+                                } else {
+                                    ix.data.clone()
+                                };
+                                Ok(InnerInstruction {
+                                    program_id_index: ix.program_id_index,
+                                    accounts: ix.accounts.clone(),
+                                    data,
+                                    stack_height: ix.stack_height,
+                                })
+                            })
+                            .collect::<Result<_>>()?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            false,
+        ),
+    };
+
+    let (log_messages, log_messages_none) = match &meta.logs {
+        None => (Vec::new(), true),
+        Some(logs) => (render_block_logs(logs, file, tables, dicts, store)?, false),
+    };
+
+    let (return_data, return_data_none) = match &meta.return_data {
+        None => (None, true),
+        Some(rd) => (
+            Some(ReturnData {
+                program_id: resolve(&rd.program_id_index, store)?.to_vec(),
+                data: rd.data.clone(),
+            }),
+            false,
+        ),
+    };
+
+    let token_balances = |post: bool| -> Result<Vec<TokenBalance>> {
+        meta.token_balances
+            .iter()
+            .filter_map(|c| {
+                let amount = if post { c.post_amount() } else { c.pre.clone() }?;
+                Some(token_balance(c, &amount, store))
+            })
+            .collect()
+    };
+
+    let rewards = rehydrate_rewards(&meta.rewards, store)?;
+
+    let err = match &meta.err {
+        None => None,
+        Some(e) if file.uses_typed_errors() => Some(TransactionError {
+            err: decode_tx_error(e)?,
+        }),
+        Some(e) => Some(TransactionError { err: e.clone() }),
+    };
+
+    let addresses = |v: &[CompactPubkey]| -> Result<Vec<Vec<u8>>> {
+        v.iter().map(|k| Ok(resolve(k, store)?.to_vec())).collect()
+    };
+
+    Ok(TransactionStatusMeta {
+        err,
+        fee: meta.fee,
+        pre_balances,
+        post_balances,
+
+        inner_instructions,
+        inner_instructions_none,
+
+        log_messages,
+        log_messages_none,
+
+        pre_token_balances: token_balances(false)?,
+        post_token_balances: token_balances(true)?,
+
+        rewards,
+
+        loaded_writable_addresses: addresses(&meta.loaded_writable_indices)?,
+        loaded_readonly_addresses: addresses(&meta.loaded_readonly_indices)?,
+
+        return_data,
+        return_data_none,
+
+        compute_units_consumed: meta.compute_units_consumed,
+        cost_units: meta.cost_units,
+    })
+}
+
+#[inline]
+fn resolve<'a>(k: &'a CompactPubkey, store: &'a KeyStore) -> Result<&'a [u8; 32]> {
+    k.resolve(store)
+        .with_context(|| format!("pubkey {:?} not in registry ({} keys)", k, store.len()))
+}
+
+fn rehydrate_rewards(rewards: &[CompactReward], store: &KeyStore) -> Result<Vec<Reward>> {
+    rewards
+        .iter()
+        .map(|rw| {
+            Ok(Reward {
+                pubkey: pubkey_string(&rw.pubkey_index, store)?,
+                lamports: rw.lamports,
+                post_balance: rw.post_balance,
+                reward_type: rw.reward_type,
+                commission: rw.commission.map(|c| c.to_string()).unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+#[inline]
+fn resolve_keys<'a>(keys: &'a [CompactPubkey], store: &'a KeyStore) -> Result<Vec<&'a [u8; 32]>> {
+    keys.iter().map(|k| resolve(k, store)).collect()
+}
+
+/// Base58 key, empty for [`CompactPubkey::NONE`].
+#[inline]
+fn pubkey_string(k: &CompactPubkey, store: &KeyStore) -> Result<String> {
+    if *k == CompactPubkey::NONE {
+        return Ok(String::new());
+    }
+    Ok(Pubkey::new_from_array(*resolve(k, store)?).to_string())
+}
+
+#[inline]
+fn header(h: &CompactMessageHeader) -> MessageHeader {
+    MessageHeader {
+        num_required_signatures: h.num_required_signatures,
+        num_readonly_signed_accounts: h.num_readonly_signed_accounts,
+        num_readonly_unsigned_accounts: h.num_readonly_unsigned_accounts,
+    }
+}
+
+#[inline]
+fn recent_blockhash<'a>(
+    rb: &CompactRecentBlockhash<'a>,
+    bh: &'a BlockhashRegistry,
+) -> Result<&'a [u8; 32]> {
+    match rb {
+        CompactRecentBlockhash::Id(id) => bh
+            .get(*id)
+            .with_context(|| format!("blockhash id {} not in registry", id)),
+        CompactRecentBlockhash::Nonce(n) => Ok(n.0),
+    }
+}
+
+#[inline]
+fn instructions(
+    ixs: &[CompactInstruction<'_>],
+    file: CompactFileHeader,
+    store: &KeyStore,
+) -> Result<Vec<CompiledInstruction>> {
+    ixs.iter()
+        .map(|ix| {
+            let data = if file.uses_native_ix() {
+                instructions::decode_data(&ix.data, store)?.into_owned()
+            } else if file.uses_key_refs() {
+                decode_ix_data(&ix.data, store)?.into_owned()
+            } else {
+                ix.data.to_vec()
+            };
+            Ok(CompiledInstruction {
+                program_id_index: ix.program_id_index,
+                accounts: ix.accounts.to_vec(),
+                data,
+            })
+        })
+        .collect()
+}
+
+fn token_balance(
+    tb: &CompactTokenBalanceChange,
+    amount: &CompactTokenAmount,
+    store: &KeyStore,
+) -> Result<TokenBalance> {
+    Ok(TokenBalance {
+        account_index: tb.account_index,
+        mint: pubkey_string(&tb.mint_index, store)?,
+        ui_token_amount: amount.ui_token_amount(tb.decimals)?,
+        owner: pubkey_string(&tb.owner_index, store)?,
+        program_id: pubkey_string(&tb.program_id_index, store)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CompactBlockHeader, CompactLayout, CompactTxWithMeta, KeyIndex, LogInterner, StringDict,
+        compact_meta_from_proto, encode_log_keys, to_compact_transaction,
+    };
+    use car_reader::{confirmed_block::UiTokenAmount, real_number_string_trimmed};
+    use std::{str::FromStr, sync::Arc};
+
+    fn key(i: u8) -> [u8; 32] {
+        let mut k = [i; 32];
+        k[0] = 0xA0 ^ i;
+        k
+    }
+
+    fn b58(k: [u8; 32]) -> String {
+        Pubkey::new_from_array(k).to_string()
+    }
+
+    #[test]
+    fn meta_round_trips_through_compact() {
+        let cb = Pubkey::from_str("ComputeBudget111111111111111111111111111111")
+            .unwrap()
+            .to_bytes();
+        let store = Arc::new(KeyStore {
+            keys: vec![cb, key(1), key(2), key(3)],
+        });
+        let index = KeyIndex::build(store.clone());
+
+        let token = |account_index, amount: u64, decimals: u8, owner: [u8; 32]| TokenBalance {
+            account_index,
+            mint: b58(key(2)),
+            ui_token_amount: Some(UiTokenAmount {
+                ui_amount: amount as f64 / 10f64.powi(decimals as i32),
+                decimals: decimals as u32,
+                amount: amount.to_string(),
+                ui_amount_string: real_number_string_trimmed(amount, decimals),
+            }),
+            owner: b58(owner),
+            program_id: String::new(),
+        };
+
+        let meta = TransactionStatusMeta {
+            err: Some(TransactionError {
+                err: vec![8, 0, 0, 0, 1, 0, 0, 0],
+            }),
+            fee: 5000,
+            pre_balances: vec![10, 20],
+            post_balances: vec![5, 20],
+            inner_instructions: vec![InnerInstructions {
+                index: 0,
+                instructions: vec![InnerInstruction {
+                    program_id_index: 1,
+                    accounts: vec![0, 1],
+                    data: vec![2, 0, 0, 0],
+                    stack_height: Some(2),
+                }],
+            }],
+            inner_instructions_none: false,
+            log_messages: vec![
+                format!("Program {} invoke [1]", b58(cb)),
+                "shared across the epoch".to_string(),
+                "only in this block".to_string(),
+                format!("Program {} success", b58(cb)),
+            ],
+            log_messages_none: false,
+            pre_token_balances: vec![token(1, 1_500_000, 6, key(3))],
+            // Owner outside the registry is kept inline.
+            post_token_balances: vec![token(1, 0, 6, key(9))],
+            rewards: vec![Reward {
+                pubkey: b58(key(1)),
+                lamports: -7,
+                post_balance: 3,
+                reward_type: 2,
+                commission: "5".to_string(),
+            }],
+            loaded_writable_addresses: vec![key(3).to_vec()],
+            loaded_readonly_addresses: vec![key(8).to_vec()],
+            return_data: Some(ReturnData {
+                program_id: key(1).to_vec(),
+                data: vec![1, 2, 3],
+            }),
+            return_data_none: false,
+            compute_units_consumed: Some(1234),
+            cost_units: None,
+        };
+
+        let dicts = Arc::new(LogDicts {
+            strings: StringDict::new(vec!["shared across the epoch".into()]),
+            ..LogDicts::default()
+        });
+        let mut logs = LogInterner::with_dicts(dicts.clone());
+        let mut compact = compact_meta_from_proto(&meta, &index, &mut logs).unwrap();
+        compact.encode_balances(&[], None);
+        let tables = logs.take();
+        assert_eq!(index.misses(), 2);
+        // Dictionary hits stay out of the block table.
+        assert_eq!(tables.strings.strings, ["only in this block"]);
+
+        let bytes = postcard::to_allocvec(&compact).unwrap();
+        let compact: CompactMetaV1 = postcard::from_bytes(&bytes).unwrap();
+        let file = CompactFileHeader::new(CompactLayout::Plain);
+        let balances = compact
+            .balances(file, &[], &mut BalanceChain::default(), false)
+            .unwrap();
+
+        assert_eq!(
+            rehydrate_meta(&compact, balances, file, &tables, &dicts, &store).unwrap(),
+            meta
+        );
+    }
+
+    const SYSTEM: [u8; 32] = [0; 32];
+
+    fn ix(program_id_index: u8, accounts: &[u8], data: &[u8]) -> CompiledInstruction {
+        CompiledInstruction {
+            program_id_index,
+            accounts: accounts.to_vec(),
+            data: data.to_vec(),
+        }
+    }
+
+    /// A legacy transfer plus a call carrying a registry key in its data,
+    /// and a v0 call through a lookup table on a durable nonce.
+    fn transactions<'a>(
+        keys: &'a [[u8; 32]],
+        sigs: &'a [[u8; 64]],
+        hash: &'a [u8; 32],
+    ) -> [VersionedTransaction<'a>; 2] {
+        let header = MessageHeader {
+            num_required_signatures: 1,
+            num_readonly_signed_accounts: 0,
+            num_readonly_unsigned_accounts: 1,
+        };
+        let mut transfer = vec![2, 0, 0, 0];
+        transfer.extend_from_slice(&5u64.to_le_bytes());
+        let mut call = vec![9];
+        call.extend_from_slice(&keys[2]);
+
+        let legacy = VersionedTransaction {
+            signatures: vec![&sigs[0]],
+            message: VersionedMessage::Legacy(LegacyMessage {
+                header,
+                account_keys: vec![&keys[1], &keys[2], &keys[0], &keys[3]],
+                recent_blockhash: hash,
+                instructions: vec![ix(2, &[0, 1], &transfer), ix(3, &[1, 0], &call)],
+            }),
+        };
+        let v0 = VersionedTransaction {
+            signatures: vec![&sigs[1], &sigs[2]],
+            message: VersionedMessage::V0(V0Message {
+                header: MessageHeader {
+                    num_required_signatures: 2,
+                    ..header
+                },
+                // Outside the registry, kept inline.
+                account_keys: vec![&keys[1], &keys[4], &keys[3]],
+                recent_blockhash: &keys[5],
+                instructions: vec![ix(2, &[0, 1, 3, 4], &[7, 7, 7])],
+                address_table_lookups: vec![MessageAddressTableLookup {
+                    account_key: &keys[2],
+                    writable_indexes: vec![0],
+                    readonly_indexes: vec![4, 1],
+                }],
+            }),
+        };
+        [legacy, v0]
+    }
+
+    fn registries() -> (Vec<[u8; 32]>, Arc<KeyStore>, BlockhashRegistry) {
+        let keys = vec![SYSTEM, key(1), key(2), key(3), key(9), key(10)];
+        let store = Arc::new(KeyStore {
+            keys: keys[..4].to_vec(),
+        });
+        let bh = BlockhashRegistry::new(vec![[0xB0; 32], [0xB1; 32]], vec![[0xAF; 32]]);
+        (keys, store, bh)
+    }
+
+    #[test]
+    fn transactions_round_trip_through_compact() {
+        let (keys, store, bh) = registries();
+        let index = KeyIndex::build(store.clone());
+        let sigs = [[1; 64], [2; 64], [3; 64]];
+        let file = CompactFileHeader::new(CompactLayout::Plain);
+
+        for vtx in transactions(&keys, &sigs, &bh.hashes[1]) {
+            let compact = to_compact_transaction(&vtx, &index, &bh.index).unwrap();
+            let bytes = postcard::to_allocvec(&compact).unwrap();
+            let compact: CompactTransaction = postcard::from_bytes(&bytes).unwrap();
+            assert_eq!(
+                rehydrate_transaction(&compact, file, &store, &bh).unwrap(),
+                vtx
+            );
+        }
+    }
+
+    #[test]
+    fn block_round_trips_through_compact() {
+        let (keys, store, bh) = registries();
+        let index = KeyIndex::build(store.clone());
+        let sigs = [[1; 64], [2; 64], [3; 64]];
+        let file = CompactFileHeader::new(CompactLayout::Plain);
+        let dicts = Arc::new(LogDicts::default());
+        let txs = transactions(&keys, &sigs, &bh.prev_tail[0]);
+
+        let meta = TransactionStatusMeta {
+            fee: 5000,
+            pre_balances: vec![100_000, 0, 1, 1],
+            post_balances: vec![94_995, 5, 1, 1],
+            log_messages: vec![format!("Program {} invoke [1]", b58(key(3)))],
+            return_data_none: true,
+            ..TransactionStatusMeta::default()
+        };
+
+        let mut logs = LogInterner::with_dicts(dicts.clone());
+        let mut balances = BalanceChain::default();
+        let compact_txs = txs
+            .iter()
+            .enumerate()
+            .map(|(i, vtx)| {
+                let tx = to_compact_transaction(vtx, &index, &bh.index).unwrap();
+                let metadata = (i == 0).then(|| {
+                    let mut m = compact_meta_from_proto(&meta, &index, &mut logs).unwrap();
+                    m.encode_balances(&balance_keys(&tx, &m), Some(&mut balances));
+                    m
+                });
+                CompactTxWithMeta { tx, metadata }
+            })
+            .collect();
+        let mut log_tables = logs.take();
+        encode_log_keys(&mut log_tables, &index);
+
+        let record = CompactBlockRecord {
+            header: CompactBlockHeader {
+                slot: 7,
+                parent_slot: 6,
+                blockhash: 1,
+                previous_blockhash: 0,
+                block_time: Some(1_600_000_000),
+                block_height: Some(5),
+            },
+            log_tables,
+            rewards: Vec::new(),
+            entries: None,
+            txs: compact_txs,
+        };
+        let bytes = postcard::to_allocvec(&record).unwrap();
+        let record: CompactBlockRecord = postcard::from_bytes(&bytes).unwrap();
+
+        let block = rehydrate_block(
+            &record,
+            file,
+            &store,
+            &bh,
+            &dicts,
+            &mut VoteChain::default(),
+        )
+        .unwrap();
+        assert_eq!(block.blockhash, Some(&bh.hashes[1]));
+        assert_eq!(block.previous_blockhash, Some(&bh.hashes[0]));
+        let [legacy, v0] = txs.clone();
+        assert_eq!(block.transactions, vec![(legacy, Some(meta)), (v0, None)]);
+    }
+}
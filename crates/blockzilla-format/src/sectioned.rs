@@ -0,0 +1,492 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    BlockLogTables, CompactBlockHeader, CompactBlockRecord, CompactEntry, CompactFileHeader,
+    CompactInnerInstructions, CompactLogStream, CompactMetaV1, CompactPubkey, CompactReturnData,
+    CompactReward, CompactTransaction, CompactTxWithMeta, DataTable, LegacyTokenBalances, LogEvent,
+    StringTable, upgrade_token_balances,
+};
+
+/// Sections of one sectioned transaction, in encoding order.
+///
+/// Every section is a varint byte length followed by postcard bytes. An empty
+/// section means "absent": no meta for `Status`/`Balances`/`TokenBalances`,
+/// `None` for `InnerInstructions` and `LogEvents`, and empty tables for
+/// `LogStrings`/`LogData` (the usual case: logs point into the block tables).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxSection {
+    /// `CompactTransaction`
+    Tx,
+    /// err, fee, rewards, loaded addresses, return data, compute/cost units
+    Status,
+    /// (pre_balances, post_balances)
+    Balances,
+    /// `Vec<CompactTokenBalanceChange>`, `LegacyTokenBalances` before format v10
+    TokenBalances,
+    /// `Vec<CompactInnerInstructions>`
+    InnerInstructions,
+    /// `Vec<LogEvent>`
+    LogEvents,
+    /// `StringTable`
+    LogStrings,
+    /// `DataTable`
+    LogData,
+}
+
+pub const TX_SECTIONS: usize = 8;
+
+#[derive(Serialize)]
+struct StatusRef<'a> {
+    err: &'a Option<Vec<u8>>,
+    fee: u64,
+    rewards: &'a [CompactReward],
+    loaded_writable_indices: &'a [CompactPubkey],
+    loaded_readonly_indices: &'a [CompactPubkey],
+    return_data: &'a Option<CompactReturnData>,
+    compute_units_consumed: Option<u64>,
+    cost_units: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Status {
+    err: Option<Vec<u8>>,
+    fee: u64,
+    rewards: Vec<CompactReward>,
+    loaded_writable_indices: Vec<CompactPubkey>,
+    loaded_readonly_indices: Vec<CompactPubkey>,
+    return_data: Option<CompactReturnData>,
+    compute_units_consumed: Option<u64>,
+    cost_units: Option<u64>,
+}
+
+#[inline]
+fn put<T: Serialize>(out: &mut Vec<u8>, v: Option<T>) -> Result<()> {
+    let len = match &v {
+        Some(v) => postcard::experimental::serialized_size(v)? as u32,
+        None => 0,
+    };
+    postcard::to_io(&len, &mut *out)?;
+    if let Some(v) = v {
+        postcard::to_io(&v, &mut *out)?;
+    }
+    Ok(())
+}
+
+/// Append one transaction in the sectioned layout.
+pub fn write_sectioned_tx(tx: &CompactTxWithMeta<'_>, out: &mut Vec<u8>) -> Result<()> {
+    let meta = tx.metadata.as_ref();
+    let logs = meta.and_then(|m| m.logs.as_ref());
+
+    put(out, Some(&tx.tx))?;
+    put(
+        out,
+        meta.map(|m| StatusRef {
+            err: &m.err,
+            fee: m.fee,
+            rewards: &m.rewards,
+            loaded_writable_indices: &m.loaded_writable_indices,
+            loaded_readonly_indices: &m.loaded_readonly_indices,
+            return_data: &m.return_data,
+            compute_units_consumed: m.compute_units_consumed,
+            cost_units: m.cost_units,
+        }),
+    )?;
+    put(out, meta.map(|m| (&m.pre_balances, &m.post_balances)))?;
+    put(out, meta.map(|m| &m.token_balances))?;
+    put(out, meta.and_then(|m| m.inner_instructions.as_ref()))?;
+    put(out, logs.map(|l| &l.events))?;
+    put(
+        out,
+        logs.map(|l| &l.strings).filter(|t| !t.strings.is_empty()),
+    )?;
+    put(out, logs.map(|l| &l.data).filter(|t| !t.arrays.is_empty()))?;
+    Ok(())
+}
+
+/// Append the block log tables: a strings section then a data section.
+/// They go between the block header and the tx count.
+pub fn write_sectioned_tables(tables: &BlockLogTables, out: &mut Vec<u8>) -> Result<()> {
+    put(out, Some(&tables.strings))?;
+    put(out, Some(&tables.data))
+}
+
+/// Append the block rewards section, right after the log tables (format v11).
+pub fn write_sectioned_rewards(rewards: &[CompactReward], out: &mut Vec<u8>) -> Result<()> {
+    put(out, Some(rewards))
+}
+
+/// Append the block entries section, right after the rewards (format v12).
+/// `None` leaves it empty.
+pub fn write_sectioned_entries(entries: Option<&[CompactEntry]>, out: &mut Vec<u8>) -> Result<()> {
+    put(out, entries)
+}
+
+#[inline]
+fn take_section(buf: &[u8]) -> Result<(&[u8], &[u8])> {
+    let (len, rest) = postcard::take_from_bytes::<u32>(buf).context("section len")?;
+    anyhow::ensure!(
+        rest.len() >= len as usize,
+        "section of {} bytes overruns the frame ({} left)",
+        len,
+        rest.len()
+    );
+    Ok(rest.split_at(len as usize))
+}
+
+/// One sectioned transaction; sections are sliced, not decoded.
+#[derive(Debug, Clone, Copy)]
+pub struct SectionedTx<'a> {
+    sections: [&'a [u8]; TX_SECTIONS],
+}
+
+impl<'a> SectionedTx<'a> {
+    /// Split one transaction off the front of `buf`.
+    pub fn parse(mut buf: &'a [u8]) -> Result<(Self, &'a [u8])> {
+        let mut sections = [&[][..]; TX_SECTIONS];
+        for s in sections.iter_mut() {
+            (*s, buf) = take_section(buf)?;
+        }
+        Ok((Self { sections }, buf))
+    }
+
+    /// Raw postcard bytes of a section (empty if absent).
+    #[inline]
+    pub fn section(&self, s: TxSection) -> &'a [u8] {
+        self.sections[s as usize]
+    }
+
+    #[inline]
+    pub fn has_meta(&self) -> bool {
+        !self.section(TxSection::Status).is_empty()
+    }
+
+    #[inline]
+    fn decode<T: Deserialize<'a>>(&self, s: TxSection) -> Result<Option<T>> {
+        let b = self.section(s);
+        if b.is_empty() {
+            return Ok(None);
+        }
+        postcard::from_bytes(b)
+            .with_context(|| format!("decode {:?} section", s))
+            .map(Some)
+    }
+
+    pub fn tx(&self) -> Result<CompactTransaction<'a>> {
+        postcard::from_bytes(self.section(TxSection::Tx)).context("decode Tx section")
+    }
+
+    pub fn inner_instructions(&self) -> Result<Option<Vec<CompactInnerInstructions>>> {
+        self.decode(TxSection::InnerInstructions)
+    }
+
+    pub fn log_events(&self) -> Result<Option<Vec<LogEvent>>> {
+        self.decode(TxSection::LogEvents)
+    }
+
+    pub fn log_strings(&self) -> Result<Option<StringTable>> {
+        self.decode(TxSection::LogStrings)
+    }
+
+    pub fn log_data(&self) -> Result<Option<DataTable>> {
+        self.decode(TxSection::LogData)
+    }
+
+    /// Decode every meta section back into a `CompactMetaV1`; `file` is the
+    /// header of the file the transaction came from.
+    pub fn meta(&self, file: CompactFileHeader) -> Result<Option<CompactMetaV1>> {
+        let Some(status) = self.decode::<Status>(TxSection::Status)? else {
+            return Ok(None);
+        };
+        let (pre_balances, post_balances) = self
+            .decode(TxSection::Balances)?
+            .context("meta without Balances section")?;
+        let token_balances = if file.uses_token_balance_changes() {
+            self.decode(TxSection::TokenBalances)?
+        } else {
+            self.decode::<LegacyTokenBalances>(TxSection::TokenBalances)?
+                .map(upgrade_token_balances)
+        }
+        .context("meta without TokenBalances section")?;
+
+        let logs = match self.log_events()? {
+            Some(events) => Some(CompactLogStream {
+                events,
+                strings: self.log_strings()?.unwrap_or_default(),
+                data: self.log_data()?.unwrap_or_default(),
+            }),
+            None => None,
+        };
+
+        Ok(Some(CompactMetaV1 {
+            err: status.err,
+            fee: status.fee,
+            pre_balances,
+            post_balances,
+            inner_instructions: self.inner_instructions()?,
+            logs,
+            token_balances,
+            rewards: status.rewards,
+            loaded_writable_indices: status.loaded_writable_indices,
+            loaded_readonly_indices: status.loaded_readonly_indices,
+            return_data: status.return_data,
+            compute_units_consumed: status.compute_units_consumed,
+            cost_units: status.cost_units,
+        }))
+    }
+
+    pub fn to_record(&self, file: CompactFileHeader) -> Result<CompactTxWithMeta<'a>> {
+        Ok(CompactTxWithMeta {
+            tx: self.tx()?,
+            metadata: self.meta(file)?,
+        })
+    }
+}
+
+/// Sectioned compact.bin frame: postcard header, log strings section, log
+/// data section, rewards section (from format v11), entries section (from
+/// format v12), varint tx count, then the sectioned transactions back to back.
+#[derive(Debug)]
+pub struct SectionedBlock<'a> {
+    pub header: CompactBlockHeader,
+    pub tx_count: usize,
+    log_strings: &'a [u8],
+    log_data: &'a [u8],
+    rewards: &'a [u8],
+    entries: &'a [u8],
+    txs: &'a [u8],
+}
+
+impl<'a> SectionedBlock<'a> {
+    /// `file` is the header of the compact.bin `frame` comes from.
+    pub fn parse(frame: &'a [u8], file: CompactFileHeader) -> Result<Self> {
+        let (header, rest) =
+            postcard::take_from_bytes::<CompactBlockHeader>(frame).context("block header")?;
+        let (log_strings, rest) = take_section(rest)?;
+        let (log_data, rest) = take_section(rest)?;
+        let (rewards, rest) = if file.uses_block_rewards() {
+            take_section(rest)?
+        } else {
+            (&[][..], rest)
+        };
+        let (entries, rest) = if file.uses_entries() {
+            take_section(rest)?
+        } else {
+            (&[][..], rest)
+        };
+        let (tx_count, txs) = postcard::take_from_bytes::<usize>(rest).context("tx count")?;
+        Ok(Self {
+            header,
+            tx_count,
+            log_strings,
+            log_data,
+            rewards,
+            entries,
+            txs,
+        })
+    }
+
+    pub fn log_strings(&self) -> Result<StringTable> {
+        postcard::from_bytes(self.log_strings).context("decode block log strings")
+    }
+
+    pub fn log_data(&self) -> Result<DataTable> {
+        postcard::from_bytes(self.log_data).context("decode block log data")
+    }
+
+    /// Block rewards, empty before format v11.
+    pub fn rewards(&self) -> Result<Vec<CompactReward>> {
+        if self.rewards.is_empty() {
+            return Ok(Vec::new());
+        }
+        postcard::from_bytes(self.rewards).context("decode block rewards")
+    }
+
+    /// Block entries, `None` when not stored.
+    pub fn entries(&self) -> Result<Option<Vec<CompactEntry>>> {
+        if self.entries.is_empty() {
+            return Ok(None);
+        }
+        postcard::from_bytes(self.entries)
+            .context("decode block entries")
+            .map(Some)
+    }
+
+    pub fn log_tables(&self) -> Result<BlockLogTables> {
+        Ok(BlockLogTables {
+            strings: self.log_strings()?,
+            data: self.log_data()?,
+        })
+    }
+
+    /// Transactions in block order.
+    pub fn txs(&self) -> SectionedTxIter<'a> {
+        SectionedTxIter {
+            buf: self.txs,
+            left: self.tx_count,
+        }
+    }
+
+    /// Fully decoded block, `file` being the header of its compact.bin.
+    pub fn to_record(&self, file: CompactFileHeader) -> Result<CompactBlockRecord<'a>> {
+        let txs = self
+            .txs()
+            .map(|tx| tx?.to_record(file))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(CompactBlockRecord {
+            header: self.header.clone(),
+            log_tables: self.log_tables()?,
+            rewards: self.rewards()?,
+            entries: self.entries()?,
+            txs,
+        })
+    }
+}
+
+pub struct SectionedTxIter<'a> {
+    buf: &'a [u8],
+    left: usize,
+}
+
+impl<'a> Iterator for SectionedTxIter<'a> {
+    type Item = Result<SectionedTx<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        match SectionedTx::parse(self.buf) {
+            Ok((tx, rest)) => {
+                self.buf = rest;
+                Some(Ok(tx))
+            }
+            Err(e) => {
+                self.left = 0;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CompactLayout, CompactLegacyMessage, CompactMessage, CompactMessageHeader,
+        CompactRecentBlockhash, Signature,
+    };
+
+    fn meta(with_logs: bool) -> CompactMetaV1 {
+        let mut strings = StringTable::default();
+        let text = strings.push("Program log: hello");
+        CompactMetaV1 {
+            err: None,
+            fee: 5000,
+            pre_balances: vec![10, 20],
+            post_balances: vec![5, 20],
+            inner_instructions: Some(Vec::new()),
+            logs: with_logs.then(|| CompactLogStream {
+                events: vec![LogEvent::Plain { text }],
+                strings,
+                data: DataTable::default(),
+            }),
+            token_balances: Vec::new(),
+            rewards: Vec::new(),
+            loaded_writable_indices: vec![CompactPubkey::Id(3)],
+            loaded_readonly_indices: Vec::new(),
+            return_data: None,
+            compute_units_consumed: Some(150),
+            cost_units: None,
+        }
+    }
+
+    #[test]
+    fn sections_round_trip_and_skip() {
+        let sig = [1u8; 64];
+        let tx = CompactTransaction {
+            signatures: [Signature(&sig)].into_iter().collect(),
+            message: CompactMessage::Legacy(CompactLegacyMessage {
+                header: CompactMessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 0,
+                },
+                account_keys: vec![CompactPubkey::Id(1)],
+                recent_blockhash: CompactRecentBlockhash::Id(0),
+                instructions: Vec::new(),
+            }),
+        };
+        let txs = [Some(meta(true)), None, Some(meta(false))].map(|metadata| CompactTxWithMeta {
+            tx: tx.clone(),
+            metadata,
+        });
+
+        let header = CompactBlockHeader {
+            slot: 9,
+            parent_slot: 8,
+            blockhash: 1,
+            previous_blockhash: 0,
+            block_time: None,
+            block_height: None,
+        };
+        let mut tables = BlockLogTables::default();
+        tables.strings.push("Program log: shared");
+        let rewards = [CompactReward {
+            pubkey_index: CompactPubkey::Id(2),
+            lamports: 2500,
+            post_balance: 1_000_000,
+            reward_type: 1,
+            commission: None,
+        }];
+        let mut frame = postcard::to_stdvec(&header).unwrap();
+        write_sectioned_tables(&tables, &mut frame).unwrap();
+        write_sectioned_rewards(&rewards, &mut frame).unwrap();
+        let entries = [CompactEntry {
+            num_hashes: 12_500,
+            tx_count: 3,
+            hash: None,
+        }];
+        write_sectioned_entries(Some(&entries), &mut frame).unwrap();
+        postcard::to_io(&txs.len(), &mut frame).unwrap();
+        for t in &txs {
+            write_sectioned_tx(t, &mut frame).unwrap();
+        }
+
+        let file = CompactFileHeader::new(CompactLayout::Sectioned);
+        let block = SectionedBlock::parse(&frame, file).unwrap();
+        let got: Vec<_> = block.txs().collect::<Result<_>>().unwrap();
+        assert_eq!(got.len(), 3);
+
+        // Logs are reachable without touching any other section.
+        let strings = got[0].log_strings().unwrap().unwrap();
+        assert_eq!(strings.strings, ["Program log: hello"]);
+        assert!(!got[1].has_meta());
+        assert!(got[1].log_strings().unwrap().is_none());
+        assert!(got[2].log_events().unwrap().is_none());
+        assert_eq!(
+            block.log_strings().unwrap().strings,
+            ["Program log: shared"]
+        );
+
+        // Full decode matches the plain postcard encoding.
+        let record = block.to_record(file).unwrap();
+        assert_eq!(record.header.slot, 9);
+        assert_eq!(record.rewards[0].lamports, 2500);
+        assert_eq!(record.entries.as_deref(), Some(&entries[..]));
+        assert_eq!(
+            postcard::to_stdvec(&record.log_tables).unwrap(),
+            postcard::to_stdvec(&tables).unwrap()
+        );
+        for (a, b) in record.txs.iter().zip(&txs) {
+            assert_eq!(
+                postcard::to_stdvec(a).unwrap(),
+                postcard::to_stdvec(b).unwrap()
+            );
+        }
+
+        // A truncated frame fails instead of reading past the end.
+        let cut = SectionedBlock::parse(&frame[..frame.len() - 3], file).unwrap();
+        assert!(cut.txs().any(|t| t.is_err()));
+    }
+}
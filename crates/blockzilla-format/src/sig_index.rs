@@ -0,0 +1,378 @@
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use ph::fmph;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{CompactFileHeader, CompactLayout, TRAILER_MARK, raw_file_body};
+
+/// Entry count + partition count, then per partition its entry count u64
+/// and mphf byte len u64
+const SIG_INDEX_HEADER_LEN: usize = 8 + 4;
+/// fingerprint u32, block ordinal u32, tx index u32
+const SIG_ENTRY_LEN: usize = 4 + 4 + 4;
+/// Partitions by the last MPHF key byte; one is built in memory at a time.
+const SIG_PARTS: usize = 256;
+/// Spilled push: key u128 + entry
+const SPILL_ENTRY_LEN: usize = 16 + SIG_ENTRY_LEN;
+
+/// MPHF key: first 16 signature bytes.
+#[inline]
+fn sig_key(sig: &[u8; 64]) -> u128 {
+    u128::from_le_bytes(sig[..16].try_into().unwrap())
+}
+
+/// Partition of an MPHF key.
+#[inline]
+fn key_part(key: u128) -> usize {
+    (key >> 120) as usize
+}
+
+/// Verification fingerprint: the next 4 signature bytes.
+#[inline]
+fn sig_fingerprint(sig: &[u8; 64]) -> u32 {
+    u32::from_le_bytes(sig[16..20].try_into().unwrap())
+}
+
+/// Where a transaction lives in compact.bin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SigLocation {
+    /// Frame ordinal in compact.bin (same as the slot index entry number).
+    pub block: u32,
+    /// Transaction position inside the full block (0-based), votes.bin
+    /// ones included: [`crate::SlotReader`] merges them back.
+    pub tx: u32,
+}
+
+/// Collects signatures during compaction. They are spilled to one file per
+/// key partition, so only a partition has to fit in memory when writing.
+pub struct SigIndexBuilder {
+    spill: Vec<(PathBuf, BufWriter<File>)>,
+    len: usize,
+}
+
+impl SigIndexBuilder {
+    /// Spill files go to `dir`; they are removed by `write` (or on drop).
+    pub fn new(dir: &Path) -> Result<Self> {
+        let spill = (0..SIG_PARTS)
+            .map(|i| {
+                let path = dir.join(format!("sig-index.part{:03}.tmp", i));
+                let f = File::create(&path)
+                    .with_context(|| format!("Failed to create {}", path.display()))?;
+                Ok((path, BufWriter::with_capacity(64 << 10, f)))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { spill, len: 0 })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Record the first signature of a transaction.
+    #[inline]
+    pub fn push(&mut self, sig: &[u8; 64], loc: SigLocation) -> Result<()> {
+        let key = sig_key(sig);
+        let mut b = [0u8; SPILL_ENTRY_LEN];
+        b[..16].copy_from_slice(&key.to_le_bytes());
+        b[16..20].copy_from_slice(&sig_fingerprint(sig).to_le_bytes());
+        b[20..24].copy_from_slice(&loc.block.to_le_bytes());
+        b[24..28].copy_from_slice(&loc.tx.to_le_bytes());
+        self.spill[key_part(key)]
+            .1
+            .write_all(&b)
+            .context("spill signature")?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Build the MPHFs and write sig-index.bin.
+    ///
+    /// Layout (little endian), between the file header and trailer: entry
+    /// count u64, partition count u32, per partition its
+    /// entry count u64 and mphf len u64; then per partition the mphf (ph
+    /// serialization) and its entries (fingerprint u32, block u32, tx u32) in
+    /// MPHF order.
+    ///
+    /// The MPHF needs distinct keys, so repeated 16-byte prefixes keep their
+    /// first occurrence only. Returns how many were dropped.
+    pub fn write(mut self, path: &Path, epoch: u64) -> Result<usize> {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut w = BufWriter::with_capacity(64 << 20, f);
+        let file_header = CompactFileHeader::new(CompactLayout::SigIndex).with_epoch(epoch);
+        w.write_all(&file_header.to_bytes())
+            .context("write sig index header")?;
+        let write_header = |w: &mut BufWriter<File>, n: usize, table: &[(u64, u64)]| {
+            w.write_all(&(n as u64).to_le_bytes())?;
+            w.write_all(&(SIG_PARTS as u32).to_le_bytes())?;
+            for (len, mphf_len) in table {
+                w.write_all(&len.to_le_bytes())?;
+                w.write_all(&mphf_len.to_le_bytes())?;
+            }
+            anyhow::Ok(())
+        };
+        let mut table = vec![(0u64, 0u64); SIG_PARTS];
+        write_header(&mut w, 0, &table).context("write sig index header")?;
+
+        let mut dropped = 0;
+        for (i, (spill_path, spill)) in self.spill.drain(..).enumerate() {
+            drop(spill.into_inner().context("flush signature spill")?);
+            let bytes = std::fs::read(&spill_path)
+                .with_context(|| format!("read {}", spill_path.display()))?;
+            std::fs::remove_file(&spill_path).ok();
+
+            // Stable sort: the first of repeated keys stays first.
+            let mut part: Vec<(u128, [u8; SIG_ENTRY_LEN])> = bytes
+                .chunks_exact(SPILL_ENTRY_LEN)
+                .map(|c| {
+                    let key = u128::from_le_bytes(c[..16].try_into().unwrap());
+                    (key, c[16..].try_into().unwrap())
+                })
+                .collect();
+            part.sort_by_key(|e| e.0);
+            let before = part.len();
+            part.dedup_by_key(|e| e.0);
+            dropped += before - part.len();
+
+            let keys: Vec<u128> = part.iter().map(|e| e.0).collect();
+            let mphf: fmph::GOFunction = keys.as_slice().into();
+            let mut slots = vec![[0u8; SIG_ENTRY_LEN]; part.len()];
+            for (k, e) in &part {
+                slots[mphf.get_or_panic(k) as usize] = *e;
+            }
+
+            mphf.write(&mut w).context("write mphf")?;
+            w.write_all(slots.as_flattened())
+                .context("write sig entries")?;
+            table[i] = (part.len() as u64, mphf.write_bytes() as u64);
+        }
+
+        w.seek(SeekFrom::Start(file_header.encoded_len() as u64))
+            .context("seek sig index header")?;
+        write_header(&mut w, self.len - dropped, &table).context("write sig index header")?;
+        let mut f = w.into_inner().context("flush sig index")?;
+
+        // The header was patched in place, so the file is hashed afterwards.
+        f.seek(SeekFrom::Start(0)).context("seek sig index start")?;
+        let mut hash = blake3::Hasher::new();
+        hash.update_reader(&mut f).context("hash sig index")?;
+        f.write_all(&TRAILER_MARK.to_le_bytes())
+            .and_then(|()| f.write_all(hash.finalize().as_bytes()))
+            .context("write sig index trailer")?;
+        Ok(dropped)
+    }
+}
+
+impl Drop for SigIndexBuilder {
+    fn drop(&mut self) {
+        for (path, _) in &self.spill {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// One MPHF and its entries.
+struct SigPart {
+    mphf: fmph::GOFunction,
+    entries_off: usize,
+    len: usize,
+}
+
+/// Signature → (block, tx) lookup over a mmapped sig-index.bin.
+pub struct SigIndex {
+    /// `SIG_PARTS` of them, by [`key_part`]
+    parts: Vec<SigPart>,
+    map: Mmap,
+    len: usize,
+}
+
+impl SigIndex {
+    /// compact.bin -> sig-index.bin (same directory)
+    pub fn path_for(compact_path: &Path) -> PathBuf {
+        compact_path.with_file_name("sig-index.bin")
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let f = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        // SAFETY: read-only mapping, the index is never modified after compaction.
+        let file = unsafe { Mmap::map(&f) }.with_context(|| format!("mmap {}", path.display()))?;
+        // Offsets below are into `map`, the index after the file header.
+        let map = raw_file_body(&file, CompactLayout::SigIndex)
+            .with_context(|| format!("signature index {}", path.display()))?;
+        let base = CompactFileHeader::from_prefix(&file)?.map_or(0, |h| h.encoded_len());
+
+        anyhow::ensure!(
+            map.len() >= SIG_INDEX_HEADER_LEN,
+            "truncated signature index {}",
+            path.display()
+        );
+        let u32_at = |o: usize| u32::from_le_bytes(map[o..o + 4].try_into().unwrap());
+        let u64_at = |o: usize| u64::from_le_bytes(map[o..o + 8].try_into().unwrap()) as usize;
+
+        let len = u64_at(0);
+        let parts = u32_at(8) as usize;
+        anyhow::ensure!(
+            parts == SIG_PARTS,
+            "{} signature index partitions in {}",
+            parts,
+            path.display()
+        );
+        // (entry count, mphf len) per partition, then the first mphf
+        let mut off = SIG_INDEX_HEADER_LEN + parts * 16;
+        anyhow::ensure!(
+            map.len() >= off,
+            "truncated signature index {}",
+            path.display()
+        );
+        let table: Vec<_> = (0..parts)
+            .map(|i| {
+                let o = SIG_INDEX_HEADER_LEN + i * 16;
+                (u64_at(o), u64_at(o + 8))
+            })
+            .collect();
+
+        let mut parts = Vec::with_capacity(table.len());
+        for (n, mphf_len) in table {
+            let entries_off = off.saturating_add(mphf_len);
+            let end = (n as u64)
+                .saturating_mul(SIG_ENTRY_LEN as u64)
+                .saturating_add(entries_off as u64);
+            anyhow::ensure!(
+                end <= map.len() as u64,
+                "invalid signature index size {} in {}",
+                map.len(),
+                path.display()
+            );
+            let mphf = fmph::GOFunction::read(&mut &map[off..entries_off]).context("read mphf")?;
+            parts.push(SigPart {
+                mphf,
+                entries_off: base + entries_off,
+                len: n,
+            });
+            off = end as usize;
+        }
+        anyhow::ensure!(
+            off == map.len() && parts.iter().map(|p| p.len).sum::<usize>() == len,
+            "invalid signature index size {} in {}",
+            map.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            parts,
+            map: file,
+            len,
+        })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Location of the transaction whose first signature is `sig`.
+    ///
+    /// A `Some` is a 20-byte prefix match; callers holding the block can
+    /// compare the full signature.
+    #[inline]
+    pub fn get(&self, sig: &[u8; 64]) -> Option<SigLocation> {
+        let key = sig_key(sig);
+        let part = &self.parts[key_part(key)];
+        let i = part.mphf.get(&key)? as usize;
+        if i >= part.len {
+            return None;
+        }
+
+        let off = part.entries_off + i * SIG_ENTRY_LEN;
+        let e = &self.map[off..off + SIG_ENTRY_LEN];
+        let u32_at = |o: usize| u32::from_le_bytes(e[o..o + 4].try_into().unwrap());
+
+        (u32_at(0) == sig_fingerprint(sig)).then(|| SigLocation {
+            block: u32_at(4),
+            tx: u32_at(8),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sig(i: u32) -> [u8; 64] {
+        let mut s = [0u8; 64];
+        for (j, c) in s.chunks_exact_mut(4).enumerate() {
+            c.copy_from_slice(
+                &i.wrapping_mul(0x9E37_79B9)
+                    .rotate_left(j as u32)
+                    .to_le_bytes(),
+            );
+        }
+        s
+    }
+
+    #[test]
+    fn finds_signatures_and_rejects_strangers() {
+        let dir = std::env::temp_dir().join(format!("bz-sigidx-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sig-index.bin");
+
+        let mut b = SigIndexBuilder::new(&dir).unwrap();
+        for i in 0..3_000u32 {
+            b.push(
+                &sig(i),
+                SigLocation {
+                    block: i / 7,
+                    tx: i % 7,
+                },
+            )
+            .unwrap();
+        }
+        // Repeated signature keeps the first location.
+        b.push(&sig(5), SigLocation { block: 999, tx: 0 }).unwrap();
+        assert_eq!(b.write(&path, 0).unwrap(), 1);
+        // Only the index is left.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let idx = SigIndex::open(&path).unwrap();
+        assert_eq!(idx.len(), 3_000);
+        for i in 0..3_000u32 {
+            assert_eq!(
+                idx.get(&sig(i)),
+                Some(SigLocation {
+                    block: i / 7,
+                    tx: i % 7
+                })
+            );
+        }
+        for i in 3_000..6_000u32 {
+            assert_eq!(idx.get(&sig(i)), None);
+        }
+
+        // A cut file has lost its trailer.
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(SigIndex::open(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,528 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    CompactBlockRecord, CompactFileHeader, CompactLayout, CompactReplayBlock, CompactVoteBlock,
+    FILE_HEADER_LEN, FLAG_SPLIT_VOTES, FRAME_MAGIC, FrameError, PostcardFramedWriter,
+    VOTE_CHAIN_SLOTS, VoteChain, decode_compact_frame, decode_replay_frame, decode_vote_frame,
+    raw_file_body, split_paths, votes_path,
+};
+
+/// slot u64, offset u64, frame len u32, tx count u32, blockhash id u32
+pub const SLOT_INDEX_ENTRY_LEN: usize = 8 + 8 + 4 + 4 + 4;
+
+/// One compact.bin frame (block.bin with `--split-runtime`, votes.bin in
+/// the votes slot index). `tx_count` counts the full block, votes.bin ones
+/// included.
+///
+/// `offset` points at the start of the frame (its magic from format v14, its
+/// u32 length prefix before), `frame_len` is the payload length (without the
+/// prefix and checksum).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotIndexEntry {
+    pub slot: u64,
+    pub offset: u64,
+    pub frame_len: u32,
+    pub tx_count: u32,
+    pub blockhash: u32,
+}
+
+impl SlotIndexEntry {
+    #[inline]
+    pub fn to_bytes(&self) -> [u8; SLOT_INDEX_ENTRY_LEN] {
+        let mut b = [0u8; SLOT_INDEX_ENTRY_LEN];
+        b[0..8].copy_from_slice(&self.slot.to_le_bytes());
+        b[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        b[16..20].copy_from_slice(&self.frame_len.to_le_bytes());
+        b[20..24].copy_from_slice(&self.tx_count.to_le_bytes());
+        b[24..28].copy_from_slice(&self.blockhash.to_le_bytes());
+        b
+    }
+
+    #[inline]
+    pub fn from_bytes(b: &[u8; SLOT_INDEX_ENTRY_LEN]) -> Self {
+        Self {
+            slot: u64::from_le_bytes(b[0..8].try_into().unwrap()),
+            offset: u64::from_le_bytes(b[8..16].try_into().unwrap()),
+            frame_len: u32::from_le_bytes(b[16..20].try_into().unwrap()),
+            tx_count: u32::from_le_bytes(b[20..24].try_into().unwrap()),
+            blockhash: u32::from_le_bytes(b[24..28].try_into().unwrap()),
+        }
+    }
+}
+
+/// Streams entries to slot-index.bin (fixed size records between the file
+/// header and trailer).
+pub struct SlotIndexWriter<W> {
+    w: PostcardFramedWriter<W>,
+    last_slot: Option<u64>,
+}
+
+impl<W: Write> SlotIndexWriter<W> {
+    pub fn new(w: W, epoch: u64) -> Result<Self> {
+        let header = CompactFileHeader::new(CompactLayout::SlotIndex).with_epoch(epoch);
+        Ok(Self {
+            w: PostcardFramedWriter::with_header(w, header)?,
+            last_slot: None,
+        })
+    }
+
+    /// Entries must come in increasing slot order (compact.bin order).
+    #[inline]
+    pub fn push(&mut self, e: &SlotIndexEntry) -> Result<()> {
+        if let Some(prev) = self.last_slot {
+            anyhow::ensure!(
+                e.slot > prev,
+                "slot index out of order: {} after {}",
+                e.slot,
+                prev
+            );
+        }
+        self.last_slot = Some(e.slot);
+        self.w.write_raw(&e.to_bytes()).context("write slot index")
+    }
+
+    /// Write the trailer and flush. Nothing may be pushed after it.
+    pub fn finish(&mut self) -> Result<()> {
+        self.w.finish().context("finish slot index")
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.w.into_inner()
+    }
+}
+
+/// In-memory slot index, sorted by slot.
+#[derive(Debug, Clone, Default)]
+pub struct SlotIndex {
+    pub entries: Vec<SlotIndexEntry>,
+}
+
+impl SlotIndex {
+    /// compact.bin -> slot-index.bin (same directory)
+    pub fn path_for(compact_path: &Path) -> PathBuf {
+        compact_path.with_file_name("slot-index.bin")
+    }
+
+    /// compact.bin -> votes-slot-index.bin, the slot index of votes.bin
+    pub fn votes_path_for(compact_path: &Path) -> PathBuf {
+        compact_path.with_file_name("votes-slot-index.bin")
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Self::from_bytes(&bytes).with_context(|| format!("slot index {}", path.display()))
+    }
+
+    /// Index from the bytes of a slot index file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let body = raw_file_body(bytes, CompactLayout::SlotIndex)?;
+        anyhow::ensure!(
+            body.len().is_multiple_of(SLOT_INDEX_ENTRY_LEN),
+            "invalid slot index size {} (not multiple of {})",
+            body.len(),
+            SLOT_INDEX_ENTRY_LEN
+        );
+
+        let entries: Vec<_> = body
+            .chunks_exact(SLOT_INDEX_ENTRY_LEN)
+            .map(|c| SlotIndexEntry::from_bytes(c.try_into().unwrap()))
+            .collect();
+        anyhow::ensure!(
+            entries.windows(2).all(|w| w[0].slot < w[1].slot),
+            "slot index is not sorted"
+        );
+
+        Ok(Self { entries })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entry for `slot`, `None` if the slot was skipped or is out of range.
+    #[inline]
+    pub fn get(&self, slot: u64) -> Option<&SlotIndexEntry> {
+        let i = self.entries.binary_search_by_key(&slot, |e| e.slot).ok()?;
+        Some(&self.entries[i])
+    }
+}
+
+/// Random access to compact.bin (or block.bin) blocks through the slot index,
+/// with the votes of votes.bin merged back in when attached.
+pub struct SlotReader<R> {
+    r: R,
+    index: SlotIndex,
+    header: CompactFileHeader,
+    buf: Vec<u8>,
+    /// votes.bin through its own slot index.
+    votes: Option<Box<SlotReader<R>>>,
+}
+
+impl SlotReader<BufReader<File>> {
+    /// Open the data file indexed next to `compact_path`: compact.bin, or
+    /// block.bin for a split archive. votes.bin is attached when the votes
+    /// were split off.
+    pub fn open(compact_path: &Path) -> Result<Self> {
+        let open = |path: &Path, index: &Path| -> Result<Self> {
+            let index = SlotIndex::load(index)?;
+            let f =
+                File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+            Self::new(BufReader::new(f), index)
+                .with_context(|| format!("Failed to read {}", path.display()))
+        };
+        let (block_path, _) = split_paths(compact_path);
+        let path = if !compact_path.exists() && block_path.exists() {
+            block_path.as_path()
+        } else {
+            compact_path
+        };
+        let r = open(path, &SlotIndex::path_for(compact_path))?;
+        if r.header.flags & FLAG_SPLIT_VOTES == 0 {
+            return Ok(r);
+        }
+        let votes = open(
+            &votes_path(compact_path),
+            &SlotIndex::votes_path_for(compact_path),
+        )?;
+        r.with_votes(votes)
+    }
+}
+
+impl<R: Read + Seek> SlotReader<R> {
+    pub fn new(mut r: R, index: SlotIndex) -> Result<Self> {
+        let mut prefix = Vec::new();
+        r.seek(SeekFrom::Start(0)).context("seek to header")?;
+        (&mut r)
+            .take(FILE_HEADER_LEN as u64)
+            .read_to_end(&mut prefix)
+            .context("read compact header")?;
+        let header = CompactFileHeader::from_prefix(&prefix)?.unwrap_or(CompactFileHeader::LEGACY);
+        Ok(Self {
+            r,
+            index,
+            header,
+            buf: Vec::new(),
+            votes: None,
+        })
+    }
+
+    /// Merge the votes read by `votes`, a reader over votes.bin, into the
+    /// blocks read.
+    pub fn with_votes(mut self, votes: SlotReader<R>) -> Result<Self> {
+        votes.header.expect(CompactLayout::Votes)?;
+        self.votes = Some(Box::new(votes));
+        Ok(self)
+    }
+
+    #[inline]
+    pub fn index(&self) -> &SlotIndex {
+        &self.index
+    }
+
+    #[inline]
+    pub fn header(&self) -> CompactFileHeader {
+        self.header
+    }
+
+    /// Raw frame payload for `slot`, laid out as `header()` says.
+    pub fn read_frame(&mut self, slot: u64) -> Result<Option<&[u8]>> {
+        read_frame(&mut self.r, &self.index, self.header, &mut self.buf, slot)
+    }
+
+    /// Decode the block stored for `slot`, with its votes when votes.bin
+    /// is attached. compact.bin only, see [`Self::read_replay`] for block.bin.
+    pub fn read_slot(&mut self, slot: u64) -> Result<Option<CompactBlockRecord<'_>>> {
+        let header = self.header;
+        let Some(frame) = read_frame(&mut self.r, &self.index, header, &mut self.buf, slot)? else {
+            return Ok(None);
+        };
+        let mut block = decode_compact_frame(header, frame)?;
+        anyhow::ensure!(
+            block.header.slot == slot,
+            "slot index points at slot {} for slot {}",
+            block.header.slot,
+            slot
+        );
+        if let Some(votes) = self.votes.as_deref_mut() {
+            block.merge_votes(votes.read_votes(slot)?)?;
+        }
+        Ok(Some(block))
+    }
+
+    /// Decode the transactions of `slot`, from either file layout, with its
+    /// votes when votes.bin is attached.
+    pub fn read_replay(&mut self, slot: u64) -> Result<Option<CompactReplayBlock<'_>>> {
+        let header = self.header;
+        let Some(frame) = read_frame(&mut self.r, &self.index, header, &mut self.buf, slot)? else {
+            return Ok(None);
+        };
+        let mut block = decode_replay_frame(header, frame)?;
+        anyhow::ensure!(
+            block.header.slot == slot,
+            "slot index points at slot {} for slot {}",
+            block.header.slot,
+            slot
+        );
+        if let Some(votes) = self.votes.as_deref_mut() {
+            block.merge_votes(votes.read_votes(slot)?)?;
+        }
+        Ok(Some(block))
+    }
+
+    /// The votes.bin frame of `slot`; every block has one.
+    fn read_votes(&mut self, slot: u64) -> Result<CompactVoteBlock<'_>> {
+        let header = self.header;
+        let frame = self
+            .read_frame(slot)?
+            .with_context(|| format!("votes.bin has no frame for slot {}", slot))?;
+        decode_vote_frame(header, frame)
+    }
+
+    /// Reset `chain` and feed it the blocks stored before `slot` in its
+    /// `VOTE_CHAIN_SLOTS` window, so the block of `slot` can be rehydrated
+    /// with it.
+    pub fn seed_votes(&mut self, slot: u64, chain: &mut VoteChain) -> Result<()> {
+        *chain = VoteChain::default();
+        let start = slot - slot % VOTE_CHAIN_SLOTS;
+        let from = self.index.entries.partition_point(|e| e.slot < start);
+        let to = self.index.entries.partition_point(|e| e.slot < slot);
+        for i in from..to {
+            let s = self.index.entries[i].slot;
+            if let Some(b) = self.read_replay(s)? {
+                chain.replay(&b.header, &b.txs)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [`SlotReader::read_frame`] over the reader's parts, so a frame of
+/// compact.bin and one of votes.bin can be borrowed at once.
+fn read_frame<'b, R: Read + Seek>(
+    r: &mut R,
+    index: &SlotIndex,
+    header: CompactFileHeader,
+    buf: &'b mut Vec<u8>,
+    slot: u64,
+) -> Result<Option<&'b [u8]>> {
+    let Some(e) = index.get(slot).copied() else {
+        return Ok(None);
+    };
+
+    r.seek(SeekFrom::Start(e.offset))
+        .with_context(|| format!("seek to slot {} at {}", slot, e.offset))?;
+
+    let mut lenb = [0u8; 4];
+    if header.uses_frame_magic() {
+        r.read_exact(&mut lenb).context("read frame magic")?;
+        let found = u32::from_le_bytes(lenb);
+        if found != FRAME_MAGIC {
+            return Err(FrameError::BadMagic {
+                offset: e.offset,
+                found,
+            }
+            .into());
+        }
+    }
+    r.read_exact(&mut lenb).context("read frame len")?;
+    let len = u32::from_le_bytes(lenb);
+    anyhow::ensure!(
+        len == e.frame_len,
+        "slot {}: frame len {} does not match index ({})",
+        slot,
+        len,
+        e.frame_len
+    );
+
+    buf.resize(len as usize, 0);
+    r.read_exact(buf).context("read frame payload")?;
+    if header.uses_checksums() {
+        let mut crcb = [0u8; 4];
+        r.read_exact(&mut crcb).context("read frame crc32c")?;
+        let (stored, computed) = (u32::from_le_bytes(crcb), crc32c::crc32c(buf));
+        if stored != computed {
+            return Err(FrameError::BadChecksum {
+                offset: e.offset,
+                len,
+                stored,
+                computed,
+            }
+            .into());
+        }
+    }
+    Ok(Some(&buf[..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BlockLogTables, CompactBlockHeader, CompactLegacyMessage, CompactMessage,
+        CompactMessageHeader, CompactRecentBlockhash, CompactTransaction, CompactTxWithMeta,
+    };
+    use std::io::Cursor;
+
+    /// Blocks 10, 11, 13 and 20 in a `layout` file, with their index.
+    fn archive(layout: CompactLayout) -> (Vec<u8>, SlotIndexWriter<Vec<u8>>) {
+        let header = CompactFileHeader::new(layout);
+        let mut w = PostcardFramedWriter::with_header(Vec::new(), header).unwrap();
+        let mut idx = SlotIndexWriter::new(Vec::new(), 0).unwrap();
+
+        for (i, slot) in [10u64, 11, 13, 20].into_iter().enumerate() {
+            let block = CompactBlockRecord {
+                header: CompactBlockHeader {
+                    slot,
+                    parent_slot: slot - 1,
+                    blockhash: i as u32,
+                    previous_blockhash: i.saturating_sub(1) as u32,
+                    block_time: Some(1_600_000_000 + slot as i64),
+                    block_height: None,
+                },
+                log_tables: BlockLogTables::default(),
+                rewards: Vec::new(),
+                entries: None,
+                txs: Vec::new(),
+            };
+            let offset = w.position();
+            match layout {
+                CompactLayout::SplitBlock => w.write(&block.split().0).unwrap(),
+                _ => w.write(&block).unwrap(),
+            }
+            idx.push(&SlotIndexEntry {
+                slot,
+                offset,
+                frame_len: (w.position() - offset - w.frame_overhead()) as u32,
+                tx_count: 0,
+                blockhash: i as u32,
+            })
+            .unwrap();
+        }
+        w.finish().unwrap();
+        (w.into_inner(), idx)
+    }
+
+    fn entries(mut idx: SlotIndexWriter<Vec<u8>>) -> SlotIndex {
+        idx.finish().unwrap();
+        SlotIndex::from_bytes(&idx.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn seeks_to_slot() {
+        let (mut file, mut idx) = archive(CompactLayout::Plain);
+        // Slots must be strictly increasing.
+        let twenty = SlotIndexEntry {
+            slot: 20,
+            offset: 0,
+            frame_len: 0,
+            tx_count: 0,
+            blockhash: 0,
+        };
+        assert!(idx.push(&twenty).is_err());
+
+        idx.finish().unwrap();
+        let mut bytes = idx.into_inner();
+        let index = SlotIndex::from_bytes(&bytes).unwrap();
+        // The index is covered by its file hash.
+        bytes[FILE_HEADER_LEN] ^= 1;
+        assert!(SlotIndex::from_bytes(&bytes).is_err());
+
+        let mut r = SlotReader::new(Cursor::new(file.clone()), index).unwrap();
+        let h = r.read_slot(13).unwrap().unwrap().header;
+        assert_eq!((h.slot, h.blockhash), (13, 2));
+        assert_eq!(r.read_slot(20).unwrap().unwrap().header.parent_slot, 19);
+        assert_eq!(r.read_replay(11).unwrap().unwrap().header.blockhash, 1);
+        assert!(r.read_slot(12).unwrap().is_none());
+
+        // A flipped bit is caught by the frame checksum.
+        let e = *r.index().get(11).unwrap();
+        file[e.offset as usize + 9] ^= 1;
+        let mut r = SlotReader::new(Cursor::new(file), r.index).unwrap();
+        let err = r.read_slot(11).unwrap_err().to_string();
+        assert!(err.contains("crc32c"), "{}", err);
+    }
+
+    #[test]
+    fn seeks_in_block_bin() {
+        let (file, idx) = archive(CompactLayout::SplitBlock);
+        let mut r = SlotReader::new(Cursor::new(file), entries(idx)).unwrap();
+        let h = r.read_replay(13).unwrap().unwrap().header;
+        assert_eq!((h.slot, h.blockhash), (13, 2));
+        assert!(r.read_replay(12).unwrap().is_none());
+        // Full records need runtime.bin.
+        assert!(r.read_slot(13).is_err());
+    }
+
+    #[test]
+    fn merges_votes_bin() {
+        let (file, idx) = archive(CompactLayout::Plain);
+        let tx = || -> CompactTxWithMeta<'static> {
+            CompactTxWithMeta {
+                tx: CompactTransaction {
+                    signatures: heapless::Vec::new(),
+                    message: CompactMessage::Legacy(CompactLegacyMessage {
+                        header: CompactMessageHeader {
+                            num_required_signatures: 1,
+                            num_readonly_signed_accounts: 0,
+                            num_readonly_unsigned_accounts: 0,
+                        },
+                        account_keys: Vec::new(),
+                        recent_blockhash: CompactRecentBlockhash::Id(0),
+                        instructions: Vec::new(),
+                    }),
+                },
+                metadata: None,
+            }
+        };
+        // Every block has a votes.bin frame, slot 13 holds two votes.
+        let header = CompactFileHeader::new(CompactLayout::Votes);
+        let mut w = PostcardFramedWriter::with_header(Vec::new(), header).unwrap();
+        let mut vidx = SlotIndexWriter::new(Vec::new(), 0).unwrap();
+        for (i, slot) in [10u64, 11, 13, 20].into_iter().enumerate() {
+            let n = if slot == 13 { 2 } else { 0 };
+            let offset = w.position();
+            w.write(&CompactVoteBlock {
+                slot,
+                positions: (0..n).collect(),
+                txs: (0..n).map(|_| tx()).collect(),
+            })
+            .unwrap();
+            vidx.push(&SlotIndexEntry {
+                slot,
+                offset,
+                frame_len: (w.position() - offset - w.frame_overhead()) as u32,
+                tx_count: n,
+                blockhash: i as u32,
+            })
+            .unwrap();
+        }
+        w.finish().unwrap();
+
+        let votes = SlotReader::new(Cursor::new(w.into_inner()), entries(vidx)).unwrap();
+        let index = entries(idx);
+        let mut r = SlotReader::new(Cursor::new(file.clone()), index.clone())
+            .unwrap()
+            .with_votes(votes)
+            .unwrap();
+        assert_eq!(r.read_slot(13).unwrap().unwrap().txs.len(), 2);
+        assert_eq!(r.read_replay(13).unwrap().unwrap().txs.len(), 2);
+        assert_eq!(r.read_slot(20).unwrap().unwrap().txs.len(), 0);
+
+        // compact.bin is not a votes.bin.
+        let plain = SlotReader::new(Cursor::new(file.clone()), index.clone()).unwrap();
+        let r = SlotReader::new(Cursor::new(file), index).unwrap();
+        assert!(r.with_votes(plain).is_err());
+    }
+}
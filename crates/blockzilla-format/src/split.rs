@@ -0,0 +1,452 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    BlockLogTables, CompactBlockHeader, CompactBlockRecord, CompactEntry, CompactFileHeader,
+    CompactLayout, CompactMetaV1, CompactReward, CompactTokenBalanceChange, CompactTransaction,
+    CompactTxWithMeta, LegacyTokenBalances, PostcardFramedReader, compact_file::frame_slot,
+    decode_compact_frame,
+};
+
+/// compact.bin -> (block.bin, runtime.bin) (same directory)
+pub fn split_paths(compact_path: &Path) -> (PathBuf, PathBuf) {
+    (
+        compact_path.with_file_name("block.bin"),
+        compact_path.with_file_name("runtime.bin"),
+    )
+}
+
+/// block.bin frame: everything needed to replay the block.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactReplayBlock<'a> {
+    pub header: CompactBlockHeader,
+    /// See [`CompactBlockRecord::entries`].
+    pub entries: Option<Vec<CompactEntry>>,
+    #[serde(borrow)]
+    pub txs: Vec<CompactTransaction<'a>>,
+}
+
+/// block.bin frame before format v12, without entries.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactReplayBlockV1<'a> {
+    pub header: CompactBlockHeader,
+    #[serde(borrow)]
+    pub txs: Vec<CompactTransaction<'a>>,
+}
+
+impl<'a> From<CompactReplayBlockV1<'a>> for CompactReplayBlock<'a> {
+    fn from(b: CompactReplayBlockV1<'a>) -> Self {
+        Self {
+            header: b.header,
+            entries: None,
+            txs: b.txs,
+        }
+    }
+}
+
+/// runtime.bin frame: status meta of the block at the same frame ordinal in
+/// block.bin, one entry per transaction in the same order.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactRuntimeBlock {
+    /// Copy of the block slot, checked when joining the two files.
+    pub slot: u64,
+    /// Block-level log tables; only the logs in `metas` refer to them.
+    pub log_tables: BlockLogTables,
+    /// Block-level rewards, see [`CompactBlockRecord::rewards`].
+    pub rewards: Vec<CompactReward>,
+    pub metas: Vec<Option<CompactMetaV1>>,
+}
+
+/// runtime.bin frame before format v11, without block rewards. `T` as in
+/// [`CompactMetaV1`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactRuntimeBlockV2<T = Vec<CompactTokenBalanceChange>> {
+    pub slot: u64,
+    pub log_tables: BlockLogTables,
+    pub metas: Vec<Option<CompactMetaV1<T>>>,
+}
+
+impl<T> From<CompactRuntimeBlockV2<T>> for CompactRuntimeBlock
+where
+    CompactMetaV1<T>: Into<CompactMetaV1>,
+{
+    fn from(r: CompactRuntimeBlockV2<T>) -> Self {
+        Self {
+            slot: r.slot,
+            log_tables: r.log_tables,
+            rewards: Vec::new(),
+            metas: r.metas.into_iter().map(|m| m.map(Into::into)).collect(),
+        }
+    }
+}
+
+impl<'a> CompactBlockRecord<'a> {
+    /// Split into the block.bin and runtime.bin frames.
+    pub fn split(self) -> (CompactReplayBlock<'a>, CompactRuntimeBlock) {
+        let slot = self.header.slot;
+        let (txs, metas) = self.txs.into_iter().map(|t| (t.tx, t.metadata)).unzip();
+        (
+            CompactReplayBlock {
+                header: self.header,
+                entries: self.entries,
+                txs,
+            },
+            CompactRuntimeBlock {
+                slot,
+                log_tables: self.log_tables,
+                rewards: self.rewards,
+                metas,
+            },
+        )
+    }
+
+    /// Join aligned block.bin / runtime.bin frames back into one record.
+    pub fn join(block: CompactReplayBlock<'a>, runtime: CompactRuntimeBlock) -> Result<Self> {
+        anyhow::ensure!(
+            block.header.slot == runtime.slot,
+            "runtime frame is slot {} for block slot {}",
+            runtime.slot,
+            block.header.slot
+        );
+        anyhow::ensure!(
+            block.txs.len() == runtime.metas.len(),
+            "slot {}: {} txs but {} runtime entries",
+            runtime.slot,
+            block.txs.len(),
+            runtime.metas.len()
+        );
+
+        let txs = block
+            .txs
+            .into_iter()
+            .zip(runtime.metas)
+            .map(|(tx, metadata)| CompactTxWithMeta { tx, metadata })
+            .collect();
+        Ok(Self {
+            header: block.header,
+            log_tables: runtime.log_tables,
+            rewards: runtime.rewards,
+            entries: block.entries,
+            txs,
+        })
+    }
+}
+
+/// Decode the replay part of one frame of a file with `header`: a block.bin
+/// frame, or a compact.bin block without its status meta.
+pub fn decode_replay_frame<'a>(
+    header: CompactFileHeader,
+    frame: &'a [u8],
+) -> Result<CompactReplayBlock<'a>> {
+    match header.layout {
+        CompactLayout::SplitBlock if header.uses_entries() => {
+            postcard::from_bytes(frame).context("postcard decode")
+        }
+        CompactLayout::SplitBlock => {
+            let b: CompactReplayBlockV1 = postcard::from_bytes(frame).context("postcard decode")?;
+            Ok(b.into())
+        }
+        _ => Ok(decode_compact_frame(header, frame)?.split().0),
+    }
+}
+
+/// Reads block.bin, and runtime.bin in lockstep when attached.
+///
+/// Replay-only consumers never open runtime.bin and so never pay for logs.
+pub struct SplitReader<R> {
+    header: CompactFileHeader,
+    block: PostcardFramedReader<R>,
+    runtime: Option<PostcardFramedReader<R>>,
+    /// The last read resynced either file
+    resynced: bool,
+}
+
+impl SplitReader<BufReader<File>> {
+    /// Open the split files next to `compact_path`; runtime.bin only if `with_runtime`.
+    pub fn open(compact_path: &Path, with_runtime: bool) -> Result<Self> {
+        let (block_path, runtime_path) = split_paths(compact_path);
+        let open = |p: &Path| -> Result<_> {
+            let f = File::open(p).with_context(|| format!("Failed to open {}", p.display()))?;
+            Ok(BufReader::with_capacity(8 << 20, f))
+        };
+
+        let runtime = if with_runtime {
+            Some(open(&runtime_path)?)
+        } else {
+            None
+        };
+        Self::new(open(&block_path)?, runtime)
+            .with_context(|| format!("Failed to read {}", block_path.display()))
+    }
+}
+
+impl<R: BufRead> SplitReader<R> {
+    /// Both inputs must start with their file header.
+    pub fn new(mut block: R, runtime: Option<R>) -> Result<Self> {
+        let header = CompactFileHeader::read(&mut block)?;
+        header.expect(CompactLayout::SplitBlock)?;
+        let runtime = match runtime {
+            Some(mut r) => {
+                let h = CompactFileHeader::read(&mut r)?;
+                h.expect(CompactLayout::SplitRuntime)?;
+                Some(PostcardFramedReader::with_header(r, h))
+            }
+            None => None,
+        };
+        Ok(Self {
+            header,
+            block: PostcardFramedReader::with_header(block, header),
+            runtime,
+            resynced: false,
+        })
+    }
+
+    /// Skip bad frames of either file, see
+    /// [`PostcardFramedReader::recovering`]. A block is lost with either of
+    /// its frames.
+    pub fn recovering(self) -> Self {
+        Self {
+            block: self.block.recovering(),
+            runtime: self.runtime.map(|r| r.recovering()),
+            ..self
+        }
+    }
+
+    /// Bytes of both files dropped to resync.
+    pub fn skipped(&self) -> u64 {
+        self.block.skipped() + self.runtime.as_ref().map_or(0, |r| r.skipped())
+    }
+
+    /// Whether blocks may be missing before the one last read, see
+    /// [`crate::CompactReader::resynced`].
+    #[inline]
+    pub fn resynced(&self) -> bool {
+        self.resynced
+    }
+
+    /// block.bin header.
+    #[inline]
+    pub fn header(&self) -> CompactFileHeader {
+        self.header
+    }
+
+    /// Move both files to their next frames, runtime.bin only skipped unless
+    /// `full`. Once either resynced, the one behind skips to the slot of the
+    /// other, and what is left of one after the other ended is dropped.
+    /// Whether each has a frame.
+    fn advance(&mut self, full: bool) -> Result<(bool, Option<bool>)> {
+        let mut block = self.block.advance()?;
+        let Some(rt) = self.runtime.as_mut() else {
+            self.resynced = self.block.resynced();
+            return Ok((block, None));
+        };
+        let mut runtime = if full {
+            rt.advance()?
+        } else {
+            rt.skip().context("skip runtime frame")?
+        };
+        self.resynced = self.block.resynced() || rt.resynced();
+        // Resyncs need checksums, so skipped runtime frames were read whole.
+        while self.resynced && (block || runtime) {
+            let b = block.then(|| frame_slot(self.block.frame())).transpose()?;
+            let r = runtime.then(|| frame_slot(rt.frame())).transpose()?;
+            match (b, r) {
+                (Some(b), Some(r)) if b == r => break,
+                (Some(b), r) if r.is_none_or(|r| b < r) => block = self.block.advance()?,
+                _ => runtime = rt.advance()?,
+            }
+        }
+        Ok((block, Some(runtime)))
+    }
+
+    /// Next replay block. Skips the matching runtime frame if one is attached.
+    pub fn read_block(&mut self) -> Result<Option<CompactReplayBlock<'_>>> {
+        let (more, skipped) = self.advance(false)?;
+        let block = if more {
+            Some(decode_replay_frame(self.header, self.block.frame())?)
+        } else {
+            None
+        };
+        match (&block, skipped) {
+            (Some(b), Some(false)) => {
+                anyhow::bail!("runtime.bin ended before slot {}", b.header.slot)
+            }
+            (None, Some(true)) => anyhow::bail!("block.bin ended before runtime.bin"),
+            _ => Ok(block),
+        }
+    }
+
+    /// Next block with its status meta. Needs runtime.bin.
+    pub fn read_full(&mut self) -> Result<Option<CompactBlockRecord<'_>>> {
+        anyhow::ensure!(self.runtime.is_some(), "runtime.bin is not attached");
+        let (more, more_runtime) = self.advance(true)?;
+        let h = self.header;
+        let block = if more {
+            Some(decode_replay_frame(h, self.block.frame())?)
+        } else {
+            None
+        };
+        let runtime = match self.runtime.as_ref() {
+            Some(rt) if more_runtime == Some(true) => {
+                let frame = rt.frame();
+                Some(if h.uses_block_rewards() {
+                    postcard::from_bytes(frame).context("postcard decode")?
+                } else if h.uses_token_balance_changes() {
+                    postcard::from_bytes::<CompactRuntimeBlockV2>(frame)
+                        .context("postcard decode")?
+                        .into()
+                } else {
+                    postcard::from_bytes::<CompactRuntimeBlockV2<LegacyTokenBalances>>(frame)
+                        .context("postcard decode")?
+                        .into()
+                })
+            }
+            _ => None,
+        };
+        match (block, runtime) {
+            (Some(b), Some(r)) => CompactBlockRecord::join(b, r).map(Some),
+            (None, None) => Ok(None),
+            (Some(b), None) => anyhow::bail!("runtime.bin ended before slot {}", b.header.slot),
+            (None, Some(r)) => anyhow::bail!("block.bin ended before slot {}", r.slot),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CompactLegacyMessage, CompactMessage, CompactMessageHeader, CompactPubkey,
+        CompactRecentBlockhash, FILE_HEADER_LEN, PostcardFramedWriter, Signature,
+    };
+
+    fn record<'a>(slot: u64, sig: &'a [u8; 64], n: usize) -> CompactBlockRecord<'a> {
+        let tx = CompactTransaction {
+            signatures: [Signature(sig)].into_iter().collect(),
+            message: CompactMessage::Legacy(CompactLegacyMessage {
+                header: CompactMessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 1,
+                },
+                account_keys: vec![CompactPubkey::Id(1), CompactPubkey::Id(2)],
+                recent_blockhash: CompactRecentBlockhash::Id(slot as i32),
+                instructions: Vec::new(),
+            }),
+        };
+        CompactBlockRecord {
+            header: CompactBlockHeader {
+                slot,
+                parent_slot: slot - 1,
+                blockhash: slot as u32,
+                previous_blockhash: slot as u32 - 1,
+                block_time: None,
+                block_height: Some(slot),
+            },
+            log_tables: BlockLogTables::default(),
+            rewards: Vec::new(),
+            entries: None,
+            txs: (0..n)
+                .map(|_| CompactTxWithMeta {
+                    tx: tx.clone(),
+                    metadata: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn split_files_stay_aligned() {
+        let sig = [7u8; 64];
+        let writer = |layout| {
+            PostcardFramedWriter::with_header(Vec::new(), CompactFileHeader::new(layout)).unwrap()
+        };
+        let mut block = writer(CompactLayout::SplitBlock);
+        let mut runtime = writer(CompactLayout::SplitRuntime);
+        let mut short = writer(CompactLayout::SplitRuntime);
+        for (slot, n) in [(5u64, 2usize), (6, 0), (8, 3)] {
+            let (b, r) = record(slot, &sig, n).split();
+            block.write(&b).unwrap();
+            runtime.write(&r).unwrap();
+            if slot < 8 {
+                short.write(&r).unwrap();
+            }
+        }
+        block.finish().unwrap();
+        runtime.finish().unwrap();
+        short.finish().unwrap();
+        let (block, runtime, short) =
+            (block.into_inner(), runtime.into_inner(), short.into_inner());
+
+        let mut r = SplitReader::new(&block[..], Some(&runtime[..])).unwrap();
+        let mut seen = Vec::new();
+        while let Some(b) = r.read_full().unwrap() {
+            seen.push((b.header.slot, b.txs.len()));
+        }
+        assert_eq!(seen, [(5, 2), (6, 0), (8, 3)]);
+
+        // Replay-only readers don't need runtime.bin at all.
+        let mut r = SplitReader::new(&block[..], None).unwrap();
+        assert_eq!(r.read_block().unwrap().unwrap().txs.len(), 2);
+        assert!(r.read_full().is_err());
+
+        // A runtime.bin that is one frame off is rejected.
+        let h = FILE_HEADER_LEN;
+        let first = h + 12 + u32::from_le_bytes(runtime[h + 4..h + 8].try_into().unwrap()) as usize;
+        let shifted = [&runtime[..h], &runtime[first..]].concat();
+        let mut r = SplitReader::new(&block[..], Some(&shifted[..])).unwrap();
+        assert!(r.read_full().is_err());
+
+        // So is one that ends early, even when only skipped.
+        let mut r = SplitReader::new(&block[..], Some(&short[..])).unwrap();
+        assert!(r.read_block().unwrap().is_some());
+        assert!(r.read_block().unwrap().is_some());
+        let err = r.read_block().unwrap_err().to_string();
+        assert!(err.contains("runtime.bin ended"), "{}", err);
+
+        // A recovering reader drops both frames of a block with a bad one.
+        let second = |file: &[u8]| {
+            h + 12 + u32::from_le_bytes(file[h + 4..h + 8].try_into().unwrap()) as usize
+        };
+        let mut rotten_runtime = runtime.clone();
+        rotten_runtime[second(&runtime) + 8] ^= 1;
+        let mut r = SplitReader::new(&block[..], Some(&rotten_runtime[..]))
+            .unwrap()
+            .recovering();
+        let mut seen = Vec::new();
+        loop {
+            let Some(b) = r.read_full().unwrap() else {
+                break;
+            };
+            let slot = b.header.slot;
+            drop(b);
+            seen.push((slot, r.resynced()));
+        }
+        assert_eq!(seen, [(5, false), (8, true)]);
+        assert!(r.skipped() > 0);
+
+        let mut rotten_block = block.clone();
+        rotten_block[h + 8] ^= 1;
+        let mut r = SplitReader::new(&rotten_block[..], Some(&runtime[..]))
+            .unwrap()
+            .recovering();
+        let mut seen = Vec::new();
+        loop {
+            let Some(b) = r.read_block().unwrap() else {
+                break;
+            };
+            let slot = b.header.slot;
+            drop(b);
+            seen.push((slot, r.resynced()));
+        }
+        assert_eq!(seen, [(6, true), (8, false)]);
+
+        // Swapped files are caught by the header.
+        assert!(SplitReader::new(&runtime[..], Some(&block[..])).is_err());
+    }
+}
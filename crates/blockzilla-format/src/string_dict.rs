@@ -0,0 +1,279 @@
+use anyhow::{Context, Result};
+use gxhash::HashMap as GxHashMap;
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    CompactFileHeader, CompactLayout, StrId, StringTable,
+    program_logs::template::{Template, TemplateDict},
+    raw_file_body,
+    registry::gxhash64,
+    write_raw_file,
+};
+
+/// Epoch string dictionary (strings.bin): the log strings worth sharing
+/// across the blocks of an epoch, most used first.
+///
+/// It plays the part of registry.bin for log strings: a `StrId` below
+/// `len()` is a dictionary id, anything above indexes the block table (see
+/// [`StrRef`]).
+#[derive(Debug, Default)]
+pub struct StringDict {
+    strings: Vec<String>,
+    /// gxhash64(string) -> id, first string wins on collision
+    ids: GxHashMap<u64, StrId>,
+}
+
+impl StringDict {
+    pub fn new(strings: Vec<String>) -> Self {
+        let mut ids = GxHashMap::default();
+        for (i, s) in strings.iter().enumerate() {
+            ids.entry(gxhash64(s.as_str())).or_insert(i as StrId);
+        }
+        Self { strings, ids }
+    }
+
+    /// registry.bin -> strings.bin
+    pub fn path_for(registry_path: &Path) -> PathBuf {
+        registry_path.with_file_name("strings.bin")
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    #[inline]
+    pub fn strings(&self) -> &[String] {
+        &self.strings
+    }
+
+    /// Dictionary id of `s`.
+    #[inline]
+    pub fn lookup(&self, s: &str) -> Option<StrId> {
+        let id = *self.ids.get(&gxhash64(s))?;
+        (self.strings[id as usize] == s).then_some(id)
+    }
+
+    /// [`Self::encode`] between the file header and trailer.
+    pub fn write(&self, path: &Path, epoch: u64) -> Result<()> {
+        let header = CompactFileHeader::new(CompactLayout::StringDict).with_epoch(epoch);
+        write_raw_file(path, header, |w| self.encode(w))
+    }
+
+    /// Layout (little endian): string count u32, then
+    /// every string as byte len u32 + utf8 bytes.
+    pub fn encode<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&(self.strings.len() as u32).to_le_bytes())
+            .context("write dict header")?;
+        for s in &self.strings {
+            w.write_all(&(s.len() as u32).to_le_bytes())
+                .context("write dict string")?;
+            w.write_all(s.as_bytes()).context("write dict string")?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut r = raw_file_body(&bytes, CompactLayout::StringDict)
+            .with_context(|| format!("string dictionary {}", path.display()))?;
+
+        let mut len = [0u8; 4];
+        r.read_exact(&mut len).context("read dict header")?;
+        let n = u32::from_le_bytes(len) as usize;
+        let mut strings = Vec::with_capacity(n);
+        for _ in 0..n {
+            r.read_exact(&mut len).context("read dict string")?;
+            let mut b = vec![0u8; u32::from_le_bytes(len) as usize];
+            r.read_exact(&mut b).context("read dict string")?;
+            strings.push(String::from_utf8(b).context("dict string is not utf8")?);
+        }
+        anyhow::ensure!(
+            r.read(&mut len)? == 0,
+            "trailing bytes in {}",
+            path.display()
+        );
+
+        Ok(Self::new(strings))
+    }
+}
+
+/// Epoch dictionaries log ids resolve through, next to registry.bin.
+#[derive(Debug, Default)]
+pub struct LogDicts {
+    pub strings: StringDict,
+    pub templates: TemplateDict,
+}
+
+impl LogDicts {
+    /// Dictionaries a file with `header` was written against: strings.bin
+    /// from format v3 on, templates.bin from v4 on, empty before. They must
+    /// be the very ones, see [`Self::id`].
+    pub fn load_for(header: CompactFileHeader, registry_path: &Path) -> Result<Self> {
+        let mut dicts = Self::default();
+        if !header.uses_string_dict() {
+            return Ok(dicts);
+        }
+        dicts.strings = StringDict::load(&StringDict::path_for(registry_path))?;
+        if header.uses_templates() {
+            dicts.templates = TemplateDict::load(&TemplateDict::path_for(registry_path))?;
+        }
+        let id = dicts.id();
+        anyhow::ensure!(
+            id == header.dicts,
+            "log dictionaries next to {} are {:016x}, the file was written against {:016x}",
+            registry_path.display(),
+            id,
+            header.dicts
+        );
+        Ok(dicts)
+    }
+
+    /// First 8 bytes of the blake3 hash of the encoded strings.bin and
+    /// templates.bin contents.
+    pub fn id(&self) -> u64 {
+        let mut h = blake3::Hasher::new();
+        self.strings
+            .encode(&mut h)
+            .and_then(|()| self.templates.encode(&mut h))
+            .expect("hashing cannot fail");
+        u64::from_le_bytes(h.finalize().as_bytes()[..8].try_into().unwrap())
+    }
+}
+
+/// Where a `StrId` points, given the dictionary length of its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrRef {
+    /// Epoch dictionary id
+    Global(StrId),
+    /// Index into the block (or stream) string table
+    Local(StrId),
+}
+
+impl StrRef {
+    #[inline]
+    pub fn of(id: StrId, dict_len: usize) -> Self {
+        match id.checked_sub(dict_len as StrId) {
+            Some(local) => StrRef::Local(local),
+            None => StrRef::Global(id),
+        }
+    }
+
+    #[inline]
+    pub fn to_id(self, dict_len: usize) -> StrId {
+        match self {
+            StrRef::Global(id) => id,
+            StrRef::Local(id) => id + dict_len as StrId,
+        }
+    }
+}
+
+/// Resolves `StrId`s against a dictionary and a string table, and template
+/// ids against the template dictionary.
+#[derive(Debug, Clone, Copy)]
+pub struct LogStrings<'a> {
+    dict: &'a [String],
+    templates: Option<&'a TemplateDict>,
+    table: &'a StringTable,
+}
+
+impl<'a> LogStrings<'a> {
+    pub fn new(dicts: &'a LogDicts, table: &'a StringTable) -> Self {
+        Self {
+            dict: &dicts.strings.strings,
+            templates: Some(&dicts.templates),
+            table,
+        }
+    }
+
+    /// No dictionary: ids are plain table indexes (self-contained streams,
+    /// files older than format v3).
+    pub fn local(table: &'a StringTable) -> Self {
+        Self {
+            dict: &[],
+            templates: None,
+            table,
+        }
+    }
+
+    #[inline]
+    pub fn resolve(&self, id: StrId) -> &'a str {
+        match StrRef::of(id, self.dict.len()) {
+            StrRef::Global(id) => &self.dict[id as usize],
+            StrRef::Local(id) => self.table.resolve(id),
+        }
+    }
+
+    #[inline]
+    pub fn template(&self, id: u32) -> Result<&'a Template> {
+        self.templates
+            .and_then(|t| t.get(id))
+            .with_context(|| format!("log template id out of bounds: {}", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dict_round_trips_and_splits_ids() {
+        let dict = StringDict::new(vec!["Instruction: Transfer".into(), "".into()]);
+        let path = std::env::temp_dir().join(format!("bz-strings-{}.bin", std::process::id()));
+        dict.write(&path, 0).unwrap();
+        let dict = StringDict::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(dict.lookup("Instruction: Transfer"), Some(0));
+        assert_eq!(dict.lookup(""), Some(1));
+        assert_eq!(dict.lookup("Instruction: Burn"), None);
+
+        let mut table = StringTable::default();
+        table.push("local");
+        let dicts = LogDicts {
+            strings: dict,
+            ..LogDicts::default()
+        };
+        let st = LogStrings::new(&dicts, &table);
+        assert_eq!(st.resolve(0), "Instruction: Transfer");
+        assert_eq!(st.resolve(StrRef::Local(0).to_id(2)), "local");
+        assert_eq!(StrRef::of(2, dicts.strings.len()), StrRef::Local(0));
+        assert_eq!(LogStrings::local(&table).resolve(0), "local");
+    }
+
+    #[test]
+    fn files_only_load_their_own_dicts() {
+        let dir = std::env::temp_dir().join(format!("bz-dicts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let registry = dir.join("registry.bin");
+        let dicts = LogDicts {
+            strings: StringDict::new(vec!["Instruction: Transfer".into()]),
+            ..LogDicts::default()
+        };
+        dicts
+            .strings
+            .write(&StringDict::path_for(&registry), 0)
+            .unwrap();
+        dicts
+            .templates
+            .write(&TemplateDict::path_for(&registry), 0)
+            .unwrap();
+
+        let header = CompactFileHeader::new(crate::CompactLayout::Plain);
+        let loaded = LogDicts::load_for(header.with_dicts(dicts.id()), &registry);
+        let stale = LogDicts::load_for(header.with_dicts(LogDicts::default().id()), &registry);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.unwrap().strings.strings(), dicts.strings.strings());
+        assert!(stale.is_err());
+    }
+}
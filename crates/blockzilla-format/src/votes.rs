@@ -0,0 +1,259 @@
+use anyhow::{Context, Result};
+use car_reader::versioned_transaction::{VersionedMessage, VersionedTransaction};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    CompactBlockRecord, CompactFileHeader, CompactReplayBlock, CompactTokenBalanceChange,
+    CompactTxWithMeta, LegacyTokenBalances, VOTE_PROGRAM,
+};
+
+/// compact.bin -> votes.bin (same directory)
+pub fn votes_path(compact_path: &Path) -> PathBuf {
+    compact_path.with_file_name("votes.bin")
+}
+
+/// Whether every instruction of `vtx` calls the Vote program; those are the
+/// transactions moved to votes.bin.
+pub fn is_vote_only(vtx: &VersionedTransaction) -> bool {
+    let (keys, ixs) = match &vtx.message {
+        VersionedMessage::Legacy(m) => (&m.account_keys, &m.instructions),
+        VersionedMessage::V0(m) => (&m.account_keys, &m.instructions),
+    };
+    !ixs.is_empty()
+        && ixs.iter().all(|ix| {
+            keys.get(ix.program_id_index as usize)
+                .is_some_and(|k| *k == VOTE_PROGRAM.as_array())
+        })
+}
+
+/// votes.bin frame: the vote-only transactions of the block at the same frame
+/// ordinal in compact.bin. Their logs refer to that frame's log tables, and
+/// signature and slot indexes count them at their position in the full block.
+/// `T` as in [`crate::CompactMetaV1`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompactVoteBlock<'a, T = Vec<CompactTokenBalanceChange>> {
+    /// Copy of the block slot, checked when merging.
+    pub slot: u64,
+    /// Position of each transaction in the full block, ascending.
+    pub positions: Vec<u32>,
+    #[serde(borrow)]
+    pub txs: Vec<CompactTxWithMeta<'a, T>>,
+}
+
+impl<'a> From<CompactVoteBlock<'a, LegacyTokenBalances>> for CompactVoteBlock<'a> {
+    fn from(v: CompactVoteBlock<'a, LegacyTokenBalances>) -> Self {
+        Self {
+            slot: v.slot,
+            positions: v.positions,
+            txs: v.txs.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// Decode one votes.bin frame of a file with `header`.
+pub fn decode_vote_frame<'a>(
+    header: CompactFileHeader,
+    frame: &'a [u8],
+) -> Result<CompactVoteBlock<'a>> {
+    if header.uses_token_balance_changes() {
+        postcard::from_bytes(frame).context("postcard decode")
+    } else {
+        let v: CompactVoteBlock<LegacyTokenBalances> =
+            postcard::from_bytes(frame).context("postcard decode")?;
+        Ok(v.into())
+    }
+}
+
+/// `txs` of block `slot` with `votes` put back at their `positions`.
+fn merge_at<T>(slot: u64, txs: Vec<T>, positions: Vec<u32>, votes: Vec<T>) -> Result<Vec<T>> {
+    anyhow::ensure!(
+        positions.len() == votes.len(),
+        "slot {}: {} vote positions for {} votes",
+        slot,
+        positions.len(),
+        votes.len()
+    );
+    if votes.is_empty() {
+        return Ok(txs);
+    }
+
+    let total = txs.len() + votes.len();
+    let mut rest = txs.into_iter();
+    let mut votes_in = positions.into_iter().zip(votes).peekable();
+    let mut out = Vec::with_capacity(total);
+    for i in 0..total {
+        let tx = match votes_in.next_if(|(p, _)| *p as usize == i) {
+            Some((_, tx)) => tx,
+            None => rest
+                .next()
+                .with_context(|| format!("slot {}: vote position out of order", slot))?,
+        };
+        out.push(tx);
+    }
+    Ok(out)
+}
+
+impl<'a> CompactBlockRecord<'a> {
+    /// Put the transactions of the matching votes.bin frame back in place.
+    pub fn merge_votes(&mut self, votes: CompactVoteBlock<'a>) -> Result<()> {
+        anyhow::ensure!(
+            self.header.slot == votes.slot,
+            "votes frame is slot {} for block slot {}",
+            votes.slot,
+            self.header.slot
+        );
+        let txs = std::mem::take(&mut self.txs);
+        self.txs = merge_at(votes.slot, txs, votes.positions, votes.txs)?;
+        Ok(())
+    }
+}
+
+impl<'a> CompactReplayBlock<'a> {
+    /// [`CompactBlockRecord::merge_votes`] for a replay block: the votes
+    /// come back without their meta.
+    pub fn merge_votes(&mut self, votes: CompactVoteBlock<'a>) -> Result<()> {
+        anyhow::ensure!(
+            self.header.slot == votes.slot,
+            "votes frame is slot {} for block slot {}",
+            votes.slot,
+            self.header.slot
+        );
+        let txs = std::mem::take(&mut self.txs);
+        let vote_txs = votes.txs.into_iter().map(|t| t.tx).collect();
+        self.txs = merge_at(votes.slot, txs, votes.positions, vote_txs)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BlockLogTables, CompactBlockHeader, CompactFileHeader, CompactLayout, CompactLegacyMessage,
+        CompactMessage, CompactMessageHeader, CompactPubkey, CompactReader, CompactRecentBlockhash,
+        CompactTransaction, FILE_HEADER_LEN, PostcardFramedWriter, Signature,
+    };
+
+    fn tx(sig: &[u8; 64], blockhash: i32) -> CompactTxWithMeta<'_> {
+        CompactTxWithMeta {
+            tx: CompactTransaction {
+                signatures: [Signature(sig)].into_iter().collect(),
+                message: CompactMessage::Legacy(CompactLegacyMessage {
+                    header: CompactMessageHeader {
+                        num_required_signatures: 1,
+                        num_readonly_signed_accounts: 0,
+                        num_readonly_unsigned_accounts: 1,
+                    },
+                    account_keys: vec![CompactPubkey::Id(1), CompactPubkey::Id(2)],
+                    recent_blockhash: CompactRecentBlockhash::Id(blockhash),
+                    instructions: Vec::new(),
+                }),
+            },
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn votes_merge_back_in_place() {
+        let sig = [7u8; 64];
+        let writer = |layout| {
+            PostcardFramedWriter::with_header(Vec::new(), CompactFileHeader::new(layout)).unwrap()
+        };
+        let mut compact = writer(CompactLayout::Plain);
+        let mut votes = writer(CompactLayout::Votes);
+
+        // Full blocks: the blockhash id of a tx is its position, votes are odd.
+        let blocks: [(u64, &[i32], &[u32]); 3] =
+            [(5, &[0, 2], &[1, 3]), (6, &[], &[0]), (8, &[0, 1], &[])];
+        for (slot, rest, positions) in blocks {
+            compact
+                .write(&CompactBlockRecord {
+                    header: CompactBlockHeader {
+                        slot,
+                        parent_slot: slot - 1,
+                        blockhash: 0,
+                        previous_blockhash: 0,
+                        block_time: None,
+                        block_height: None,
+                    },
+                    log_tables: BlockLogTables::default(),
+                    rewards: Vec::new(),
+                    entries: None,
+                    txs: rest.iter().map(|&i| tx(&sig, i)).collect(),
+                })
+                .unwrap();
+            votes
+                .write(&CompactVoteBlock {
+                    slot,
+                    positions: positions.to_vec(),
+                    txs: positions.iter().map(|&p| tx(&sig, p as i32)).collect(),
+                })
+                .unwrap();
+        }
+        compact.finish().unwrap();
+        votes.finish().unwrap();
+        let (compact, votes) = (compact.into_inner(), votes.into_inner());
+
+        let read_all = |mut r: CompactReader<&[u8]>| {
+            let mut seen = Vec::new();
+            loop {
+                let Some(b) = r.read().unwrap() else {
+                    break;
+                };
+                let ids: Vec<i32> = b
+                    .txs
+                    .iter()
+                    .map(|t| match &t.tx.message {
+                        CompactMessage::Legacy(m) => match m.recent_blockhash {
+                            CompactRecentBlockhash::Id(i) => i,
+                            _ => unreachable!(),
+                        },
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                let slot = b.header.slot;
+                drop(b);
+                seen.push((slot, ids, r.resynced()));
+            }
+            seen
+        };
+        let with_votes = |compact| {
+            CompactReader::new(compact)
+                .unwrap()
+                .with_votes(&votes[..])
+                .unwrap()
+        };
+        assert_eq!(
+            read_all(with_votes(&compact[..])),
+            [
+                (5, vec![0, 1, 2, 3], false),
+                (6, vec![0], false),
+                (8, vec![0, 1], false)
+            ]
+        );
+
+        // A recovering reader drops the votes of a lost block with it.
+        let second = FILE_HEADER_LEN
+            + 12
+            + u32::from_le_bytes(compact[FILE_HEADER_LEN + 4..][..4].try_into().unwrap()) as usize;
+        let mut rotten = compact.clone();
+        rotten[second + 8] ^= 1;
+        let mut r = with_votes(&rotten[..]);
+        r.read().unwrap();
+        assert!(r.read().is_err());
+        assert_eq!(
+            read_all(with_votes(&rotten[..]).recovering()),
+            [(5, vec![0, 1, 2, 3], false), (8, vec![0, 1], true)]
+        );
+
+        // Without votes.bin the blocks simply have no votes.
+        let mut r = CompactReader::new(&compact[..]).unwrap();
+        assert_eq!(r.read().unwrap().unwrap().txs.len(), 2);
+
+        // votes.bin is not a compact.bin and the other way round.
+        assert!(CompactReader::new(&votes[..]).is_err());
+        let r = CompactReader::new(&compact[..]).unwrap();
+        assert!(r.with_votes(&compact[..]).is_err());
+    }
+}
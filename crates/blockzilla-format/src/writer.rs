@@ -1,28 +1,91 @@
 use anyhow::{Context, Result};
 use std::io::Write;
 
+use crate::{CompactFileHeader, FRAME_MAGIC, TRAILER_LEN, TRAILER_MARK};
+
 pub struct PostcardFramedWriter<W> {
     w: W,
+    /// Bytes written so far (frame offsets for indexes)
+    pos: u64,
+    /// Whole-file hash, `None` for files without checksums (or once finished)
+    hash: Option<blake3::Hasher>,
+    /// Frames start with `FRAME_MAGIC`
+    magic: bool,
+    /// Frame payload scratch for `write` with checksums
+    buf: Vec<u8>,
 }
 
 impl<W: Write> PostcardFramedWriter<W> {
+    /// Headerless frames without checksums (format v1).
     pub fn new(w: W) -> Self {
-        Self { w }
+        Self {
+            w,
+            pos: 0,
+            hash: None,
+            magic: false,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Write `header`, then frames as its version lays them out.
+    pub fn with_header(w: W, header: CompactFileHeader) -> Result<Self> {
+        let mut s = Self::new(w);
+        if header.uses_checksums() {
+            s.hash = Some(blake3::Hasher::new());
+        }
+        s.magic = header.uses_frame_magic();
+        s.write_raw(&header.to_bytes())?;
+        Ok(s)
     }
 
     #[inline]
     pub fn write<T: serde::Serialize>(&mut self, v: &T) -> Result<()> {
+        if self.hash.is_some() {
+            let mut buf = std::mem::take(&mut self.buf);
+            buf.clear();
+            postcard::to_io(v, &mut buf)?;
+            let res = self.write_bytes(&buf);
+            self.buf = buf;
+            return res;
+        }
         let len = postcard::experimental::serialized_size(v)? as u32;
         self.w.write_all(&len.to_le_bytes())?;
         postcard::to_io(v, &mut self.w)?;
+        self.pos += 4 + len as u64;
         Ok(())
     }
 
+    /// Offset of the next frame, counted from where this writer started.
+    #[inline]
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Bytes a frame takes besides its payload: magic, length prefix and
+    /// checksum.
+    #[inline]
+    pub fn frame_overhead(&self) -> u64 {
+        let crc = if self.hash.is_some() { 4 } else { 0 };
+        let magic = if self.magic { 4 } else { 0 };
+        magic + 4 + crc
+    }
+
     #[inline]
     pub fn flush(&mut self) -> Result<()> {
         self.w.flush().context("flush")
     }
 
+    /// Write the trailer (files with checksums) and flush. Nothing may be
+    /// written after it.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(hash) = self.hash.take() {
+            self.w.write_all(&TRAILER_MARK.to_le_bytes())?;
+            self.w.write_all(hash.finalize().as_bytes())?;
+            self.pos += TRAILER_LEN as u64;
+        }
+        self.flush()
+    }
+
     #[inline]
     pub fn into_inner(self) -> W {
         self.w
@@ -33,8 +96,38 @@ impl<W: std::io::Write> PostcardFramedWriter<W> {
     #[inline]
     pub fn write_bytes(&mut self, payload: &[u8]) -> anyhow::Result<()> {
         let len = payload.len() as u32;
-        self.w.write_all(&len.to_le_bytes())?;
-        self.w.write_all(payload)?;
+        anyhow::ensure!(len != TRAILER_MARK, "frame of {} bytes", len);
+        if self.magic {
+            self.write_raw(&FRAME_MAGIC.to_le_bytes())?;
+        }
+        self.write_raw(&len.to_le_bytes())?;
+        self.write_raw(payload)?;
+        if self.hash.is_some() {
+            self.write_raw(&crc32c::crc32c(payload).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Unframed bytes, e.g. a file header. Counted in `position()`.
+    #[inline]
+    pub fn write_raw(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.w.write_all(bytes)?;
+        if let Some(h) = self.hash.as_mut() {
+            h.update(bytes);
+        }
+        self.pos += bytes.len() as u64;
         Ok(())
     }
 }
+
+/// Unframed bytes as `write_raw`, for encoders that stream into a writer.
+impl<W: Write> Write for PostcardFramedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_raw(buf).map_err(std::io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.w.flush()
+    }
+}
@@ -1,14 +1,19 @@
+use gxhash::GxHasher;
+use gxhash::HashMap;
+use gxhash::HashMapExt;
 use std::hash::Hasher;
 use std::io::Read;
 use std::mem::MaybeUninit;
-use gxhash::GxHasher;
-use gxhash::HashMapExt;
-use gxhash::HashMap;
 
-use crate::confirmed_block::TransactionStatusMeta;
+use crate::confirmed_block::{Reward, TransactionStatusMeta};
 use crate::error::{CarReadError, CarReadResult, GroupError};
-use crate::metadata_decoder::{ZstdReusableDecoder, decode_transaction_status_meta_from_frame};
-use crate::node::{CborArrayIter, CborCidRef, Node, NodeDecodeError, decode_node, is_block_node};
+use crate::metadata_decoder::{
+    ZstdReusableDecoder, decode_rewards_from_frame, decode_transaction_status_meta_from_frame,
+};
+use crate::node::{
+    BlockNode, CborArrayIter, CborCidRef, EntryNode, Node, NodeDecodeError, decode_node,
+    is_block_node,
+};
 use crate::versioned_transaction::VersionedTransaction;
 
 use wincode::Deserialize;
@@ -111,6 +116,43 @@ impl CarBlockGroup {
         decode_node(payload).map_err(GroupError::Node)
     }
 
+    /// Block-level rewards of `block` (this group's block node), empty when
+    /// it has no `RewardsNode`.
+    pub fn rewards(
+        &self,
+        block: &BlockNode<'_>,
+        zstd: &mut ZstdReusableDecoder,
+    ) -> Result<Vec<Reward>, GroupError> {
+        let Some(cid) = &block.rewards else {
+            return Ok(Vec::new());
+        };
+        let Node::Rewards(rewards) = self.decode_by_hash(cid.hash_bytes())? else {
+            return Err(GroupError::Other(
+                "rewards cid is not a Rewards node".into(),
+            ));
+        };
+        if rewards.data.next.is_some() {
+            return Err(GroupError::DataFrameHasNext);
+        }
+        decode_rewards_from_frame(rewards.slot, rewards.data.data, zstd)
+            .map_err(|e| GroupError::Other(format!("block rewards: {e}")))
+    }
+
+    /// Entry nodes of `block` (this group's block node), in PoH order.
+    pub fn entries<'a>(&'a self, block: &BlockNode<'a>) -> Result<Vec<EntryNode<'a>>, GroupError> {
+        block
+            .entries
+            .iter()
+            .map(|cid| {
+                let cid = cid.map_err(|e| GroupError::Node(NodeDecodeError::from(e)))?;
+                match self.decode_by_hash(cid.hash_bytes())? {
+                    Node::Entry(entry) => Ok(entry),
+                    _ => Err(GroupError::Other("entry cid is not an Entry node".into())),
+                }
+            })
+            .collect()
+    }
+
     pub fn transactions<'a>(&'a self) -> Result<TxIter<'a>, GroupError> {
         let block = match decode_node(self.block_payload()).map_err(GroupError::Node)? {
             Node::Block(b) => b,
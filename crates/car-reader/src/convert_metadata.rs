@@ -69,15 +69,15 @@ pub fn stored_meta_to_proto(m: StoredTransactionStatusMeta) -> TransactionStatus
 
         rewards,
 
-        // You do not store these, so default empty.
+        // Address lookup tables postdate the bincode epochs.
         loaded_writable_addresses: Vec::new(),
         loaded_readonly_addresses: Vec::new(),
 
         return_data,
         return_data_none,
 
-        compute_units_consumed: None,
-        cost_units: None,
+        compute_units_consumed: m.compute_units_consumed.into_option(),
+        cost_units: m.cost_units.into_option(),
     }
 }
 
@@ -123,30 +123,55 @@ fn token_balance_to_proto(
         account_index: tb.account_index as u32,
         mint: tb.mint.clone(),
         ui_token_amount: Some(ui_token_amount_to_proto(&tb.ui_token_amount)),
-        //TODO: fixme as this should be an option
-        owner: String::default(),
-        //TODO: fixme as this should be an option
-        program_id: String::default(),
+        // Empty when the layout predates these fields, same as the proto.
+        owner: tb.owner.clone(),
+        program_id: tb.program_id.clone(),
     }
 }
 
 #[inline]
 fn ui_token_amount_to_proto(a: &stored::StoredTokenAmount) -> confirmed_block::UiTokenAmount {
-    // You have: ui_amount (f64), decimals (u8), amount (String)
-    // Prost expects: ui_amount_string also. Best effort:
-    // - keep the raw integer string in `amount`
-    // - keep `ui_amount` as-is
-    // - set `ui_amount_string` from ui_amount to preserve human formatting
+    // `ui_amount_string` is not stored: RPC derives it from the raw amount, not from
+    // the f64, so do the same to get identical strings.
     confirmed_block::UiTokenAmount {
         ui_amount: a.ui_amount,
         decimals: a.decimals as u32,
         amount: a.amount.clone(),
-        ui_amount_string: a.ui_amount.to_string(),
+        ui_amount_string: ui_amount_string(&a.amount, a.decimals),
     }
 }
 
+/// `ui_amount_string` of a stored raw `amount`. Digits beyond u64 are scaled
+/// all the same; an amount that is not a number is carried as it is rather
+/// than read as 0.
+pub fn ui_amount_string(amount: &str, decimals: u8) -> String {
+    if amount.is_empty() || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return amount.to_string();
+    }
+    let digits = amount.trim_start_matches('0');
+    real_digits_string_trimmed(if digits.is_empty() { "0" } else { digits }, decimals)
+}
+
+/// `amount` scaled down by `decimals`, trailing zeros (and dot) removed.
+#[inline]
+pub fn real_number_string_trimmed(amount: u64, decimals: u8) -> String {
+    real_digits_string_trimmed(&amount.to_string(), decimals)
+}
+
+/// [`real_number_string_trimmed`] of a decimal digit string.
+fn real_digits_string_trimmed(digits: &str, decimals: u8) -> String {
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return digits.to_string();
+    }
+    // Left-pad so there is at least one integer digit.
+    let mut s = format!("{:0>1$}", digits, decimals + 1);
+    s.insert(s.len() - decimals, '.');
+    s.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
 #[inline]
-fn reward_to_proto(r: &stored::StoredExtendedReward) -> confirmed_block::Reward {
+pub(crate) fn reward_to_proto(r: &stored::StoredExtendedReward) -> confirmed_block::Reward {
     confirmed_block::Reward {
         pubkey: r.pubkey.clone(),
         lamports: r.lamports,
@@ -158,12 +183,31 @@ fn reward_to_proto(r: &stored::StoredExtendedReward) -> confirmed_block::Reward
 }
 
 #[inline]
-fn map_reward_type(rt: Option<u8>) -> confirmed_block::RewardType {
+fn map_reward_type(rt: Option<stored::StoredRewardType>) -> confirmed_block::RewardType {
     match rt {
-        Some(1) => confirmed_block::RewardType::Fee,
-        Some(2) => confirmed_block::RewardType::Rent,
-        Some(3) => confirmed_block::RewardType::Staking,
-        Some(4) => confirmed_block::RewardType::Voting,
-        _ => confirmed_block::RewardType::Unspecified,
+        Some(stored::StoredRewardType::Fee) => confirmed_block::RewardType::Fee,
+        Some(stored::StoredRewardType::Rent) => confirmed_block::RewardType::Rent,
+        Some(stored::StoredRewardType::Staking) => confirmed_block::RewardType::Staking,
+        Some(stored::StoredRewardType::Voting) => confirmed_block::RewardType::Voting,
+        None => confirmed_block::RewardType::Unspecified,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ui_amount_strings_keep_the_raw_amount() {
+        assert_eq!(ui_amount_string("819050032691", 5), "8190500.32691");
+        assert_eq!(ui_amount_string("0", 6), "0");
+        assert_eq!(ui_amount_string("0042", 1), "4.2");
+        // Above u64::MAX, no longer "0".
+        assert_eq!(
+            ui_amount_string("123456789012345678901234", 9),
+            "123456789012345.678901234"
+        );
+        assert_eq!(ui_amount_string("-1", 2), "-1");
+        assert_eq!(ui_amount_string("", 2), "");
     }
 }
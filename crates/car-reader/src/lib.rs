@@ -14,6 +14,7 @@ pub mod stored_transaction_error;
 pub mod stored_transaction_status_meta;
 pub mod versioned_transaction;
 
+pub use convert_metadata::{real_number_string_trimmed, ui_amount_string};
 pub use reader::CarBlockReader;
 
 pub mod confirmed_block {
@@ -2,8 +2,11 @@ use core::fmt;
 use prost::Message;
 use zstd::zstd_safe;
 
-use crate::confirmed_block::TransactionStatusMeta;
-use crate::stored_transaction_status_meta::StoredTransactionStatusMeta;
+use crate::confirmed_block::{Reward, Rewards, TransactionStatusMeta};
+use crate::convert_metadata::reward_to_proto;
+use crate::stored_transaction_status_meta::{
+    StoredMetaLayout, StoredTransactionStatusMeta, decode_stored_rewards,
+};
 
 pub const BINCODE_EPOCH_CUTOFF: u64 = 148;
 
@@ -13,6 +16,11 @@ pub enum MetadataDecodeError {
     Bincode(String),
     ProstDecode(prost::DecodeError),
     ProtoConvert(String),
+    /// Bincode meta that no known [`StoredMetaLayout`] decodes exactly.
+    UnknownLayout {
+        len: usize,
+        detail: String,
+    },
 }
 
 impl fmt::Display for MetadataDecodeError {
@@ -22,6 +30,12 @@ impl fmt::Display for MetadataDecodeError {
             MetadataDecodeError::Bincode(e) => write!(f, "bincode decode: {e}"),
             MetadataDecodeError::ProstDecode(e) => write!(f, "protobuf decode: {e}"),
             MetadataDecodeError::ProtoConvert(e) => write!(f, "protobuf convert: {e}"),
+            MetadataDecodeError::UnknownLayout { len, detail } => {
+                write!(
+                    f,
+                    "bincode meta ({len} bytes) matches no known layout: {detail}"
+                )
+            }
         }
     }
 }
@@ -124,10 +138,7 @@ pub fn decode_transaction_status_meta(
     let epoch = slot_to_epoch(slot);
 
     if epoch < BINCODE_EPOCH_CUTOFF {
-        *out = wincode::deserialize::<StoredTransactionStatusMeta>(metadata_bytes)
-            .inspect_err(|_err| println!("invalid metadata : {:?}", metadata_bytes))
-            .map_err(|err| MetadataDecodeError::Bincode(err.to_string()))?
-            .into();
+        *out = decode_stored_meta(metadata_bytes)?.into();
     } else {
         out.merge(metadata_bytes)
             .map_err(MetadataDecodeError::ProstDecode)?;
@@ -136,6 +147,65 @@ pub fn decode_transaction_status_meta(
     Ok(())
 }
 
+/// Decode a bincode `StoredTransactionStatusMeta`, whatever layout it was written with.
+///
+/// Layouts are tried oldest first; a layout only matches if it consumes every byte,
+/// anything else is reported as [`MetadataDecodeError::UnknownLayout`].
+pub fn decode_stored_meta(
+    metadata_bytes: &[u8],
+) -> Result<StoredTransactionStatusMeta, MetadataDecodeError> {
+    let mut detail = Vec::with_capacity(StoredMetaLayout::ALL.len());
+
+    for layout in StoredMetaLayout::ALL {
+        match layout.decode(metadata_bytes) {
+            Ok((meta, false)) => return Ok(meta),
+            Ok((_, true)) => detail.push(format!("{layout:?}: trailing bytes")),
+            Err(err) => detail.push(format!("{layout:?}: {err}")),
+        }
+    }
+
+    Err(MetadataDecodeError::UnknownLayout {
+        len: metadata_bytes.len(),
+        detail: detail.join(", "),
+    })
+}
+
+/// Decode block rewards from a `RewardsNode` frame (possibly zstd-compressed;
+/// possibly empty): bincode `StoredExtendedRewards` for early epochs, oldest
+/// reward layout first, protobuf `Rewards` for later ones.
+pub fn decode_rewards_from_frame(
+    slot: u64,
+    frame: &[u8],
+    zstd: &mut ZstdReusableDecoder,
+) -> Result<Vec<Reward>, MetadataDecodeError> {
+    if frame.is_empty() {
+        return Ok(Vec::new());
+    }
+    let bytes = if zstd
+        .decompress_if_zstd(frame)
+        .map_err(MetadataDecodeError::ZstdDecompress)?
+    {
+        zstd.output()
+    } else {
+        frame
+    };
+
+    if slot_to_epoch(slot) >= BINCODE_EPOCH_CUTOFF {
+        return Rewards::decode(bytes)
+            .map(|r| r.rewards)
+            .map_err(MetadataDecodeError::ProstDecode);
+    }
+    for with_commission in [false, true] {
+        if let Ok((rewards, false)) = decode_stored_rewards(bytes, with_commission) {
+            return Ok(rewards.iter().map(reward_to_proto).collect());
+        }
+    }
+    Err(MetadataDecodeError::Bincode(format!(
+        "block rewards ({} bytes) match no known layout",
+        bytes.len()
+    )))
+}
+
 #[inline(always)]
 pub const fn slot_to_epoch(slot: u64) -> u64 {
     slot / 432000
@@ -278,4 +348,318 @@ mod tests {
             .inspect_err(|err| println!("{err}"));
         assert!(res.is_ok())
     }
+
+    // Golden corpus: bincode meta blobs and the proto RPC returns for them.
+    // memo_token_transfer is the chain blob of the test above without its
+    // stray `59`. The ones under layouts/ are hand-built, one per
+    // `StoredMetaLayout`, and only pin how each layout decodes.
+    mod golden {
+        use super::*;
+        use crate::confirmed_block::{
+            InnerInstruction, InnerInstructions, ReturnData, Reward, RewardType, TokenBalance,
+            TransactionError, UiTokenAmount,
+        };
+
+        const LEGACY_SLOT: u64 = (BINCODE_EPOCH_CUTOFF - 1) * 432_000;
+
+        fn decode(bytes: &[u8]) -> TransactionStatusMeta {
+            let mut out = TransactionStatusMeta::default();
+            decode_transaction_status_meta(LEGACY_SLOT, bytes, &mut out).unwrap();
+            out
+        }
+
+        fn token_amount(ui_amount: f64, decimals: u32, amount: &str, ui: &str) -> UiTokenAmount {
+            UiTokenAmount {
+                ui_amount,
+                decimals,
+                amount: amount.to_string(),
+                ui_amount_string: ui.to_string(),
+            }
+        }
+
+        #[test]
+        fn balances_only() {
+            let meta = decode(include_bytes!(
+                "../testdata/legacy_meta/layouts/balances_only.bin"
+            ));
+            assert_eq!(
+                meta,
+                TransactionStatusMeta {
+                    fee: 5000,
+                    pre_balances: vec![10_000_000, 1_000_000_000, 1],
+                    post_balances: vec![9_995_000, 1_000_000_000, 1],
+                    inner_instructions_none: true,
+                    log_messages_none: true,
+                    return_data_none: true,
+                    ..Default::default()
+                }
+            );
+        }
+
+        #[test]
+        fn memo_token_transfer() {
+            let meta = decode(include_bytes!(
+                "../testdata/legacy_meta/memo_token_transfer.bin"
+            ));
+            let kin = "kinXdEcpDQeHPEuQnqmUgtYykqKGVFq6CeVX5iAHJq6";
+            let balance = |account_index, ui_token_amount| TokenBalance {
+                account_index,
+                mint: kin.to_string(),
+                ui_token_amount: Some(ui_token_amount),
+                owner: String::new(),
+                program_id: String::new(),
+            };
+
+            assert_eq!(meta.err, None);
+            assert_eq!(meta.fee, 10_000);
+            assert_eq!(
+                meta.pre_balances,
+                [10820417821000, 0, 2039280, 2039280, 121159680, 1118555520]
+            );
+            assert_eq!(
+                meta.post_balances,
+                [10820417811000, 0, 2039280, 2039280, 121159680, 1118555520]
+            );
+            assert!(meta.inner_instructions.is_empty() && !meta.inner_instructions_none);
+            assert_eq!(meta.log_messages.len(), 7);
+            assert_eq!(meta.log_messages[4], "Program log: Instruction: Transfer");
+            assert_eq!(
+                meta.pre_token_balances,
+                [
+                    balance(
+                        2,
+                        token_amount(8190500.32691, 5, "819050032691", "8190500.32691")
+                    ),
+                    balance(3, token_amount(3000.0, 5, "300000000", "3000")),
+                ]
+            );
+            assert_eq!(
+                meta.post_token_balances,
+                [
+                    balance(3, token_amount(2000.0, 5, "200000000", "2000")),
+                    balance(
+                        2,
+                        token_amount(8191500.32691, 5, "819150032691", "8191500.32691")
+                    ),
+                ]
+            );
+            assert!(meta.rewards.is_empty());
+            assert!(meta.return_data_none);
+        }
+
+        #[test]
+        fn instruction_error_rent_reward() {
+            let meta = decode(include_bytes!(
+                "../testdata/legacy_meta/layouts/instruction_error_rent_reward.bin"
+            ));
+            assert_eq!(
+                meta,
+                TransactionStatusMeta {
+                    // InstructionError(1, Custom(6001))
+                    err: Some(TransactionError {
+                        err: vec![8, 0, 0, 0, 1, 25, 0, 0, 0, 0x71, 0x17, 0, 0],
+                    }),
+                    fee: 5000,
+                    pre_balances: vec![2_039_280_000, 2_039_280, 1],
+                    post_balances: vec![2_039_275_000, 2_039_280, 1],
+                    inner_instructions: vec![InnerInstructions {
+                        index: 0,
+                        instructions: vec![InnerInstruction {
+                            program_id_index: 2,
+                            accounts: vec![0, 1],
+                            data: vec![2, 0, 0, 0, 64, 66, 15, 0, 0, 0, 0, 0],
+                            stack_height: None,
+                        }],
+                    }],
+                    log_messages: vec![
+                        "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA invoke [1]".into(),
+                        "Program log: Error: insufficient funds".into(),
+                        "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA failed: custom program error: 0x1771".into(),
+                    ],
+                    rewards: vec![Reward {
+                        pubkey: "SysvarRent111111111111111111111111111111111".into(),
+                        lamports: -2439,
+                        post_balance: 2_039_277_561,
+                        reward_type: RewardType::Rent as i32,
+                        commission: String::new(),
+                    }],
+                    return_data_none: true,
+                    ..Default::default()
+                }
+            );
+        }
+
+        #[test]
+        fn reward_commission() {
+            let meta = decode(include_bytes!(
+                "../testdata/legacy_meta/layouts/reward_commission.bin"
+            ));
+            // InsufficientFundsForFee
+            assert_eq!(
+                meta.err,
+                Some(TransactionError {
+                    err: vec![4, 0, 0, 0]
+                })
+            );
+            assert_eq!(
+                meta.rewards,
+                [
+                    Reward {
+                        pubkey: "Vote111111111111111111111111111111111111111".into(),
+                        lamports: 1_000_000,
+                        post_balance: 2_000_000_000,
+                        reward_type: RewardType::Voting as i32,
+                        commission: "10".into(),
+                    },
+                    Reward {
+                        pubkey: "Stake11111111111111111111111111111111111111".into(),
+                        lamports: -5,
+                        post_balance: 10,
+                        reward_type: RewardType::Fee as i32,
+                        commission: String::new(),
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn token_owner_return_data() {
+            let meta = decode(include_bytes!(
+                "../testdata/legacy_meta/layouts/token_owner_return_data.bin"
+            ));
+            let balance = |ui_token_amount| TokenBalance {
+                account_index: 1,
+                mint: "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v".into(),
+                ui_token_amount: Some(ui_token_amount),
+                owner: "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM".into(),
+                program_id: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".into(),
+            };
+            assert_eq!(
+                meta,
+                TransactionStatusMeta {
+                    fee: 5000,
+                    pre_balances: vec![100, 200],
+                    post_balances: vec![95, 200],
+                    log_messages: vec!["Program log: ok".into()],
+                    pre_token_balances: vec![balance(token_amount(1.5, 6, "1500000", "1.5"))],
+                    post_token_balances: vec![balance(token_amount(0.0, 6, "0", "0"))],
+                    return_data: Some(ReturnData {
+                        program_id: (0..32).collect(),
+                        data: vec![7, 8],
+                    }),
+                    compute_units_consumed: Some(1234),
+                    ..Default::default()
+                }
+            );
+        }
+
+        #[test]
+        fn unknown_layout_is_an_error() {
+            let mut bytes =
+                include_bytes!("../testdata/legacy_meta/layouts/balances_only.bin").to_vec();
+            // Not a valid Option tag for `inner_instructions` in any layout.
+            bytes.push(7);
+
+            let mut out = TransactionStatusMeta::default();
+            let err = decode_transaction_status_meta(LEGACY_SLOT, &bytes, &mut out).unwrap_err();
+            assert!(matches!(
+                err,
+                MetadataDecodeError::UnknownLayout { len: 77, .. }
+            ));
+        }
+
+        fn try_rewards(slot: u64, frame: &[u8]) -> Result<Vec<Reward>, MetadataDecodeError> {
+            // The decoder keeps its 1 MiB buffer inline, too much for a debug test thread.
+            std::thread::scope(|s| {
+                std::thread::Builder::new()
+                    .stack_size(16 << 20)
+                    .spawn_scoped(s, || {
+                        decode_rewards_from_frame(slot, frame, &mut ZstdReusableDecoder::new())
+                    })
+                    .unwrap()
+                    .join()
+                    .unwrap()
+            })
+        }
+
+        fn rewards(slot: u64, frame: &[u8]) -> Vec<Reward> {
+            try_rewards(slot, frame).unwrap()
+        }
+
+        fn reward(
+            pubkey: &str,
+            lamports: i64,
+            post_balance: u64,
+            reward_type: RewardType,
+        ) -> Reward {
+            Reward {
+                pubkey: pubkey.into(),
+                lamports,
+                post_balance,
+                reward_type: reward_type as i32,
+                commission: String::new(),
+            }
+        }
+
+        const VOTE: &str = "Vote111111111111111111111111111111111111111";
+        const STAKE: &str = "Stake11111111111111111111111111111111111111";
+
+        #[test]
+        fn block_rewards_without_commission() {
+            let frame = include_bytes!("../testdata/block_rewards/no_commission.bin");
+            assert_eq!(
+                rewards(LEGACY_SLOT, frame),
+                [
+                    reward(VOTE, 1_000_000, 2_000_000_000, RewardType::Voting),
+                    reward(STAKE, -5, 10, RewardType::Fee),
+                ]
+            );
+        }
+
+        #[test]
+        fn block_rewards_with_commission() {
+            let frame = include_bytes!("../testdata/block_rewards/commission.bin");
+            // The commission-less layout is probed first and must not match.
+            assert!(!matches!(
+                decode_stored_rewards(frame, false),
+                Ok((_, false))
+            ));
+            assert_eq!(
+                rewards(LEGACY_SLOT, frame),
+                [
+                    Reward {
+                        commission: "10".into(),
+                        ..reward(VOTE, 1_000_000, 2_000_000_000, RewardType::Voting)
+                    },
+                    reward(STAKE, -5, 10, RewardType::Staking),
+                ]
+            );
+        }
+
+        #[test]
+        fn block_rewards_protobuf() {
+            let frame = include_bytes!("../testdata/block_rewards/protobuf.bin");
+            let slot = BINCODE_EPOCH_CUTOFF * 432_000;
+            let expected = [
+                Reward {
+                    commission: "10".into(),
+                    ..reward(VOTE, 1_000_000, 2_000_000_000, RewardType::Voting)
+                },
+                reward(STAKE, -5, 10, RewardType::Staking),
+            ];
+            assert_eq!(rewards(slot, frame), expected);
+
+            let compressed = zstd::encode_all(&frame[..], 0).unwrap();
+            assert_eq!(rewards(slot, &compressed), expected);
+            assert!(rewards(slot, &[]).is_empty());
+        }
+
+        #[test]
+        fn block_rewards_unknown_layout_is_an_error() {
+            let mut frame = include_bytes!("../testdata/block_rewards/commission.bin").to_vec();
+            frame.push(0);
+            let err = try_rewards(LEGACY_SLOT, &frame).unwrap_err();
+            assert!(matches!(err, MetadataDecodeError::Bincode(_)));
+        }
+    }
 }
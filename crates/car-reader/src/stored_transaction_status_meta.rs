@@ -1,3 +1,4 @@
+use std::marker::PhantomData;
 use std::mem::MaybeUninit;
 use wincode::ReadResult;
 use wincode::error::invalid_tag_encoding;
@@ -29,15 +30,44 @@ pub struct InnerInstructions {
     pub instructions: Vec<CompiledInstruction>,
 }
 
+/// bincode `RewardType`: a plain enum, so the tag is a u32 starting at 0.
+#[derive(SchemaRead, Clone, Copy, Debug, PartialEq, Eq)]
+#[wincode(tag_encoding = "u32")]
+pub enum StoredRewardType {
+    Fee,
+    Rent,
+    Staking,
+    Voting,
+}
+
+/// Latest reward layout. Older blobs lack `commission`, see [`RewardWithoutCommission`].
 #[derive(SchemaRead, Clone)]
 pub struct StoredExtendedReward {
     pub pubkey: String,
     pub lamports: i64,
     pub post_balance: u64,
-    pub reward_type: Option<u8>,
+    pub reward_type: Option<StoredRewardType>,
     pub commission: Option<u8>,
 }
 
+/// Reward as written before `commission` existed.
+pub struct RewardWithoutCommission;
+
+impl<'de> SchemaRead<'de> for RewardWithoutCommission {
+    type Dst = StoredExtendedReward;
+
+    fn read(reader: &mut impl Reader<'de>, dst: &mut MaybeUninit<Self::Dst>) -> ReadResult<()> {
+        dst.write(StoredExtendedReward {
+            pubkey: String::get(reader)?,
+            lamports: i64::get(reader)?,
+            post_balance: u64::get(reader)?,
+            reward_type: Option::<StoredRewardType>::get(reader)?,
+            commission: None,
+        });
+        Ok(())
+    }
+}
+
 #[derive(SchemaRead, Clone)]
 pub struct StoredTokenAmount {
     pub ui_amount: f64,
@@ -45,14 +75,33 @@ pub struct StoredTokenAmount {
     pub amount: String,
 }
 
+/// Latest token balance layout. Older blobs lack `owner` and `program_id`
+/// (see [`TokenBalanceWithoutOwner`]); they are left empty then, like the proto does.
 #[derive(SchemaRead, Clone)]
 pub struct StoredTransactionTokenBalance {
     pub account_index: u8,
     pub mint: String,
     pub ui_token_amount: StoredTokenAmount,
-    // can't have Option EOF here as it will interpret next StoredTransactionTokenBalance
-    //pub owner: OptionEof<String>,
-    //pub program_id: OptionEof<String>,
+    pub owner: String,
+    pub program_id: String,
+}
+
+/// Token balance as written before `owner` and `program_id` existed.
+pub struct TokenBalanceWithoutOwner;
+
+impl<'de> SchemaRead<'de> for TokenBalanceWithoutOwner {
+    type Dst = StoredTransactionTokenBalance;
+
+    fn read(reader: &mut impl Reader<'de>, dst: &mut MaybeUninit<Self::Dst>) -> ReadResult<()> {
+        dst.write(StoredTransactionTokenBalance {
+            account_index: u8::get(reader)?,
+            mint: String::get(reader)?,
+            ui_token_amount: StoredTokenAmount::get(reader)?,
+            owner: String::new(),
+            program_id: String::new(),
+        });
+        Ok(())
+    }
 }
 
 #[derive(SchemaRead)]
@@ -62,21 +111,14 @@ pub enum TransactionResult {
     Err(StoredTransactionError),
 }
 
-#[derive(SchemaRead)]
 pub struct StoredTransactionStatusMeta {
     pub status: TransactionResult,
     pub fee: u64,
-    #[wincode(with = "containers::Vec<_, BincodeLen>")]
     pub pre_balances: Vec<u64>,
-    #[wincode(with = "containers::Vec<_, BincodeLen>")]
     pub post_balances: Vec<u64>,
-    #[wincode(with = "OptionEof<containers::Vec<_, BincodeLen>>")]
     pub inner_instructions: OptionEof<Vec<InnerInstructions>>,
-    #[wincode(with = "OptionEof<containers::Vec<String, BincodeLen>>")]
     pub log_messages: OptionEof<Vec<String>>,
-    #[wincode(with = "OptionEof<containers::Vec<_, BincodeLen>>")]
     pub pre_token_balances: OptionEof<Vec<StoredTransactionTokenBalance>>,
-    #[wincode(with = "OptionEof<containers::Vec<_, BincodeLen>>")]
     pub post_token_balances: OptionEof<Vec<StoredTransactionTokenBalance>>,
     pub rewards: OptionEof<Vec<StoredExtendedReward>>,
     pub return_data: OptionEof<TransactionReturnData>,
@@ -84,6 +126,148 @@ pub struct StoredTransactionStatusMeta {
     pub cost_units: OptionEof<u64>,
 }
 
+impl StoredTransactionStatusMeta {
+    /// Reads the meta with `B` as token balance layout and `R` as reward layout.
+    ///
+    /// Trailing fields that were added over time are `OptionEof`, but fields added
+    /// *inside* vec elements can't be detected that way, hence the explicit layouts.
+    fn read_layout<'de, B, R>(reader: &mut impl Reader<'de>) -> ReadResult<Self>
+    where
+        B: SchemaRead<'de, Dst = StoredTransactionTokenBalance>,
+        R: SchemaRead<'de, Dst = StoredExtendedReward>,
+    {
+        Ok(Self {
+            status: TransactionResult::get(reader)?,
+            fee: u64::get(reader)?,
+            pre_balances: containers::Vec::<u64, BincodeLen>::get(reader)?,
+            post_balances: containers::Vec::<u64, BincodeLen>::get(reader)?,
+            inner_instructions: OptionEof::<containers::Vec<InnerInstructions, BincodeLen>>::get(
+                reader,
+            )?,
+            log_messages: OptionEof::<containers::Vec<String, BincodeLen>>::get(reader)?,
+            pre_token_balances: OptionEof::<containers::Vec<B, BincodeLen>>::get(reader)?,
+            post_token_balances: OptionEof::<containers::Vec<B, BincodeLen>>::get(reader)?,
+            rewards: OptionEof::<containers::Vec<R, BincodeLen>>::get(reader)?,
+            return_data: OptionEof::<TransactionReturnData>::get(reader)?,
+            compute_units_consumed: OptionEof::<u64>::get(reader)?,
+            cost_units: OptionEof::<u64>::get(reader)?,
+        })
+    }
+}
+
+/// Reads the latest layout.
+impl<'de> SchemaRead<'de> for StoredTransactionStatusMeta {
+    type Dst = StoredTransactionStatusMeta;
+
+    fn read(reader: &mut impl Reader<'de>, dst: &mut MaybeUninit<Self::Dst>) -> ReadResult<()> {
+        dst.write(Self::read_layout::<
+            StoredTransactionTokenBalance,
+            StoredExtendedReward,
+        >(reader)?);
+        Ok(())
+    }
+}
+
+/// Field layouts the bincode meta went through before the switch to protobuf.
+///
+/// The blob carries no version, so [`StoredMetaLayout::decode`] is tried for each
+/// layout in [`StoredMetaLayout::ALL`] order and the first one that consumes the
+/// whole input wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoredMetaLayout {
+    /// Token balances without owner/program_id, rewards without commission.
+    /// This is what the bincode epochs actually contain.
+    NoCommission,
+    /// Rewards carry `commission`.
+    Commission,
+    /// Token balances also carry `owner` and `program_id`.
+    TokenOwner,
+}
+
+impl StoredMetaLayout {
+    pub const ALL: [StoredMetaLayout; 3] = [
+        StoredMetaLayout::NoCommission,
+        StoredMetaLayout::Commission,
+        StoredMetaLayout::TokenOwner,
+    ];
+
+    /// Decodes `bytes` with this layout. The returned flag is `true` when bytes
+    /// were left over, i.e. the layout doesn't actually match the blob.
+    pub fn decode(self, mut bytes: &[u8]) -> ReadResult<(StoredTransactionStatusMeta, bool)> {
+        match self {
+            StoredMetaLayout::NoCommission => <LayoutProbe<
+                TokenBalanceWithoutOwner,
+                RewardWithoutCommission,
+            > as SchemaRead>::get(&mut bytes),
+            StoredMetaLayout::Commission => <LayoutProbe<
+                TokenBalanceWithoutOwner,
+                StoredExtendedReward,
+            > as SchemaRead>::get(&mut bytes),
+            StoredMetaLayout::TokenOwner => <LayoutProbe<
+                StoredTransactionTokenBalance,
+                StoredExtendedReward,
+            > as SchemaRead>::get(&mut bytes),
+        }
+    }
+}
+
+/// Reads a meta with the given layout, then checks whether anything is left.
+struct LayoutProbe<B, R>(PhantomData<(B, R)>);
+
+impl<'de, B, R> SchemaRead<'de> for LayoutProbe<B, R>
+where
+    B: SchemaRead<'de, Dst = StoredTransactionTokenBalance>,
+    R: SchemaRead<'de, Dst = StoredExtendedReward>,
+{
+    type Dst = (StoredTransactionStatusMeta, bool);
+
+    fn read(reader: &mut impl Reader<'de>, dst: &mut MaybeUninit<Self::Dst>) -> ReadResult<()> {
+        let meta = StoredTransactionStatusMeta::read_layout::<B, R>(reader)?;
+        let trailing = match u8::get(reader) {
+            Ok(_) => true,
+            Err(e) if is_eof(&e) => false,
+            Err(e) => return Err(e),
+        };
+        dst.write((meta, trailing));
+        Ok(())
+    }
+}
+
+/// Decodes bincode block rewards (`StoredExtendedRewards`), with or without
+/// `commission`. The flag is `true` when bytes were left over.
+pub fn decode_stored_rewards(
+    mut bytes: &[u8],
+    with_commission: bool,
+) -> ReadResult<(Vec<StoredExtendedReward>, bool)> {
+    if with_commission {
+        <RewardsProbe<StoredExtendedReward> as SchemaRead>::get(&mut bytes)
+    } else {
+        <RewardsProbe<RewardWithoutCommission> as SchemaRead>::get(&mut bytes)
+    }
+}
+
+/// Reads block rewards with the given reward layout, then checks whether
+/// anything is left.
+struct RewardsProbe<R>(PhantomData<R>);
+
+impl<'de, R> SchemaRead<'de> for RewardsProbe<R>
+where
+    R: SchemaRead<'de, Dst = StoredExtendedReward>,
+{
+    type Dst = (Vec<StoredExtendedReward>, bool);
+
+    fn read(reader: &mut impl Reader<'de>, dst: &mut MaybeUninit<Self::Dst>) -> ReadResult<()> {
+        let rewards = containers::Vec::<R, BincodeLen>::get(reader)?;
+        let trailing = match u8::get(reader) {
+            Ok(_) => true,
+            Err(e) if is_eof(&e) => false,
+            Err(e) => return Err(e),
+        };
+        dst.write((rewards, trailing));
+        Ok(())
+    }
+}
+
 impl From<StoredTransactionStatusMeta> for confirmed_block::TransactionStatusMeta {
     fn from(m: StoredTransactionStatusMeta) -> Self {
         convert_metadata::stored_meta_to_proto(m)
@@ -211,17 +395,9 @@ where
 
         if let Err(e) = u8::read(reader, &mut discriminant) {
             // Only accept true EOF as "absent field"
-            if let wincode::ReadError::Io(read_err) = &e {
-                if let wincode::io::ReadError::ReadSizeLimit(_) = read_err {
-                    dst.write(OptionEof::None);
-                    return Ok(());
-                }
-                if let wincode::io::ReadError::Io(ioe) = read_err
-                    && ioe.kind() == std::io::ErrorKind::UnexpectedEof
-                {
-                    dst.write(OptionEof::None);
-                    return Ok(());
-                }
+            if is_eof(&e) {
+                dst.write(OptionEof::None);
+                return Ok(());
             }
             println!("{:#?}", e);
             return Err(e);
@@ -244,3 +420,74 @@ where
         }
     }
 }
+
+/// True when `e` means the input ran out, as opposed to malformed data.
+fn is_eof(e: &wincode::ReadError) -> bool {
+    match e {
+        wincode::ReadError::Io(wincode::io::ReadError::ReadSizeLimit(_)) => true,
+        wincode::ReadError::Io(wincode::io::ReadError::Io(ioe)) => {
+            ioe.kind() == std::io::ErrorKind::UnexpectedEof
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_COMMISSION: &[u8] = include_bytes!("../testdata/block_rewards/no_commission.bin");
+    const COMMISSION: &[u8] = include_bytes!("../testdata/block_rewards/commission.bin");
+
+    fn summary(
+        rewards: &[StoredExtendedReward],
+    ) -> Vec<(i64, Option<StoredRewardType>, Option<u8>)> {
+        rewards
+            .iter()
+            .map(|r| (r.lamports, r.reward_type, r.commission))
+            .collect()
+    }
+
+    #[test]
+    fn rewards_probe_without_commission() {
+        let (rewards, trailing) = decode_stored_rewards(NO_COMMISSION, false).unwrap();
+        assert!(!trailing);
+        assert_eq!(
+            summary(&rewards),
+            [
+                (1_000_000, Some(StoredRewardType::Voting), None),
+                (-5, Some(StoredRewardType::Fee), None),
+            ]
+        );
+        assert_eq!(
+            rewards[0].pubkey,
+            "Vote111111111111111111111111111111111111111"
+        );
+        assert_eq!(rewards[1].post_balance, 10);
+    }
+
+    #[test]
+    fn rewards_probe_with_commission() {
+        // The second reward's pubkey length lands on the first reward's commission.
+        assert!(decode_stored_rewards(COMMISSION, false).is_err());
+
+        let (rewards, trailing) = decode_stored_rewards(COMMISSION, true).unwrap();
+        assert!(!trailing);
+        assert_eq!(
+            summary(&rewards),
+            [
+                (1_000_000, Some(StoredRewardType::Voting), Some(10)),
+                (-5, Some(StoredRewardType::Staking), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewards_probe_flags_trailing_bytes() {
+        let mut bytes = NO_COMMISSION.to_vec();
+        bytes.push(0);
+        let (rewards, trailing) = decode_stored_rewards(&bytes, false).unwrap();
+        assert_eq!(rewards.len(), 2);
+        assert!(trailing);
+    }
+}
@@ -1,7 +1,10 @@
 use anyhow::Result;
 use tracing::info;
 
-use crate::{Cli, build_blockhash_registry, build_registry, compact, epoch_paths, file_nonempty};
+use crate::{
+    Cli, build_blockhash_registry, build_global_registry, build_registry, compact, compact_output,
+    epoch_paths, file_nonempty, global_paths,
+};
 
 pub(crate) fn run(cli: &Cli, epoch: u64) -> Result<()> {
     let (_, _, registry_path, bh_path, compact_path) = epoch_paths(cli, epoch);
@@ -24,10 +27,21 @@ pub(crate) fn run(cli: &Cli, epoch: u64) -> Result<()> {
         build_registry::run(cli, epoch)?;
     }
 
-    if cli.resume && file_nonempty(&compact_path) {
+    let (_, remap_path) = global_paths(cli, epoch);
+    if cli.resume && file_nonempty(&remap_path) {
+        info!(
+            "Resume: global remap exists, skipping: {}",
+            remap_path.display()
+        );
+    } else {
+        build_global_registry::run(cli, epoch)?;
+    }
+
+    let compact_out = compact_output(cli, &compact_path);
+    if cli.resume && file_nonempty(&compact_out) {
         info!(
             "Resume: compact exists, skipping phase 2: {}",
-            compact_path.display()
+            compact_out.display()
         );
     } else {
         compact::run(cli, epoch)?;
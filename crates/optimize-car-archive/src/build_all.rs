@@ -2,7 +2,7 @@ use anyhow::{Context, Result};
 use std::{fs, path::Path, time::Instant};
 use tracing::{error, info, warn};
 
-use crate::{Cli, epoch_paths, file_nonempty};
+use crate::{Cli, compact_output, epoch_paths, file_nonempty, global_paths};
 
 pub(crate) fn run(cli: &Cli) -> Result<()> {
     info!(
@@ -88,13 +88,25 @@ fn process_single_epoch(cli: &Cli, epoch: u64) -> Result<()> {
         );
     }
 
-    if !(cli.resume && file_nonempty(&compact_path)) {
+    let (_, remap_path) = global_paths(cli, epoch);
+    if !(cli.resume && file_nonempty(&remap_path)) {
+        crate::build_global_registry::run(cli, epoch)
+            .with_context(|| format!("Failed to build global remap for epoch {}", epoch))?;
+    } else {
+        info!(
+            "Resume: global remap exists, skipping: {}",
+            remap_path.display()
+        );
+    }
+
+    let compact_out = compact_output(cli, &compact_path);
+    if !(cli.resume && file_nonempty(&compact_out)) {
         crate::compact::run(cli, epoch)
             .with_context(|| format!("Failed to build compact for epoch {}", epoch))?;
     } else {
         info!(
             "Resume: compact exists, skipping: {}",
-            compact_path.display()
+            compact_out.display()
         );
     }
 
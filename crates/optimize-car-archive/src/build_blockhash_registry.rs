@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use std::{fs::File, io::Write, path::Path};
+use std::{fs::File, path::Path};
 use tracing::info;
 
 use car_reader::{
@@ -8,6 +8,8 @@ use car_reader::{
     node::{CborCidRef, Node, decode_node},
 };
 
+use blockzilla_format::{CompactFileHeader, CompactLayout, PostcardFramedWriter};
+
 use crate::{Cli, ProgressTracker, epoch_paths};
 
 const MAX_BLOCKHASHES_PER_EPOCH: usize = 432_000;
@@ -85,11 +87,12 @@ fn build_blockhash_registry_for_epoch(cli: &Cli, epoch: u64) -> Result<()> {
     let n = out.len() / 32;
 
     // Direct write (no tmp + rename)
-    let mut f = File::create(&bh_path).with_context(|| format!("create {}", bh_path.display()))?;
-    //let mut w = BufWriter::with_capacity(BUFFER_SIZE, f);
-    f.write_all(&out)
+    let f = File::create(&bh_path).with_context(|| format!("create {}", bh_path.display()))?;
+    let header = CompactFileHeader::new(CompactLayout::BlockhashRegistry).with_epoch(epoch);
+    let mut w = PostcardFramedWriter::with_header(f, header)?;
+    w.write_raw(&out)
         .with_context(|| "write blockhash registry")?;
-    f.flush().context("flush blockhash registry")?;
+    w.finish().context("flush blockhash registry")?;
 
     progress.final_report();
     info!("Blockhash registry written: {} hashes", n);
@@ -0,0 +1,57 @@
+use anyhow::{Context, Result};
+use std::time::Instant;
+use tracing::info;
+
+use blockzilla_format::{GlobalRegistry, KeyStore};
+
+use crate::{Cli, epoch_paths, global_paths};
+
+/// Assign global ids to an epoch registry.
+///
+/// New keys are appended to global-registry.bin and the local → global table
+/// is written to the epoch's global-remap.bin. Re-running an epoch is a no-op
+/// for the global file since its keys are already interned.
+pub(crate) fn run(cli: &Cli, epoch: u64) -> Result<()> {
+    let (_, epoch_dir, registry_path, _, _) = epoch_paths(cli, epoch);
+    let (global_path, remap_path) = global_paths(cli, epoch);
+
+    if !registry_path.exists() {
+        anyhow::bail!(
+            "Registry not found: {}. Run registry build first.",
+            registry_path.display()
+        );
+    }
+
+    std::fs::create_dir_all(&epoch_dir)
+        .with_context(|| format!("Failed to create {}", epoch_dir.display()))?;
+
+    info!("Building global remap epoch={}", epoch);
+    info!("  registry: {}", registry_path.display());
+    info!("  global:   {}", global_path.display());
+    info!("  out:      {}", remap_path.display());
+
+    let start = Instant::now();
+
+    let store = KeyStore::load(&registry_path)?;
+    let mut global = GlobalRegistry::open(&global_path)?;
+    let before = global.len();
+
+    let remap = global.remap(&store);
+    let appended = global.flush(&global_path)?;
+
+    let tmp_path = remap_path.with_extension("bin.tmp");
+    remap.write(&tmp_path, epoch)?;
+    std::fs::rename(&tmp_path, &remap_path)
+        .with_context(|| format!("rename {} -> {}", tmp_path.display(), remap_path.display()))?;
+
+    info!(
+        "Global registry: {} local keys, {} new (global {} -> {}) in {:.2}s",
+        store.len(),
+        appended,
+        before,
+        global.len(),
+        start.elapsed().as_secs_f64()
+    );
+
+    Ok(())
+}
@@ -1,19 +1,23 @@
 use anyhow::{Context, Result};
 use car_reader::{car_stream::CarStream, versioned_transaction::VersionedMessage};
-use gxhash::{GxBuildHasher, HashMap as GxHashMap};
-use solana_pubkey::{pubkey, Pubkey};
-use std::{path::Path, str::FromStr, time::Instant};
+use gxhash::{GxBuildHasher, HashMap as GxHashMap, HashSet as GxHashSet};
+use solana_pubkey::{Pubkey, pubkey};
+use std::{path::Path, str::FromStr, sync::Arc, time::Instant};
 use tracing::info;
 
 use car_reader::{
     car_block_group::CarBlockGroup,
     error::GroupError,
-    node::{decode_node, Node},
+    node::{Node, decode_node},
 };
 
-use blockzilla_format::write_registry;
+use blockzilla_format::{
+    KeyIndex, KeyStore, LogInterner, StringDict, parse_logs_into,
+    program_logs::template::{TemplateDict, TemplateMiner},
+    unknown_program_logs, write_registry,
+};
 
-use crate::{epoch_paths, Cli, ProgressTracker};
+use crate::{Cli, ProgressTracker, epoch_paths};
 
 pub(crate) fn run(cli: &Cli, epoch: u64) -> Result<()> {
     let (car_path, epoch_dir, registry_path, _, _) = epoch_paths(cli, epoch);
@@ -29,11 +33,17 @@ pub(crate) fn run(cli: &Cli, epoch: u64) -> Result<()> {
     info!("  out:      {}", registry_path.display());
 
     let mut counter = PubkeyCounter::new(50_000_000);
+    let mut logs = LogCounters {
+        strings: (cli.string_dict > 0).then(|| StringCounter::new(cli.string_dict << 10)),
+        templates: (cli.log_templates > 0).then(|| TemplateMiner::new(cli.log_templates)),
+    };
+    let logs_on = logs.strings.is_some() || logs.templates.is_some();
     let mut progress = ProgressTracker::new("Phase 1/2");
 
     let mut stream = CarStream::open_zstd(Path::new(&car_path))?;
     while let Some(group) = stream.next_group()? {
-        let (blocks_delta, txs_delta, slot) = registry_process_block(group, &mut counter)?;
+        let (blocks_delta, txs_delta, slot) =
+            registry_process_block(group, &mut counter, logs_on.then_some(&mut logs))?;
         if let Some(s) = slot {
             progress.update_slot(s);
         }
@@ -66,9 +76,39 @@ pub(crate) fn run(cli: &Cli, epoch: u64) -> Result<()> {
         sort_start.elapsed().as_secs_f64()
     );
 
-    write_registry(&registry_path, &keys)?;
+    write_registry(&registry_path, epoch, &keys)?;
     info!("Registry written: {} keys", keys.len());
 
+    let index_path = KeyIndex::path_for(&registry_path);
+    KeyIndex::build(Arc::new(KeyStore { keys })).write(&index_path, epoch)?;
+    info!("Key index written: {}", index_path.display());
+
+    // Written even when disabled: compact files resolve strings through it.
+    let dict_path = StringDict::path_for(&registry_path);
+    let dict = StringDict::new(match logs.strings {
+        Some(s) => s.top(cli.string_dict),
+        None => Vec::new(),
+    });
+    dict.write(&dict_path, epoch)?;
+    info!(
+        "String dictionary written: {} strings ({})",
+        dict.len(),
+        dict_path.display()
+    );
+
+    // Same for templates.bin.
+    let templates_path = TemplateDict::path_for(&registry_path);
+    let templates = TemplateDict::new(match logs.templates {
+        Some(m) => m.top(cli.log_templates, 2),
+        None => Vec::new(),
+    });
+    templates.write(&templates_path, epoch)?;
+    info!(
+        "Log templates written: {} templates ({})",
+        templates.len(),
+        templates_path.display()
+    );
+
     Ok(())
 }
 
@@ -88,9 +128,129 @@ impl PubkeyCounter {
     }
 }
 
+/// Bytes a tracked string costs besides its own: boxed str, count and hash
+/// table slot, rounded up.
+const STRING_ENTRY_OVERHEAD: usize = 32;
+
+/// Counts, per log string, the blocks it is interned in, in bounded memory.
+///
+/// Once the tracked strings take `max_bytes` the less used half is dropped.
+/// Strings that keep coming back survive the prunes; what gets lost is the
+/// tail, which would not make the dictionary anyway. Strings over half the
+/// budget are not tracked at all.
+struct StringCounter {
+    counts: GxHashMap<Box<str>, u32>,
+    /// Bytes of the tracked strings, `STRING_ENTRY_OVERHEAD` each included
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl StringCounter {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            counts: GxHashMap::default(),
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    #[inline]
+    fn add(&mut self, s: &str) {
+        if let Some(c) = self.counts.get_mut(s) {
+            *c += 1;
+            return;
+        }
+        let size = s.len() + STRING_ENTRY_OVERHEAD;
+        if size > self.max_bytes / 2 {
+            return;
+        }
+        // Each prune drops at least one string once two are tracked, and a
+        // single one leaves room for this one.
+        while self.bytes + size > self.max_bytes {
+            self.prune();
+        }
+        self.bytes += size;
+        self.counts.insert(s.into(), 1);
+    }
+
+    /// Drop the less used half of the strings. Ties at the median are kept
+    /// up to half, so equal counts don't empty the table.
+    fn prune(&mut self) {
+        let mut counts: Vec<u32> = self.counts.values().copied().collect();
+        let mid = counts.len() / 2;
+        let median = *counts.select_nth_unstable(mid).1;
+        let above = counts[mid + 1..].iter().filter(|&&c| c > median).count();
+        let mut ties = counts.len() - mid - above;
+        let bytes = &mut self.bytes;
+        self.counts.retain(|s, c| {
+            let keep = if *c != median || ties == 0 {
+                *c > median
+            } else {
+                ties -= 1;
+                true
+            };
+            if !keep {
+                *bytes -= s.len() + STRING_ENTRY_OVERHEAD;
+            }
+            keep
+        });
+    }
+
+    /// The `k` strings saving the most bytes, most used first (smallest ids).
+    fn top(self, k: usize) -> Vec<String> {
+        // A string seen in a single block saves nothing.
+        let mut items: Vec<(Box<str>, u32)> =
+            self.counts.into_iter().filter(|(_, c)| *c > 1).collect();
+        let saved = |(s, c): &(Box<str>, u32)| *c as u64 * s.len().max(1) as u64;
+        if items.len() > k {
+            items.select_nth_unstable_by(k, |a, b| saved(b).cmp(&saved(a)));
+            items.truncate(k);
+        }
+        items.sort_unstable_by(|(sa, ca), (sb, cb)| cb.cmp(ca).then_with(|| sa.cmp(sb)));
+        items.into_iter().map(|(s, _)| s.into()).collect()
+    }
+}
+
+/// What the registry pass learns from logs for the epoch dictionaries.
+struct LogCounters {
+    strings: Option<StringCounter>,
+    templates: Option<TemplateMiner>,
+}
+
+impl LogCounters {
+    /// Intern the block logs like compaction does, count the block's strings
+    /// and mine templates from the payloads no parser understood.
+    ///
+    /// The epoch registry doesn't exist yet, so pubkeys resolve against the
+    /// keys of the block itself (program ids in logs are always among them).
+    fn add_block(&mut self, keys: Vec<[u8; 32]>, logs: &[Vec<String>]) {
+        if logs.is_empty() || keys.is_empty() {
+            return;
+        }
+        let index = KeyIndex::build_cold(Arc::new(KeyStore { keys }));
+        let mut interner = LogInterner::default();
+        for lines in logs {
+            let cls = parse_logs_into(lines, &index, &mut interner);
+            if let Some(miner) = self.templates.as_mut() {
+                for (program, payload) in unknown_program_logs(&cls, &interner.strings()) {
+                    if let Some(key) = index.store().get(program) {
+                        miner.add(key, payload, &index);
+                    }
+                }
+            }
+        }
+        if let Some(strings) = self.strings.as_mut() {
+            for s in &interner.take().strings.strings {
+                strings.add(s);
+            }
+        }
+    }
+}
+
 fn registry_process_block(
     group: &CarBlockGroup,
     counter: &mut PubkeyCounter,
+    mut logs: Option<&mut LogCounters>,
 ) -> Result<(u64, u64, Option<u64>), GroupError> {
     let block = match decode_node(group.block_payload()).map_err(GroupError::Node)? {
         Node::Block(b) => b,
@@ -101,10 +261,36 @@ fn registry_process_block(
     let mut it = group.transactions().unwrap();
     let mut txs = 0u64;
 
+    // Only filled when learning from logs
+    let mut block_keys: GxHashSet<[u8; 32]> = GxHashSet::default();
+    let mut block_logs: Vec<Vec<String>> = Vec::new();
+
     while let Some(r) = it.next_tx().unwrap() {
         let (vtx, maybe_meta) = r;
         txs += 1;
 
+        if logs.is_some() {
+            let keys = match &vtx.message {
+                VersionedMessage::Legacy(m) => &m.account_keys,
+                VersionedMessage::V0(m) => &m.account_keys,
+            };
+            block_keys.extend(keys.iter().map(|k| **k));
+            if let Some(meta) = maybe_meta {
+                for pk in meta
+                    .loaded_writable_addresses
+                    .iter()
+                    .chain(&meta.loaded_readonly_addresses)
+                {
+                    if let Ok(k) = <[u8; 32]>::try_from(pk.as_slice()) {
+                        block_keys.insert(k);
+                    }
+                }
+                if !meta.log_messages.is_empty() {
+                    block_logs.push(meta.log_messages.clone());
+                }
+            }
+        }
+
         match &vtx.message {
             VersionedMessage::Legacy(m) => {
                 for k in &m.account_keys {
@@ -139,15 +325,88 @@ fn registry_process_block(
                 if let Ok(pk) = Pubkey::from_str(&tb.mint) {
                     counter.add32(pk.as_array());
                 }
-                if !tb.owner.is_empty() && let Ok(pk) = Pubkey::from_str(&tb.owner) {
+                if !tb.owner.is_empty()
+                    && let Ok(pk) = Pubkey::from_str(&tb.owner)
+                {
                     counter.add32(pk.as_array());
                 }
-                if !tb.program_id.is_empty() && let Ok(pk) = Pubkey::from_str(&tb.program_id) {
+                if !tb.program_id.is_empty()
+                    && let Ok(pk) = Pubkey::from_str(&tb.program_id)
+                {
                     counter.add32(pk.as_array());
                 }
             }
         }
     }
 
+    if let Some(logs) = logs.as_mut() {
+        logs.add_block(block_keys.into_iter().collect(), &block_logs);
+    }
+
     Ok((1, txs, Some(block_slot)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_keeps_the_most_used_half() {
+        let mut c = StringCounter::new(1 << 20);
+        for s in ["a", "a", "a", "b", "c", "d"] {
+            c.add(s);
+        }
+        c.prune();
+        // One of the three ties at the median stays with "a".
+        assert_eq!(c.counts.len(), 2);
+        assert_eq!(c.counts.get("a"), Some(&3));
+        assert_eq!(c.bytes, 2 * (1 + STRING_ENTRY_OVERHEAD));
+    }
+
+    #[test]
+    fn counts_stay_in_budget() {
+        let max_bytes = 64 * (8 + STRING_ENTRY_OVERHEAD);
+        let mut c = StringCounter::new(max_bytes);
+        for i in 0..10_000 {
+            c.add("Program log: Instruction: Transfer");
+            c.add(&format!("Program log: {:08}", i));
+            assert!(c.bytes <= max_bytes);
+        }
+        assert_eq!(
+            c.counts.get("Program log: Instruction: Transfer"),
+            Some(&10_000)
+        );
+        let tracked: usize = c
+            .counts
+            .keys()
+            .map(|s| s.len() + STRING_ENTRY_OVERHEAD)
+            .sum();
+        assert_eq!(tracked, c.bytes);
+
+        // Too long to ever pay for itself within the budget.
+        c.add(&"x".repeat(max_bytes));
+        assert!(!c.counts.contains_key("x".repeat(max_bytes).as_str()));
+    }
+
+    #[test]
+    fn top_ranks_by_bytes_saved() {
+        let counter = || {
+            let mut c = StringCounter::new(1 << 20);
+            for _ in 0..5 {
+                c.add("short");
+            }
+            for _ in 0..2 {
+                c.add("a much longer log line that saves more");
+            }
+            c.add("seen once");
+            c
+        };
+        // 2 x 38 bytes beat 5 x 5.
+        assert_eq!(counter().top(1), ["a much longer log line that saves more"]);
+        // Most used first; single block strings save nothing.
+        assert_eq!(
+            counter().top(10),
+            ["short", "a much longer log line that saves more"]
+        );
+    }
+}
@@ -1,25 +1,30 @@
 use anyhow::{Context, Result};
 use car_reader::car_stream::CarStream;
 use car_reader::versioned_transaction::{VersionedMessage, VersionedTransaction};
-use gxhash::{HashMap as GxHashMap};
 use std::{
     fs::File,
-    io::{BufReader, BufWriter, Read, Seek, SeekFrom},
+    io::{BufReader, BufWriter, Read},
     path::Path,
+    sync::Arc,
 };
 use tracing::{error, info, warn};
 
 use car_reader::{
     car_block_group::CarBlockGroup,
     error::GroupError,
+    metadata_decoder::ZstdReusableDecoder,
     node::{Node, decode_node},
 };
 
 use blockzilla_format::{
-    BlockhashRegistry, CompactAddressTableLookup, CompactBlockHeader, CompactInstruction,
-    CompactLegacyMessage, CompactMessage, CompactMessageHeader, CompactRecentBlockhash,
-    CompactTransaction, CompactTxWithMeta, CompactV0Message, KeyIndex, KeyStore,
-    PostcardFramedWriter, Signature, compact_meta_from_proto,
+    BalanceChain, BlockhashRegistry, CompactBlockHeader, CompactFileHeader, CompactLayout,
+    CompactTxWithMeta, FLAG_SKIPPED_BAD_TXS, FLAG_SPLIT_VOTES, KeyIndex, KeyStore, LogDicts,
+    LogInterner, PostcardFramedWriter, SigIndex, SigIndexBuilder, SigLocation, SlotIndex,
+    SlotIndexEntry, SlotIndexWriter, StringDict, VoteChain, balance_keys, compact_entries,
+    compact_meta_from_proto, compact_reward, encode_log_keys, is_vote_only,
+    program_logs::template::TemplateDict, raw_file_body, split_paths, to_compact_transaction,
+    to_compact_vote_transaction, votes_path, write_sectioned_entries, write_sectioned_rewards,
+    write_sectioned_tables, write_sectioned_tx,
 };
 
 use crate::{BUFFER_SIZE, Cli, ProgressTracker, epoch_paths};
@@ -34,15 +39,22 @@ fn tx_kind(vtx: &VersionedTransaction) -> &'static str {
 }
 
 /// Loads a plain blockhash registry file:
-/// - format: raw concatenated [u8;32] hashes
+/// - format: raw concatenated [u8;32] hashes (between file header and
+///   trailer from format v13)
 /// - id: position in file (0-based)
 fn load_blockhash_registry_plain(path: &Path) -> Result<Vec<[u8; 32]>> {
     let f = File::open(path).with_context(|| format!("open {}", path.display()))?;
     let mut r = BufReader::with_capacity(BUFFER_SIZE, f);
 
-    let mut bytes = Vec::new();
-    r.read_to_end(&mut bytes)
+    let mut file = Vec::new();
+    r.read_to_end(&mut file)
         .with_context(|| format!("read {}", path.display()))?;
+    // Registries from before format v13 are bare hashes.
+    let bytes = match CompactFileHeader::from_prefix(&file)? {
+        Some(_) => raw_file_body(&file, CompactLayout::BlockhashRegistry)
+            .with_context(|| format!("blockhash registry {}", path.display()))?,
+        None => &file[..],
+    };
 
     if bytes.len() % 32 != 0 {
         anyhow::bail!(
@@ -68,44 +80,45 @@ fn load_blockhash_registry_plain(path: &Path) -> Result<Vec<[u8; 32]>> {
 /// Load exactly the last PREV_TAIL_LEN blockhashes from a previous epoch registry file.
 /// Returns fewer if the file has fewer than PREV_TAIL_LEN hashes.
 fn load_prev_epoch_tail(path: &Path) -> Result<Vec<[u8; 32]>> {
-    let mut f = File::open(path).with_context(|| format!("open {}", path.display()))?;
-
-    let len = f
-        .metadata()
-        .with_context(|| format!("stat {}", path.display()))?
-        .len();
-
-    if len % 32 != 0 {
-        anyhow::bail!(
-            "Invalid blockhash registry length: {} (not multiple of 32) path={}",
-            len,
-            path.display()
-        );
-    }
-
-    let total = (len / 32) as usize;
-    if total == 0 {
-        return Ok(Vec::new());
-    }
-
-    let take = total.min(PREV_TAIL_LEN);
-    let offset = (total - take) as u64 * 32;
+    let hashes = load_blockhash_registry_plain(path)?;
+    let take = hashes.len().min(PREV_TAIL_LEN);
+    Ok(hashes[hashes.len() - take..].to_vec())
+}
 
-    f.seek(SeekFrom::Start(offset))
-        .with_context(|| format!("seek {} to {}", path.display(), offset))?;
+/// Epoch blockhash registry plus the previous epoch tail (when available).
+pub(crate) fn load_blockhash_registry(
+    cli: &Cli,
+    epoch: u64,
+    bh_registry_path: &Path,
+) -> Result<BlockhashRegistry> {
+    let hashes = load_blockhash_registry_plain(bh_registry_path)?;
+    info!("Blockhash registry loaded: {} hashes", hashes.len());
 
-    let mut r = BufReader::with_capacity(BUFFER_SIZE, f);
-    let mut bytes = vec![0u8; take * 32];
-    r.read_exact(&mut bytes)
-        .with_context(|| format!("read tail from {}", path.display()))?;
+    // Load previous epoch tail if possible.
+    let prev_tail = if epoch == 0 {
+        Vec::new()
+    } else {
+        let (_, _prev_dir, _prev_reg, prev_bh_path, _prev_compact) = epoch_paths(cli, epoch - 1);
+        if prev_bh_path.exists() {
+            let tail = load_prev_epoch_tail(&prev_bh_path)?;
+            info!(
+                "Prev epoch tail loaded: {} hashes (epoch={}) from {}",
+                tail.len(),
+                epoch - 1,
+                prev_bh_path.display()
+            );
+            tail
+        } else {
+            warn!(
+                "Prev epoch blockhash registry missing (epoch={} path={}), prev tail disabled",
+                epoch - 1,
+                prev_bh_path.display()
+            );
+            Vec::new()
+        }
+    };
 
-    let mut out = Vec::with_capacity(take);
-    for i in 0..take {
-        let mut h = [0u8; 32];
-        h.copy_from_slice(&bytes[i * 32..(i + 1) * 32]);
-        out.push(h);
-    }
-    Ok(out)
+    Ok(BlockhashRegistry::new(hashes, prev_tail))
 }
 
 pub(crate) fn run(cli: &Cli, epoch: u64) -> Result<()> {
@@ -136,100 +149,344 @@ pub(crate) fn run(cli: &Cli, epoch: u64) -> Result<()> {
     info!("  car:      {}", car_path.display());
     info!("  registry: {}", registry_path.display());
     info!("  bh-reg:   {}", bh_registry_path.display());
-    info!("  out:      {}", compact_path.display());
+    let (block_path, runtime_path) = split_paths(&compact_path);
+    if cli.split_runtime {
+        info!("  out:      {}", block_path.display());
+        info!("  runtime:  {}", runtime_path.display());
+    } else {
+        info!("  out:      {}", compact_path.display());
+    }
+    let votes_path = votes_path(&compact_path);
+    if cli.split_votes {
+        info!("  votes:    {}", votes_path.display());
+    }
 
-    let store = KeyStore::load(&registry_path)?;
+    let store = Arc::new(KeyStore::load(&registry_path)?);
     let len = store.len();
-    let index = KeyIndex::build(store.keys);
+    let index_path = KeyIndex::path_for(&registry_path);
+    let index = match KeyIndex::load(&index_path, store.clone()) {
+        Ok(index) => index,
+        Err(e) => {
+            if index_path.exists() {
+                warn!("Key index unusable, rebuilding: {:#}", e);
+            }
+            let index = KeyIndex::build(store);
+            index.write(&index_path, epoch)?;
+            index
+        }
+    };
     info!("Registry loaded: {} keys", len);
 
-    let hashes = load_blockhash_registry_plain(&bh_registry_path)?;
-    info!("Blockhash registry loaded: {} hashes", hashes.len());
-
-    // Load previous epoch tail if possible.
-    let prev_tail = if epoch == 0 {
-        Vec::new()
+    // The registry pass writes both dictionaries, empty or not; the files
+    // record which ones they were written against.
+    let dict_path = StringDict::path_for(&registry_path);
+    let dict = StringDict::load(&dict_path)
+        .context("string dictionary missing, run build-registry first")?;
+    info!("String dictionary loaded: {} strings", dict.len());
+
+    let templates_path = TemplateDict::path_for(&registry_path);
+    let templates = TemplateDict::load(&templates_path)
+        .context("log templates missing, run build-registry first")?;
+    info!("Log templates loaded: {} templates", templates.len());
+
+    let dicts = Arc::new(LogDicts {
+        strings: dict,
+        templates,
+    });
+    let dicts_id = dicts.id();
+
+    let bh = load_blockhash_registry(cli, epoch, &bh_registry_path)?;
+
+    // block.bin takes compact.bin's place in split mode; indexes point into it.
+    let data_path = if cli.split_runtime {
+        block_path
     } else {
-        let (_, _prev_dir, _prev_reg, prev_bh_path, _prev_compact) = epoch_paths(cli, epoch - 1);
-        if prev_bh_path.exists() {
-            let tail = load_prev_epoch_tail(&prev_bh_path)?;
-            info!(
-                "Prev epoch tail loaded: {} hashes (epoch={}) from {}",
-                tail.len(),
-                epoch - 1,
-                prev_bh_path.display()
-            );
-            tail
-        } else {
-            warn!(
-                "Prev epoch blockhash registry missing (epoch={} path={}), prev tail disabled",
-                epoch - 1,
-                prev_bh_path.display()
-            );
-            Vec::new()
-        }
+        compact_path.clone()
     };
-
-    let bh = BlockhashRegistry::new(hashes, prev_tail);
-
-    let tmp_path = compact_path.with_extension("bin.tmp");
+    let tmp_path = data_path.with_extension("bin.tmp");
 
     let out = File::create(&tmp_path)
         .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
     let out = BufWriter::with_capacity(BUFFER_SIZE, out);
-    let mut writer = PostcardFramedWriter::new(out);
+    let data_layout = if cli.split_runtime {
+        CompactLayout::SplitBlock
+    } else if cli.sectioned {
+        CompactLayout::Sectioned
+    } else {
+        CompactLayout::Plain
+    };
+    let mut flags = 0;
+    if cli.split_votes {
+        flags |= FLAG_SPLIT_VOTES;
+    }
+    if cli.skip_bad_txs {
+        flags |= FLAG_SKIPPED_BAD_TXS;
+    }
+    let file_header = |layout| {
+        CompactFileHeader::new(layout)
+            .with_epoch(epoch)
+            .with_flags(flags)
+            .with_dicts(dicts_id)
+    };
+    let mut writer = PostcardFramedWriter::with_header(out, file_header(data_layout))?;
+
+    let runtime_tmp = runtime_path.with_extension("bin.tmp");
+    let mut runtime = if cli.split_runtime {
+        let out = File::create(&runtime_tmp)
+            .with_context(|| format!("Failed to create {}", runtime_tmp.display()))?;
+        let out = BufWriter::with_capacity(BUFFER_SIZE, out);
+        Some(PostcardFramedWriter::with_header(
+            out,
+            file_header(CompactLayout::SplitRuntime),
+        )?)
+    } else {
+        None
+    };
+
+    let votes_tmp = votes_path.with_extension("bin.tmp");
+    let mut votes = if cli.split_votes {
+        let out = File::create(&votes_tmp)
+            .with_context(|| format!("Failed to create {}", votes_tmp.display()))?;
+        let out = BufWriter::with_capacity(BUFFER_SIZE, out);
+        Some(PostcardFramedWriter::with_header(
+            out,
+            file_header(CompactLayout::Votes),
+        )?)
+    } else {
+        None
+    };
+
+    let slot_index_path = SlotIndex::path_for(&compact_path);
+    let slot_index_tmp = slot_index_path.with_extension("bin.tmp");
+    let idx_out = File::create(&slot_index_tmp)
+        .with_context(|| format!("Failed to create {}", slot_index_tmp.display()))?;
+    let mut slot_index = SlotIndexWriter::new(BufWriter::with_capacity(1 << 20, idx_out), epoch)?;
+    // votes.bin gets its own slot index, so readers can merge votes back in.
+    let votes_index_path = SlotIndex::votes_path_for(&compact_path);
+    let votes_index_tmp = votes_index_path.with_extension("bin.tmp");
+    let mut votes_index = if cli.split_votes {
+        let out = File::create(&votes_index_tmp)
+            .with_context(|| format!("Failed to create {}", votes_index_tmp.display()))?;
+        Some(SlotIndexWriter::new(
+            BufWriter::with_capacity(1 << 20, out),
+            epoch,
+        )?)
+    } else {
+        None
+    };
+    let mut sigs = SigIndexBuilder::new(&epoch_dir)?;
 
     let mut progress = ProgressTracker::new("Phase 2/2");
 
     let mut block_count: u32 = 0;
+    let mut skipped: u64 = 0;
 
-    // Reusable per-block buffers
-    let mut tx_payload: Vec<u8> = Vec::with_capacity(8 << 20);
-    let mut block_payload: Vec<u8> = Vec::with_capacity(8 << 20);
-    let mut varint_buf: [u8; varint_max::<usize>()] = [0u8; varint_max::<usize>()];
+    let mut bufs = BlockBuffers::new(dicts);
 
     let mut stream = CarStream::open_zstd(Path::new(&car_path))?;
     while let Some(group) = stream.next_group()? {
+        let offset = writer.position();
+        let votes_offset = votes.as_ref().map(|v| v.position());
+        let out = Outputs {
+            writer: &mut writer,
+            layout: match runtime.as_mut() {
+                Some(rt) => Layout::Split(rt),
+                None if cli.sectioned => Layout::Sectioned,
+                None => Layout::Plain,
+            },
+            votes: votes.as_mut(),
+            sigs: &mut sigs,
+        };
         let (blocks_delta, txs_delta, slot) = compact_process_block_manual(
             group,
             &index,
-            &bh.index,
-            &mut writer,
+            &bh,
+            out,
             block_count,
-            &mut tx_payload,
-            &mut block_payload,
-            &mut varint_buf,
+            &mut bufs,
+            cli.skip_bad_txs.then_some(&mut skipped),
         )?;
-        block_count = block_count.wrapping_add(1);
-        progress.update(blocks_delta, txs_delta);
         if let Some(s) = slot {
+            slot_index.push(&SlotIndexEntry {
+                slot: s,
+                offset,
+                frame_len: (writer.position() - offset - writer.frame_overhead()) as u32,
+                tx_count: txs_delta as u32,
+                blockhash: block_count,
+            })?;
+            if let (Some(idx), Some(v), Some(voffset)) =
+                (votes_index.as_mut(), votes.as_ref(), votes_offset)
+            {
+                idx.push(&SlotIndexEntry {
+                    slot: s,
+                    offset: voffset,
+                    frame_len: (v.position() - voffset - v.frame_overhead()) as u32,
+                    tx_count: bufs.vote_positions.len() as u32,
+                    blockhash: block_count,
+                })?;
+            }
             progress.update_slot(s);
         }
+        block_count = block_count.wrapping_add(1);
+        progress.update(blocks_delta, txs_delta);
+    }
+
+    writer.finish()?;
+    if let Some(rt) = runtime.as_mut() {
+        rt.finish()?;
+    }
+    if let Some(v) = votes.as_mut() {
+        v.finish()?;
+    }
+    slot_index.finish()?;
+    if let Some(idx) = votes_index.as_mut() {
+        idx.finish()?;
     }
 
-    writer.flush()?;
-    std::fs::rename(&tmp_path, &compact_path).with_context(|| {
+    let sig_index_path = SigIndex::path_for(&compact_path);
+    let sig_index_tmp = sig_index_path.with_extension("bin.tmp");
+    let sig_count = sigs.len();
+    let dropped = sigs.write(&sig_index_tmp, epoch)?;
+    if dropped > 0 {
+        warn!(
+            "Signature index: {} transactions share a 16-byte signature prefix and are not indexed",
+            dropped
+        );
+    }
+    info!("Signature index built: {} signatures", sig_count - dropped);
+    if skipped > 0 {
+        warn!(
+            "{} transactions failed to convert and were left out",
+            skipped
+        );
+    }
+    if index.misses() > 0 {
+        warn!(
+            "{} pubkeys were not in the registry and were stored inline; registry {} is stale",
+            index.misses(),
+            registry_path.display()
+        );
+    }
+    // Indexes first: an existing compact.bin implies its indexes exist.
+    std::fs::rename(&sig_index_tmp, &sig_index_path).with_context(|| {
+        format!(
+            "rename {} -> {}",
+            sig_index_tmp.display(),
+            sig_index_path.display()
+        )
+    })?;
+    std::fs::rename(&slot_index_tmp, &slot_index_path).with_context(|| {
         format!(
             "rename {} -> {}",
-            tmp_path.display(),
-            compact_path.display()
+            slot_index_tmp.display(),
+            slot_index_path.display()
         )
     })?;
+    if votes_index.is_some() {
+        std::fs::rename(&votes_index_tmp, &votes_index_path).with_context(|| {
+            format!(
+                "rename {} -> {}",
+                votes_index_tmp.display(),
+                votes_index_path.display()
+            )
+        })?;
+    }
+    // runtime.bin before block.bin: an existing block.bin implies a complete split.
+    if runtime.is_some() {
+        std::fs::rename(&runtime_tmp, &runtime_path).with_context(|| {
+            format!(
+                "rename {} -> {}",
+                runtime_tmp.display(),
+                runtime_path.display()
+            )
+        })?;
+    }
+    // votes.bin before compact.bin, for the same reason.
+    if votes.is_some() {
+        std::fs::rename(&votes_tmp, &votes_path).with_context(|| {
+            format!("rename {} -> {}", votes_tmp.display(), votes_path.display())
+        })?;
+    }
+    std::fs::rename(&tmp_path, &data_path)
+        .with_context(|| format!("rename {} -> {}", tmp_path.display(), data_path.display()))?;
 
     progress.final_report();
     Ok(())
 }
 
+/// Reusable per-block buffers
+struct BlockBuffers {
+    tx_payload: Vec<u8>,
+    runtime_payload: Vec<u8>,
+    block_payload: Vec<u8>,
+    varint: [u8; varint_max::<usize>()],
+    /// Log tables of the block being compacted
+    logs: LogInterner,
+    /// Last vote of each validator, for vote record deltas
+    votes: VoteChain,
+    /// Last balance of each account, for pre balance deltas
+    balances: BalanceChain,
+    /// Block rewards frames
+    zstd: ZstdReusableDecoder,
+    /// votes.bin frame of the block being compacted
+    vote_payload: Vec<u8>,
+    vote_positions: Vec<u32>,
+}
+
+impl BlockBuffers {
+    fn new(dicts: Arc<LogDicts>) -> Self {
+        Self {
+            tx_payload: Vec::with_capacity(8 << 20),
+            runtime_payload: Vec::with_capacity(8 << 20),
+            block_payload: Vec::with_capacity(8 << 20),
+            varint: [0u8; varint_max::<usize>()],
+            logs: LogInterner::with_dicts(dicts),
+            votes: VoteChain::default(),
+            balances: BalanceChain::default(),
+            zstd: ZstdReusableDecoder::new(),
+            vote_payload: Vec::with_capacity(8 << 20),
+            vote_positions: Vec::new(),
+        }
+    }
+}
+
+/// How transactions are laid out in the output frames.
+enum Layout<'w, W> {
+    /// compact.bin frame: postcard `CompactBlockRecord`
+    Plain,
+    /// compact.bin frame: `SectionedBlock`
+    Sectioned,
+    /// block.bin frame (`CompactReplayBlock`) plus runtime.bin frame (`CompactRuntimeBlock`)
+    Split(&'w mut PostcardFramedWriter<W>),
+}
+
+/// Where a compacted block is written.
+struct Outputs<'w, W> {
+    /// compact.bin, or block.bin in split mode
+    writer: &'w mut PostcardFramedWriter<W>,
+    layout: Layout<'w, W>,
+    /// votes.bin with `--split-votes`
+    votes: Option<&'w mut PostcardFramedWriter<W>>,
+    sigs: &'w mut SigIndexBuilder,
+}
+
+/// Compact one block. With `skipped` (`--skip-bad-txs`), transactions that
+/// fail to convert are counted there and left out instead of failing the block.
 fn compact_process_block_manual<W: std::io::Write>(
     group: &CarBlockGroup,
     index: &KeyIndex,
-    bh_index: & GxHashMap<[u8; 32], i32>,
-    writer: &mut PostcardFramedWriter<W>,
+    bh: &BlockhashRegistry,
+    out: Outputs<'_, W>,
     block_i: u32,
-    tx_payload: &mut Vec<u8>,
-    block_payload: &mut Vec<u8>,
-    varint_tmp: &mut [u8; varint_max::<usize>()],
+    bufs: &mut BlockBuffers,
+    mut skipped: Option<&mut u64>,
 ) -> Result<(u64, u64, Option<u64>), GroupError> {
+    let Outputs {
+        writer,
+        mut layout,
+        votes: votes_out,
+        sigs,
+    } = out;
     let block = match decode_node(group.block_payload()).map_err(GroupError::Node)? {
         Node::Block(b) => b,
         _ => return Err(GroupError::WrongRootKind),
@@ -245,7 +502,45 @@ fn compact_process_block_manual<W: std::io::Write>(
         block_height: block.meta.block_height,
     };
 
+    let BlockBuffers {
+        tx_payload,
+        runtime_payload,
+        block_payload,
+        varint,
+        logs,
+        votes,
+        balances,
+        zstd,
+        vote_payload,
+        vote_positions,
+    } = bufs;
+    votes.begin_block(&header);
+    balances.begin_block();
     tx_payload.clear();
+    runtime_payload.clear();
+    vote_payload.clear();
+    vote_positions.clear();
+
+    let rewards = group
+        .rewards(&block, zstd)?
+        .iter()
+        .map(|rw| compact_reward(rw, index))
+        .collect::<Result<Vec<_>>>()
+        .map_err(|e| {
+            error!("FAIL compact_reward: block_slot={} {:?}", block_slot, e);
+            GroupError::Other(format!("block rewards: {e}"))
+        })?;
+    let entries = compact_entries(&group.entries(&block)?, bh.hashes.get(block_i as usize))
+        .map_err(|e| {
+            error!("FAIL compact_entries: block_slot={} {:?}", block_slot, e);
+            GroupError::Other(format!("block entries: {e}"))
+        })?;
+    if entries.last().is_some_and(|e| e.hash.is_some()) {
+        warn!(
+            "block_slot={} blockhash differs from registry id {}, last entry hash kept",
+            block_slot, block_i
+        );
+    }
 
     let mut txs: u64 = 0;
     let mut tx_index_in_block: u32 = 0;
@@ -253,48 +548,141 @@ fn compact_process_block_manual<W: std::io::Write>(
     let mut it = group.transactions()?;
 
     while let Some((vtx, maybe_meta)) = it.next_tx()? {
+        let vote_tx = maybe_meta
+            .and_then(|meta| to_compact_vote_transaction(vtx, meta, index, &bh.index, votes));
+        // Vote records carry their own meta.
+        let maybe_meta = maybe_meta.filter(|_| vote_tx.is_none());
+
+        let compact_tx = match vote_tx
+            .map_or_else(|| to_compact_transaction(vtx, index, &bh.index), Ok)
+        {
+            Ok(tx) => tx,
+            Err(e) => match skipped.as_deref_mut() {
+                Some(n) => {
+                    warn!(
+                        "SKIP to_compact_transaction: block_slot={} tx_index_in_block={} kind={}: {:#}",
+                        block_slot,
+                        tx_index_in_block,
+                        tx_kind(vtx),
+                        e
+                    );
+                    *n += 1;
+                    continue;
+                }
+                None => {
+                    error!(
+                        "FAIL to_compact_transaction: block_slot={} tx_index_in_block={} kind={} sigs={}",
+                        block_slot,
+                        tx_index_in_block,
+                        tx_kind(vtx),
+                        vtx.signatures.len(),
+                    );
+                    error!("to_compact_transaction error: {:?}", e);
+                    return Err(GroupError::TxDecode);
+                }
+            },
+        };
+
+        let metadata_opt = match maybe_meta
+            .map(|meta| compact_meta_from_proto(meta, index, logs))
+            .transpose()
+        {
+            Ok(Some(mut compact_meta)) => {
+                // Sectioned transactions decode on their own; vote-only ones may
+                // be read back without votes.bin.
+                let chain = (!matches!(layout, Layout::Sectioned) && !is_vote_only(vtx))
+                    .then_some(&mut *balances);
+                compact_meta.encode_balances(&balance_keys(&compact_tx, &compact_meta), chain);
+                Some(compact_meta)
+            }
+            Ok(None) => None,
+            Err(e) => match skipped.as_deref_mut() {
+                Some(n) => {
+                    warn!(
+                        "SKIP compact_meta_from_proto: block_slot={} tx_index_in_block={}: {:#}",
+                        block_slot, tx_index_in_block, e
+                    );
+                    *n += 1;
+                    continue;
+                }
+                None => {
+                    error!(
+                        "FAIL compact_meta_from_proto: block_slot={} tx_index_in_block={}",
+                        block_slot, tx_index_in_block
+                    );
+                    error!("compact_meta_from_proto error: {:?}", e);
+                    return Err(GroupError::TxMetaDecode);
+                }
+            },
+        };
+
+        // Positions count stored transactions, skipped ones excluded.
+        if let Some(sig) = vtx.signatures.first() {
+            sigs.push(
+                sig,
+                SigLocation {
+                    block: block_i,
+                    tx: tx_index_in_block,
+                },
+            )
+            .map_err(|e| GroupError::Other(format!("signature index: {e}")))?;
+        }
+
         txs += 1;
         tx_index_in_block += 1;
 
-        let compact_tx = to_compact_transaction(vtx, index, bh_index).map_err(|e| {
-            error!(
-                "FAIL to_compact_transaction: block_slot={} tx_index_in_block={} kind={} sigs={}",
-                block_slot,
-                tx_index_in_block,
-                tx_kind(vtx),
-                vtx.signatures.len(),
-            );
-            error!("to_compact_transaction error: {:?}", e);
-            GroupError::TxDecode
-        })?;
+        if votes_out.is_some() && is_vote_only(vtx) {
+            vote_positions.push(tx_index_in_block - 1);
+            let elem = CompactTxWithMeta {
+                tx: compact_tx,
+                metadata: metadata_opt,
+            };
+            postcard::to_io(&elem, &mut *vote_payload).map_err(|_| GroupError::Io)?;
+            continue;
+        }
 
-        let metadata_opt = if let Some(meta) = maybe_meta {
-            let compact_meta = compact_meta_from_proto(meta, index).map_err(|e| {
-                error!(
-                    "FAIL compact_meta_from_proto: block_slot={} tx_index_in_block={}",
-                    block_slot, tx_index_in_block
-                );
-                error!("compact_meta_from_proto error: {:?}", e);
-                GroupError::TxMetaDecode
-            })?;
-            Some(compact_meta)
-        } else {
-            None
-        };
+        if let Layout::Split(_) = layout {
+            postcard::to_io(&compact_tx, &mut *tx_payload).map_err(|_| GroupError::Io)?;
+            postcard::to_io(&metadata_opt, &mut *runtime_payload).map_err(|_| GroupError::Io)?;
+            continue;
+        }
 
         let elem = CompactTxWithMeta {
             tx: compact_tx,
             metadata: metadata_opt,
         };
-
-        postcard::to_io(&elem, &mut *tx_payload).map_err(|_| GroupError::Io)?;
+        if let Layout::Sectioned = layout {
+            write_sectioned_tx(&elem, tx_payload).map_err(|_| GroupError::Io)?;
+        } else {
+            postcard::to_io(&elem, &mut *tx_payload).map_err(|_| GroupError::Io)?;
+        }
     }
 
+    let mut log_tables = logs.take();
+    encode_log_keys(&mut log_tables, index);
+
     block_payload.clear();
     postcard::to_io(&header, &mut *block_payload).map_err(|_| GroupError::Io)?;
+    match &layout {
+        Layout::Plain => {
+            postcard::to_io(&log_tables, &mut *block_payload).map_err(|_| GroupError::Io)?;
+            postcard::to_io(&rewards, &mut *block_payload).map_err(|_| GroupError::Io)?;
+            postcard::to_io(&Some(&entries), &mut *block_payload).map_err(|_| GroupError::Io)?;
+        }
+        Layout::Sectioned => {
+            write_sectioned_tables(&log_tables, block_payload).map_err(|_| GroupError::Io)?;
+            write_sectioned_rewards(&rewards, block_payload).map_err(|_| GroupError::Io)?;
+            write_sectioned_entries(Some(&entries), block_payload).map_err(|_| GroupError::Io)?;
+        }
+        // Logs and rewards live in runtime.bin, entries are replay data.
+        Layout::Split(_) => {
+            postcard::to_io(&Some(&entries), &mut *block_payload).map_err(|_| GroupError::Io)?;
+        }
+    }
 
-    let tx_count = txs as usize;
-    let len_bytes = varint_usize(tx_count, varint_tmp);
+    // Transactions in this frame, votes.bin ones excluded.
+    let tx_count = txs as usize - vote_positions.len();
+    let len_bytes = varint_usize(tx_count, varint);
     block_payload.extend_from_slice(len_bytes);
     block_payload.extend_from_slice(&*tx_payload);
 
@@ -302,6 +690,27 @@ fn compact_process_block_manual<W: std::io::Write>(
         .write_bytes(block_payload)
         .map_err(|_| GroupError::Io)?;
 
+    if let Layout::Split(rt) = &mut layout {
+        // CompactRuntimeBlock { slot, log_tables, rewards, metas }
+        block_payload.clear();
+        postcard::to_io(&block_slot, &mut *block_payload).map_err(|_| GroupError::Io)?;
+        postcard::to_io(&log_tables, &mut *block_payload).map_err(|_| GroupError::Io)?;
+        postcard::to_io(&rewards, &mut *block_payload).map_err(|_| GroupError::Io)?;
+        block_payload.extend_from_slice(varint_usize(tx_count, varint));
+        block_payload.extend_from_slice(&*runtime_payload);
+        rt.write_bytes(block_payload).map_err(|_| GroupError::Io)?;
+    }
+
+    if let Some(out) = votes_out {
+        // CompactVoteBlock { slot, positions, txs }
+        block_payload.clear();
+        postcard::to_io(&block_slot, &mut *block_payload).map_err(|_| GroupError::Io)?;
+        postcard::to_io(&*vote_positions, &mut *block_payload).map_err(|_| GroupError::Io)?;
+        block_payload.extend_from_slice(varint_usize(vote_positions.len(), varint));
+        block_payload.extend_from_slice(&*vote_payload);
+        out.write_bytes(block_payload).map_err(|_| GroupError::Io)?;
+    }
+
     Ok((1, txs, Some(block_slot)))
 }
 
@@ -330,107 +739,3 @@ pub fn varint_usize(n: usize, out: &mut [u8; varint_max::<usize>()]) -> &mut [u8
     debug_assert_eq!(value, 0);
     &mut out[..]
 }
-
-pub fn to_compact_transaction<'a>(
-    vtx: &'a car_reader::versioned_transaction::VersionedTransaction,
-    index: &KeyIndex,
-    bh_index: & GxHashMap<[u8; 32], i32>,
-) -> Result<CompactTransaction<'a>> {
-    let signatures = vtx.signatures.iter().map(|s| Signature(s)).collect();
-
-    let message = match &vtx.message {
-        VersionedMessage::Legacy(m) => {
-            let header = CompactMessageHeader {
-                num_required_signatures: m.header.num_required_signatures,
-                num_readonly_signed_accounts: m.header.num_readonly_signed_accounts,
-                num_readonly_unsigned_accounts: m.header.num_readonly_unsigned_accounts,
-            };
-
-            let account_keys = m
-                .account_keys
-                .iter()
-                .map(|a| index.lookup_unchecked(a))
-                .collect();
-
-            let recent_blockhash = bh_index
-                .get(m.recent_blockhash)
-                .copied()
-                .map(CompactRecentBlockhash::Id)
-                .unwrap_or_else(|| {
-                    CompactRecentBlockhash::Nonce(blockzilla_format::Nonce(m.recent_blockhash))
-                });
-
-            let instructions = m
-                .instructions
-                .iter()
-                .map(|ix| CompactInstruction {
-                    program_id_index: ix.program_id_index,
-                    accounts: ix.accounts.as_ref(),
-                    data: ix.data.as_ref(),
-                })
-                .collect();
-
-            CompactMessage::Legacy(CompactLegacyMessage {
-                header,
-                account_keys,
-                recent_blockhash,
-                instructions,
-            })
-        }
-
-        VersionedMessage::V0(m) => {
-            let header = CompactMessageHeader {
-                num_required_signatures: m.header.num_required_signatures,
-                num_readonly_signed_accounts: m.header.num_readonly_signed_accounts,
-                num_readonly_unsigned_accounts: m.header.num_readonly_unsigned_accounts,
-            };
-
-            let account_keys = m
-                .account_keys
-                .iter()
-                .map(|a| index.lookup_unchecked(a))
-                .collect();
-
-            let recent_blockhash = bh_index
-                .get(m.recent_blockhash)
-                .copied()
-                .map(CompactRecentBlockhash::Id)
-                .unwrap_or_else(|| {
-                    CompactRecentBlockhash::Nonce(blockzilla_format::Nonce(m.recent_blockhash))
-                });
-
-            let instructions = m
-                .instructions
-                .iter()
-                .map(|ix| CompactInstruction {
-                    program_id_index: ix.program_id_index,
-                    accounts: ix.accounts.as_ref(),
-                    data: ix.data.as_ref(),
-                })
-                .collect();
-
-            let address_table_lookups = m
-                .address_table_lookups
-                .iter()
-                .map(|lookup| CompactAddressTableLookup {
-                    account_key: index.lookup_unchecked(lookup.account_key),
-                    writable_indexes: lookup.writable_indexes.as_ref(),
-                    readonly_indexes: lookup.readonly_indexes.as_ref(),
-                })
-                .collect();
-
-            CompactMessage::V0(CompactV0Message {
-                header,
-                account_keys,
-                recent_blockhash,
-                instructions,
-                address_table_lookups,
-            })
-        }
-    };
-
-    Ok(CompactTransaction {
-        signatures,
-        message,
-    })
-}
@@ -13,8 +13,10 @@ pub const SLOTS_PER_EPOCH: u64 = 432_000;
 mod build;
 mod build_all;
 mod build_blockhash_registry;
+mod build_global_registry;
 mod build_registry;
 mod compact;
+mod verify;
 
 pub(crate) fn file_nonempty(path: &Path) -> bool {
     std::fs::metadata(path)
@@ -45,6 +47,36 @@ pub(crate) struct Cli {
     #[arg(long, default_value_t = false, global = true)]
     pub(crate) skip_bad_txs: bool,
 
+    /// Write block.bin (replay data) and runtime.bin (status meta) instead of compact.bin
+    #[arg(long, default_value_t = false, global = true)]
+    pub(crate) split_runtime: bool,
+
+    /// Write compact.bin transactions as length-prefixed sections that readers can skip
+    #[arg(
+        long,
+        default_value_t = false,
+        global = true,
+        conflicts_with = "split_runtime"
+    )]
+    pub(crate) sectioned: bool,
+
+    /// Move vote-only transactions to votes.bin, next to compact.bin
+    #[arg(
+        long,
+        default_value_t = false,
+        global = true,
+        conflicts_with = "split_runtime"
+    )]
+    pub(crate) split_votes: bool,
+
+    /// Log strings kept in the epoch dictionary (strings.bin) by the registry pass, 0 disables
+    #[arg(long, default_value_t = 65_536, global = true)]
+    pub(crate) string_dict: usize,
+
+    /// Log templates mined for unknown programs (templates.bin) by the registry pass, 0 disables
+    #[arg(long, default_value_t = 16_384, global = true)]
+    pub(crate) log_templates: usize,
+
     #[command(subcommand)]
     pub(crate) cmd: Cmd,
 }
@@ -70,6 +102,11 @@ pub(crate) enum Cmd {
         epoch: u64,
     },
 
+    /// Assign stable global ids to registry.bin (appends to global-registry.bin)
+    BuildGlobalRegistry {
+        epoch: u64,
+    },
+
     /// Pass 2 only: build compact.bin from CAR + registry.bin
     Compact {
         epoch: u64,
@@ -77,6 +114,15 @@ pub(crate) enum Cmd {
 
     /// Process all epochs found in the cache directory
     BuildAll,
+
+    /// Compare compact.bin against the source CAR, block by block
+    Verify {
+        epoch: u64,
+
+        /// Stop after this many divergences
+        #[arg(long, default_value_t = 20)]
+        max_divergences: usize,
+    },
 }
 
 fn main() -> Result<()> {
@@ -88,9 +134,14 @@ fn main() -> Result<()> {
         Cmd::Build { epoch, .. } => build::run(&cli, epoch),
         Cmd::BuildRegistry { epoch } => build_registry::run(&cli, epoch),
         Cmd::BuildBlockhashRegistry { epoch } => build_blockhash_registry::run(&cli, epoch),
+        Cmd::BuildGlobalRegistry { epoch } => build_global_registry::run(&cli, epoch),
 
         Cmd::Compact { epoch } => compact::run(&cli, epoch),
         Cmd::BuildAll => build_all::run(&cli),
+        Cmd::Verify {
+            epoch,
+            max_divergences,
+        } => verify::run(&cli, epoch, max_divergences),
     }
 }
 
@@ -103,6 +154,25 @@ pub(crate) fn epoch_paths(cli: &Cli, epoch: u64) -> (PathBuf, PathBuf, PathBuf,
     (car_path, epoch_dir, registry_path, bh_path, compact_path)
 }
 
+/// File whose presence marks a finished compaction (block.bin is renamed last in split mode).
+pub(crate) fn compact_output(cli: &Cli, compact_path: &Path) -> PathBuf {
+    if cli.split_runtime {
+        blockzilla_format::split_paths(compact_path).0
+    } else {
+        compact_path.to_path_buf()
+    }
+}
+
+/// (global registry shared by all epochs, per-epoch remap)
+pub(crate) fn global_paths(cli: &Cli, epoch: u64) -> (PathBuf, PathBuf) {
+    let global_path = cli.output_dir.join("global-registry.bin");
+    let remap_path = cli
+        .output_dir
+        .join(format!("epoch-{}", epoch))
+        .join("global-remap.bin");
+    (global_path, remap_path)
+}
+
 pub(crate) fn format_duration(seconds: f64) -> String {
     let total_secs = seconds as u64;
 
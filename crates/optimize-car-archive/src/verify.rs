@@ -0,0 +1,588 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+use tracing::{error, info};
+
+use car_reader::{
+    car_stream::CarStream,
+    confirmed_block::TransactionStatusMeta,
+    error::GroupError,
+    metadata_decoder::ZstdReusableDecoder,
+    node::{Node, decode_node},
+    versioned_transaction::{VersionedMessage, VersionedTransaction},
+};
+
+use blockzilla_format::{
+    CompactBlockRecord, CompactFileHeader, CompactReader, FLAG_SPLIT_VOTES, KeyStore, LogDicts,
+    SplitReader, TxAt, VoteChain, rehydrate_block, split_paths, votes_path,
+};
+
+use crate::{BUFFER_SIZE, Cli, ProgressTracker, compact::load_blockhash_registry, epoch_paths};
+
+/// One mismatch between the CAR and the compact archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Divergence {
+    slot: u64,
+    tx: Option<usize>,
+    what: String,
+}
+
+/// Collects divergences and decides when to stop.
+struct Report {
+    max: usize,
+    found: Vec<Divergence>,
+}
+
+impl Report {
+    fn diverge(&mut self, slot: u64, tx: Option<usize>, what: &str) {
+        match tx {
+            Some(tx) => error!("DIVERGENCE slot={} tx={}: {}", slot, tx, what),
+            None => error!("DIVERGENCE slot={}: {}", slot, what),
+        }
+        self.found.push(Divergence {
+            slot,
+            tx,
+            what: what.to_string(),
+        });
+    }
+
+    #[inline]
+    fn full(&self) -> bool {
+        self.found.len() >= self.max
+    }
+}
+
+/// compact.bin (any version or layout), or block.bin + runtime.bin joined back together.
+enum Compact {
+    Single(CompactReader<BufReader<File>>),
+    Split(SplitReader<BufReader<File>>),
+}
+
+impl Compact {
+    fn header(&self) -> CompactFileHeader {
+        match self {
+            Compact::Single(r) => r.header(),
+            Compact::Split(r) => r.header(),
+        }
+    }
+
+    fn read(&mut self) -> Result<Option<CompactBlockRecord<'_>>> {
+        match self {
+            Compact::Single(r) => r.read(),
+            Compact::Split(r) => r.read_full(),
+        }
+    }
+}
+
+/// Stream the CAR and compact.bin side by side and compare every block.
+pub(crate) fn run(cli: &Cli, epoch: u64, max_divergences: usize) -> Result<()> {
+    let report = check(cli, epoch, max_divergences)?;
+    if !report.found.is_empty() {
+        anyhow::bail!(
+            "epoch {}: {} divergence(s){}",
+            epoch,
+            report.found.len(),
+            if report.full() {
+                " (stopped early)"
+            } else {
+                ""
+            }
+        );
+    }
+    info!("Epoch {} compact archive matches the CAR", epoch);
+    Ok(())
+}
+
+/// Open the compact data of the epoch. Split archives have block.bin instead
+/// of compact.bin; the headers tell the rest, like `SlotReader::open`.
+fn open_compact(compact_path: &Path) -> Result<(Compact, Vec<PathBuf>)> {
+    let (block_path, runtime_path) = split_paths(compact_path);
+    if !compact_path.exists() && block_path.exists() {
+        let r = SplitReader::open(compact_path, true)?;
+        return Ok((Compact::Split(r), vec![block_path, runtime_path]));
+    }
+
+    let f = File::open(compact_path)
+        .with_context(|| format!("Failed to open {}", compact_path.display()))?;
+    let mut r = CompactReader::new(BufReader::with_capacity(BUFFER_SIZE, f))
+        .with_context(|| format!("Failed to read {}", compact_path.display()))?;
+    let mut paths = vec![compact_path.to_path_buf()];
+    if r.header().flags & FLAG_SPLIT_VOTES != 0 {
+        let path = votes_path(compact_path);
+        let f = File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        r = r
+            .with_votes(BufReader::with_capacity(BUFFER_SIZE, f))
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        paths.push(path);
+    }
+    Ok((Compact::Single(r), paths))
+}
+
+fn check(cli: &Cli, epoch: u64, max_divergences: usize) -> Result<Report> {
+    let (car_path, _, registry_path, bh_registry_path, compact_path) = epoch_paths(cli, epoch);
+
+    for p in [&car_path, &registry_path, &bh_registry_path] {
+        if !p.exists() {
+            anyhow::bail!("Input not found: {}", p.display());
+        }
+    }
+    let (mut compact, data_paths) = open_compact(&compact_path)?;
+
+    info!("Verifying compact archive epoch={}", epoch);
+    info!("  car:      {}", car_path.display());
+    for p in &data_paths {
+        info!("  compact:  {}", p.display());
+    }
+
+    let store = KeyStore::load(&registry_path)?;
+    info!("Registry loaded: {} keys", store.len());
+    let bh = load_blockhash_registry(cli, epoch, &bh_registry_path)?;
+
+    let header = compact.header();
+    info!("  format:   v{} {:?}", header.version, header.layout);
+    let dicts = LogDicts::load_for(header, &registry_path)?;
+    if header.uses_string_dict() {
+        info!("String dictionary loaded: {} strings", dicts.strings.len());
+    }
+    if header.uses_templates() {
+        info!("Log templates loaded: {} templates", dicts.templates.len());
+    }
+
+    let mut report = Report {
+        max: max_divergences.max(1),
+        found: Vec::new(),
+    };
+    let mut progress = ProgressTracker::new("Verify");
+    let mut last_slot = 0;
+    // Blockhash of the previous CAR block, none before the first.
+    let mut last_blockhash: Option<[u8; 32]> = None;
+    let mut votes = VoteChain::default();
+    let mut zstd = ZstdReusableDecoder::new();
+
+    let mut stream = CarStream::open_zstd(Path::new(&car_path))?;
+    while let Some(group) = stream.next_group()? {
+        let block = match decode_node(group.block_payload()).map_err(GroupError::Node)? {
+            Node::Block(b) => b,
+            _ => anyhow::bail!("CAR group root is not a block"),
+        };
+        let slot = block.slot;
+        last_slot = slot;
+
+        let Some(record) = compact.read()? else {
+            report.diverge(slot, None, "compact.bin ended before the CAR");
+            break;
+        };
+        let got = match rehydrate_block(&record, header, &store, &bh, &dicts, &mut votes) {
+            Ok(got) => got,
+            Err(e) => {
+                let tx = e.downcast_ref::<TxAt>().map(|at| at.tx);
+                report.diverge(slot, tx, &format!("rehydrate failed: {:#}", e));
+                if report.full() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        if got.slot != slot {
+            report.diverge(slot, None, &format!("compact block is slot {}", got.slot));
+            break;
+        }
+        if got.parent_slot != block.meta.parent_slot.unwrap_or(0) {
+            report.diverge(slot, None, "parent_slot");
+        }
+        if got.block_time != block.meta.blocktime {
+            report.diverge(slot, None, "block_time");
+        }
+        if got.block_height != block.meta.block_height {
+            report.diverge(slot, None, "block_height");
+        }
+        if header.uses_block_rewards() && got.rewards != group.rewards(&block, &mut zstd)? {
+            report.diverge(slot, None, "rewards");
+        }
+        // The blockhash is the hash of the block's last entry.
+        let entries = group.entries(&block)?;
+        let blockhash = entries.last().map(|e| e.hash);
+        if got.blockhash.map(|h| &h[..]) != blockhash {
+            report.diverge(slot, None, "blockhash");
+        }
+        if let Some(prev) = &last_blockhash
+            && got.previous_blockhash != Some(prev)
+        {
+            report.diverge(slot, None, "previous_blockhash");
+        }
+        last_blockhash = blockhash.and_then(|h| h.try_into().ok());
+        if let Some(got_entries) = &got.entries {
+            let same = got_entries.len() == entries.len()
+                && got_entries.iter().zip(&entries).all(|(g, e)| {
+                    g.num_hashes == e.num_hashes
+                        && g.hash[..] == *e.hash
+                        && g.tx_count as usize == e.transactions.len()
+                });
+            if !same {
+                report.diverge(slot, None, "entries");
+            }
+        }
+
+        let mut got_txs = got.transactions.iter();
+        let mut it = group.transactions()?;
+        let mut tx_i = 0usize;
+        while let Some((vtx, meta)) = it.next_tx()? {
+            let Some((got_tx, got_meta)) = got_txs.next() else {
+                report.diverge(slot, Some(tx_i), "missing from compact block");
+                break;
+            };
+
+            for what in tx_diffs(vtx, got_tx) {
+                report.diverge(slot, Some(tx_i), what);
+            }
+            match (meta, got_meta) {
+                (Some(src), Some(got)) => {
+                    for what in meta_diffs(src, got) {
+                        report.diverge(slot, Some(tx_i), &what);
+                    }
+                }
+                (None, None) => {}
+                (Some(_), None) => report.diverge(slot, Some(tx_i), "meta dropped"),
+                (None, Some(_)) => report.diverge(slot, Some(tx_i), "meta added"),
+            }
+
+            tx_i += 1;
+            if report.full() {
+                break;
+            }
+        }
+        let extra = got_txs.count();
+        if extra > 0 && !report.full() {
+            report.diverge(slot, None, &format!("{} extra txs in compact block", extra));
+        }
+
+        progress.update(1, tx_i as u64);
+        progress.update_slot(slot);
+
+        if report.full() {
+            break;
+        }
+    }
+
+    if !report.full() && compact.read()?.is_some() {
+        report.diverge(
+            last_slot,
+            None,
+            "compact.bin has blocks past the end of the CAR",
+        );
+    }
+
+    progress.final_report();
+    Ok(report)
+}
+
+fn tx_diffs(src: &VersionedTransaction, got: &VersionedTransaction) -> Vec<&'static str> {
+    let mut out = Vec::new();
+    if src.signatures != got.signatures {
+        out.push("signatures");
+    }
+
+    match (&src.message, &got.message) {
+        (VersionedMessage::Legacy(a), VersionedMessage::Legacy(b)) => {
+            if a.header != b.header {
+                out.push("header");
+            }
+            if a.account_keys != b.account_keys {
+                out.push("account_keys");
+            }
+            if a.recent_blockhash != b.recent_blockhash {
+                out.push("recent_blockhash");
+            }
+            if a.instructions != b.instructions {
+                out.push("instructions");
+            }
+        }
+        (VersionedMessage::V0(a), VersionedMessage::V0(b)) => {
+            if a.header != b.header {
+                out.push("header");
+            }
+            if a.account_keys != b.account_keys {
+                out.push("account_keys");
+            }
+            if a.recent_blockhash != b.recent_blockhash {
+                out.push("recent_blockhash");
+            }
+            if a.instructions != b.instructions {
+                out.push("instructions");
+            }
+            if a.address_table_lookups != b.address_table_lookups {
+                out.push("address_table_lookups");
+            }
+        }
+        _ => out.push("message version"),
+    }
+    out
+}
+
+fn meta_diffs(src: &TransactionStatusMeta, got: &TransactionStatusMeta) -> Vec<String> {
+    let mut out = Vec::new();
+
+    macro_rules! field {
+        ($($f:ident),* $(,)?) => {
+            $(
+                if src.$f != got.$f {
+                    out.push(stringify!($f).to_string());
+                }
+            )*
+        };
+    }
+
+    field!(
+        err,
+        fee,
+        pre_balances,
+        post_balances,
+        inner_instructions,
+        inner_instructions_none,
+        log_messages_none,
+        pre_token_balances,
+        post_token_balances,
+        rewards,
+        loaded_writable_addresses,
+        loaded_readonly_addresses,
+        return_data,
+        return_data_none,
+        compute_units_consumed,
+        cost_units,
+    );
+
+    // Logs must render byte for byte; point at the first line that doesn't.
+    if src.log_messages != got.log_messages {
+        let n = src.log_messages.len().max(got.log_messages.len());
+        let i = (0..n)
+            .find(|&i| src.log_messages.get(i) != got.log_messages.get(i))
+            .unwrap_or(n);
+        out.push(format!(
+            "log_messages[{}]: {:?} != {:?}",
+            i,
+            src.log_messages.get(i),
+            got.log_messages.get(i)
+        ));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build;
+    use clap::Parser;
+    use minicbor::Encoder;
+    use prost::Message;
+
+    /// First slot of the fixture, in the protobuf meta era.
+    const SLOT: u64 = 200 * 432_000;
+
+    struct Tx {
+        sig: u8,
+        payer: [u8; 32],
+        recent_blockhash: [u8; 32],
+        fee: u64,
+    }
+
+    fn key(i: u8) -> [u8; 32] {
+        let mut k = [i; 32];
+        k[0] = 0xA0 ^ i;
+        k
+    }
+
+    fn cid(n: u32) -> [u8; 36] {
+        let mut c = [0u8; 36];
+        c[..4].copy_from_slice(&[0x01, 0x71, 0x12, 0x20]);
+        c[4..8].copy_from_slice(&n.to_le_bytes());
+        c
+    }
+
+    fn cid_ref(e: &mut Encoder<&mut Vec<u8>>, c: &[u8; 36]) {
+        let mut bytes = vec![0];
+        bytes.extend_from_slice(c);
+        e.bytes(&bytes).unwrap();
+    }
+
+    fn data_frame(e: &mut Encoder<&mut Vec<u8>>, data: &[u8]) {
+        e.array(6).unwrap().u64(6).unwrap();
+        e.null().unwrap().null().unwrap().null().unwrap();
+        e.bytes(data).unwrap().null().unwrap();
+    }
+
+    fn uvarint(mut n: usize, out: &mut Vec<u8>) {
+        while n >= 0x80 {
+            out.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+    }
+
+    /// A legacy system transfer from `payer`, in wire format.
+    fn wire(tx: &Tx) -> Vec<u8> {
+        let mut out = vec![1];
+        out.extend_from_slice(&[tx.sig; 64]);
+        out.extend_from_slice(&[1, 0, 1, 3]);
+        for k in [tx.payer, key(2), [0; 32]] {
+            out.extend_from_slice(&k);
+        }
+        out.extend_from_slice(&tx.recent_blockhash);
+        out.extend_from_slice(&[1, 2, 2, 0, 1, 12, 2, 0, 0, 0]);
+        out.extend_from_slice(&5u64.to_le_bytes());
+        out
+    }
+
+    fn meta(tx: &Tx) -> TransactionStatusMeta {
+        let system = "11111111111111111111111111111111";
+        TransactionStatusMeta {
+            fee: tx.fee,
+            pre_balances: vec![1_000_000, 10, 1],
+            post_balances: vec![1_000_000 - 5000 - 5, 15, 1],
+            log_messages: vec![
+                format!("Program {} invoke [1]", system),
+                format!("Program {} success", system),
+            ],
+            return_data_none: true,
+            ..TransactionStatusMeta::default()
+        }
+    }
+
+    /// A zstd CAR with one entry per block, its hash the blockhash.
+    fn write_car(path: &Path, blocks: &[(u64, [u8; 32], Vec<Tx>)]) {
+        let mut car = Vec::new();
+        uvarint(1, &mut car);
+        car.push(0xA0);
+        let mut next = 0u32;
+        let mut section = |car: &mut Vec<u8>, payload: &[u8]| {
+            next += 1;
+            let c = cid(next);
+            uvarint(c.len() + payload.len(), car);
+            car.extend_from_slice(&c);
+            car.extend_from_slice(payload);
+            c
+        };
+
+        for (slot, hash, txs) in blocks {
+            let mut tx_cids = Vec::new();
+            for (i, tx) in txs.iter().enumerate() {
+                let mut node = Vec::new();
+                let mut e = Encoder::new(&mut node);
+                e.array(5).unwrap().u64(0).unwrap();
+                data_frame(&mut e, &wire(tx));
+                data_frame(&mut e, &meta(tx).encode_to_vec());
+                e.u64(*slot).unwrap().u64(i as u64).unwrap();
+                tx_cids.push(section(&mut car, &node));
+            }
+
+            let mut node = Vec::new();
+            let mut e = Encoder::new(&mut node);
+            e.array(4).unwrap().u64(1).unwrap().u64(12).unwrap();
+            e.bytes(hash).unwrap().array(tx_cids.len() as u64).unwrap();
+            for c in &tx_cids {
+                cid_ref(&mut e, c);
+            }
+            let entry = section(&mut car, &node);
+
+            let mut node = Vec::new();
+            let mut e = Encoder::new(&mut node);
+            e.array(6).unwrap().u64(2).unwrap().u64(*slot).unwrap();
+            e.array(0).unwrap().array(1).unwrap();
+            cid_ref(&mut e, &entry);
+            e.array(3).unwrap();
+            e.u64(slot - 1)
+                .unwrap()
+                .i64(1_700_000_000)
+                .unwrap()
+                .u64(*slot)
+                .unwrap();
+            e.null().unwrap();
+            section(&mut car, &node);
+        }
+
+        std::fs::write(path, zstd::encode_all(&car[..], 0).unwrap()).unwrap();
+    }
+
+    fn fixture(fee: u64) -> Vec<(u64, [u8; 32], Vec<Tx>)> {
+        let first = [0xB0; 32];
+        vec![
+            (
+                SLOT,
+                first,
+                vec![
+                    // Durable nonce: not in the blockhash registry.
+                    Tx {
+                        sig: 1,
+                        payer: key(1),
+                        recent_blockhash: [0xEE; 32],
+                        fee: 5000,
+                    },
+                    Tx {
+                        sig: 2,
+                        payer: key(3),
+                        recent_blockhash: [0xEE; 32],
+                        fee: 5000,
+                    },
+                ],
+            ),
+            (
+                SLOT + 1,
+                [0xB1; 32],
+                vec![Tx {
+                    sig: 3,
+                    payer: key(1),
+                    recent_blockhash: first,
+                    fee,
+                }],
+            ),
+        ]
+    }
+
+    #[test]
+    fn verify_reports_a_changed_fee() {
+        // Every ZstdReusableDecoder keeps its 1 MiB buffer inline, more than
+        // the default test thread stack holds in debug builds.
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(compact_then_verify)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn compact_then_verify() {
+        let dir = std::env::temp_dir().join(format!("bz-verify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let arg = |p: &Path| p.to_str().unwrap().to_string();
+        let cli = Cli::parse_from([
+            "optimize-car-archive".to_string(),
+            "--cache-dir".to_string(),
+            arg(&dir),
+            "--output-dir".to_string(),
+            arg(&dir.join("out")),
+            "build".to_string(),
+            "0".to_string(),
+        ]);
+        let (car_path, ..) = epoch_paths(&cli, 0);
+
+        write_car(&car_path, &fixture(5000));
+        build::run(&cli, 0).unwrap();
+        assert_eq!(check(&cli, 0, 20).unwrap().found, []);
+
+        write_car(&car_path, &fixture(6000));
+        assert_eq!(
+            check(&cli, 0, 20).unwrap().found,
+            [Divergence {
+                slot: SLOT + 1,
+                tx: Some(0),
+                what: "fee".to_string(),
+            }]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}